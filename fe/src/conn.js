@@ -45,11 +45,41 @@ Object.assign(conn.send, {
     joinGame: (name, password) => {
         conn.send({ type: 'join-game', name, password });
     },
+    joinRoom: (id, password) => {
+        conn.send({ type: 'join-room', id, password });
+    },
+    listRooms: () => {
+        conn.send({ type: 'list-rooms' });
+    },
     startGame: () => {
         conn.send({ type: 'start-game' });
     },
-    gameCommand: (command) => {
-        conn.send({ type: 'game-command', command });
+    cancelStart: () => {
+        conn.send({ type: 'cancel-start' });
+    },
+    queueMatch: (mode) => {
+        conn.send({ type: 'queue-match', mode });
+    },
+    leaveQueue: () => {
+        conn.send({ type: 'leave-queue' });
+    },
+    kickPlayer: (name) => {
+        conn.send({ type: 'kick-player', name });
+    },
+    banPlayer: (name) => {
+        conn.send({ type: 'ban-player', name });
+    },
+    transferOwnership: (name) => {
+        conn.send({ type: 'transfer-ownership', name });
+    },
+    pause: () => {
+        conn.send({ type: 'pause' });
+    },
+    resume: () => {
+        conn.send({ type: 'resume' });
+    },
+    gameCommand: (command, time, seq) => {
+        conn.send({ type: 'game-command', command, time, seq });
     },
     field: (field) => {
         conn.send({ type: 'field', field });
@@ -63,13 +93,24 @@ conn.on('message', msg => {
     switch (msg.type) {
     case 'name-taken': return emit('name-taken');
     case 'client-list': return emit('client-list', msg.clients);
+    case 'room-list': return emit('room-list', msg.rooms);
     case 'started-game': return emit('started-game', msg.client_fields);
+    case 'queued': return emit('queued', msg.mode, msg.waiting);
+    case 'left-queue': return emit('left-queue');
+    case 'failed-queue-match': return emit('failed-queue-match', msg.reason);
     case 'joined-game': return emit('joined-game');
+    case 'kicked': return emit('kicked', msg.banned);
+    case 'room-full': return emit('room-full');
+    case 'room-closed': return emit('room-closed');
+    case 'countdown': return emit('countdown', msg.seconds_left);
+    case 'disqualified': return emit('disqualified', msg.name, msg.reason);
+    case 'paused': return emit('paused', msg.paused);
     case 'failed-join-game': return emit('failed-join-game');
     case 'player-list': return emit('player-list', msg.players);
     case 'confirmed-start-game': return emit('confirmed-start-game');
-    case 'ended-game': return emit('ended-game');
+    case 'ended-game': return emit('ended-game', msg.stats);
     case 'fields': return emit('fields', msg.fields);
+    case 'field-delta': return emit('field-delta', msg.fields);
     default: console.log('receive message with unknown type', msg);
     }
 });
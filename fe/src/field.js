@@ -15,7 +15,10 @@ const TILE_COLORS = {
     Z: [0.96, 0.28, 0.33, 1],
     J: [0.39, 0.48, 0.77, 1],
     L: [0.92, 0.6, 0.31, 1],
+    G: [0.5, 0.5, 0.5, 1],
 };
+// used for custom pieces
+const CUSTOM_COLOR = [0.8, 0.8, 0.8, 1];
 const GHOST_COLOR = [1, 1, 1, 0.5];
 const TILE_SHAPES = {
     I: [[0, 0], [1, 0], [2, 0], [3, 0]],
@@ -49,7 +52,7 @@ export default class Field {
     }
 
     updateField ({ name, field }) {
-        if (this.field && field.a.y < this.field.a.y) {
+        if (this.field && this.field.a && field.a && field.a.y < this.field.a.y) {
             // active moved down
             const dy = this.field.a.y - field.a.y;
             this.activeDownDelta = dy;
@@ -116,7 +119,7 @@ export default class Field {
 
             for (let x = 0; x < this.field.w; x++) {
                 const tile = this.field.t[y * this.field.w + x].substr(0, 1);
-                tileShader.uniforms.color = TILE_COLORS[tile];
+                tileShader.uniforms.color = TILE_COLORS[tile] || CUSTOM_COLOR;
                 tileShader.uniforms.pos = [px + x * TILE_SIZE, py + posY];
                 quad.draw();
             }
@@ -124,29 +127,30 @@ export default class Field {
             posY += tileHeight;
         }
 
-        // draw active ghost tile
-        const ghostY = this.field
-        this.drawTiles(
-            px + this.field.a.x * TILE_SIZE,
-            py,
-            this.field.a.g,
-            yOffsets,
-            this.field.a.p,
-            this.field.a.t,
-            0,
-            GHOST_COLOR,
-        );
-
-        // draw active tile
-        this.drawTiles(
-            px + this.field.a.x * TILE_SIZE,
-            py,
-            this.field.a.y,
-            yOffsets,
-            this.field.a.p,
-            this.field.a.t,
-            this.activeDownDelta,
-        );
+        if (this.field.a) {
+            // draw active ghost tile
+            this.drawTiles(
+                px + this.field.a.x * TILE_SIZE,
+                py,
+                this.field.a.g,
+                yOffsets,
+                this.field.a.p,
+                this.field.a.t,
+                0,
+                GHOST_COLOR,
+            );
+
+            // draw active tile
+            this.drawTiles(
+                px + this.field.a.x * TILE_SIZE,
+                py,
+                this.field.a.y,
+                yOffsets,
+                this.field.a.p,
+                this.field.a.t,
+                this.activeDownDelta,
+            );
+        }
 
         this.activeDownDelta = 0;
 
@@ -169,7 +173,7 @@ export default class Field {
     }
 
     drawTiles (x, py, y, yOff, p, t, downDeltaY, colorOverride) {
-        tileShader.uniforms.color = colorOverride || TILE_COLORS[p];
+        tileShader.uniforms.color = colorOverride || TILE_COLORS[p] || CUSTOM_COLOR;
         tileShader.uniforms.expand = true;
         for (let i = 0; i < t.length; i += 2) {
             const [dx, dy] = [t[i], t[i + 1]];
@@ -186,7 +190,7 @@ export default class Field {
                     TILE_SIZE * downDeltaY,
                     0,
                     -downDeltaY * downDeltaY,
-                    TILE_COLORS[p],
+                    TILE_COLORS[p] || CUSTOM_COLOR,
                 );
             }
         }
@@ -195,7 +199,8 @@ export default class Field {
     drawPiece (x, y, p) {
         if (!p) return;
         const shape = TILE_SHAPES[p];
-        tileShader.uniforms.color = TILE_COLORS[p];
+        if (!shape) return; // TODO: custom piece previews
+        tileShader.uniforms.color = TILE_COLORS[p] || CUSTOM_COLOR;
         tileShader.uniforms.expand = true;
         for (const [dx, diy] of shape) {
             tileShader.uniforms.pos = [x + dx * TILE_SIZE, y - diy * TILE_SIZE];
@@ -4,7 +4,7 @@ import createFBO from 'gl-fbo';
 import quad from './quad';
 import { gameFBO as gameFBOShader, bloomThreshold, bloomComposite, gaussianPass } from './shaders';
 import Field from './field';
-import { createActiveField } from '../../tetris-wasm/pkg';
+import { createActiveField, getLevel, getGravityInterval, RemoteField } from '../../tetris-wasm/pkg';
 import * as gamepad from './gamepad';
 
 const LOCK_DELAY = 0.5;
@@ -32,6 +32,12 @@ export default class Game {
         this.fbo = new GameFBO();
         this.field = createActiveField();
         this.fields = {};
+        // other players’ fields as sent by the server
+        this.remoteFields = {};
+        this.onRemoteFields = fields => this.receiveFields(fields);
+        this.onRemoteFieldDeltas = deltas => this.receiveFieldDeltas(deltas);
+        conn.on('fields', this.onRemoteFields);
+        conn.on('field-delta', this.onRemoteFieldDeltas);
         this.dirty = true;
         this.time = 0;
         this.score = 0;
@@ -39,13 +45,11 @@ export default class Game {
     }
 
     getLevel () {
-        // TODO: needs tweaking
-        return Math.ceil(Math.log(Math.pow(this.score / 1000, 1.4) + 2));
+        return getLevel(this.score);
     }
 
     getStepCooldown () {
-        const level = this.getLevel();
-        return Math.pow(0.8 - ((level - 1) * 0.007), level - 1);
+        return getGravityInterval(this.getLevel());
     }
 
     updateFields (fields) {
@@ -59,9 +63,72 @@ export default class Game {
         }
     }
 
+    receiveFields (fields) {
+        for (const name in fields) {
+            const json = JSON.stringify(fields[name]);
+            if (name in this.remoteFields) this.remoteFields[name].update(json);
+            else this.remoteFields[name] = RemoteField.deserialize(json);
+            this.updateRemoteField(name);
+        }
+    }
+
+    receiveFieldDeltas (deltas) {
+        for (const name in deltas) {
+            // deltas apply to the last full state, which is always sent first
+            const field = this.remoteFields[name];
+            if (!field) continue;
+            field.applyDelta(JSON.stringify(deltas[name]));
+            this.updateRemoteField(name);
+        }
+    }
+
+    updateRemoteField (name) {
+        const field = this.remoteFields[name];
+        const width = field.getFieldWidth();
+        const height = field.getFieldHeight();
+        const tiles = [];
+
+        for (let y = 0; y < height; y++) {
+            for (let x = 0; x < width; x++) {
+                const tile = field.getFieldTile(x, y);
+                if (typeof tile === 'number') tiles.push(`X${tile}`);
+                else tiles.push(tile.trim());
+            }
+        }
+
+        const activePiece = field.getActivePiece();
+        const ghostPiece = field.getGhostPiece();
+        const a = activePiece && {
+            p: activePiece.type,
+            x: activePiece.pos.x,
+            y: activePiece.pos.y,
+            g: ghostPiece.pos.y,
+            t: activePiece.getTiles(),
+        };
+
+        this.updateFields({
+            [name]: {
+                name,
+                field: {
+                    w: width,
+                    // the server doesn’t send the visible height, so assume it’s the same
+                    h: Math.min(height, this.field.getFieldTopHeight()),
+                    s: field.getScore(),
+                    l: field.getLevel(),
+                    b: false,
+                    t: tiles,
+                    n: field.getNextPiece(),
+                    o: null,
+                    a,
+                },
+            },
+        });
+    }
+
     onKeyDown (key) {
         const name = keymap[key];
         if (name) {
+            this.field.recordInput();
             if (name === 'hardDropActive') {
                 this.bounce = true;
                 this.field.sonicDropActive(this.time);
@@ -106,7 +173,8 @@ export default class Game {
 
         if (bounce) {
             const clearedLines = this.field.clearLines(CLEAR_TIMEOUT, this.time);
-            this.scoreClearedLines(clearedLines);
+            this.scoreClearedLines(clearedLines.count);
+            clearedLines.free();
         }
 
         if (this.dirty) {
@@ -213,6 +281,9 @@ export default class Game {
     }
 
     dispose () {
+        conn.removeListener('fields', this.onRemoteFields);
+        conn.removeListener('field-delta', this.onRemoteFieldDeltas);
+        for (const name in this.remoteFields) this.remoteFields[name].free();
         this.fbo.dispose();
     }
 }
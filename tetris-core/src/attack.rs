@@ -0,0 +1,100 @@
+//! Garbage sent by line clears in versus play.
+
+use crate::field::{LockResult, SpinType};
+use serde::{Deserialize, Serialize};
+
+/// Number of garbage lines sent for each kind of line clear.
+///
+/// Tables are indexed by the number of cleared lines (or the combo count, starting at 0 for the
+/// first clear). Indices past the end of a table use its last entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AttackTable {
+    /// Attack for regular line clears.
+    pub lines: Vec<usize>,
+    /// Attack for full spins.
+    pub spin: Vec<usize>,
+    /// Attack for mini spins.
+    pub spin_mini: Vec<usize>,
+    /// Combo bonus.
+    pub combo: Vec<usize>,
+    /// Bonus for back-to-back clears.
+    pub back_to_back: usize,
+    /// Bonus for perfect clears.
+    pub perfect_clear: usize,
+}
+
+impl Default for AttackTable {
+    fn default() -> Self {
+        Self::guideline()
+    }
+}
+
+impl AttackTable {
+    /// Returns the attack table from the guideline.
+    pub fn guideline() -> AttackTable {
+        AttackTable {
+            lines: vec![0, 0, 1, 2, 4],
+            spin: vec![0, 2, 4, 6],
+            spin_mini: vec![0, 0, 1],
+            combo: vec![0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 4, 5],
+            back_to_back: 1,
+            perfect_clear: 10,
+        }
+    }
+
+    /// Returns the number of garbage lines sent by a locked piece.
+    ///
+    /// The result can be passed to the opponent’s [`ActiveField::queue_garbage`].
+    ///
+    /// [`ActiveField::queue_garbage`]: crate::field::ActiveField::queue_garbage
+    pub fn attack(&self, result: &LockResult) -> usize {
+        if result.lines_cleared == 0 {
+            return 0;
+        }
+
+        let table = match result.spin {
+            SpinType::None => &self.lines,
+            SpinType::Mini => &self.spin_mini,
+            SpinType::Full => &self.spin,
+        };
+        let mut attack = lookup(table, result.lines_cleared);
+        attack += lookup(&self.combo, result.combo.saturating_sub(1));
+        if result.back_to_back {
+            attack += self.back_to_back;
+        }
+        if result.perfect_clear {
+            attack += self.perfect_clear;
+        }
+        attack
+    }
+}
+
+fn lookup(table: &[usize], index: usize) -> usize {
+    table
+        .get(index)
+        .or_else(|| table.last())
+        .copied()
+        .unwrap_or(0)
+}
+
+#[test]
+fn guideline_attack() {
+    let table = AttackTable::guideline();
+
+    assert_eq!(table.attack(&LockResult::test(0, SpinType::Full)), 0);
+    assert_eq!(table.attack(&LockResult::test(1, SpinType::None)), 0);
+    assert_eq!(table.attack(&LockResult::test(4, SpinType::None)), 4);
+    assert_eq!(table.attack(&LockResult::test(2, SpinType::Full)), 4);
+    assert_eq!(table.attack(&LockResult::test(2, SpinType::Mini)), 1);
+    // out of range line counts use the last entry
+    assert_eq!(table.attack(&LockResult::test(6, SpinType::None)), 4);
+
+    let bonus = LockResult {
+        combo: 20,
+        back_to_back: true,
+        perfect_clear: true,
+        ..LockResult::test(4, SpinType::None)
+    };
+    assert_eq!(table.attack(&bonus), 4 + 5 + 1 + 10);
+}
@@ -0,0 +1,296 @@
+//! Versus battles between several players.
+
+use crate::attack::AttackTable;
+use crate::config::GameConfig;
+use crate::field::Duration;
+use crate::garbage::{GarbageGenerator, GarbagePattern};
+use crate::sim::{Input, Simulation, StepResult};
+
+/// Garbage sent from one player to another.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attack {
+    /// Index of the attacking player.
+    pub from: usize,
+    /// Index of the receiving player.
+    pub to: usize,
+    /// Number of garbage lines sent.
+    pub lines: usize,
+}
+
+/// Things that happened during a single battle step.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BattleStepResult {
+    /// Step results of each player, by index. Knocked out players get a default result.
+    pub players: Vec<StepResult>,
+    /// Garbage sent during this step.
+    pub attacks: Vec<Attack>,
+    /// Players that were knocked out during this step.
+    pub knocked_out: Vec<usize>,
+}
+
+/// A participant in a battle.
+#[derive(Debug, Clone)]
+struct Player {
+    sim: Simulation,
+    /// Index of the player that receives this player’s attacks.
+    target: usize,
+    is_knocked_out: bool,
+}
+
+/// A versus battle that routes garbage between the fields of two or more players.
+///
+/// When a piece locks, the attack it produces first cancels the attacker’s own pending garbage
+/// (line clears already cancel one line each) and the rest is sent to the attacker’s target.
+/// Players are knocked out when their field tops out.
+#[derive(Debug, Clone)]
+pub struct Battle {
+    players: Vec<Player>,
+    attack_table: AttackTable,
+    /// Places the holes in garbage lines.
+    garbage: GarbageGenerator,
+}
+
+impl Battle {
+    /// Creates a battle between the given simulations.
+    ///
+    /// Each player initially targets the next player. The seed determines garbage hole columns.
+    pub fn new(sims: Vec<Simulation>, seed: u64) -> Battle {
+        let count = sims.len();
+        Battle {
+            players: sims
+                .into_iter()
+                .enumerate()
+                .map(|(i, sim)| Player {
+                    sim,
+                    target: (i + 1) % count,
+                    is_knocked_out: false,
+                })
+                .collect(),
+            attack_table: AttackTable::default(),
+            garbage: GarbageGenerator::new(GarbagePattern::default(), seed),
+        }
+    }
+
+    /// Creates a battle between the given number of players that are all dealt the same piece
+    /// sequence, so that no player gets luckier pieces than the others.
+    ///
+    /// If the configuration has no seed, a random seed is shared by all players. The seed also
+    /// determines garbage hole columns.
+    pub fn with_config(config: &GameConfig, players: usize) -> Battle {
+        let config = config.with_fixed_seed();
+        let sims = (0..players)
+            .map(|_| Simulation::with_config(&config))
+            .collect();
+        Battle::new(sims, config.seed().unwrap_or_default())
+    }
+
+    /// Returns the attack table.
+    pub fn attack_table(&self) -> &AttackTable {
+        &self.attack_table
+    }
+
+    /// Sets the attack table.
+    pub fn set_attack_table(&mut self, attack_table: AttackTable) {
+        self.attack_table = attack_table;
+    }
+
+    /// Returns how the holes in garbage lines are placed.
+    pub fn garbage_pattern(&self) -> GarbagePattern {
+        self.garbage.pattern()
+    }
+
+    /// Sets how the holes in garbage lines are placed.
+    pub fn set_garbage_pattern(&mut self, pattern: GarbagePattern) {
+        self.garbage.set_pattern(pattern);
+    }
+
+    /// Returns the number of players, including knocked out players.
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    /// Returns the simulation of a player.
+    pub fn player(&self, index: usize) -> &Simulation {
+        &self.players[index].sim
+    }
+
+    /// Returns the simulation of a player mutably.
+    pub fn player_mut(&mut self, index: usize) -> &mut Simulation {
+        &mut self.players[index].sim
+    }
+
+    /// Returns the index of the player that receives a player’s attacks.
+    pub fn target(&self, index: usize) -> usize {
+        self.players[index].target
+    }
+
+    /// Sets the player that receives a player’s attacks.
+    ///
+    /// Targeting oneself or a knocked out player is ignored.
+    pub fn set_target(&mut self, index: usize, target: usize) {
+        if target != index && self.players.get(target).is_some_and(|p| !p.is_knocked_out) {
+            self.players[index].target = target;
+        }
+    }
+
+    /// Returns true if the player has been knocked out.
+    pub fn is_knocked_out(&self, index: usize) -> bool {
+        self.players[index].is_knocked_out
+    }
+
+    /// Returns the indices of all players that have not been knocked out.
+    pub fn alive_players(&self) -> impl Iterator<Item = usize> + '_ {
+        self.players
+            .iter()
+            .enumerate()
+            .filter(|(_, player)| !player.is_knocked_out)
+            .map(|(i, _)| i)
+    }
+
+    /// Returns true if at most one player is left.
+    pub fn is_over(&self) -> bool {
+        self.alive_players().count() <= 1
+    }
+
+    /// Returns the last player standing once the battle is over.
+    pub fn winner(&self) -> Option<usize> {
+        let mut alive = self.alive_players();
+        match (alive.next(), alive.next()) {
+            (Some(winner), None) if self.players.len() > 1 => Some(winner),
+            _ => None,
+        }
+    }
+
+    /// Applies input for a player. See [`Simulation::apply_input`].
+    pub fn apply_input(&mut self, index: usize, input: Input) -> bool {
+        let player = &mut self.players[index];
+        !player.is_knocked_out && player.sim.apply_input(input)
+    }
+
+    /// Advances all players by the given amount of time and routes attacks.
+    pub fn step(&mut self, dt: Duration) -> BattleStepResult {
+        let mut result = BattleStepResult::default();
+
+        for (i, player) in self.players.iter_mut().enumerate() {
+            if player.is_knocked_out {
+                result.players.push(StepResult::default());
+                continue;
+            }
+            let step = player.sim.step(dt);
+            if let Some(locked) = &step.locked {
+                let attack = self.attack_table.attack(locked);
+                let remaining = attack.saturating_sub(locked.cancelled_garbage);
+                let lines = player.sim.field_mut().cancel_garbage(remaining);
+                if lines > 0 && player.target != i {
+                    result.attacks.push(Attack {
+                        from: i,
+                        to: player.target,
+                        lines,
+                    });
+                }
+            }
+            if step.topped_out {
+                player.is_knocked_out = true;
+                result.knocked_out.push(i);
+            }
+            result.players.push(step);
+        }
+
+        for attack in &result.attacks {
+            let target = &mut self.players[attack.to];
+            if target.is_knocked_out {
+                continue;
+            }
+            target
+                .sim
+                .field_mut()
+                .queue_garbage_with(attack.lines, &mut self.garbage);
+        }
+
+        if !result.knocked_out.is_empty() {
+            self.retarget();
+        }
+
+        result
+    }
+
+    /// Points players that target a knocked out player at the next player still alive.
+    fn retarget(&mut self) {
+        let count = self.players.len();
+        for i in 0..count {
+            let target = self.players[i].target;
+            if !self.players[target].is_knocked_out {
+                continue;
+            }
+            let next = (1..count)
+                .map(|offset| (i + offset) % count)
+                .find(|&j| !self.players[j].is_knocked_out);
+            self.players[i].target = next.unwrap_or(i);
+        }
+    }
+}
+
+#[test]
+fn battle_routing() {
+    use crate::field::{ActiveField, PieceType, Tile};
+
+    let sim = |seed| Simulation::new(ActiveField::with_seed(seed), 0.5, 0.5);
+    let mut battle = Battle::new(vec![sim(1), sim(2), sim(3)], 0);
+    assert_eq!(battle.target(0), 1);
+    assert_eq!(battle.target(2), 0);
+
+    // set up a tetris for player 0 and give them some pending garbage to cancel
+    battle.step(0.);
+    {
+        let field = battle.player_mut(0).field_mut();
+        for y in 0..4 {
+            for x in 1..10 {
+                field.field_mut().set_tile(x, y, Tile::Garbage);
+            }
+        }
+        // not a perfect clear
+        field.field_mut().set_tile(9, 4, Tile::Garbage);
+        field.queue_garbage(1, 0);
+        field.spawn_active(Some(PieceType::I), 0.);
+    }
+    battle.apply_input(0, Input::RotateCW);
+    for _ in 0..5 {
+        battle.apply_input(0, Input::MoveLeft);
+    }
+    battle.apply_input(0, Input::HardDrop);
+    let result = battle.step(1. / 60.);
+    // 4 lines of attack, one of which was cancelled by the line clear
+    assert_eq!(
+        result.attacks,
+        vec![Attack {
+            from: 0,
+            to: 1,
+            lines: 3
+        }]
+    );
+
+    // knocking out a player retargets everyone who was attacking them
+    let mut steps = 0;
+    while !battle.is_knocked_out(1) && steps < 1000 {
+        battle.apply_input(1, Input::HardDrop);
+        battle.step(1. / 60.);
+        steps += 1;
+    }
+    assert!(battle.is_knocked_out(1));
+    assert_eq!(battle.target(0), 2);
+    assert!(!battle.is_over());
+    assert_eq!(battle.winner(), None);
+}
+
+#[test]
+fn battle_shared_seed() {
+    let battle = Battle::with_config(&GameConfig::default(), 3);
+    let queue = battle.player(0).field().peek(14);
+    for i in 1..battle.player_count() {
+        assert_eq!(battle.player(i).field().peek(14), queue);
+    }
+    assert_eq!(
+        battle.player(1).field().seed(),
+        battle.player(2).field().seed()
+    );
+}
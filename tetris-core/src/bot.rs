@@ -0,0 +1,252 @@
+//! A simple computer player.
+
+use crate::eval::Weights;
+use crate::field::{ActiveField, ActivePiece, Duration};
+use crate::sim::Input;
+use serde::{Deserialize, Serialize};
+
+/// Bot settings.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BotConfig {
+    /// Weights used to evaluate fields.
+    pub weights: Weights,
+    /// Number of candidate fields kept at each search depth.
+    pub beam_width: usize,
+    /// Number of pieces to look ahead, including the active piece. This is limited by the
+    /// preview depth of the field.
+    pub depth: usize,
+    /// Maximum number of inputs per second.
+    pub inputs_per_second: f64,
+    /// Whether the bot may use the hold piece.
+    pub use_hold: bool,
+}
+
+impl Default for BotConfig {
+    fn default() -> Self {
+        BotConfig {
+            weights: Weights::default(),
+            beam_width: 8,
+            depth: 2,
+            inputs_per_second: 10.,
+            use_hold: true,
+        }
+    }
+}
+
+/// A placement the bot is working towards.
+#[derive(Debug, Clone, Copy)]
+struct Plan {
+    /// Whether to hold before moving the piece.
+    hold: bool,
+    /// The target position of the piece.
+    target: ActivePiece,
+}
+
+/// A candidate in the beam search.
+struct Node {
+    field: ActiveField,
+    score: f64,
+    plan: Plan,
+}
+
+/// A beam search player.
+///
+/// The bot does not modify the field; instead, it returns inputs that should be applied to it.
+#[derive(Debug, Clone)]
+pub struct Bot {
+    config: BotConfig,
+    plan: Option<Plan>,
+    /// Time left until the next input may be emitted.
+    cooldown: Duration,
+}
+
+impl Bot {
+    pub fn new(config: BotConfig) -> Bot {
+        Bot {
+            config,
+            plan: None,
+            cooldown: 0.,
+        }
+    }
+
+    /// Returns the bot settings.
+    pub fn config(&self) -> &BotConfig {
+        &self.config
+    }
+
+    /// Returns the placement the bot would choose for the active piece, and whether it would
+    /// swap with the held piece first. This can be used to show hints to players.
+    pub fn suggest_placement(&self, field: &ActiveField) -> Option<(bool, ActivePiece)> {
+        self.plan(field).map(|plan| (plan.hold, plan.target))
+    }
+
+    /// Finds the best placement for the active piece.
+    fn plan(&self, field: &ActiveField) -> Option<Plan> {
+        let depth = self.config.depth.clamp(1, field.preview_depth() + 1);
+
+        let mut beam = Vec::new();
+        let can_hold = self.config.use_hold
+            && field
+                .active_piece()
+                .is_some_and(|piece| !piece.was_held_piece());
+        for hold in [false, true].iter().copied() {
+            if hold && !can_hold {
+                continue;
+            }
+            let mut root = field.clone();
+            if hold {
+                root.swap_held_piece(0.);
+            }
+            let active_piece = match root.active_piece() {
+                Some(piece) => *piece,
+                None => continue,
+            };
+            beam.push(Node {
+                field: root,
+                score: 0.,
+                plan: Plan {
+                    hold,
+                    target: active_piece,
+                },
+            });
+        }
+
+        let mut best = None;
+        for level in 0..depth {
+            let mut next = Vec::new();
+            for node in &beam {
+                for placement in node.field.enumerate_placements() {
+                    let mut child = node.field.clone();
+                    child.apply_placement(&placement);
+                    child.clear_lines(f64::NEG_INFINITY, 0.);
+                    if child.is_top_out() {
+                        continue;
+                    }
+                    child.spawn_active(None, 0.);
+
+                    let score = node.score
+                        + self
+                            .config
+                            .weights
+                            .evaluate_placement(child.field(), &placement.info);
+                    let plan = if level == 0 {
+                        Plan {
+                            hold: node.plan.hold,
+                            target: placement.piece,
+                        }
+                    } else {
+                        node.plan
+                    };
+                    next.push(Node {
+                        field: child,
+                        score,
+                        plan,
+                    });
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            next.sort_by(|a, b| b.score.total_cmp(&a.score));
+            next.truncate(self.config.beam_width.max(1));
+            best = Some(next[0].plan);
+            beam = next;
+        }
+        best
+    }
+
+    /// Advances the bot by the given amount of time and returns the inputs it wants to apply.
+    ///
+    /// The inputs should be applied to the field in order before calling this again.
+    pub fn update(&mut self, field: &ActiveField, dt: Duration) -> Vec<Input> {
+        self.cooldown -= dt;
+        let mut inputs = Vec::new();
+        let interval = 1. / self.config.inputs_per_second;
+
+        while self.cooldown <= 0. {
+            let input = match self.next_input(field, inputs.len()) {
+                Some(input) => input,
+                None => break,
+            };
+            inputs.push(input);
+            self.cooldown += interval;
+            // anything after these depends on the new active piece
+            if input == Input::Hold || input == Input::HardDrop {
+                break;
+            }
+        }
+        self.cooldown = self.cooldown.max(0.);
+        inputs
+    }
+
+    /// Returns the input at the given index of the path to the current plan.
+    fn next_input(&mut self, field: &ActiveField, index: usize) -> Option<Input> {
+        let active_piece = field.active_piece()?;
+
+        let is_stale = self
+            .plan
+            .is_none_or(|plan| !plan.hold && plan.target.piece_type() != active_piece.piece_type());
+        if is_stale {
+            self.plan = self.plan(field);
+        }
+        let plan = match &mut self.plan {
+            Some(plan) => plan,
+            None => return Some(Input::HardDrop),
+        };
+        if plan.hold {
+            plan.hold = false;
+            return Some(Input::Hold);
+        }
+
+        let path = match field.find_path_to(&plan.target) {
+            Some(path) => path,
+            None => {
+                self.plan = None;
+                return None;
+            }
+        };
+        let input = path.get(index).copied().unwrap_or(Input::HardDrop);
+        if input == Input::HardDrop {
+            self.plan = None;
+        }
+        Some(input)
+    }
+}
+
+#[test]
+fn bot_plays() {
+    use crate::sim::Simulation;
+
+    let mut bot = Bot::new(BotConfig {
+        inputs_per_second: 1000.,
+        ..BotConfig::default()
+    });
+    let mut sim = Simulation::new(ActiveField::with_seed(4), 0.5, 0.);
+    let mut cleared = 0;
+    for _ in 0..60 * 2 {
+        for input in bot.update(sim.field(), 1. / 60.) {
+            sim.apply_input(input);
+        }
+        cleared += sim.step(1. / 60.).cleared_lines;
+    }
+    assert!(!sim.is_game_over());
+    assert!(cleared > 10, "only cleared {} lines", cleared);
+}
+
+#[test]
+fn bot_suggestions() {
+    let mut field = ActiveField::with_seed(2);
+    field.spawn_active(None, 0.);
+    let bot = Bot::new(BotConfig {
+        use_hold: false,
+        ..BotConfig::default()
+    });
+    let (hold, target) = bot.suggest_placement(&field).unwrap();
+    assert!(!hold);
+    assert!(field
+        .enumerate_placements()
+        .iter()
+        .any(|placement| placement.piece.pos() == target.pos()
+            && placement.piece.rotation() == target.rotation()));
+}
@@ -0,0 +1,242 @@
+//! Game settings.
+
+use crate::field::{ActiveField, ClearTimeout, Duration, Field, HoldConfig};
+use crate::piece_set::PieceSet;
+use crate::rotation::RotationSystem;
+use crate::speed::SpeedCurve;
+use serde::{Deserialize, Serialize};
+
+/// Settings for a game, used to set up an [`ActiveField`] or a [`Simulation`](crate::sim::Simulation).
+///
+/// Use [`GameConfig::builder`] to create a configuration that differs from the defaults.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    width: usize,
+    height: usize,
+    top_height: usize,
+    speed_curve: SpeedCurve,
+    lock_delay: Duration,
+    clear_timeout: ClearTimeout,
+    seed: Option<u64>,
+    piece_set: PieceSet,
+    rotation_system: RotationSystem,
+    hold_config: HoldConfig,
+    preview_depth: usize,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        let field = Field::new();
+        GameConfig {
+            width: field.width(),
+            height: field.height(),
+            top_height: field.top_height(),
+            speed_curve: SpeedCurve::default(),
+            lock_delay: Self::LOCK_DELAY,
+            clear_timeout: ClearTimeout::uniform(Self::CLEAR_TIMEOUT),
+            seed: None,
+            piece_set: PieceSet::standard(),
+            rotation_system: RotationSystem::default(),
+            hold_config: HoldConfig::default(),
+            preview_depth: ActiveField::PREVIEW_DEPTH,
+        }
+    }
+}
+
+impl GameConfig {
+    /// The default lock delay in seconds.
+    pub const LOCK_DELAY: Duration = 0.5;
+    /// The default time in seconds after which cleared lines are removed.
+    pub const CLEAR_TIMEOUT: Duration = 0.5;
+
+    /// Returns a builder that starts out with the default settings.
+    pub fn builder() -> GameConfigBuilder {
+        GameConfigBuilder(GameConfig::default())
+    }
+
+    /// Returns the field width in tiles.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Returns the field height in tiles.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the visible field height in tiles.
+    pub fn top_height(&self) -> usize {
+        self.top_height
+    }
+
+    /// Returns the speed curve.
+    pub fn speed_curve(&self) -> &SpeedCurve {
+        &self.speed_curve
+    }
+
+    /// Returns the lock delay.
+    pub fn lock_delay(&self) -> Duration {
+        self.lock_delay
+    }
+
+    /// Returns the time after which cleared lines are removed.
+    pub fn clear_timeout(&self) -> ClearTimeout {
+        self.clear_timeout
+    }
+
+    /// Returns the seed of the piece RNG, or None if it's random.
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    /// Returns this configuration with a fixed seed, choosing a random one if it has none.
+    ///
+    /// All fields created from the result are dealt the same piece sequence.
+    pub fn with_fixed_seed(&self) -> GameConfig {
+        GameConfig {
+            seed: Some(self.seed.unwrap_or_else(rand::random)),
+            ..self.clone()
+        }
+    }
+
+    /// Returns the set of pieces that appear in the queue.
+    pub fn piece_set(&self) -> &PieceSet {
+        &self.piece_set
+    }
+
+    /// Returns the rotation system.
+    pub fn rotation_system(&self) -> &RotationSystem {
+        &self.rotation_system
+    }
+
+    /// Returns the hold configuration.
+    pub fn hold_config(&self) -> &HoldConfig {
+        &self.hold_config
+    }
+
+    /// Returns the preview depth.
+    pub fn preview_depth(&self) -> usize {
+        self.preview_depth
+    }
+
+    /// Checks that the settings can be used to create a field, e.g. after deserializing them.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.width == 0 || self.width > Field::MAX_WIDTH {
+            return Err("unsupported field width");
+        }
+        if self.top_height == 0 || self.top_height > self.height {
+            return Err("visible height must be between 1 and the field height");
+        }
+        self.piece_set.validate()
+    }
+}
+
+/// Builds a [`GameConfig`].
+#[derive(Debug, Clone)]
+pub struct GameConfigBuilder(GameConfig);
+
+impl GameConfigBuilder {
+    /// Sets the field size and the visible height, which is the threshold for topping out.
+    pub fn field_size(mut self, width: usize, height: usize, top_height: usize) -> Self {
+        self.0.width = width;
+        self.0.height = height;
+        self.0.top_height = top_height;
+        self
+    }
+
+    /// Sets the speed curve.
+    pub fn speed_curve(mut self, speed_curve: SpeedCurve) -> Self {
+        self.0.speed_curve = speed_curve;
+        self
+    }
+
+    /// Sets the lock delay.
+    pub fn lock_delay(mut self, lock_delay: Duration) -> Self {
+        self.0.lock_delay = lock_delay;
+        self
+    }
+
+    /// Sets the time after which cleared lines are removed, which may depend on the number of
+    /// lines cleared at once.
+    pub fn clear_timeout(mut self, clear_timeout: impl Into<ClearTimeout>) -> Self {
+        self.0.clear_timeout = clear_timeout.into();
+        self
+    }
+
+    /// Sets the seed of the piece RNG.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.0.seed = Some(seed);
+        self
+    }
+
+    /// Sets the set of pieces that appear in the queue.
+    pub fn piece_set(mut self, piece_set: PieceSet) -> Self {
+        self.0.piece_set = piece_set;
+        self
+    }
+
+    /// Sets the rotation system.
+    pub fn rotation_system(mut self, rotation_system: RotationSystem) -> Self {
+        self.0.rotation_system = rotation_system;
+        self
+    }
+
+    /// Sets how swapping with the held piece behaves.
+    pub fn hold_config(mut self, hold_config: HoldConfig) -> Self {
+        self.0.hold_config = hold_config;
+        self
+    }
+
+    /// Sets the preview depth.
+    pub fn preview_depth(mut self, preview_depth: usize) -> Self {
+        self.0.preview_depth = preview_depth;
+        self
+    }
+
+    /// Validates and returns the configuration.
+    pub fn build(self) -> Result<GameConfig, &'static str> {
+        self.0.validate()?;
+        Ok(self.0)
+    }
+}
+
+#[test]
+fn game_config() {
+    use crate::field::EmptyHold;
+    use crate::sim::Simulation;
+
+    assert!(GameConfig::builder()
+        .field_size(20, 40, 22)
+        .build()
+        .is_err());
+    assert!(GameConfig::builder()
+        .field_size(10, 20, 22)
+        .build()
+        .is_err());
+
+    let config = GameConfig::builder()
+        .field_size(6, 30, 20)
+        .seed(7)
+        .preview_depth(2)
+        .hold_config(HoldConfig {
+            empty_hold: EmptyHold::Redeal,
+            ..HoldConfig::default()
+        })
+        .lock_delay(1.)
+        .build()
+        .unwrap();
+    let field = ActiveField::with_config(&config);
+    assert_eq!(field.field().width(), 6);
+    assert_eq!(field.field().top_height(), 20);
+    assert_eq!(field.seed(), 7);
+    assert_eq!(field.preview_depth(), 2);
+    assert_eq!(field.hold_config(), config.hold_config());
+    assert_eq!(field.peek(5), ActiveField::with_config(&config).peek(5),);
+
+    let sim = Simulation::with_config(&config);
+    assert_eq!(sim.field().field().width(), 6);
+
+    let invalid: GameConfig = serde_json::from_str(r#"{"width": 0}"#).unwrap();
+    assert!(invalid.validate().is_err());
+}
@@ -0,0 +1,103 @@
+//! Co-op doubles, where several players control their own pieces on one shared field.
+
+use crate::field::{ActiveField, Field};
+
+/// A field shared by several players, each with their own active piece.
+///
+/// Every player has an [`ActiveField`] with a separate queue, hold and piece RNG, whose field is
+/// kept in sync with the shared field. The active pieces of the other players are added to it as
+/// obstacles, so pieces collide with each other but don’t count towards line clears.
+#[derive(Debug, Clone)]
+pub struct Doubles {
+    field: Field,
+    players: Vec<ActiveField>,
+}
+
+impl Doubles {
+    /// Creates a doubles game on the given field, replacing the fields of the players.
+    ///
+    /// Each player spawns pieces in their own lane, spread evenly across the field.
+    pub fn new(field: Field, mut players: Vec<ActiveField>) -> Doubles {
+        let count = players.len() as isize;
+        let width = field.width() as isize;
+        for (i, player) in players.iter_mut().enumerate() {
+            let lane_center = (2 * i as isize + 1) * width / (2 * count);
+            player.set_spawn_column_offset(lane_center - width / 2);
+        }
+        let mut doubles = Doubles { field, players };
+        doubles.sync();
+        doubles
+    }
+
+    /// Returns the shared field, without any active pieces.
+    pub fn field(&self) -> &Field {
+        &self.field
+    }
+
+    /// Returns the number of players.
+    pub fn player_count(&self) -> usize {
+        self.players.len()
+    }
+
+    /// Returns the state of a player.
+    pub fn player(&self, player: usize) -> Option<&ActiveField> {
+        self.players.get(player)
+    }
+
+    /// Updates the state of a player, e.g. to move their active piece, and applies the changes
+    /// to the shared field.
+    ///
+    /// Panics if the player doesn’t exist.
+    pub fn update<R>(&mut self, player: usize, f: impl FnOnce(&mut ActiveField) -> R) -> R {
+        let result = f(&mut self.players[player]);
+        let mut field = self.players[player].field().clone();
+        field.clear_obstacles();
+        self.field = field;
+        self.sync();
+        result
+    }
+
+    /// Copies the shared field to every player, with the other players’ pieces as obstacles.
+    fn sync(&mut self) {
+        for i in 0..self.players.len() {
+            let mut field = self.field.clone();
+            for (j, other) in self.players.iter().enumerate() {
+                if let (true, Some(piece)) = (i != j, other.active_piece()) {
+                    field.add_obstacle(piece, piece.pos());
+                }
+            }
+            *self.players[i].field_mut() = field;
+        }
+    }
+}
+
+#[test]
+fn doubles() {
+    use crate::geom::Point2;
+
+    let players = vec![ActiveField::with_seed(1), ActiveField::with_seed(2)];
+    let mut doubles = Doubles::new(Field::new(), players);
+    doubles.update(0, |field| field.spawn_active(None, 0.));
+    doubles.update(1, |field| field.spawn_active(None, 0.));
+    assert!(!doubles.player(0).unwrap().is_top_out());
+    assert!(!doubles.player(1).unwrap().is_top_out());
+
+    // pieces collide with each other
+    while doubles.update(0, |field| field.move_active_right(0.).is_success()) {}
+    let piece = *doubles.player(0).unwrap().active_piece().unwrap();
+    let right = piece.pos() + Point2::new(1, 0);
+    assert!(doubles.player(0).unwrap().field().collide(&piece, right));
+    assert!(!doubles.field().collide(&piece, right));
+
+    // locked pieces end up on the shared field
+    doubles.update(1, |field| field.hard_drop_active(0.));
+    let tiles: u32 = (0..doubles.field().height())
+        .filter_map(|y| doubles.field().row_bits(y))
+        .map(u16::count_ones)
+        .sum();
+    assert_eq!(tiles, 4);
+    assert_eq!(
+        doubles.player(0).unwrap().field().tiles(),
+        doubles.field().tiles()
+    );
+}
@@ -0,0 +1,123 @@
+//! Heuristic field evaluation.
+
+use crate::field::analysis::SurfaceMetrics;
+use crate::field::Field;
+use serde::{Deserialize, Serialize};
+
+/// Information about the placement that produced a field, used by some features.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlacementInfo {
+    /// Number of lines cleared by the placement.
+    pub lines_cleared: usize,
+    /// Height of the center of the placed piece above the floor.
+    pub landing_height: usize,
+    /// Number of the piece’s own tiles that were removed by line clears, times the number of
+    /// cleared lines.
+    pub eroded_cells: usize,
+}
+
+/// Weights of a linear field evaluator.
+///
+/// Higher scores are better, so undesirable features should have negative weights.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Weights {
+    /// Weight of the sum of all column heights.
+    pub aggregate_height: f64,
+    /// Weight of the height of the tallest column.
+    pub max_height: f64,
+    /// Weight of the number of holes.
+    pub holes: f64,
+    /// Weight of the height differences between adjacent columns.
+    pub bumpiness: f64,
+    /// Weight of the cumulative well depth.
+    pub cumulative_wells: f64,
+    /// Weight of the number of row transitions.
+    pub row_transitions: f64,
+    /// Weight of the number of column transitions.
+    pub column_transitions: f64,
+    /// Weight of the number of cleared lines.
+    pub lines: f64,
+    /// Weight of the landing height of the placed piece.
+    pub landing_height: f64,
+    /// Weight of the number of eroded piece cells.
+    pub eroded_cells: f64,
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self::dellacherie()
+    }
+}
+
+impl Weights {
+    /// Returns weights that are all zero.
+    pub fn zero() -> Weights {
+        Weights {
+            aggregate_height: 0.,
+            max_height: 0.,
+            holes: 0.,
+            bumpiness: 0.,
+            cumulative_wells: 0.,
+            row_transitions: 0.,
+            column_transitions: 0.,
+            lines: 0.,
+            landing_height: 0.,
+            eroded_cells: 0.,
+        }
+    }
+
+    /// Returns the weights of Pierre Dellacherie’s hand-tuned evaluator.
+    pub fn dellacherie() -> Weights {
+        Weights {
+            holes: -4.,
+            cumulative_wells: -1.,
+            row_transitions: -1.,
+            column_transitions: -1.,
+            landing_height: -1.,
+            eroded_cells: 1.,
+            ..Weights::zero()
+        }
+    }
+
+    /// Scores a field on its own.
+    pub fn evaluate(&self, field: &Field) -> f64 {
+        self.evaluate_placement(field, &PlacementInfo::default())
+    }
+
+    /// Scores a field that resulted from the given placement.
+    pub fn evaluate_placement(&self, field: &Field, placement: &PlacementInfo) -> f64 {
+        self.evaluate_metrics(&field.surface_metrics(), placement)
+    }
+
+    /// Scores precomputed surface metrics.
+    pub fn evaluate_metrics(&self, metrics: &SurfaceMetrics, placement: &PlacementInfo) -> f64 {
+        self.aggregate_height * metrics.aggregate_height as f64
+            + self.max_height * metrics.max_height as f64
+            + self.holes * metrics.holes as f64
+            + self.bumpiness * metrics.bumpiness as f64
+            + self.cumulative_wells * metrics.cumulative_wells as f64
+            + self.row_transitions * metrics.row_transitions as f64
+            + self.column_transitions * metrics.column_transitions as f64
+            + self.lines * placement.lines_cleared as f64
+            + self.landing_height * placement.landing_height as f64
+            + self.eroded_cells * placement.eroded_cells as f64
+    }
+}
+
+#[test]
+fn evaluator_prefers_clean_fields() {
+    use crate::field::Tile;
+
+    let weights = Weights::default();
+    let mut flat = Field::new();
+    let mut holey = Field::new();
+    for x in 0..flat.width() - 1 {
+        flat.set_tile(x, 0, Tile::Garbage);
+        holey.set_tile(x, 1, Tile::Garbage);
+    }
+    holey.set_tile(0, 0, Tile::Garbage);
+
+    assert!(weights.evaluate(&flat) > weights.evaluate(&holey));
+    assert_eq!(Weights::zero().evaluate(&holey), 0.);
+}
@@ -1,21 +1,45 @@
 //! Tetris playfields.
 
-use crate::geom::{Matrix3, Point2, Vector3};
+use crate::config::GameConfig;
+use crate::geom::{Point2, Rect};
+use crate::piece_set::{PieceSet, PieceShape};
+use crate::rng::Pcg32;
+use crate::rotation::RotationSystem;
 use core::ops::Add;
 use core::str::FromStr;
 use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::collections::VecDeque;
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
+pub mod analysis;
+mod ascii;
+mod cascade;
+mod encoding;
+mod hash;
+mod mirror;
+mod opener;
+mod search;
+
+pub use opener::{OpenerStep, PcSetup};
+pub use search::Placement;
+
+/// A point in time, in seconds, or in ticks if driven by a simulation with a tick rate.
 pub type Timestamp = f64;
+/// A span of time, in the same unit as [`Timestamp`].
 pub type Duration = f64;
 
 /// A shape.
 pub trait Shape {
     /// Iterates over all tiles in this shape.
     fn iter_tiles<'a>(&self) -> Box<dyn Iterator<Item = Point2<isize>> + 'a>;
+
+    /// Returns the smallest rectangle covering all tiles, relative to the shape’s origin, or None
+    /// if it has no tiles.
+    fn bounding_box(&self) -> Option<Rect<isize>> {
+        Rect::around_tiles(self.iter_tiles())
+    }
 }
 
 /// Possible rotations.
@@ -52,9 +76,9 @@ impl From<usize> for Rotation {
     }
 }
 
-impl Into<usize> for Rotation {
-    fn into(self) -> usize {
-        self.cw_steps()
+impl From<Rotation> for usize {
+    fn from(val: Rotation) -> Self {
+        val.cw_steps()
     }
 }
 
@@ -69,6 +93,7 @@ impl Add<isize> for Rotation {
 
 /// Types of tetris pieces.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "PieceTypeData", into = "PieceTypeData")]
 pub enum PieceType {
     I,
     J,
@@ -77,10 +102,13 @@ pub enum PieceType {
     S,
     T,
     Z,
+    /// A custom piece that was registered in a `PieceSet`. IDs must be less than
+    /// `PieceSet::MAX_CUSTOM`; use `PieceType::custom` to check this.
+    Custom(u8),
 }
 
 impl PieceType {
-    /// Returns a vector containing all piece types.
+    /// Returns a vector containing all built-in piece types.
     pub fn all() -> Vec<PieceType> {
         vec![
             PieceType::I,
@@ -93,92 +121,62 @@ impl PieceType {
         ]
     }
 
-    /// Returns the clockwise rotation matrix.
-    pub fn cw_rotation(&self) -> Matrix3<isize> {
+    /// Returns the custom piece type with the given ID, if it’s in range.
+    pub fn custom(id: u8) -> Option<PieceType> {
+        if (id as usize) < PieceSet::MAX_CUSTOM {
+            Some(PieceType::Custom(id))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the index of this piece type in `PieceType::all()`.
+    ///
+    /// Custom pieces are numbered after the built-in piece types.
+    ///
+    /// # Panics
+    /// Panics if this is a custom piece type with an out-of-range ID.
+    pub fn index(&self) -> u8 {
         match self {
-            PieceType::I => ((0, -1, 0).into(), (1, 0, 0).into(), (1, 0, 1).into()).into(),
-            PieceType::O => Matrix3::identity(),
-            PieceType::J | PieceType::L | PieceType::S | PieceType::T | PieceType::Z => {
-                ((0, -1, 0).into(), (1, 0, 0).into(), (0, 0, 1).into()).into()
-            }
+            PieceType::I => 0,
+            PieceType::J => 1,
+            PieceType::L => 2,
+            PieceType::O => 3,
+            PieceType::S => 4,
+            PieceType::T => 5,
+            PieceType::Z => 6,
+            PieceType::Custom(id) => id.checked_add(7).expect("custom piece ID out of range"),
+        }
+    }
+
+    /// Returns the piece type with the given index (see `index`).
+    pub fn from_index(index: u8) -> Option<PieceType> {
+        match index {
+            0..=6 => PieceType::all().get(index as usize).copied(),
+            _ => PieceType::custom(index - 7),
+        }
+    }
+
+    /// Returns true if this is a custom piece type.
+    pub fn is_custom(&self) -> bool {
+        matches!(self, PieceType::Custom(_))
+    }
+
+    /// Returns the tile offsets of this piece type in the given rotation.
+    ///
+    /// Custom piece types have no built-in tiles; their shapes are defined by a `PieceSet`.
+    pub fn rotated_tiles(&self, rotation: Rotation) -> &'static [(isize, isize)] {
+        match ROTATED_TILES.get(self.index() as usize) {
+            Some(rotations) => &rotations[rotation.cw_steps()],
+            None => &[],
         }
     }
 
-    /// Like `iter_tiles`, but rotates the tiles first (using the `cw_rotation` matrix).
+    /// Like `iter_tiles`, but rotates the tiles first.
     pub fn iter_tiles_rotated(&self, rotation: Rotation) -> impl Iterator<Item = Point2<isize>> {
-        struct Iter<'a>(Box<dyn Iterator<Item = Point2<isize>> + 'a>, Matrix3<isize>);
-
-        impl<'a> Iterator for Iter<'a> {
-            type Item = Point2<isize>;
-            fn next(&mut self) -> Option<Point2<isize>> {
-                self.0
-                    .next()
-                    .map(|p| self.1 * Vector3::from(p))
-                    .map(|v| v.into())
-            }
-        }
-
-        let mut matrix = Matrix3::identity();
-        let cw_rotation = self.cw_rotation();
-        for _ in 0..rotation.cw_steps() {
-            matrix *= cw_rotation;
-        }
-
-        Iter(self.iter_tiles(), matrix)
-    }
-
-    /// Returns the wall pop table, or an error if the rotation is invalid.
-    fn wall_pop(
-        &self,
-        from_rot: Rotation,
-        to_rot: Rotation,
-    ) -> Result<&'static [(isize, isize)], ()> {
-        const WALL_POP_TABLE_INDEX: [(Rotation, Rotation, usize); 8] = [
-            (Rotation::None, Rotation::CW, 0),
-            (Rotation::CW, Rotation::None, 1),
-            (Rotation::CW, Rotation::Flip, 2),
-            (Rotation::Flip, Rotation::CW, 3),
-            (Rotation::Flip, Rotation::CCW, 4),
-            (Rotation::CCW, Rotation::Flip, 5),
-            (Rotation::CCW, Rotation::None, 5),
-            (Rotation::None, Rotation::CCW, 7),
-        ];
-        const WALL_POP_I: [&[(isize, isize)]; 8] = [
-            &[(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
-            &[(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
-            &[(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
-            &[(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
-            &[(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
-            &[(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
-            &[(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
-            &[(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
-        ];
-        const WALL_POP_JLSTZ: [&[(isize, isize)]; 8] = [
-            &[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
-            &[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
-            &[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
-            &[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
-            &[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
-            &[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
-            &[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
-            &[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
-        ];
-        const WALL_POP_O: &[(isize, isize)] = &[(0, 0)];
-
-        let table_index = WALL_POP_TABLE_INDEX
+        self.rotated_tiles(rotation)
             .iter()
-            .find(|(f, t, _)| *f == from_rot && *t == to_rot);
-        if let Some((_, _, index)) = table_index {
-            match self {
-                PieceType::I => Ok(WALL_POP_I[*index]),
-                PieceType::O => Ok(WALL_POP_O),
-                PieceType::J | PieceType::L | PieceType::S | PieceType::T | PieceType::Z => {
-                    Ok(WALL_POP_JLSTZ[*index])
-                }
-            }
-        } else {
-            Err(())
-        }
+            .map(|tile| (*tile).into())
     }
 
     pub fn stringify(&self, s: &mut String) {
@@ -190,67 +188,108 @@ impl PieceType {
             PieceType::S => s.push('S'),
             PieceType::T => s.push('T'),
             PieceType::Z => s.push('Z'),
+            PieceType::Custom(id) => s.push_str(&format!("C{}$", id)),
         }
     }
 }
 
-impl Shape for PieceType {
-    fn iter_tiles<'a>(&self) -> Box<dyn Iterator<Item = Point2<isize>> + 'a> {
-        struct Iter(PieceType, u8);
-        impl Iterator for Iter {
-            type Item = Point2<isize>;
-            fn next(&mut self) -> Option<Point2<isize>> {
-                use PieceType::*;
-
-                let i = self.1;
-                self.1 += 1;
-                if i > 3 {
-                    return None;
-                }
-                Some(
-                    match (self.0, i) {
-                        (I, 0) => (-1, 0),
-                        (I, 1) => (0, 0),
-                        (I, 2) => (1, 0),
-                        (I, 3) => (2, 0),
-
-                        (J, 0) => (-1, 1),
-                        (J, 1) => (-1, 0),
-                        (J, 2) => (0, 0),
-                        (J, 3) => (1, 0),
-
-                        (L, 0) => (1, 1),
-                        (L, 1) => (-1, 0),
-                        (L, 2) => (0, 0),
-                        (L, 3) => (1, 0),
-
-                        (O, 0) => (0, 0),
-                        (O, 1) => (1, 0),
-                        (O, 2) => (0, 1),
-                        (O, 3) => (1, 1),
-
-                        (S, 0) => (0, 1),
-                        (S, 1) => (1, 1),
-                        (S, 2) => (-1, 0),
-                        (S, 3) => (0, 0),
-
-                        (T, 0) => (0, 1),
-                        (T, 1) => (-1, 0),
-                        (T, 2) => (0, 0),
-                        (T, 3) => (1, 0),
-
-                        (Z, 0) => (-1, 1),
-                        (Z, 1) => (0, 1),
-                        (Z, 2) => (0, 0),
-                        (Z, 3) => (1, 0),
-
-                        _ => panic!(),
+/// Serialized form of a piece type, which is checked for out-of-range custom IDs.
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "PieceType")]
+enum PieceTypeData {
+    I,
+    J,
+    L,
+    O,
+    S,
+    T,
+    Z,
+    Custom(u8),
+}
+
+impl TryFrom<PieceTypeData> for PieceType {
+    type Error = &'static str;
+
+    fn try_from(data: PieceTypeData) -> Result<PieceType, Self::Error> {
+        match data {
+            PieceTypeData::I => Ok(PieceType::I),
+            PieceTypeData::J => Ok(PieceType::J),
+            PieceTypeData::L => Ok(PieceType::L),
+            PieceTypeData::O => Ok(PieceType::O),
+            PieceTypeData::S => Ok(PieceType::S),
+            PieceTypeData::T => Ok(PieceType::T),
+            PieceTypeData::Z => Ok(PieceType::Z),
+            PieceTypeData::Custom(id) => {
+                PieceType::custom(id).ok_or("custom piece ID out of range")
+            }
+        }
+    }
+}
+
+impl From<PieceType> for PieceTypeData {
+    fn from(piece_type: PieceType) -> PieceTypeData {
+        match piece_type {
+            PieceType::I => PieceTypeData::I,
+            PieceType::J => PieceTypeData::J,
+            PieceType::L => PieceTypeData::L,
+            PieceType::O => PieceTypeData::O,
+            PieceType::S => PieceTypeData::S,
+            PieceType::T => PieceTypeData::T,
+            PieceType::Z => PieceTypeData::Z,
+            PieceType::Custom(id) => PieceTypeData::Custom(id),
+        }
+    }
+}
+
+/// Tile offsets of each piece type in its spawn orientation, in `PieceType::all()` order.
+const PIECE_TILES: [[(isize, isize); 4]; 7] = [
+    [(-1, 0), (0, 0), (1, 0), (2, 0)],  // I
+    [(-1, 1), (-1, 0), (0, 0), (1, 0)], // J
+    [(1, 1), (-1, 0), (0, 0), (1, 0)],  // L
+    [(0, 0), (1, 0), (0, 1), (1, 1)],   // O
+    [(0, 1), (1, 1), (-1, 0), (0, 0)],  // S
+    [(0, 1), (-1, 0), (0, 0), (1, 0)],  // T
+    [(-1, 1), (0, 1), (0, 0), (1, 0)],  // Z
+];
+
+/// Tile offsets of each piece type in each rotation, indexed by piece index and clockwise steps.
+const ROTATED_TILES: [[[(isize, isize); 4]; 4]; 7] = rotate_piece_tiles();
+
+/// Generates the rotation lookup table from `PIECE_TILES`.
+///
+/// Pieces rotate clockwise about their origin tile, except for I pieces (which rotate about the
+/// corner between their two center tiles) and O pieces (which don’t rotate).
+const fn rotate_piece_tiles() -> [[[(isize, isize); 4]; 4]; 7] {
+    let mut table = [[[(0, 0); 4]; 4]; 7];
+    let mut piece = 0;
+    while piece < 7 {
+        let mut rotation = 0;
+        while rotation < 4 {
+            let mut tile = 0;
+            while tile < 4 {
+                let (mut x, mut y) = PIECE_TILES[piece][tile];
+                let mut step = 0;
+                while step < rotation {
+                    match piece {
+                        0 => (x, y) = (y + 1, -x),
+                        3 => (),
+                        _ => (x, y) = (y, -x),
                     }
-                    .into(),
-                )
+                    step += 1;
+                }
+                table[piece][rotation][tile] = (x, y);
+                tile += 1;
             }
+            rotation += 1;
         }
-        Box::new(Iter(*self, 0))
+        piece += 1;
+    }
+    table
+}
+
+impl Shape for PieceType {
+    fn iter_tiles<'a>(&self) -> Box<dyn Iterator<Item = Point2<isize>> + 'a> {
+        Box::new(self.iter_tiles_rotated(Rotation::None))
     }
 }
 
@@ -265,27 +304,171 @@ impl FromStr for PieceType {
             "S" => Ok(Self::S),
             "T" => Ok(Self::T),
             "Z" => Ok(Self::Z),
-            _ => Err(()),
+            _ => s
+                .strip_prefix('C')
+                .map(|id| id.strip_suffix('$').unwrap_or(id))
+                .and_then(|id| id.parse().ok())
+                .and_then(Self::custom)
+                .ok_or(()),
+        }
+    }
+}
+
+/// Which of a tile’s four neighbors belong to the same locked piece, as a bit set.
+///
+/// Renderers can use this to draw connected piece sprites.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct TileLinks(u8);
+
+impl TileLinks {
+    pub const LEFT: TileLinks = TileLinks(1);
+    pub const RIGHT: TileLinks = TileLinks(2);
+    pub const UP: TileLinks = TileLinks(4);
+    pub const DOWN: TileLinks = TileLinks(8);
+    const ALL: [(TileLinks, isize, isize); 4] = [
+        (Self::LEFT, -1, 0),
+        (Self::RIGHT, 1, 0),
+        (Self::UP, 0, 1),
+        (Self::DOWN, 0, -1),
+    ];
+
+    /// Returns the raw bits.
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Returns true if all links in `other` are set.
+    pub fn contains(self, other: TileLinks) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Returns true if no links are set.
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the link in the opposite direction.
+    fn opposite(self) -> TileLinks {
+        match self {
+            Self::LEFT => Self::RIGHT,
+            Self::RIGHT => Self::LEFT,
+            Self::UP => Self::DOWN,
+            _ => Self::UP,
+        }
+    }
+}
+
+impl core::ops::BitOr for TileLinks {
+    type Output = TileLinks;
+
+    fn bitor(self, other: TileLinks) -> TileLinks {
+        TileLinks(self.0 | other.0)
+    }
+}
+
+/// An item carried by a tile.
+///
+/// Items are triggered when the row containing them is cleared, which emits a
+/// [`FieldEvent::ItemTriggered`]. The field applies the effects of built-in items that only affect
+/// itself; all other effects are up to the embedding game.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "ItemData", into = "ItemData")]
+pub enum Item {
+    /// Clears the lowest row of the field.
+    ClearBottomRow,
+    /// Scrambles an opponent’s field. Applied by the embedding game.
+    Scramble,
+    /// An item defined by the embedding game. IDs must be less than `Item::MAX_CUSTOM`; use
+    /// `Item::custom` to check this.
+    Custom(u8),
+}
+
+impl Item {
+    /// The maximum number of custom item types.
+    pub const MAX_CUSTOM: usize = 254;
+
+    /// Returns the custom item with the given ID, if it’s in range.
+    pub fn custom(id: u8) -> Option<Item> {
+        if (id as usize) < Self::MAX_CUSTOM {
+            Some(Item::Custom(id))
+        } else {
+            None
+        }
+    }
+
+    /// Returns a unique index for this item.
+    ///
+    /// # Panics
+    /// Panics if this is a custom item with an out-of-range ID.
+    pub fn index(&self) -> u8 {
+        match self {
+            Item::ClearBottomRow => 0,
+            Item::Scramble => 1,
+            Item::Custom(id) => id.checked_add(2).expect("custom item ID out of range"),
+        }
+    }
+
+    /// Returns the item with the given index (see `index`).
+    pub fn from_index(index: u8) -> Option<Item> {
+        match index {
+            0 => Some(Item::ClearBottomRow),
+            1 => Some(Item::Scramble),
+            index => Item::custom(index - 2),
+        }
+    }
+}
+
+/// Serialized form of an item, which is checked for out-of-range custom IDs.
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "Item")]
+enum ItemData {
+    ClearBottomRow,
+    Scramble,
+    Custom(u8),
+}
+
+impl TryFrom<ItemData> for Item {
+    type Error = &'static str;
+
+    fn try_from(data: ItemData) -> Result<Item, Self::Error> {
+        match data {
+            ItemData::ClearBottomRow => Ok(Item::ClearBottomRow),
+            ItemData::Scramble => Ok(Item::Scramble),
+            ItemData::Custom(id) => Item::custom(id).ok_or("custom item ID out of range"),
+        }
+    }
+}
+
+impl From<Item> for ItemData {
+    fn from(item: Item) -> ItemData {
+        match item {
+            Item::ClearBottomRow => ItemData::ClearBottomRow,
+            Item::Scramble => ItemData::Scramble,
+            Item::Custom(id) => ItemData::Custom(id),
         }
     }
 }
 
 /// Types of tiles in a playfield.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Tile {
     /// an empty tile.
     Empty,
     /// A regular non-empty tile.
     Piece(PieceType),
+    /// A garbage tile that was sent by an opponent.
+    Garbage,
     /// A tile that is part of a cleared row and is marked for removal. Contains time of creation.
     Clear(Timestamp),
+    /// A tile carrying an item, which is triggered when its row is cleared.
+    Item(Item),
 }
 
 impl Tile {
     /// Returns true if a line made from this this tile is not yet clear but can be marked clear.
     pub fn is_clearable(&self) -> bool {
         match self {
-            Tile::Piece(_) => true,
+            Tile::Piece(_) | Tile::Garbage | Tile::Item(_) => true,
             Tile::Empty | Tile::Clear(_) => false,
         }
     }
@@ -294,17 +477,32 @@ impl Tile {
         match self {
             Tile::Empty => s.push(' '),
             Tile::Piece(ty) => ty.stringify(s),
+            Tile::Garbage => s.push('G'),
             Tile::Clear(inst) => s.push_str(&format!("X{}$", inst)),
+            Tile::Item(item) => s.push_str(&format!("*{}$", item.index())),
         }
     }
 
-    pub fn parse_from_str(s: &str) -> Result<(Self, usize), ()> {
+    /// Parses a single stringified tile from the beginning of the string.
+    ///
+    /// Returns the tile and the number of bytes it occupied.
+    pub fn parse_from_str(s: &str) -> Option<(Self, usize)> {
         let mut chars = s.chars();
-        let first = chars.next().ok_or(())?;
+        let first = chars.next()?;
         if let Ok(piece) = first.to_string().parse() {
-            Ok((Tile::Piece(piece), 1))
+            Some((Tile::Piece(piece), 1))
         } else if first == ' ' {
-            Ok((Tile::Empty, 1))
+            Some((Tile::Empty, 1))
+        } else if first == 'G' {
+            Some((Tile::Garbage, 1))
+        } else if first == 'C' {
+            let len = s.find('$')? + 1;
+            let piece = s[..len].parse().ok()?;
+            Some((Tile::Piece(piece), len))
+        } else if first == '*' {
+            let len = s.find('$')? + 1;
+            let item = Item::from_index(s[1..len - 1].parse().ok()?)?;
+            Some((Tile::Item(item), len))
         } else if first == 'X' {
             let mut num = String::new();
             let mut len = 1;
@@ -316,14 +514,81 @@ impl Tile {
                     num.push(c);
                 }
             }
-            let inst = num.parse().map_err(|_| ())?;
-            Ok((Tile::Clear(inst), len))
+            let inst = num.parse().ok()?;
+            Some((Tile::Clear(inst), len))
         } else {
-            Err(())
+            None
+        }
+    }
+
+    /// Parses a list of stringified tiles, as produced by calling [`Tile::stringify`] on each
+    /// tile in turn.
+    pub fn parse_list(s: &str) -> Option<Vec<Tile>> {
+        let mut tiles = Vec::new();
+        let mut cursor = 0;
+        while cursor < s.len() {
+            let (tile, len) = Tile::parse_from_str(&s[cursor..])?;
+            tiles.push(tile);
+            cursor += len;
         }
+        Some(tiles)
+    }
+}
+
+/// Kinds of actions that can be performed on an active piece.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PieceAction {
+    /// The piece has just spawned and has not been moved yet.
+    #[default]
+    Spawn,
+    /// The piece was moved by an offset.
+    Move,
+    /// The piece was rotated.
+    Rotate,
+}
+
+/// Spin qualifiers for locked pieces.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SpinType {
+    /// Not a spin.
+    #[default]
+    None,
+    /// A T-spin mini (only one of the front corners is occupied).
+    Mini,
+    /// A full T-spin.
+    Full,
+}
+
+/// The outcome of an attempt to move or rotate a piece.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MoveResult {
+    /// The piece was moved.
+    Moved,
+    /// The piece was rotated using the kick with the given index. Index 0 is the first offset of
+    /// the rotation system, which usually means no offset.
+    KickedWith(usize),
+    /// Nothing happened because the piece would collide.
+    Blocked,
+    /// Nothing happened because there is no active piece.
+    NoActivePiece,
+}
+
+impl MoveResult {
+    /// Returns true if the piece was moved or rotated.
+    pub fn is_success(&self) -> bool {
+        matches!(self, MoveResult::Moved | MoveResult::KickedWith(_))
     }
 }
 
+/// The outcome of spawning a piece.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpawnResult {
+    /// The piece spawned normally.
+    Spawned,
+    /// The piece spawned in an obstructed position, which tops out the field.
+    Blocked,
+}
+
 /// An active piece.
 #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct ActivePiece {
@@ -332,6 +597,34 @@ pub struct ActivePiece {
     rotation: Rotation,
     was_held_piece: bool,
     last_move_time: Timestamp,
+    /// The last action that successfully changed the piece’s position or rotation.
+    #[serde(default)]
+    last_action: PieceAction,
+    /// Number of times the lock delay was reset by moving while on the ground.
+    #[serde(default)]
+    lock_resets: usize,
+    /// The lowest row this piece has reached, which is used to refresh lock resets.
+    #[serde(default = "ActivePiece::default_lowest_y")]
+    lowest_y: isize,
+    /// The shape of a custom piece; built-in pieces use their built-in shapes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    custom_shape: Option<PieceShape>,
+    /// If true, each mino of the piece occupies 2×2 tiles and the piece moves two tiles at a
+    /// time.
+    #[serde(default)]
+    big: bool,
+    /// Index of the kick used by the last rotation, if the last action was a rotation.
+    #[serde(default)]
+    kick_index: Option<usize>,
+    /// Whether the piece could not move left, right or up after the last rotation.
+    #[serde(default)]
+    is_immobile: bool,
+    /// Number of tiles this piece was moved down by soft dropping.
+    #[serde(default)]
+    soft_drop_cells: usize,
+    /// Number of tiles this piece was moved down by hard dropping.
+    #[serde(default)]
+    hard_drop_cells: usize,
 }
 
 impl ActivePiece {
@@ -342,9 +635,32 @@ impl ActivePiece {
             rotation: Rotation::None,
             was_held_piece: false,
             last_move_time: time,
+            last_action: PieceAction::Spawn,
+            lock_resets: 0,
+            lowest_y: Self::default_lowest_y(),
+            custom_shape: None,
+            big: false,
+            kick_index: None,
+            is_immobile: false,
+            soft_drop_cells: 0,
+            hard_drop_cells: 0,
         }
     }
 
+    /// Creates an active piece of a custom piece type with the given shape.
+    pub fn with_shape(piece_type: PieceType, shape: PieceShape, time: Timestamp) -> ActivePiece {
+        let mut piece = ActivePiece::new(piece_type, time);
+        piece.custom_shape = Some(shape);
+        piece
+    }
+
+    /// Index of the last kick in SRS kick tables.
+    const LAST_SRS_KICK: usize = 4;
+
+    fn default_lowest_y() -> isize {
+        isize::MAX
+    }
+
     /// Returns the position.
     pub fn pos(&self) -> Point2<isize> {
         self.pos
@@ -360,57 +676,288 @@ impl ActivePiece {
         self.rotation
     }
 
-    /// Attempts to move this piece by a specific offset.
+    /// Returns true if this is a big piece.
+    pub fn is_big(&self) -> bool {
+        self.big
+    }
+
+    /// Returns the number of tiles per mino along each axis.
+    fn scale(&self) -> isize {
+        if self.big {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// Returns true if this piece came out of hold, in which case it can't be held again.
+    pub fn was_held_piece(&self) -> bool {
+        self.was_held_piece
+    }
+
+    /// Returns the number of times the lock delay was reset by moving the piece on the ground.
+    ///
+    /// This is reset whenever the piece falls below the lowest row it has reached so far.
+    pub fn lock_resets(&self) -> usize {
+        self.lock_resets
+    }
+
+    /// Returns the time the piece last moved or rotated, from which the lock delay is measured.
+    pub fn last_move_time(&self) -> Timestamp {
+        self.last_move_time
+    }
+
+    /// Returns the last action that successfully changed this piece.
+    pub fn last_action(&self) -> PieceAction {
+        self.last_action
+    }
+
+    /// Returns the index of the kick that the last rotation used, if the last action was a
+    /// rotation. Index 0 is the unkicked position.
+    pub fn kick_index(&self) -> Option<usize> {
+        self.kick_index
+    }
+
+    /// Returns true if the last action was a rotation into a position where the piece can't move
+    /// left, right or up.
+    ///
+    /// This is used to detect spins of pieces other than T.
+    pub fn is_immobile(&self) -> bool {
+        self.is_immobile
+    }
+
+    /// Attempts to move this piece by a specific offset, in minos (which are larger than tiles
+    /// for big pieces).
     ///
     /// Will only check for collisions at the end position, assuming that the piece will only ever
     /// moved one tile at a time.
-    pub fn try_move(&mut self, field: &Field, dx: isize, dy: isize, time: Timestamp) {
+    ///
+    /// Returns `Moved` or `Blocked`.
+    pub fn try_move(&mut self, field: &Field, dx: isize, dy: isize, time: Timestamp) -> MoveResult {
+        let dx = dx * self.scale();
+        let dy = dy * self.scale();
         if !field.collide(self, (self.pos.x + dx, self.pos.y + dy).into()) {
+            let was_on_ground = self.is_on_ground(field);
             self.pos.x += dx;
             self.pos.y += dy;
-            self.last_move_time = time;
+            self.last_action = PieceAction::Move;
+            self.kick_index = None;
+            self.is_immobile = false;
+            self.reset_lock_delay(was_on_ground, time);
+            MoveResult::Moved
+        } else {
+            MoveResult::Blocked
+        }
+    }
+
+    /// Resets the lock delay after the piece was moved or rotated.
+    fn reset_lock_delay(&mut self, was_on_ground: bool, time: Timestamp) {
+        self.last_move_time = time;
+        if self.pos.y < self.lowest_y {
+            self.lowest_y = self.pos.y;
+            self.lock_resets = 0;
+        } else if was_on_ground {
+            self.lock_resets += 1;
         }
     }
 
     /// Returns true if this piece is on the ground.
     pub fn is_on_ground(&self, field: &Field) -> bool {
-        field.collide(self, (self.pos.x, self.pos.y - 1).into())
+        field.collide(self, (self.pos.x, self.pos.y - self.scale()).into())
     }
 
-    /// Attempts to rotate this piece, employing wall popping.
-    pub fn try_rotate(&mut self, field: &Field, rotation: isize, time: Timestamp) {
-        struct Rotated(PieceType, Rotation);
-        impl Shape for Rotated {
-            fn iter_tiles<'a>(&self) -> Box<dyn Iterator<Item = Point2<isize>> + 'a> {
-                Box::new(self.0.iter_tiles_rotated(self.1))
+    /// Attempts to rotate this piece, employing wall popping using the given rotation system.
+    ///
+    /// Returns `KickedWith` the index of the kick that was used, or `Blocked`.
+    pub fn try_rotate(
+        &mut self,
+        field: &Field,
+        rotation_system: &RotationSystem,
+        rotation: isize,
+        time: Timestamp,
+    ) -> MoveResult {
+        let new_rotation = self.rotation + rotation;
+        let mut rotated = *self;
+        rotated.rotation = new_rotation;
+
+        let deltas = rotation_system.kicks(self.piece_type, self.rotation, new_rotation);
+        for (kick_index, delta) in deltas.iter().enumerate() {
+            let pos = self.pos + Point2::new(delta.0 * self.scale(), delta.1 * self.scale());
+            if !field.collide(&rotated, pos) {
+                // found valid position
+                let was_on_ground = self.is_on_ground(field);
+                self.rotation = new_rotation;
+                self.pos = pos;
+                self.last_action = PieceAction::Rotate;
+                self.kick_index = Some(kick_index);
+                let scale = self.scale();
+                self.is_immobile = [(-scale, 0), (scale, 0), (0, scale)]
+                    .iter()
+                    .all(|(dx, dy)| field.collide(self, (pos.x + dx, pos.y + dy).into()));
+                self.reset_lock_delay(was_on_ground, time);
+                return MoveResult::KickedWith(kick_index);
             }
         }
-        let new_rotation = self.rotation + rotation;
+        MoveResult::Blocked
+    }
 
-        let deltas = self.piece_type.wall_pop(self.rotation, new_rotation);
-        if let Ok(deltas) = deltas {
-            for delta in deltas {
-                let pos = self.pos + (*delta).into();
-                if !field.collide(&Rotated(self.piece_type, new_rotation), pos) {
-                    // found valid position
-                    self.rotation = new_rotation;
-                    self.pos = pos;
-                    self.last_move_time = time;
-                    break;
-                }
+    /// Determines the spin type of this piece at its current position, using the 3-corner rule.
+    ///
+    /// Only T pieces whose last action was a rotation can spin. If at least three of the four
+    /// corners diagonal to the center are occupied (or out of bounds), this is a T-spin; it is a
+    /// full T-spin if both corners on the pointing side are occupied, and a mini otherwise. Minis
+    /// that used the last SRS kick (as in T-spin triples) are upgraded to full T-spins if the piece
+    /// was rotated using the given rotation system.
+    pub fn spin_type(&self, field: &Field, rotation_system: &RotationSystem) -> SpinType {
+        if self.piece_type != PieceType::T || self.last_action != PieceAction::Rotate {
+            return SpinType::None;
+        }
+
+        let is_occupied = |dx: isize, dy: isize| {
+            let x = (self.pos.x + dx * self.scale()).try_into();
+            let y = (self.pos.y + dy * self.scale()).try_into();
+            match (x, y) {
+                (Ok(x), Ok(y)) => field.get_tile(x, y) != Some(Tile::Empty),
+                _ => true,
             }
+        };
+
+        let corners = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+        let occupied = corners
+            .iter()
+            .filter(|(dx, dy)| is_occupied(*dx, *dy))
+            .count();
+        if occupied < 3 {
+            return SpinType::None;
+        }
+
+        // the first tile of a T piece is the one it’s pointing towards
+        let front = self
+            .piece_type
+            .iter_tiles_rotated(self.rotation)
+            .next()
+            .expect("T piece has no tiles");
+        let front_a = is_occupied(front.x + front.y, front.y + front.x);
+        let front_b = is_occupied(front.x - front.y, front.y - front.x);
+        let last_srs_kick =
+            *rotation_system == RotationSystem::Srs && self.kick_index == Some(Self::LAST_SRS_KICK);
+        if (front_a && front_b) || last_srs_kick {
+            SpinType::Full
+        } else {
+            SpinType::Mini
         }
     }
 }
 
+/// Tile offsets covered by a single mino of a big piece.
+const BIG_MINO_TILES: [(isize, isize); 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
 impl Shape for ActivePiece {
     fn iter_tiles<'a>(&self) -> Box<dyn Iterator<Item = Point2<isize>> + 'a> {
-        Box::new(self.piece_type.iter_tiles_rotated(self.rotation))
+        let minos: Box<dyn Iterator<Item = Point2<isize>>> = match self.custom_shape {
+            Some(shape) => Box::new(shape.iter_tiles(self.rotation)),
+            None => Box::new(self.piece_type.iter_tiles_rotated(self.rotation)),
+        };
+        if self.big {
+            Box::new(minos.flat_map(|mino| {
+                BIG_MINO_TILES
+                    .iter()
+                    .map(move |(x, y)| Point2::new(mino.x * 2 + x, mino.y * 2 + y))
+            }))
+        } else {
+            minos
+        }
+    }
+}
+
+/// How tiles above cleared lines behave when the lines are removed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClearMode {
+    /// Everything above a removed line moves down by one row.
+    #[default]
+    Naive,
+    /// After rows are removed, connected groups of tiles fall until they rest, which may
+    /// complete more lines.
+    Cascade,
+}
+
+/// Time after which cleared lines are removed, depending on how many lines were cleared at once.
+///
+/// Converts from a single duration for all line clears.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ClearTimeoutData", into = "ClearTimeoutData")]
+pub struct ClearTimeout {
+    /// Timeouts for singles, doubles, triples and tetrises (or more).
+    by_lines: [Duration; 4],
+}
+
+/// Serialized form of a clear timeout, which is a single number if it doesn't vary.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum ClearTimeoutData {
+    Uniform(Duration),
+    ByLines([Duration; 4]),
+}
+
+impl From<ClearTimeoutData> for ClearTimeout {
+    fn from(data: ClearTimeoutData) -> Self {
+        match data {
+            ClearTimeoutData::Uniform(timeout) => ClearTimeout::uniform(timeout),
+            ClearTimeoutData::ByLines(by_lines) => ClearTimeout { by_lines },
+        }
+    }
+}
+
+impl From<ClearTimeout> for ClearTimeoutData {
+    fn from(timeout: ClearTimeout) -> Self {
+        let [first, ..] = timeout.by_lines;
+        if timeout.by_lines.iter().all(|t| *t == first) {
+            ClearTimeoutData::Uniform(first)
+        } else {
+            ClearTimeoutData::ByLines(timeout.by_lines)
+        }
+    }
+}
+
+impl From<Duration> for ClearTimeout {
+    fn from(timeout: Duration) -> Self {
+        ClearTimeout::uniform(timeout)
+    }
+}
+
+impl ClearTimeout {
+    /// Uses the same timeout for all line clears.
+    pub fn uniform(timeout: Duration) -> ClearTimeout {
+        ClearTimeout {
+            by_lines: [timeout; 4],
+        }
+    }
+
+    /// Uses different timeouts for singles, doubles, triples and tetrises. Clears of more than
+    /// four lines use the tetris timeout.
+    pub fn by_lines(
+        single: Duration,
+        double: Duration,
+        triple: Duration,
+        tetris: Duration,
+    ) -> Self {
+        ClearTimeout {
+            by_lines: [single, double, triple, tetris],
+        }
+    }
+
+    /// Returns the timeout for the given number of lines cleared at once.
+    pub fn for_lines(&self, lines: usize) -> Duration {
+        self.by_lines[lines.clamp(1, 4) - 1]
     }
 }
 
 /// A Tetris playfield.
-#[derive(Debug, Clone)]
+///
+/// Alongside the tiles, the field keeps an occupancy bitboard with one `u16` per row (bit `x` is
+/// set if the tile at `x` is not empty), so fields can be at most 16 tiles wide.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "FieldData", into = "FieldData")]
 pub struct Field {
     /// Field width in tiles.
     width: usize,
@@ -420,8 +967,79 @@ pub struct Field {
     top_height: usize,
     /// Number of rows that have been cleared but have not been removed from the data.
     clear_rows: usize,
+    /// Number of full rows collected at the bottom during a zone.
+    zone_rows: usize,
     /// Field tiles.
     tiles: Vec<Tile>,
+    /// Links between tiles of the same locked piece, one entry per tile.
+    links: Vec<TileLinks>,
+    /// Occupancy bitboard, one entry per row of tiles.
+    rows: Vec<u16>,
+    /// How removing cleared lines affects the tiles above.
+    clear_mode: ClearMode,
+    /// Items in rows that were cleared and have not been triggered yet, with their positions.
+    cleared_items: Vec<(Item, usize, usize)>,
+    /// Occupancy of tiles that pieces collide with but that aren’t part of the field, such as the
+    /// active pieces of other players. Rows above the last entry have no obstacles.
+    obstacles: Vec<u16>,
+}
+
+/// Serialized form of a field, which omits the bitboard.
+#[derive(Serialize, Deserialize)]
+struct FieldData {
+    width: usize,
+    height: usize,
+    top_height: usize,
+    clear_rows: usize,
+    tiles: Vec<Tile>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    links: Vec<TileLinks>,
+    #[serde(default)]
+    zone_rows: usize,
+    #[serde(default)]
+    clear_mode: ClearMode,
+}
+
+impl TryFrom<FieldData> for Field {
+    type Error = &'static str;
+
+    fn try_from(data: FieldData) -> Result<Field, Self::Error> {
+        let mut field = Field::from_tiles(
+            data.width,
+            data.height,
+            data.top_height,
+            data.clear_rows,
+            data.tiles,
+        )
+        .ok_or("invalid field dimensions")?;
+        if data.links.len() == field.tiles.len() {
+            field.links = data.links;
+        }
+        field.zone_rows = data.zone_rows.min(field.rows.len());
+        field.clear_mode = data.clear_mode;
+        Ok(field)
+    }
+}
+
+impl From<Field> for FieldData {
+    fn from(field: Field) -> FieldData {
+        FieldData {
+            width: field.width,
+            height: field.height,
+            top_height: field.top_height,
+            clear_rows: field.clear_rows,
+            tiles: field.tiles,
+            links: field.links,
+            zone_rows: field.zone_rows,
+            clear_mode: field.clear_mode,
+        }
+    }
+}
+
+impl Default for Field {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Field {
@@ -429,19 +1047,87 @@ impl Field {
     const HEIGHT: usize = 40;
     const TOP_HEIGHT: usize = 22;
 
-    pub fn new() -> Field {
-        let mut tiles = Vec::with_capacity(Self::WIDTH * Self::HEIGHT);
-        for _ in 0..Self::WIDTH * Self::HEIGHT {
-            tiles.push(Tile::Empty);
-        }
+    /// The maximum field width supported by the bitboard.
+    pub const MAX_WIDTH: usize = 16;
 
+    pub fn new() -> Field {
         Field {
             width: Self::WIDTH,
             height: Self::HEIGHT,
             top_height: Self::TOP_HEIGHT,
             clear_rows: 0,
-            tiles,
+            zone_rows: 0,
+            tiles: vec![Tile::Empty; Self::WIDTH * Self::HEIGHT],
+            links: vec![TileLinks::default(); Self::WIDTH * Self::HEIGHT],
+            rows: vec![0; Self::HEIGHT],
+            clear_mode: ClearMode::default(),
+            cleared_items: Vec::new(),
+            obstacles: Vec::new(),
+        }
+    }
+
+    /// Creates an empty field with the given size and visible height.
+    ///
+    /// Returns None if the width is unsupported.
+    pub fn with_size(width: usize, height: usize, top_height: usize) -> Option<Field> {
+        Field::from_tiles(
+            width,
+            height,
+            top_height,
+            0,
+            vec![Tile::Empty; width * height],
+        )
+    }
+
+    /// Creates a field from the tiles of another field, as returned by [`Field::tiles`]. The
+    /// field is exactly as high as the tiles, so there is no room above the visible area.
+    ///
+    /// Returns None if the width is unsupported or the tiles don’t form whole rows.
+    pub fn from_tile_list(width: usize, tiles: Vec<Tile>) -> Option<Field> {
+        let height = tiles.len().checked_div(width)?;
+        Self::from_tiles(width, height, height, 0, tiles)
+    }
+
+    /// Creates a field from raw tile data and builds its bitboard.
+    ///
+    /// Returns None if the width is unsupported or the tiles don’t form whole rows.
+    fn from_tiles(
+        width: usize,
+        height: usize,
+        top_height: usize,
+        clear_rows: usize,
+        tiles: Vec<Tile>,
+    ) -> Option<Field> {
+        if width == 0 || width > Self::MAX_WIDTH || !tiles.len().is_multiple_of(width) {
+            return None;
         }
+        let rows = tiles.chunks(width).map(Self::row_mask).collect();
+        Some(Field {
+            width,
+            height,
+            top_height,
+            clear_rows,
+            zone_rows: 0,
+            links: vec![TileLinks::default(); tiles.len()],
+            tiles,
+            rows,
+            clear_mode: ClearMode::default(),
+            cleared_items: Vec::new(),
+            obstacles: Vec::new(),
+        })
+    }
+
+    /// Computes the occupancy bits for a row of tiles.
+    fn row_mask(row: &[Tile]) -> u16 {
+        row.iter()
+            .enumerate()
+            .filter(|(_, tile)| **tile != Tile::Empty)
+            .fold(0, |mask, (x, _)| mask | 1 << x)
+    }
+
+    /// Returns the occupancy bits of a full row.
+    fn full_row(&self) -> u16 {
+        ((1u32 << self.width) - 1) as u16
     }
 
     /// Returns the width of the playfield.
@@ -464,35 +1150,92 @@ impl Field {
         &self.tiles
     }
 
+    /// Returns the occupancy bits of the row at the specified data coordinate.
+    ///
+    /// Bit `x` is set if the tile at `x` is not empty.
+    pub fn row_bits(&self, y: usize) -> Option<u16> {
+        self.rows.get(y).copied()
+    }
+
     /// Returns the tile at the specified data coordinates.
     pub fn get_tile(&self, x: usize, y: usize) -> Option<Tile> {
         if x >= self.width {
             return None;
         }
-        self.tiles.get(y * self.width + x).map(|tile| *tile)
+        self.tiles.get(y * self.width + x).copied()
+    }
+
+    /// Returns the links of the tile at the specified data coordinates to its neighbors.
+    pub fn tile_links(&self, x: usize, y: usize) -> TileLinks {
+        if x >= self.width {
+            return TileLinks::default();
+        }
+        self.links
+            .get(y * self.width + x)
+            .copied()
+            .unwrap_or_default()
     }
 
     /// Replaces the tile at the specified data coordinates.
     ///
-    /// Returns success.
+    /// This removes all links between the tile and its neighbors. Returns success.
     pub fn set_tile(&mut self, x: usize, y: usize, tile: Tile) -> bool {
         if x >= self.width || y * self.width >= self.tiles.len() {
             return false;
         }
+        self.unlink_tile(x, y);
         self.tiles[y * self.width + x] = tile;
+        if tile == Tile::Empty {
+            self.rows[y] &= !(1 << x);
+        } else {
+            self.rows[y] |= 1 << x;
+        }
         true
     }
 
+    /// Returns the data index of the neighbor in the direction of the link, if it exists.
+    fn neighbor_index(&self, x: usize, y: usize, dx: isize, dy: isize) -> Option<usize> {
+        let nx: usize = (x as isize + dx).try_into().ok()?;
+        if nx >= self.width {
+            return None;
+        }
+        let ny: usize = (y as isize + dy).try_into().ok()?;
+        Some(ny * self.width + nx).filter(|index| *index < self.tiles.len())
+    }
+
+    /// Removes all links of a tile, including the links of its neighbors back to it.
+    fn unlink_tile(&mut self, x: usize, y: usize) {
+        let index = y * self.width + x;
+        let links = core::mem::take(&mut self.links[index]);
+        for (link, dx, dy) in TileLinks::ALL.iter() {
+            if links.contains(*link) {
+                if let Some(neighbor) = self.neighbor_index(x, y, *dx, *dy) {
+                    self.links[neighbor].0 &= !link.opposite().0;
+                }
+            }
+        }
+    }
+
+    /// Returns true if the row at the given data coordinate is full and not already cleared.
+    fn is_row_clearable(&self, y: usize) -> bool {
+        y >= self.zone_rows
+            && self.rows.get(y) == Some(&self.full_row())
+            && !matches!(self.get_tile(0, y), Some(Tile::Clear(_)))
+    }
+
     /// Returns true if the shape collides with a non-empty tile, or with the bounds of this field.
     pub fn collide<T: Shape>(&self, shape: &T, pos: Point2<isize>) -> bool {
         for tile in shape.iter_tiles() {
-            let px = (pos.x + tile.x as isize).try_into();
-            let py = (pos.y + tile.y as isize).try_into();
+            let px: Result<usize, _> = (pos.x + tile.x).try_into();
+            let py: Result<usize, _> = (pos.y + tile.y).try_into();
 
             if let (Ok(px), Ok(py)) = (px, py) {
-                if self
-                    .get_tile(px, py)
-                    .map_or(true, |tile| tile != Tile::Empty)
+                let obstacle = self.obstacles.get(py).copied().unwrap_or(0);
+                if px >= self.width
+                    || self
+                        .rows
+                        .get(py)
+                        .is_none_or(|row| (row | obstacle) & 1 << px != 0)
                 {
                     return true;
                 }
@@ -500,78 +1243,222 @@ impl Field {
                 return true; // out of bounds
             }
         }
-        return false;
+        false
     }
 
     /// Projects the shape onto the field using the given tile type.
+    ///
+    /// The projected tiles are linked to each other.
     pub fn project<T: Shape>(&mut self, shape: &T, pos: Point2<isize>, tile_type: Tile) {
+        let mut placed = Vec::new();
         for tile in shape.iter_tiles() {
-            let px = (pos.x + tile.x as isize).try_into();
-            let py = (pos.y + tile.y as isize).try_into();
+            let px = (pos.x + tile.x).try_into();
+            let py = (pos.y + tile.y).try_into();
 
             if let (Ok(px), Ok(py)) = (px, py) {
-                self.set_tile(px, py, tile_type);
+                if self.set_tile(px, py, tile_type) {
+                    placed.push((px, py));
+                }
             }
         }
-    }
-
-    /// Marks appropriate lines as cleared and returns the number of cleared lines.
-    pub fn clear_lines(&mut self, time: Timestamp) -> usize {
-        let mut cleared = 0;
-
-        for y in 0..self.height {
-            let is_clear = {
-                let mut is_clear = true;
-                for x in 0..self.width {
-                    if !self
-                        .get_tile(x, y)
-                        .map_or(false, |tile| tile.is_clearable())
-                    {
-                        is_clear = false;
-                        break;
-                    }
-                }
-                is_clear
-            };
 
-            if is_clear {
-                // mark cleared
-                for x in 0..self.width {
-                    self.set_tile(x, y, Tile::Clear(time));
-                    self.tiles.push(Tile::Empty);
+        for &(x, y) in &placed {
+            let mut links = TileLinks::default();
+            for (link, dx, dy) in TileLinks::ALL.iter() {
+                let neighbor = (x as isize + dx, y as isize + dy);
+                if placed
+                    .iter()
+                    .any(|&(px, py)| (px as isize, py as isize) == neighbor)
+                {
+                    links = links | *link;
                 }
-                cleared += 1;
-                self.clear_rows += 1;
+            }
+            self.links[y * self.width + x] = links;
+        }
+    }
+
+    /// Adds an obstacle in the shape of the given shape, which pieces will collide with but which
+    /// doesn’t count as part of the field.
+    pub fn add_obstacle<T: Shape>(&mut self, shape: &T, pos: Point2<isize>) {
+        for tile in shape.iter_tiles() {
+            let px: Result<usize, _> = (pos.x + tile.x).try_into();
+            let py: Result<usize, _> = (pos.y + tile.y).try_into();
+            if let (Ok(px), Ok(py)) = (px, py) {
+                if px < self.width && py < self.rows.len() {
+                    if self.obstacles.len() <= py {
+                        self.obstacles.resize(py + 1, 0);
+                    }
+                    self.obstacles[py] |= 1 << px;
+                }
+            }
+        }
+    }
+
+    /// Removes all obstacles.
+    pub fn clear_obstacles(&mut self) {
+        self.obstacles.clear();
+    }
+
+    /// Marks appropriate lines as cleared and returns the indices of the cleared rows.
+    pub fn clear_lines(&mut self, time: Timestamp) -> Vec<usize> {
+        let mut cleared = Vec::new();
+
+        for y in 0..self.height {
+            if self.is_row_clearable(y) {
+                self.mark_cleared(y, time);
+                cleared.push(y);
             }
         }
 
         cleared
     }
 
+    /// Marks a row as cleared and adds an empty row at the top to make up for it.
+    fn mark_cleared(&mut self, y: usize, time: Timestamp) {
+        for x in 0..self.width {
+            if let Some(Tile::Item(item)) = self.get_tile(x, y) {
+                self.cleared_items.push((item, x, y));
+            }
+            self.set_tile(x, y, Tile::Clear(time));
+            self.tiles.push(Tile::Empty);
+            self.links.push(TileLinks::default());
+        }
+        self.rows.push(0);
+        self.clear_rows += 1;
+    }
+
+    /// Marks the lowest row that still contains tiles as cleared and returns its index.
+    ///
+    /// Rows collected during a zone are skipped.
+    pub fn clear_bottom_row(&mut self, time: Timestamp) -> Option<usize> {
+        let y = (self.zone_rows..self.rows.len())
+            .find(|&y| self.rows[y] != 0 && !matches!(self.get_tile(0, y), Some(Tile::Clear(_))))?;
+        self.mark_cleared(y, time);
+        Some(y)
+    }
+
+    /// Returns the items in rows that were cleared since the last call, along with their
+    /// positions at the time they were cleared.
+    pub fn take_cleared_items(&mut self) -> Vec<(Item, usize, usize)> {
+        core::mem::take(&mut self.cleared_items)
+    }
+
+    /// Returns the number of full rows collected at the bottom of the field during a zone.
+    pub fn zone_rows(&self) -> usize {
+        self.zone_rows
+    }
+
+    /// Moves full rows to the bottom of the field, on top of previously collected rows, instead
+    /// of clearing them. Returns the number of newly collected rows.
+    ///
+    /// Collected rows can't be cleared until [`Field::clear_zone_rows`] is called.
+    pub fn collect_lines(&mut self) -> usize {
+        let mut collected = 0;
+        for y in self.zone_rows..self.rows.len() {
+            if !self.is_row_clearable(y) {
+                continue;
+            }
+            // the row is separated from the tiles above and below it
+            for x in 0..self.width {
+                let index = y * self.width + x;
+                let vertical = self.links[index].0 & (TileLinks::UP.0 | TileLinks::DOWN.0);
+                for (link, dx, dy) in TileLinks::ALL.iter() {
+                    if vertical & link.0 != 0 {
+                        if let Some(neighbor) = self.neighbor_index(x, y, *dx, *dy) {
+                            self.links[neighbor].0 &= !link.opposite().0;
+                        }
+                    }
+                }
+                self.links[index].0 &= !vertical;
+            }
+            let start = self.zone_rows * self.width;
+            let end = (y + 1) * self.width;
+            self.tiles[start..end].rotate_right(self.width);
+            self.links[start..end].rotate_right(self.width);
+            self.rows[self.zone_rows..=y].rotate_right(1);
+            self.zone_rows += 1;
+            collected += 1;
+        }
+        collected
+    }
+
+    /// Marks all rows collected during a zone as cleared and returns their indices.
+    pub fn clear_zone_rows(&mut self, time: Timestamp) -> Vec<usize> {
+        let rows: Vec<_> = (0..self.zone_rows).collect();
+        for y in &rows {
+            self.mark_cleared(*y, time);
+        }
+        self.zone_rows = 0;
+        rows
+    }
+
     /// Removes expired clear lines.
-    pub fn clean_lines(&mut self, timeout: Duration, time: Timestamp) {
+    ///
+    /// In cascade mode, tiles then fall into the gaps, and lines completed by this are marked as
+    /// cleared at the given time. This repeats while lines keep being removed, so with a negative
+    /// timeout entire chains are resolved at once. Returns the lines that were marked as cleared.
+    ///
+    /// Lines that were cleared at the same time use the timeout for the number of lines cleared
+    /// together.
+    pub fn clean_lines(&mut self, timeout: impl Into<ClearTimeout>, time: Timestamp) -> Vec<usize> {
+        let timeout = timeout.into();
+        let mut chain = Vec::new();
+        while self.remove_expired_lines(timeout, time) && self.clear_mode == ClearMode::Cascade {
+            if !self.cascade() {
+                break;
+            }
+            let cleared = self.clear_lines(time);
+            if cleared.is_empty() {
+                break;
+            }
+            chain.extend(cleared);
+        }
+        chain
+    }
+
+    /// Removes expired clear lines from the data. Returns true if any lines were removed.
+    fn remove_expired_lines(&mut self, timeout: ClearTimeout, time: Timestamp) -> bool {
+        let clear_times: Vec<_> = (0..self.tiles.len() / self.width)
+            .filter_map(|y| match self.get_tile(0, y) {
+                Some(Tile::Clear(instant)) => Some(instant),
+                _ => None,
+            })
+            .collect();
+        let mut removed = false;
         let mut y = 0;
         while y < self.tiles.len() / self.width {
             let clear_line = match self.get_tile(0, y) {
                 Some(Tile::Clear(instant)) => {
-                    if time - instant > timeout {
-                        true
-                    } else {
-                        false
-                    }
+                    let lines = clear_times.iter().filter(|t| **t == instant).count();
+                    time - instant > timeout.for_lines(lines)
                 }
                 _ => false,
             };
 
             if clear_line {
-                for _ in 0..self.width {
-                    self.tiles.remove(y * self.width);
+                self.tiles.drain(y * self.width..(y + 1) * self.width);
+                self.links.drain(y * self.width..(y + 1) * self.width);
+                self.rows.remove(y);
+                if y < self.obstacles.len() {
+                    self.obstacles.remove(y);
                 }
                 self.clear_rows -= 1;
+                removed = true;
             } else {
                 y += 1;
             }
         }
+        removed
+    }
+
+    /// Returns the clear mode.
+    pub fn clear_mode(&self) -> ClearMode {
+        self.clear_mode
+    }
+
+    /// Sets the clear mode.
+    pub fn set_clear_mode(&mut self, clear_mode: ClearMode) {
+        self.clear_mode = clear_mode;
     }
 
     /// Returns the number of clear rows.
@@ -579,200 +1466,1085 @@ impl Field {
         self.clear_rows
     }
 
-    /// Returns whether or not this field has been topped out.
-    ///
-    /// Will only check the first top-out line, since pieces can’t be stacked in mid-air.
+    /// Returns the data coordinate of the skyline, i.e. the first row above the visible area.
+    pub fn skyline(&self) -> usize {
+        self.top_height + self.clear_rows
+    }
+
+    /// Returns whether or not the stack has reached the top row of the field data.
     pub fn is_top_out(&self) -> bool {
-        let y = self.top_height + self.clear_rows;
-        for x in 0..self.width {
-            if self
-                .get_tile(x, y)
-                .map_or(false, |tile| tile != Tile::Empty)
-            {
-                return true;
-            }
+        self.rows.last().is_some_and(|row| *row != 0)
+    }
+
+    /// Returns the number of lines that are full but have not been marked as cleared yet.
+    pub fn clearable_lines(&self) -> usize {
+        (0..self.rows.len())
+            .filter(|y| self.is_row_clearable(*y))
+            .count()
+    }
+
+    /// Returns true if the field contains nothing but full and cleared rows.
+    pub fn is_perfect_clear(&self) -> bool {
+        let full = self.full_row();
+        self.rows.iter().all(|row| *row == 0 || *row == full)
+    }
+
+    /// Inserts rows of garbage at the bottom of the field, pushing all other tiles up.
+    ///
+    /// Each row is filled except for the hole column, which is clamped to the last column if it’s
+    /// out of bounds. During a zone, garbage is inserted above the collected rows. Tiles pushed
+    /// past the top of the field data are discarded.
+    ///
+    /// Returns true if any non-empty tiles were discarded.
+    pub fn insert_garbage(&mut self, lines: usize, hole_column: usize) -> bool {
+        let len = self.tiles.len();
+        let hole_column = hole_column.min(self.width - 1);
+        let row: Vec<_> = (0..self.width)
+            .map(|x| {
+                if x == hole_column {
+                    Tile::Empty
+                } else {
+                    Tile::Garbage
+                }
+            })
+            .collect();
+        let row_mask = Self::row_mask(&row);
+        for _ in 0..lines {
+            let start = self.zone_rows * self.width;
+            self.tiles.splice(start..start, row.iter().copied());
+            self.links
+                .splice(start..start, (0..self.width).map(|_| TileLinks::default()));
+            self.rows.insert(self.zone_rows, row_mask);
+        }
+        self.tiles.truncate(len);
+        self.links.truncate(len);
+        let overflow = self.rows.drain(len / self.width..).any(|row| row != 0);
+        overflow
+    }
+}
+
+/// A number of garbage lines waiting to be inserted into a field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingGarbage {
+    /// The number of lines.
+    pub lines: usize,
+    /// The column of the hole in each line.
+    pub hole_column: usize,
+}
+
+/// Information about a piece that was locked in place.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LockResult {
+    /// The type of the locked piece.
+    pub piece_type: PieceType,
+    /// The spin the piece was locked with.
+    pub spin: SpinType,
+    /// The number of pending garbage lines that were cancelled by line clears.
+    pub cancelled_garbage: usize,
+    /// The number of garbage lines that were inserted into the field.
+    pub received_garbage: usize,
+    /// The number of lines the piece completed.
+    #[serde(default)]
+    pub lines_cleared: usize,
+    /// The number of consecutive locked pieces that cleared lines, including this one.
+    #[serde(default)]
+    pub combo: usize,
+    /// True if this was a difficult line clear directly following another difficult line clear.
+    #[serde(default)]
+    pub back_to_back: bool,
+    /// True if the line clear left the field empty.
+    #[serde(default)]
+    pub perfect_clear: bool,
+    /// The number of tiles the piece was moved down by soft dropping.
+    #[serde(default)]
+    pub soft_drop_cells: usize,
+    /// The number of tiles the piece was moved down by hard dropping.
+    #[serde(default)]
+    pub hard_drop_cells: usize,
+}
+
+impl LockResult {
+    /// Returns true if this is a difficult line clear, i.e. a tetris or a spin that cleared
+    /// lines.
+    pub fn is_difficult(&self) -> bool {
+        self.lines_cleared >= 4 || (self.lines_cleared > 0 && self.spin != SpinType::None)
+    }
+}
+
+#[cfg(test)]
+impl LockResult {
+    /// Creates the result of locking a T piece that cleared the given lines, starting a combo if
+    /// it cleared any.
+    pub(crate) fn test(lines_cleared: usize, spin: SpinType) -> LockResult {
+        LockResult {
+            piece_type: PieceType::T,
+            spin,
+            cancelled_garbage: 0,
+            received_garbage: 0,
+            lines_cleared,
+            combo: if lines_cleared > 0 { 1 } else { 0 },
+            back_to_back: false,
+            perfect_clear: false,
+            soft_drop_cells: 0,
+            hard_drop_cells: 0,
         }
-        false
+    }
+}
+
+/// Kinds of line clears, by the number of lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClearKind {
+    /// No lines were cleared.
+    #[default]
+    None,
+    Single,
+    Double,
+    Triple,
+    /// Four or more lines.
+    Tetris,
+}
+
+impl ClearKind {
+    /// Returns the kind of clearing the given number of lines.
+    pub fn from_lines(lines: usize) -> ClearKind {
+        match lines {
+            0 => ClearKind::None,
+            1 => ClearKind::Single,
+            2 => ClearKind::Double,
+            3 => ClearKind::Triple,
+            _ => ClearKind::Tetris,
+        }
+    }
+}
+
+/// Lines that were cleared by a call to `ActiveField::clear_lines`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LineClear {
+    /// Data coordinates of the rows that were marked as cleared.
+    pub rows: Vec<usize>,
+    /// The kind of line clear.
+    pub kind: ClearKind,
+    /// The spin of the piece that completed the lines.
+    pub spin: SpinType,
+    /// True if nothing but cleared rows is left in the field.
+    pub perfect_clear: bool,
+}
+
+impl LineClear {
+    /// Returns the number of cleared lines.
+    pub fn count(&self) -> usize {
+        self.rows.len()
+    }
+
+    /// Returns true if no lines were cleared.
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+}
+
+/// Events emitted by operations on an active field.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FieldEvent {
+    /// A new active piece was spawned.
+    Spawned { piece_type: PieceType },
+    /// The active piece was moved by the given offset.
+    Moved { dx: isize, dy: isize },
+    /// The active piece was rotated using the kick with the given index.
+    Rotated {
+        rotation: Rotation,
+        kick_index: usize,
+    },
+    /// The active piece was swapped with the held piece.
+    Held { piece_type: PieceType },
+    /// The active piece was locked in place.
+    PieceLocked(LockResult),
+    /// Lines were marked as cleared.
+    LinesCleared { rows: Vec<usize>, spin: SpinType },
+    /// Garbage lines were inserted into the field.
+    GarbageInserted { lines: usize },
+    /// A zone was started.
+    ZoneStarted,
+    /// Full rows were collected at the bottom of the field during a zone.
+    LinesCollected { lines: usize },
+    /// A zone ended, clearing all collected rows.
+    ZoneEnded { lines: usize },
+    /// An item was triggered because its row was cleared. Contains the item’s position at the
+    /// time.
+    ItemTriggered { item: Item, x: usize, y: usize },
+    /// The field topped out.
+    ToppedOut(TopOut),
+}
+
+/// Inputs that were buffered before a piece spawned and are applied when it spawns.
+///
+/// This implements initial rotation (IRS) and initial hold (IHS).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpawnInput {
+    /// Number of clockwise rotations to apply to the spawned piece.
+    pub rotation: isize,
+    /// Whether the spawned piece should be swapped with the held piece right away.
+    pub hold: bool,
+}
+
+impl SpawnInput {
+    /// Returns true if no inputs are buffered.
+    pub fn is_empty(&self) -> bool {
+        self.rotation % 4 == 0 && !self.hold
+    }
+}
+
+/// What happens when holding while the hold box is empty.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EmptyHold {
+    /// The active piece is held and the next piece is taken from the queue.
+    #[default]
+    ConsumeQueue,
+    /// The active piece is not held, but dealt again from the spawn position.
+    Redeal,
+}
+
+/// Configures how swapping with the held piece behaves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HoldConfig {
+    /// If false, the piece coming out of the hold box keeps the rotation of the piece going in.
+    pub reset_rotation: bool,
+    /// If true, the piece coming out of the hold box takes the position of the piece going in
+    /// instead of spawning at the top.
+    pub preserve_position: bool,
+    /// What happens when the hold box is empty.
+    pub empty_hold: EmptyHold,
+}
+
+impl Default for HoldConfig {
+    fn default() -> Self {
+        HoldConfig {
+            reset_rotation: true,
+            preserve_position: false,
+            empty_hold: EmptyHold::ConsumeQueue,
+        }
+    }
+}
+
+/// The outcome of swapping with the held piece.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HoldResult {
+    /// The active piece was swapped with the held piece.
+    Swapped,
+    /// The hold box was empty, so the active piece was held and the next piece was taken from
+    /// the queue.
+    TookFromQueue,
+    /// The hold box was empty, so the active piece was dealt again.
+    Redealt,
+    /// Nothing happened because the active piece already came out of the hold box.
+    AlreadyHeld,
+    /// Nothing happened because there is no active piece.
+    NoActivePiece,
+}
+
+/// Ways in which a field can top out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TopOut {
+    /// A new piece was spawned in an obstructed position.
+    BlockOut,
+    /// A piece was locked entirely above the skyline.
+    LockOut,
+    /// The stack was pushed past the top of the field (e.g. by garbage).
+    Overflow,
+}
+
+/// A snapshot of the game state of an active field, used for undo and rollback.
+///
+/// Configuration such as the rotation system or the piece set is not part of the snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldSnapshot {
+    field: Field,
+    queue: VecDeque<PieceType>,
+    bag_remaining: VecDeque<usize>,
+    bag_history: Vec<PieceType>,
+    held_piece: Option<PieceType>,
+    active_piece: Option<ActivePiece>,
+    pending_garbage: VecDeque<PendingGarbage>,
+    #[serde(default)]
+    seed: u64,
+    rng: Pcg32,
+    last_spin: SpinType,
+    top_out: Option<TopOut>,
+    combo: usize,
+    back_to_back: usize,
+    #[serde(default)]
+    in_zone: bool,
+}
+
+impl FieldSnapshot {
+    /// Returns the state of the piece RNG, which determines the future piece sequence.
+    pub fn rng(&self) -> Pcg32 {
+        self.rng
     }
 }
 
 /// A Tetris playfield with an active piece, queue, and held piece.
-#[derive(Debug, Clone)]
+///
+/// The entire state, including the queue, the held piece and the piece RNG, can be serialized.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ActiveField {
     /// The inner playfield.
     field: Field,
     /// Queue with the next pieces.
     queue: VecDeque<PieceType>,
+    /// Number of pieces left in each bag in the queue, starting with the bag being dealt.
+    #[serde(default)]
+    bag_remaining: VecDeque<usize>,
+    /// Pieces that have been dealt from the current bag.
+    #[serde(default)]
+    bag_history: Vec<PieceType>,
     /// The type of the piece that is currently in the hold box.
     held_piece: Option<PieceType>,
     /// The current active piece.
     active_piece: Option<ActivePiece>,
+    /// Incoming garbage that will be inserted when the next piece locks without clearing lines.
+    pending_garbage: VecDeque<PendingGarbage>,
+    /// The seed the piece RNG was created with.
+    seed: u64,
+    /// The RNG used to shuffle bags of pieces.
+    rng: Pcg32,
+    /// The rotation system used when rotating the active piece.
+    rotation_system: RotationSystem,
+    /// Maximum number of times a piece may reset the lock delay by moving on the ground.
+    lock_reset_limit: Option<usize>,
+    /// Minimum number of pieces that will always be available in the queue.
+    preview_depth: usize,
+    /// The spin of the last locked piece, used to qualify line clears.
+    last_spin: SpinType,
+    /// How the field topped out, if it has.
+    #[serde(default)]
+    top_out: Option<TopOut>,
+    /// The number of consecutive locked pieces that cleared lines.
+    #[serde(default)]
+    combo: usize,
+    /// The number of consecutive difficult line clears. Locks that don’t clear lines don’t break
+    /// the chain.
+    #[serde(default)]
+    back_to_back: usize,
+    /// If true, pieces spawn as big pieces and garbage is inserted in 2×2 blocks.
+    #[serde(default)]
+    big_mode: bool,
+    /// If true, full rows are collected at the bottom of the field instead of being cleared.
+    #[serde(default)]
+    in_zone: bool,
+    /// How swapping with the held piece behaves.
+    #[serde(default)]
+    hold_config: HoldConfig,
+    /// The pieces that appear in the queue.
+    #[serde(default)]
+    piece_set: PieceSet,
+    /// Horizontal offset of the spawn position from the center, in minos.
+    #[serde(default)]
+    spawn_column_offset: isize,
+    /// Events that have not been drained yet.
+    #[serde(skip)]
+    events: VecDeque<FieldEvent>,
+}
+
+impl Default for ActiveField {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ActiveField {
+    /// The default lock reset limit, as in the guideline.
+    pub const LOCK_RESET_LIMIT: usize = 15;
+    /// The default preview depth.
+    pub const PREVIEW_DEPTH: usize = 5;
+    /// The maximum number of undrained events that will be kept.
+    pub const MAX_EVENTS: usize = 1024;
+
+    /// Creates a new active field with a random seed.
     pub fn new() -> ActiveField {
-        ActiveField {
+        ActiveField::with_seed(rand::random())
+    }
+
+    /// Creates a new active field whose piece sequence is determined by the given seed.
+    pub fn with_seed(seed: u64) -> ActiveField {
+        let mut field = ActiveField {
             field: Field::new(),
             queue: VecDeque::new(),
+            bag_remaining: VecDeque::new(),
+            bag_history: Vec::new(),
             held_piece: None,
             active_piece: None,
+            pending_garbage: VecDeque::new(),
+            seed,
+            rng: Pcg32::new(seed),
+            rotation_system: RotationSystem::default(),
+            lock_reset_limit: Some(Self::LOCK_RESET_LIMIT),
+            preview_depth: Self::PREVIEW_DEPTH,
+            last_spin: SpinType::None,
+            top_out: None,
+            combo: 0,
+            back_to_back: 0,
+            big_mode: false,
+            in_zone: false,
+            hold_config: HoldConfig::default(),
+            piece_set: PieceSet::standard(),
+            spawn_column_offset: 0,
+            events: VecDeque::new(),
+        };
+        field.update_queue();
+        field
+    }
+
+    /// Creates a new active field with the given settings.
+    ///
+    /// If the configuration has no seed, a random seed is used. Panics if the field size is
+    /// invalid, which can only be the case for deserialized configurations.
+    pub fn with_config(config: &GameConfig) -> ActiveField {
+        let mut field = ActiveField::with_seed(config.seed().unwrap_or_else(rand::random));
+        field.field = Field::with_size(config.width(), config.height(), config.top_height())
+            .expect("invalid field size in game config");
+        field.rotation_system = config.rotation_system().clone();
+        field.hold_config = *config.hold_config();
+        field.preview_depth = config.preview_depth();
+        field.set_piece_set(config.piece_set().clone());
+        field
+    }
+
+    /// Adds an event to the event queue, discarding the oldest event if it’s full.
+    fn emit(&mut self, event: FieldEvent) {
+        if self.events.len() >= Self::MAX_EVENTS {
+            self.events.pop_front();
         }
+        self.events.push_back(event);
+    }
+
+    /// Removes and returns all events that occurred since the last call.
+    pub fn drain_events(&mut self) -> impl Iterator<Item = FieldEvent> + '_ {
+        self.events.drain(..)
     }
 
     /// Updates the queue and fills it up with items if it’s too empty.
+    ///
+    /// The queue will contain at least one more piece than the preview depth, so that it will
+    /// still contain enough pieces after popping the next piece.
     fn update_queue(&mut self) {
-        if self.queue.len() < 2 {
-            let mut rng = rand::thread_rng();
-            let mut t = PieceType::all();
-            t.shuffle(&mut rng);
+        if self.piece_set.is_empty() {
+            return;
+        }
+        while self.queue.len() <= self.preview_depth {
+            let mut t = self.piece_set.bag();
+            t.shuffle(&mut self.rng);
+            self.bag_remaining.push_back(t.len());
             for i in t {
                 self.queue.push_back(i);
             }
         }
     }
 
+    /// Takes the next piece from the queue and records it in the bag history.
+    fn pop_queue(&mut self) -> PieceType {
+        let piece = self.queue.pop_front().expect("empty queue");
+        if self.bag_remaining.front() == Some(&0) {
+            // this piece starts a new bag
+            self.bag_remaining.pop_front();
+            self.bag_history.clear();
+        }
+        if let Some(remaining) = self.bag_remaining.front_mut() {
+            *remaining -= 1;
+        }
+        self.bag_history.push(piece);
+        piece
+    }
+
     /// Spawns an active piece.
     ///
     /// If the type override is not given, this will pop the queue.
-    pub fn spawn_active(&mut self, type_override: Option<PieceType>, time: Timestamp) {
+    pub fn spawn_active(
+        &mut self,
+        type_override: Option<PieceType>,
+        time: Timestamp,
+    ) -> SpawnResult {
+        self.spawn_active_with(type_override, SpawnInput::default(), time)
+    }
+
+    /// Spawns an active piece and applies buffered initial rotation and hold inputs.
+    ///
+    /// If the type override is not given, this will pop the queue.
+    pub fn spawn_active_with(
+        &mut self,
+        type_override: Option<PieceType>,
+        input: SpawnInput,
+        time: Timestamp,
+    ) -> SpawnResult {
         self.update_queue();
-        let piece_type =
-            type_override.unwrap_or_else(|| self.queue.pop_front().expect("empty queue"));
-        let mut active_piece = ActivePiece::new(piece_type, time);
-
-        let mut active_piece_x_bounds = (0, 0);
-        let mut active_piece_baseline_offset = 0;
-        for tile in active_piece.iter_tiles() {
-            active_piece_x_bounds.0 = tile.x.min(active_piece_x_bounds.0);
-            active_piece_x_bounds.1 = tile.x.max(active_piece_x_bounds.1);
-            active_piece_baseline_offset = tile.y.min(active_piece_baseline_offset);
-        }
-        let active_piece_width = active_piece_x_bounds.1 - active_piece_x_bounds.0;
-
-        active_piece.pos.x = self.field.width as isize / 2 - active_piece_width / 2;
-        active_piece.pos.y = self.field.top_height as isize + self.field.clear_rows as isize
-            - active_piece_baseline_offset;
+        let mut piece_type = type_override.unwrap_or_else(|| self.pop_queue());
+        if input.hold {
+            let held_piece = self.held_piece.replace(piece_type);
+            self.emit(FieldEvent::Held { piece_type });
+            self.update_queue();
+            piece_type = held_piece.unwrap_or_else(|| self.pop_queue());
+        }
+        let def = self.piece_set.get(piece_type);
+        let mut active_piece = match def {
+            Some(def) if piece_type.is_custom() => {
+                ActivePiece::with_shape(piece_type, def.shape, time)
+            }
+            _ => ActivePiece::new(piece_type, time),
+        };
+        let spawn_offset = def.map_or((0, 0), |def| def.spawn_offset);
+        let mut blocked = false;
+
+        // the origin is always included in the bounds
+        let origin = Rect::new(Point2::new(0, 0), Point2::new(1, 1));
+        let bounds = active_piece
+            .bounding_box()
+            .map_or(origin, |bounds| bounds.union(&origin));
+        let active_piece_width = bounds.width() - 1;
+        let active_piece_baseline_offset = bounds.min.y;
+
+        // spawn in mino coordinates, so big pieces stay aligned to the 2×2 grid
+        active_piece.big = self.big_mode;
+        let scale = active_piece.scale();
+        let width = self.field.width as isize / scale;
+        active_piece.pos.x =
+            (width / 2 - active_piece_width / 2 + spawn_offset.0 + self.spawn_column_offset)
+                * scale;
+        active_piece.pos.y = self.field.top_height as isize
+            + self.field.clear_rows as isize
+            + (spawn_offset.1 - active_piece_baseline_offset) * scale;
+        if self.field.collide(&active_piece, active_piece.pos) {
+            blocked = true;
+        }
+        active_piece.was_held_piece = input.hold;
         active_piece.try_move(&self.field, 0, -1, time);
         self.active_piece = Some(active_piece);
+        self.emit(FieldEvent::Spawned { piece_type });
+        if input.rotation % 4 != 0 {
+            self.rotate_active(input.rotation, time);
+        }
+        if blocked {
+            self.set_top_out(TopOut::BlockOut);
+            SpawnResult::Blocked
+        } else {
+            SpawnResult::Spawned
+        }
     }
 
-    /// Attempts to rotate the active piece counter-clockwise.
-    pub fn rotate_active_ccw(&mut self, time: Timestamp) {
-        if let Some(active_piece) = &mut self.active_piece {
-            active_piece.try_rotate(&self.field, -1, time);
+    /// Attempts to rotate the active piece by the given number of clockwise steps.
+    fn rotate_active(&mut self, rotation: isize, time: Timestamp) -> MoveResult {
+        let mut active_piece = match self.active_piece {
+            Some(piece) => piece,
+            None => return MoveResult::NoActivePiece,
+        };
+        let rotation_system = self.rotation_system_for(active_piece.piece_type);
+        let result = active_piece.try_rotate(&self.field, rotation_system, rotation, time);
+        self.active_piece = Some(active_piece);
+        if let MoveResult::KickedWith(kick_index) = result {
+            self.emit(FieldEvent::Rotated {
+                rotation: active_piece.rotation,
+                kick_index,
+            });
         }
+        result
+    }
+
+    /// Returns the rotation system used for the given piece type.
+    fn rotation_system_for(&self, piece_type: PieceType) -> &RotationSystem {
+        self.piece_set
+            .get(piece_type)
+            .and_then(|def| def.rotation_system.as_ref())
+            .unwrap_or(&self.rotation_system)
+    }
+
+    /// Attempts to rotate the active piece counter-clockwise.
+    pub fn rotate_active_ccw(&mut self, time: Timestamp) -> MoveResult {
+        self.rotate_active(-1, time)
     }
 
     /// Attempts to rotate the active piece clockwise.
-    pub fn rotate_active_cw(&mut self, time: Timestamp) {
-        if let Some(active_piece) = &mut self.active_piece {
-            active_piece.try_rotate(&self.field, 1, time);
+    pub fn rotate_active_cw(&mut self, time: Timestamp) -> MoveResult {
+        self.rotate_active(1, time)
+    }
+
+    /// Attempts to move the active piece by the given offset.
+    fn move_active(&mut self, dx: isize, dy: isize, time: Timestamp) -> MoveResult {
+        let active_piece = match &mut self.active_piece {
+            Some(piece) => piece,
+            None => return MoveResult::NoActivePiece,
+        };
+        let result = active_piece.try_move(&self.field, dx, dy, time);
+        if result.is_success() {
+            let scale = active_piece.scale();
+            self.emit(FieldEvent::Moved {
+                dx: dx * scale,
+                dy: dy * scale,
+            });
         }
+        result
     }
 
     /// Attempts to move the active piece left.
-    pub fn move_active_left(&mut self, time: Timestamp) {
-        if let Some(active_piece) = &mut self.active_piece {
-            active_piece.try_move(&self.field, -1, 0, time);
-        }
+    pub fn move_active_left(&mut self, time: Timestamp) -> MoveResult {
+        self.move_active(-1, 0, time)
     }
 
     /// Attempts to move the active piece right.
-    pub fn move_active_right(&mut self, time: Timestamp) {
-        if let Some(active_piece) = &mut self.active_piece {
-            active_piece.try_move(&self.field, 1, 0, time);
-        }
+    pub fn move_active_right(&mut self, time: Timestamp) -> MoveResult {
+        self.move_active(1, 0, time)
     }
 
     /// Attempts to move the active tile down.
-    pub fn move_active_down(&mut self, time: Timestamp) {
-        if let Some(active_piece) = &mut self.active_piece {
-            active_piece.try_move(&self.field, 0, -1, time);
+    pub fn move_active_down(&mut self, time: Timestamp) -> MoveResult {
+        self.move_active(0, -1, time)
+    }
+
+    /// Returns the ghost piece, i.e. the active piece at the position it would land at if it were
+    /// dropped all the way down.
+    pub fn ghost_piece(&self) -> Option<ActivePiece> {
+        let mut piece = self.active_piece?;
+        // use field height as an upper limit in case of invalid state
+        for _ in 0..self.field.height {
+            if piece.is_on_ground(&self.field) {
+                break;
+            }
+            piece.pos.y -= piece.scale();
         }
+        Some(piece)
     }
 
     /// Returns the position of the ghost piece.
     pub fn ghost_pos(&self) -> Option<Point2<isize>> {
-        if let Some(active_piece) = self.active_piece {
-            let mut piece = active_piece.clone();
-            while !piece.is_on_ground(&self.field) {
-                piece.try_move(&self.field, 0, -1, 0.);
-            }
-            Some(piece.pos())
-        } else {
-            None
+        self.ghost_piece().map(|piece| piece.pos())
+    }
+
+    /// Moves the active piece down by one row as a soft drop, which counts towards the drop
+    /// score of the piece.
+    pub fn soft_drop_active(&mut self, time: Timestamp) -> MoveResult {
+        let result = self.move_active_down(time);
+        if let (MoveResult::Moved, Some(piece)) = (result, &mut self.active_piece) {
+            piece.soft_drop_cells += piece.scale() as usize;
         }
+        result
     }
 
     /// Moves the active tile all the way down.
-    pub fn sonic_drop_active(&mut self, time: Timestamp) {
-        // use field height as an upper limit in case of invalid state
-        for _ in 0..self.field.height {
-            self.move_active_down(time);
-            if self
-                .active_piece
-                .as_ref()
-                .map_or(true, |piece| piece.is_on_ground(&self.field))
-            {
-                break;
+    ///
+    /// Returns the distance the piece was dropped, in tiles.
+    pub fn sonic_drop_active(&mut self, time: Timestamp) -> usize {
+        self.drop_active(time)
+    }
+
+    /// Moves the active piece all the way down and locks it.
+    ///
+    /// Returns the distance the piece was dropped, in tiles.
+    pub fn hard_drop_active(&mut self, time: Timestamp) -> usize {
+        self.hard_drop_and_lock(time)
+            .map_or(0, |result| result.hard_drop_cells)
+    }
+
+    /// Moves the active piece all the way down and locks it, counting the distance towards the
+    /// drop score of the piece.
+    pub fn hard_drop_and_lock(&mut self, time: Timestamp) -> Option<LockResult> {
+        let distance = self.drop_active(time);
+        if let Some(piece) = &mut self.active_piece {
+            piece.hard_drop_cells += distance;
+        }
+        self.lock_active()
+    }
+
+    /// Moves the active piece all the way down and returns the distance.
+    fn drop_active(&mut self, time: Timestamp) -> usize {
+        let mut distance = 0;
+        if let Some(active_piece) = &mut self.active_piece {
+            // use field height as an upper limit in case of invalid state
+            for _ in 0..self.field.height {
+                if !active_piece.try_move(&self.field, 0, -1, time).is_success() {
+                    break;
+                }
+                distance += active_piece.scale();
             }
         }
+        if distance > 0 {
+            self.emit(FieldEvent::Moved {
+                dx: 0,
+                dy: -distance,
+            });
+        }
+        distance as usize
     }
 
     /// Locks the active piece in place.
-    pub fn lock_active(&mut self) {
-        self.active_piece.take().map(|piece| {
-            self.field
-                .project(&piece, piece.pos, Tile::Piece(piece.piece_type))
-        });
+    ///
+    /// If the piece completes any lines, they will cancel pending garbage. Otherwise, all pending
+    /// garbage will be inserted into the field.
+    ///
+    /// Returns information about the locked piece, or None if there was no active piece.
+    pub fn lock_active(&mut self) -> Option<LockResult> {
+        let piece = self.active_piece.take()?;
+        let spin = piece.spin_type(&self.field, self.rotation_system_for(piece.piece_type));
+        self.field
+            .project(&piece, piece.pos, Tile::Piece(piece.piece_type));
+        let skyline = self.field.skyline() as isize;
+        let locked_out = piece
+            .iter_tiles()
+            .all(|tile| piece.pos.y + tile.y >= skyline);
+
+        let lines = self.field.clearable_lines();
+        let mut cancelled_garbage = 0;
+        let mut received_garbage = 0;
+        let mut overflow = false;
+        if lines > 0 {
+            cancelled_garbage = lines - self.cancel_garbage(lines);
+            self.combo += 1;
+        } else {
+            (received_garbage, overflow) = self.insert_pending_garbage();
+            self.combo = 0;
+        }
+        self.last_spin = spin;
+
+        let mut result = LockResult {
+            piece_type: piece.piece_type,
+            spin,
+            cancelled_garbage,
+            received_garbage,
+            lines_cleared: lines,
+            combo: self.combo,
+            back_to_back: false,
+            perfect_clear: lines > 0 && self.field.is_perfect_clear(),
+            soft_drop_cells: piece.soft_drop_cells,
+            hard_drop_cells: piece.hard_drop_cells,
+        };
+        if result.is_difficult() {
+            result.back_to_back = self.back_to_back > 0;
+            self.back_to_back += 1;
+        } else if lines > 0 {
+            self.back_to_back = 0;
+        }
+        self.emit(FieldEvent::PieceLocked(result));
+        if received_garbage > 0 {
+            self.emit(FieldEvent::GarbageInserted {
+                lines: received_garbage,
+            });
+        }
+        if locked_out {
+            self.set_top_out(TopOut::LockOut);
+        } else if overflow || self.field.is_top_out() {
+            self.set_top_out(TopOut::Overflow);
+        }
+        Some(result)
+    }
+
+    /// Adds incoming garbage to the pending garbage queue.
+    pub fn queue_garbage(&mut self, lines: usize, hole_column: usize) {
+        if lines > 0 {
+            self.pending_garbage
+                .push_back(PendingGarbage { lines, hole_column });
+        }
+    }
+
+    /// Cancels up to the given number of pending garbage lines, oldest first.
+    ///
+    /// Returns the number of lines that were left over.
+    pub fn cancel_garbage(&mut self, mut lines: usize) -> usize {
+        while lines > 0 {
+            match self.pending_garbage.front_mut() {
+                Some(garbage) if garbage.lines > lines => {
+                    garbage.lines -= lines;
+                    lines = 0;
+                }
+                Some(garbage) => {
+                    lines -= garbage.lines;
+                    self.pending_garbage.pop_front();
+                }
+                None => break,
+            }
+        }
+        lines
+    }
+
+    /// Inserts all pending garbage into the field and returns the number of inserted lines.
+    ///
+    /// Returns the number of inserted lines and whether any tiles were pushed out of the field.
+    fn insert_pending_garbage(&mut self) -> (usize, bool) {
+        let mut inserted = 0;
+        let mut overflow = false;
+        for garbage in self.pending_garbage.drain(..) {
+            if self.big_mode {
+                // two rows per line, with a hole two tiles wide
+                let hole_column = (garbage.hole_column / 2 * 2).min(self.field.width - 2);
+                overflow |= self.field.insert_garbage(garbage.lines * 2, hole_column);
+                for y in 0..garbage.lines * 2 {
+                    self.field.set_tile(hole_column + 1, y, Tile::Empty);
+                }
+                inserted += garbage.lines * 2;
+            } else {
+                overflow |= self
+                    .field
+                    .insert_garbage(garbage.lines, garbage.hole_column);
+                inserted += garbage.lines;
+            }
+        }
+        (inserted, overflow)
+    }
+
+    /// Returns the pending garbage queue.
+    pub fn pending_garbage(&self) -> &VecDeque<PendingGarbage> {
+        &self.pending_garbage
+    }
+
+    /// Returns the total number of pending garbage lines.
+    pub fn pending_garbage_lines(&self) -> usize {
+        self.pending_garbage.iter().map(|g| g.lines).sum()
     }
 
     /// Returns true if the active piece should be locked in place right now.
+    ///
+    /// This is the case if it has been resting on the ground for the lock delay, or immediately
+    /// if it is on the ground and has used up all of its lock resets.
     pub fn should_lock_active(&mut self, lock_delay: Duration, time: Timestamp) -> bool {
         if let Some(active_piece) = &self.active_piece {
+            let out_of_resets = self
+                .lock_reset_limit
+                .is_some_and(|limit| active_piece.lock_resets >= limit);
             active_piece.is_on_ground(&self.field)
-                && time - active_piece.last_move_time >= lock_delay
+                && (out_of_resets || time - active_piece.last_move_time >= lock_delay)
         } else {
             false
         }
     }
 
+    /// Returns how far the active piece is into its lock delay, from 0 right after it moved to 1
+    /// when it should lock.
+    ///
+    /// Returns None if there is no active piece or it isn’t on the ground.
+    pub fn lock_progress(&self, lock_delay: Duration, time: Timestamp) -> Option<f64> {
+        let active_piece = self.active_piece.as_ref()?;
+        if !active_piece.is_on_ground(&self.field) {
+            return None;
+        }
+        let out_of_resets = self
+            .lock_reset_limit
+            .is_some_and(|limit| active_piece.lock_resets >= limit);
+        if out_of_resets || lock_delay <= 0. {
+            return Some(1.);
+        }
+        Some(((time - active_piece.last_move_time) / lock_delay).clamp(0., 1.))
+    }
+
+    /// Returns the maximum number of lock delay resets per piece, or None if unlimited.
+    pub fn lock_reset_limit(&self) -> Option<usize> {
+        self.lock_reset_limit
+    }
+
+    /// Sets the maximum number of lock delay resets per piece (None for unlimited).
+    pub fn set_lock_reset_limit(&mut self, limit: Option<usize>) {
+        self.lock_reset_limit = limit;
+    }
+
+    /// Returns the hold configuration.
+    pub fn hold_config(&self) -> &HoldConfig {
+        &self.hold_config
+    }
+
+    /// Sets how swapping with the held piece behaves.
+    pub fn set_hold_config(&mut self, hold_config: HoldConfig) {
+        self.hold_config = hold_config;
+    }
+
+    /// Returns the horizontal offset of the spawn position from the center, in minos.
+    pub fn spawn_column_offset(&self) -> isize {
+        self.spawn_column_offset
+    }
+
+    /// Sets the horizontal offset of the spawn position from the center, in minos.
+    pub fn set_spawn_column_offset(&mut self, offset: isize) {
+        self.spawn_column_offset = offset;
+    }
+
+    /// Returns the number of lock delay resets the active piece has left, if limited.
+    pub fn lock_resets_left(&self) -> Option<usize> {
+        let piece = self.active_piece.as_ref()?;
+        self.lock_reset_limit
+            .map(|limit| limit.saturating_sub(piece.lock_resets))
+    }
+
     /// Swaps the held piece and the active piece if the active piece was not a held piece.
-    pub fn swap_held_piece(&mut self, time: Timestamp) {
-        if self
-            .active_piece
-            .as_ref()
-            .map_or(false, |p| p.was_held_piece)
-        {
-            return;
+    ///
+    /// The details depend on the hold configuration.
+    pub fn swap_held_piece(&mut self, time: Timestamp) -> HoldResult {
+        let prev_piece = match self.active_piece {
+            Some(piece) if piece.was_held_piece => return HoldResult::AlreadyHeld,
+            Some(piece) => piece,
+            None => return HoldResult::NoActivePiece,
+        };
+        let result = match self.held_piece {
+            Some(held_piece) => {
+                self.spawn_active(Some(held_piece), time);
+                HoldResult::Swapped
+            }
+            None if self.hold_config.empty_hold == EmptyHold::Redeal => {
+                self.spawn_active(Some(prev_piece.piece_type), time);
+                // like any held piece, it can't be held again until it locks
+                if let Some(active_piece) = &mut self.active_piece {
+                    active_piece.was_held_piece = true;
+                }
+                self.emit(FieldEvent::Held {
+                    piece_type: prev_piece.piece_type,
+                });
+                return HoldResult::Redealt;
+            }
+            None => {
+                self.spawn_active(None, time);
+                HoldResult::TookFromQueue
+            }
+        };
+
+        let active_piece = self.active_piece.as_mut().unwrap();
+        let mut moved = *active_piece;
+        if !self.hold_config.reset_rotation {
+            moved.rotation = prev_piece.rotation;
         }
-        let new_held_piece = self.active_piece.as_ref().map(|p| p.piece_type);
-        if let Some(held_piece) = self.held_piece {
-            self.spawn_active(Some(held_piece), time);
-        } else {
-            self.spawn_active(None, time);
+        if self.hold_config.preserve_position {
+            moved.pos = prev_piece.pos;
+        }
+        // fall back to the spawn state if the piece doesn’t fit
+        if !self.field.collide(&moved, moved.pos) {
+            *active_piece = moved;
         }
-        self.active_piece.as_mut().unwrap().was_held_piece = true;
-        self.held_piece = new_held_piece;
+        active_piece.was_held_piece = true;
+
+        self.held_piece = Some(prev_piece.piece_type);
+        self.emit(FieldEvent::Held {
+            piece_type: prev_piece.piece_type,
+        });
+        result
     }
 
     /// Checks for clear lines and removes expired clear lines.
     ///
-    /// Returns the number of cleared lines.
-    pub fn clear_lines(&mut self, clear_timeout: Duration, time: Timestamp) -> usize {
-        let cleared = self.field.clear_lines(time);
-        self.field.clean_lines(clear_timeout, time);
-        cleared
+    /// Returns the lines that were cleared.
+    pub fn clear_lines(
+        &mut self,
+        clear_timeout: impl Into<ClearTimeout>,
+        time: Timestamp,
+    ) -> LineClear {
+        let clear_timeout = clear_timeout.into();
+        if self.in_zone {
+            let lines = self.field.collect_lines();
+            if lines > 0 {
+                self.emit(FieldEvent::LinesCollected { lines });
+            }
+            let cleared = self.field.clean_lines(clear_timeout, time);
+            return self.emit_cleared(cleared, time);
+        }
+        let mut cleared = self.field.clear_lines(time);
+        cleared.extend(self.field.clean_lines(clear_timeout, time));
+        self.emit_cleared(cleared, time)
+    }
+
+    /// Returns true if a zone is active.
+    pub fn is_in_zone(&self) -> bool {
+        self.in_zone
+    }
+
+    /// Starts a zone: until it ends, full rows are collected at the bottom of the field instead of
+    /// being cleared. Simulations also pause gravity during a zone.
+    pub fn start_zone(&mut self) {
+        if !self.in_zone {
+            self.in_zone = true;
+            self.emit(FieldEvent::ZoneStarted);
+        }
+    }
+
+    /// Ends the zone and clears all collected rows at once.
+    pub fn end_zone(&mut self, time: Timestamp) -> LineClear {
+        if !self.in_zone {
+            return LineClear::default();
+        }
+        self.in_zone = false;
+        let rows = self.field.clear_zone_rows(time);
+        self.emit(FieldEvent::ZoneEnded { lines: rows.len() });
+        self.emit_cleared(rows, time)
     }
 
     /// Removes expired clear lines.
-    pub fn clean_lines(&mut self, clear_timeout: Duration, time: Timestamp) {
-        self.field.clean_lines(clear_timeout, time);
+    ///
+    /// In cascade mode, this may clear more lines.
+    pub fn clean_lines(&mut self, clear_timeout: impl Into<ClearTimeout>, time: Timestamp) {
+        let cleared = self.field.clean_lines(clear_timeout, time);
+        self.emit_cleared(cleared, time);
+    }
+
+    /// Triggers the items in cleared rows and applies their effects on this field.
+    ///
+    /// Returns the rows that were cleared by item effects.
+    fn trigger_items(&mut self, time: Timestamp) -> Vec<usize> {
+        let mut rows = Vec::new();
+        loop {
+            let items = self.field.take_cleared_items();
+            if items.is_empty() {
+                break;
+            }
+            for (item, x, y) in items {
+                self.emit(FieldEvent::ItemTriggered { item, x, y });
+                if item == Item::ClearBottomRow {
+                    rows.extend(self.field.clear_bottom_row(time));
+                }
+            }
+        }
+        rows
+    }
+
+    /// Triggers items, emits an event for cleared lines and describes the line clear.
+    ///
+    /// Rows cleared by items count towards the line clear.
+    fn emit_cleared(&mut self, mut rows: Vec<usize>, time: Timestamp) -> LineClear {
+        rows.extend(self.trigger_items(time));
+        if rows.is_empty() {
+            return LineClear::default();
+        }
+        let spin = self.last_spin;
+        self.emit(FieldEvent::LinesCleared {
+            rows: rows.clone(),
+            spin,
+        });
+        LineClear {
+            kind: ClearKind::from_lines(rows.len()),
+            rows,
+            spin,
+            perfect_clear: self.field.is_perfect_clear(),
+        }
+    }
+
+    /// Marks the field as topped out, unless it already is.
+    fn set_top_out(&mut self, top_out: TopOut) {
+        if self.top_out.is_none() {
+            self.top_out = Some(top_out);
+            self.emit(FieldEvent::ToppedOut(top_out));
+        }
+    }
+
+    /// Returns how the field topped out, if it has.
+    pub fn top_out(&self) -> Option<TopOut> {
+        self.top_out
     }
 
     /// Returns true if the field has been topped out.
     pub fn is_top_out(&self) -> bool {
-        self.field.is_top_out()
+        self.top_out.is_some()
+    }
+
+    /// Returns the number of consecutive locked pieces that cleared lines, or 0 if the last piece
+    /// didn’t clear any.
+    pub fn combo(&self) -> usize {
+        self.combo
+    }
+
+    /// Returns the number of consecutive difficult line clears (tetrises and spins).
+    ///
+    /// The next difficult clear is back-to-back if this is greater than zero.
+    pub fn back_to_back(&self) -> usize {
+        self.back_to_back
     }
 
     /// Returns the active piece.
@@ -780,11 +2552,150 @@ impl ActiveField {
         self.active_piece.as_ref()
     }
 
+    /// Returns the rotation system.
+    pub fn rotation_system(&self) -> &RotationSystem {
+        &self.rotation_system
+    }
+
+    /// Sets the rotation system used when rotating the active piece.
+    pub fn set_rotation_system(&mut self, rotation_system: RotationSystem) {
+        self.rotation_system = rotation_system;
+    }
+
+    /// Returns true if pieces spawn as big pieces.
+    pub fn is_big_mode(&self) -> bool {
+        self.big_mode
+    }
+
+    /// Sets whether pieces spawn as big pieces, where each mino occupies 2×2 tiles.
+    ///
+    /// This effectively halves the field dimensions, so it can only be enabled if the field width
+    /// is even.
+    pub fn set_big_mode(&mut self, big_mode: bool) -> Result<(), &'static str> {
+        if big_mode && !self.field.width.is_multiple_of(2) {
+            return Err("big mode needs an even field width");
+        }
+        self.big_mode = big_mode;
+        Ok(())
+    }
+
+    /// Returns the clear mode of the field.
+    pub fn clear_mode(&self) -> ClearMode {
+        self.field.clear_mode()
+    }
+
+    /// Sets the clear mode of the field.
+    pub fn set_clear_mode(&mut self, clear_mode: ClearMode) {
+        self.field.set_clear_mode(clear_mode);
+    }
+
+    /// Returns the set of pieces that appear in the queue.
+    pub fn piece_set(&self) -> &PieceSet {
+        &self.piece_set
+    }
+
+    /// Sets the set of pieces that appear in the queue and refills the queue.
+    pub fn set_piece_set(&mut self, piece_set: PieceSet) {
+        self.piece_set = piece_set;
+        self.queue.clear();
+        self.bag_remaining.clear();
+        self.bag_history.clear();
+        self.update_queue();
+    }
+
+    /// Takes a snapshot of the current game state.
+    pub fn snapshot(&self) -> FieldSnapshot {
+        FieldSnapshot {
+            field: self.field.clone(),
+            queue: self.queue.clone(),
+            bag_remaining: self.bag_remaining.clone(),
+            bag_history: self.bag_history.clone(),
+            held_piece: self.held_piece,
+            active_piece: self.active_piece,
+            pending_garbage: self.pending_garbage.clone(),
+            seed: self.seed,
+            rng: self.rng,
+            last_spin: self.last_spin,
+            top_out: self.top_out,
+            combo: self.combo,
+            back_to_back: self.back_to_back,
+            in_zone: self.in_zone,
+        }
+    }
+
+    /// Restores the game state from a snapshot.
+    ///
+    /// This can also be used to resume a game in a different field, e.g. after a disconnect, since
+    /// the snapshot includes the piece RNG.
+    pub fn restore(&mut self, snapshot: FieldSnapshot) {
+        self.field = snapshot.field;
+        self.queue = snapshot.queue;
+        self.bag_remaining = snapshot.bag_remaining;
+        self.bag_history = snapshot.bag_history;
+        self.held_piece = snapshot.held_piece;
+        self.active_piece = snapshot.active_piece;
+        self.pending_garbage = snapshot.pending_garbage;
+        self.seed = snapshot.seed;
+        self.rng = snapshot.rng;
+        self.last_spin = snapshot.last_spin;
+        self.top_out = snapshot.top_out;
+        self.combo = snapshot.combo;
+        self.back_to_back = snapshot.back_to_back;
+        self.in_zone = snapshot.in_zone;
+        self.update_queue();
+    }
+
+    /// Returns the seed of the piece RNG.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the current state of the piece RNG.
+    pub fn rng(&self) -> Pcg32 {
+        self.rng
+    }
+
+    /// Sets the state of the piece RNG, which determines pieces after the current queue.
+    pub fn set_rng(&mut self, rng: Pcg32) {
+        self.rng = rng;
+    }
+
     /// Returns the queue.
     pub fn queue(&self) -> &VecDeque<PieceType> {
         &self.queue
     }
 
+    /// Returns the next n pieces in the queue.
+    ///
+    /// The queue always contains at least as many pieces as the preview depth.
+    pub fn peek(&self, n: usize) -> Vec<PieceType> {
+        self.peek_queue(n).collect()
+    }
+
+    /// Iterates over the next n pieces in the queue without allocating.
+    pub fn peek_queue(&self, n: usize) -> impl Iterator<Item = PieceType> + '_ {
+        self.queue.iter().take(n).copied()
+    }
+
+    /// Returns the pieces that have been dealt from the current bag, in order.
+    ///
+    /// Once a bag has been fully dealt, this is cleared when the first piece of the next bag is
+    /// dealt. Pieces spawned with a type override are not dealt from the bag.
+    pub fn bag_history(&self) -> &[PieceType] {
+        &self.bag_history
+    }
+
+    /// Returns the minimum number of pieces guaranteed to be in the queue.
+    pub fn preview_depth(&self) -> usize {
+        self.preview_depth
+    }
+
+    /// Sets the minimum number of pieces guaranteed to be in the queue, filling it if necessary.
+    pub fn set_preview_depth(&mut self, preview_depth: usize) {
+        self.preview_depth = preview_depth;
+        self.update_queue();
+    }
+
     /// Returns the currently held piece.
     pub fn held_piece(&self) -> Option<PieceType> {
         self.held_piece
@@ -794,6 +2705,11 @@ impl ActiveField {
     pub fn field(&self) -> &Field {
         &self.field
     }
+
+    /// Returns the field mutably, e.g. for setting up a board.
+    pub fn field_mut(&mut self) -> &mut Field {
+        &mut self.field
+    }
 }
 
 #[test]
@@ -837,21 +2753,14 @@ fn piece_type_rotations() {
 
         println!("Testing {:?} rotation {:?}", ty, r);
         println!("- 012345");
-        for y in 0..picture.len() {
+        for (y, row) in picture.iter().enumerate() {
             fn m(i: usize) -> char {
                 match i {
                     0 => ' ',
                     _ => 'X',
                 }
             }
-            println!(
-                "{} {}{}{}{}",
-                y,
-                m(picture[y][0]),
-                m(picture[y][1]),
-                m(picture[y][2]),
-                m(picture[y][3]),
-            );
+            println!("{} {}{}{}{}", y, m(row[0]), m(row[1]), m(row[2]), m(row[3]));
         }
 
         for (i, tile) in ty.iter_tiles_rotated(r).enumerate() {
@@ -876,3 +2785,740 @@ fn piece_type_rotations() {
     assert_rotated_matches(PieceType::I, Rotation::Flip, I_FLIP, I_OFF_X, I_OFF_Y);
     assert_rotated_matches(PieceType::I, Rotation::CCW, I_CCW, I_OFF_X, I_OFF_Y);
 }
+
+#[test]
+fn t_spin_detection() {
+    // a T-spin double slot with an overhang on the left
+    let mut field = ActiveField::new();
+    for x in 0..10 {
+        if x != 4 {
+            field.field.set_tile(x, 0, Tile::Piece(PieceType::I));
+        }
+        if !(3..=5).contains(&x) {
+            field.field.set_tile(x, 1, Tile::Piece(PieceType::I));
+        }
+    }
+    field.field.set_tile(3, 2, Tile::Piece(PieceType::I));
+
+    let mut piece = ActivePiece::new(PieceType::T, 0.);
+    piece.pos = (4, 1).into();
+    piece.rotation = Rotation::CW;
+    field.active_piece = Some(piece);
+
+    let mut without_overhang = field.clone();
+    without_overhang.field.set_tile(3, 2, Tile::Empty);
+
+    field.rotate_active_cw(0.);
+    let piece = field.active_piece().unwrap();
+    assert_eq!(piece.rotation(), Rotation::Flip);
+    assert_eq!(piece.kick_index(), Some(0));
+    assert!(piece.is_immobile());
+    assert_eq!(field.lock_active().unwrap().spin, SpinType::Full);
+
+    // only two corners are occupied
+    without_overhang.rotate_active_cw(0.);
+    assert!(!without_overhang.active_piece().unwrap().is_immobile());
+    assert_eq!(without_overhang.lock_active().unwrap().spin, SpinType::None);
+
+    // minis are only upgraded by the last kick of the SRS kick table
+    let mut mini = Field::new();
+    for &(x, y) in &[(3, 0), (5, 0), (3, 2)] {
+        mini.set_tile(x, y, Tile::Garbage);
+    }
+    let mut piece = ActivePiece::new(PieceType::T, 0.);
+    piece.pos = (4, 1).into();
+    piece.last_action = PieceAction::Rotate;
+    assert_eq!(piece.spin_type(&mini, &RotationSystem::Srs), SpinType::Mini);
+    piece.kick_index = Some(ActivePiece::LAST_SRS_KICK);
+    assert_eq!(piece.spin_type(&mini, &RotationSystem::Srs), SpinType::Full);
+    assert_eq!(
+        piece.spin_type(&mini, &RotationSystem::Nintendo),
+        SpinType::Mini
+    );
+}
+
+#[test]
+fn garbage_queue() {
+    let mut field = ActiveField::new();
+    field.queue_garbage(2, 3);
+    field.queue_garbage(3, 5);
+    assert_eq!(field.pending_garbage_lines(), 5);
+    assert_eq!(field.cancel_garbage(3), 0);
+    assert_eq!(field.pending_garbage_lines(), 2);
+
+    field.spawn_active(Some(PieceType::O), 0.);
+    field.sonic_drop_active(0.);
+    let result = field.lock_active().unwrap();
+    assert_eq!(result.received_garbage, 2);
+    assert_eq!(field.pending_garbage_lines(), 0);
+    for y in 0..2 {
+        assert_eq!(field.field().get_tile(5, y), Some(Tile::Empty));
+        assert_eq!(field.field().get_tile(0, y), Some(Tile::Garbage));
+    }
+    // the locked piece was pushed up
+    assert!(field.field().get_tile(5, 2).unwrap().is_clearable());
+}
+
+#[test]
+fn seeded_queue() {
+    let mut a = ActiveField::with_seed(42);
+    let mut b = ActiveField::with_seed(42);
+    for _ in 0..20 {
+        a.spawn_active(None, 0.);
+        b.spawn_active(None, 0.);
+        assert_eq!(
+            a.active_piece().unwrap().piece_type(),
+            b.active_piece().unwrap().piece_type()
+        );
+    }
+}
+
+#[test]
+fn lock_reset_limit() {
+    let mut field = ActiveField::with_seed(0);
+    field.spawn_active(Some(PieceType::T), 0.);
+    field.sonic_drop_active(0.);
+    assert!(!field.should_lock_active(0.5, 0.));
+
+    // stall by moving back and forth on the ground
+    let mut time = 0.;
+    for i in 0..ActiveField::LOCK_RESET_LIMIT {
+        time += 0.1;
+        assert!(!field.should_lock_active(0.5, time));
+        if i % 2 == 0 {
+            field.move_active_left(time);
+        } else {
+            field.move_active_right(time);
+        }
+    }
+    assert_eq!(field.lock_resets_left(), Some(0));
+    assert!(field.should_lock_active(0.5, time));
+
+    field.set_lock_reset_limit(None);
+    assert!(!field.should_lock_active(0.5, time));
+}
+
+#[test]
+fn lock_progress() {
+    let mut field = ActiveField::with_seed(0);
+    field.spawn_active(Some(PieceType::T), 0.);
+    assert_eq!(field.lock_progress(0.5, 0.), None);
+
+    field.sonic_drop_active(1.);
+    assert_eq!(field.lock_progress(0.5, 1.), Some(0.));
+    assert_eq!(field.lock_progress(0.5, 1.25), Some(0.5));
+    assert_eq!(field.lock_progress(0.5, 2.), Some(1.));
+
+    field.move_active_left(1.5);
+    assert_eq!(field.lock_progress(0.5, 1.5), Some(0.));
+}
+
+#[test]
+fn preview_depth() {
+    let mut field = ActiveField::with_seed(3);
+    field.set_preview_depth(12);
+    for _ in 0..30 {
+        assert!(field.queue().len() >= 12);
+        let next = field.peek(12);
+        assert_eq!(next.len(), 12);
+        field.spawn_active(None, 0.);
+        assert_eq!(field.active_piece().unwrap().piece_type(), next[0]);
+        assert_eq!(field.peek(11), &next[1..]);
+    }
+}
+
+#[test]
+fn active_field_serde() {
+    let mut field = ActiveField::with_seed(9);
+    field.spawn_active(None, 0.);
+    field.sonic_drop_active(0.);
+    field.lock_active();
+    field.spawn_active(None, 1.);
+    field.swap_held_piece(1.);
+    field.queue_garbage(1, 0);
+
+    let json = serde_json::to_string(&field).unwrap();
+    let mut restored: ActiveField = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.field().tiles(), field.field().tiles());
+    assert_eq!(restored.queue(), field.queue());
+    assert_eq!(restored.held_piece(), field.held_piece());
+    assert_eq!(restored.pending_garbage(), field.pending_garbage());
+
+    // the RNG state is restored as well
+    for _ in 0..20 {
+        field.spawn_active(None, 2.);
+        restored.spawn_active(None, 2.);
+        assert_eq!(
+            restored.active_piece().unwrap().piece_type(),
+            field.active_piece().unwrap().piece_type()
+        );
+    }
+}
+
+#[test]
+fn field_events() {
+    let mut field = ActiveField::with_seed(5);
+    field.spawn_active(Some(PieceType::I), 0.);
+    field.move_active_left(0.);
+    field.rotate_active_cw(0.);
+    field.sonic_drop_active(0.);
+    field.lock_active();
+
+    let events: Vec<_> = field.drain_events().collect();
+    assert_eq!(
+        events[0],
+        FieldEvent::Spawned {
+            piece_type: PieceType::I
+        }
+    );
+    assert_eq!(events[1], FieldEvent::Moved { dx: -1, dy: 0 });
+    assert_eq!(
+        events[2],
+        FieldEvent::Rotated {
+            rotation: Rotation::CW,
+            kick_index: 0
+        }
+    );
+    match events[3] {
+        FieldEvent::Moved { dx: 0, dy } => assert!(dy < 0),
+        ref e => panic!("unexpected event {:?}", e),
+    }
+    match events[4] {
+        FieldEvent::PieceLocked(result) => assert_eq!(result.piece_type, PieceType::I),
+        ref e => panic!("unexpected event {:?}", e),
+    }
+    assert_eq!(field.drain_events().count(), 0);
+}
+
+#[test]
+fn bitboard_consistency() {
+    let mut field = Field::new();
+    for x in 0..field.width() {
+        field.set_tile(x, 0, Tile::Garbage);
+    }
+    field.set_tile(3, 1, Tile::Piece(PieceType::T));
+    field.insert_garbage(2, 4);
+    assert_eq!(field.row_bits(0), Some(0b11_1110_1111));
+    assert_eq!(field.row_bits(3), Some(0b1000));
+    assert_eq!(field.clear_lines(0.), vec![2]);
+    field.clean_lines(0., 1.);
+
+    for y in 0..field.tiles().len() / field.width() {
+        let row = &field.tiles()[y * field.width()..(y + 1) * field.width()];
+        assert_eq!(field.row_bits(y), Some(Field::row_mask(row)), "row {}", y);
+    }
+    assert_eq!(field.row_bits(2), Some(0b1000));
+
+    let json = serde_json::to_string(&field).unwrap();
+    let restored: Field = serde_json::from_str(&json).unwrap();
+    assert_eq!(restored.rows, field.rows);
+
+    // out of bounds holes are moved to the last column
+    let mut field = Field::new();
+    field.insert_garbage(1, 50);
+    assert_eq!(field.row_bits(0), Some(0b01_1111_1111));
+}
+
+#[test]
+fn custom_piece_set() {
+    use crate::piece_set::RotationCenter;
+
+    let mut set = PieceSet::new();
+    let plus = PieceShape::new(
+        &[(0, 1), (-1, 0), (0, 0), (1, 0), (0, -1)],
+        RotationCenter::Origin,
+    )
+    .unwrap();
+    let ty = set.register(plus, (0, 0), [1.; 4], None).unwrap();
+
+    let mut field = ActiveField::with_seed(1);
+    field.set_piece_set(set);
+    assert!(field.queue().iter().all(|piece| *piece == ty));
+
+    field.spawn_active(None, 0.);
+    assert_eq!(field.active_piece().unwrap().iter_tiles().count(), 5);
+    field.sonic_drop_active(0.);
+    field.lock_active();
+    let tiles = field.field().tiles();
+    let count = tiles.iter().filter(|t| **t == Tile::Piece(ty)).count();
+    assert_eq!(count, 5);
+
+    let mut s = String::new();
+    Tile::Piece(ty).stringify(&mut s);
+    assert_eq!(Tile::parse_from_str(&s), Some((Tile::Piece(ty), s.len())));
+}
+
+#[test]
+fn top_out_rules() {
+    let fill = |field: &mut ActiveField, rows: usize| {
+        let mut f = Field::new();
+        for y in 0..rows {
+            for x in 1..f.width() {
+                f.set_tile(x, y, Tile::Garbage);
+            }
+        }
+        field.field = f;
+    };
+
+    // the stack reaches just below the skyline, so the piece can't move down after spawning
+    let mut field = ActiveField::with_seed(1);
+    fill(&mut field, Field::TOP_HEIGHT);
+    field.spawn_active(Some(PieceType::O), 0.);
+    assert_eq!(field.top_out(), None);
+    field.lock_active();
+    assert_eq!(field.top_out(), Some(TopOut::LockOut));
+    assert!(field
+        .drain_events()
+        .any(|e| e == FieldEvent::ToppedOut(TopOut::LockOut)));
+
+    let mut field = ActiveField::with_seed(1);
+    fill(&mut field, Field::TOP_HEIGHT + 2);
+    field.spawn_active(Some(PieceType::O), 0.);
+    assert_eq!(field.top_out(), Some(TopOut::BlockOut));
+
+    let mut field = ActiveField::with_seed(1);
+    fill(&mut field, Field::HEIGHT - 2);
+    field.queue_garbage(4, 0);
+    let mut piece = ActivePiece::new(PieceType::O, 0.);
+    piece.pos = Point2::new(1, 0);
+    field.active_piece = Some(piece);
+    field.lock_active();
+    assert_eq!(field.top_out(), Some(TopOut::Overflow));
+}
+
+#[test]
+fn initial_rotation_and_hold() {
+    let mut field = ActiveField::with_seed(3);
+    let first = field.queue()[0];
+    let second = field.queue()[1];
+
+    let input = SpawnInput {
+        rotation: 1,
+        hold: true,
+    };
+    field.spawn_active_with(None, input, 0.);
+    let piece = field.active_piece().unwrap();
+    assert_eq!(piece.piece_type(), second);
+    assert_eq!(piece.rotation(), Rotation::CW);
+    assert_eq!(field.held_piece(), Some(first));
+
+    // can't hold again after an initial hold
+    assert_eq!(field.swap_held_piece(0.), HoldResult::AlreadyHeld);
+    assert_eq!(field.active_piece().unwrap().piece_type(), second);
+}
+
+#[test]
+fn snapshot_restore() {
+    let mut field = ActiveField::with_seed(9);
+    field.spawn_active(None, 0.);
+    let snapshot = field.snapshot();
+    let queue = field.queue().clone();
+
+    for _ in 0..20 {
+        field.sonic_drop_active(0.);
+        field.lock_active();
+        field.spawn_active(None, 0.);
+    }
+    let later_queue = field.queue().clone();
+    let later_tiles = field.field().tiles().clone();
+
+    field.restore(snapshot.clone());
+    assert_eq!(field.queue(), &queue);
+    assert!(field.field().tiles().iter().all(|t| *t == Tile::Empty));
+
+    // the same moves produce the same result after restoring
+    for _ in 0..20 {
+        field.sonic_drop_active(0.);
+        field.lock_active();
+        field.spawn_active(None, 0.);
+    }
+    assert_eq!(field.queue(), &later_queue);
+    assert_eq!(field.field().tiles(), &later_tiles);
+
+    // resuming from a serialized snapshot in another field continues the same piece sequence
+    let json = serde_json::to_string(&field.snapshot()).unwrap();
+    let mut resumed = ActiveField::with_seed(1);
+    resumed.restore(serde_json::from_str(&json).unwrap());
+    assert_eq!(resumed.seed(), 9);
+    assert_eq!(resumed.rng(), field.rng());
+    for _ in 0..20 {
+        field.spawn_active(None, 0.);
+        resumed.spawn_active(None, 0.);
+        assert_eq!(
+            resumed.active_piece().unwrap().piece_type(),
+            field.active_piece().unwrap().piece_type()
+        );
+        field.lock_active();
+        resumed.lock_active();
+    }
+}
+
+#[test]
+fn combo_counter() {
+    let mut field = ActiveField::with_seed(1);
+    // four rows with a gap in column 0
+    for y in 0..4 {
+        for x in 1..field.field().width() {
+            field.field.set_tile(x, y, Tile::Garbage);
+        }
+    }
+    field.spawn_active(Some(PieceType::I), 0.);
+    let piece = field.active_piece.as_mut().unwrap();
+    piece.rotation = Rotation::CW;
+    piece.pos = Point2::new(-1, 2);
+    let result = field.lock_active().unwrap();
+    assert_eq!((result.lines_cleared, result.combo), (4, 1));
+    assert!(result.perfect_clear);
+    let clear = field.clear_lines(-1., 0.);
+    assert_eq!(clear.rows, vec![0, 1, 2, 3]);
+    assert_eq!(clear.kind, ClearKind::Tetris);
+    assert_eq!(clear.spin, SpinType::None);
+    assert!(clear.perfect_clear);
+    assert_eq!(field.combo(), 1);
+
+    field.spawn_active(Some(PieceType::O), 0.);
+    field.sonic_drop_active(0.);
+    let result = field.lock_active().unwrap();
+    assert_eq!((result.lines_cleared, result.combo), (0, 0));
+    assert_eq!(field.combo(), 0);
+}
+
+#[test]
+fn back_to_back_chain() {
+    let mut field = ActiveField::with_seed(1);
+    let tetris = |field: &mut ActiveField| {
+        for y in 0..4 {
+            for x in 1..field.field().width() {
+                field.field.set_tile(x, y, Tile::Garbage);
+            }
+        }
+        field.spawn_active(Some(PieceType::I), 0.);
+        let piece = field.active_piece.as_mut().unwrap();
+        piece.rotation = Rotation::CW;
+        piece.pos = Point2::new(-1, 2);
+        let result = field.lock_active().unwrap();
+        field.clear_lines(-1., 0.);
+        result
+    };
+
+    let result = tetris(&mut field);
+    assert!(result.is_difficult() && !result.back_to_back);
+    // locks without clears don’t break the chain
+    field.spawn_active(Some(PieceType::O), 0.);
+    field.sonic_drop_active(0.);
+    field.lock_active();
+    assert_eq!(field.back_to_back(), 1);
+    let result = tetris(&mut field);
+    assert!(result.back_to_back);
+    assert_eq!(field.back_to_back(), 2);
+
+    // a single breaks it
+    for x in 2..field.field().width() {
+        field.field.set_tile(x, 0, Tile::Garbage);
+    }
+    field.spawn_active(Some(PieceType::O), 0.);
+    field.active_piece.as_mut().unwrap().pos.x = 0;
+    field.sonic_drop_active(0.);
+    let result = field.lock_active().unwrap();
+    assert!(!result.is_difficult() && !result.back_to_back);
+    assert_eq!(field.back_to_back(), 0);
+}
+
+#[test]
+fn big_mode() {
+    let mut odd = ActiveField::with_seed(1);
+    odd.field = Field::with_size(9, 40, 20).unwrap();
+    assert!(odd.set_big_mode(true).is_err());
+    assert!(!odd.is_big_mode());
+
+    let mut field = ActiveField::with_seed(1);
+    field.set_big_mode(true).unwrap();
+    for y in 0..2 {
+        for x in 4..field.field().width() {
+            field.field.set_tile(x, y, Tile::Garbage);
+        }
+    }
+
+    field.spawn_active(Some(PieceType::O), 0.);
+    let piece = *field.active_piece().unwrap();
+    assert!(piece.is_big());
+    assert_eq!(piece.iter_tiles().count(), 16);
+    assert_eq!(piece.pos().x % 2, 0);
+    field.move_active_left(0.);
+    assert_eq!(field.active_piece().unwrap().pos().x, piece.pos().x - 2);
+
+    for _ in 0..5 {
+        field.move_active_left(0.);
+    }
+    assert_eq!(field.active_piece().unwrap().pos().x, 0);
+    field.sonic_drop_active(0.);
+    let result = field.lock_active().unwrap();
+    assert_eq!(result.lines_cleared, 2);
+
+    // garbage is inserted in pairs of rows with a wide hole
+    field.clear_lines(-1., 0.);
+    field.queue_garbage(1, 3);
+    field.spawn_active(Some(PieceType::O), 0.);
+    field.sonic_drop_active(0.);
+    field.lock_active();
+    let field = field.field();
+    for y in 0..2 {
+        assert_eq!(field.row_bits(y), Some(0b11_1111_0011));
+    }
+}
+
+#[test]
+fn bag_history() {
+    let mut field = ActiveField::with_seed(5);
+    let upcoming = field.peek(7);
+    assert_eq!(field.peek_queue(3).collect::<Vec<_>>(), upcoming[..3]);
+
+    for i in 0..7 {
+        field.spawn_active(None, 0.);
+        assert_eq!(field.bag_history(), &upcoming[..i + 1]);
+    }
+    let mut dealt = field.bag_history().to_vec();
+    dealt.sort_by_key(|piece| piece.index());
+    assert_eq!(dealt, PieceType::all());
+
+    // the next piece starts a new bag
+    let next = field.peek(1);
+    field.spawn_active(None, 0.);
+    assert_eq!(field.bag_history(), &next[..]);
+    field.spawn_active(Some(PieceType::I), 0.);
+    assert_eq!(field.bag_history().len(), 1);
+}
+
+#[test]
+fn hold_config() {
+    let mut field = ActiveField::with_seed(2);
+    assert_eq!(field.swap_held_piece(0.), HoldResult::NoActivePiece);
+
+    field.set_hold_config(HoldConfig {
+        empty_hold: EmptyHold::Redeal,
+        ..HoldConfig::default()
+    });
+    field.spawn_active(Some(PieceType::T), 0.);
+    let spawn_pos = field.active_piece().unwrap().pos();
+    field.move_active_left(0.);
+    assert_eq!(field.swap_held_piece(0.), HoldResult::Redealt);
+    assert_eq!(field.active_piece().unwrap().pos(), spawn_pos);
+    assert_eq!(field.held_piece(), None);
+    assert!(field.active_piece().unwrap().was_held_piece());
+    assert_eq!(field.swap_held_piece(0.), HoldResult::AlreadyHeld);
+
+    field.set_hold_config(HoldConfig {
+        reset_rotation: false,
+        preserve_position: true,
+        ..HoldConfig::default()
+    });
+    field.spawn_active(Some(PieceType::T), 0.);
+    field.move_active_left(0.);
+    field.move_active_down(0.);
+    field.rotate_active_cw(0.);
+    let prev = *field.active_piece().unwrap();
+    assert_eq!(field.swap_held_piece(0.), HoldResult::TookFromQueue);
+    let piece = field.active_piece().unwrap();
+    assert_eq!(field.held_piece(), Some(PieceType::T));
+    assert_eq!(piece.pos(), prev.pos());
+    assert_eq!(piece.rotation(), Rotation::CW);
+    assert!(piece.was_held_piece());
+
+    field.spawn_active(Some(PieceType::I), 0.);
+    assert_eq!(field.swap_held_piece(0.), HoldResult::Swapped);
+    assert_eq!(field.active_piece().unwrap().piece_type(), PieceType::T);
+}
+
+#[test]
+fn hard_drop() {
+    let mut field = ActiveField::with_seed(1);
+    assert_eq!(field.hard_drop_active(0.), 0);
+    field.spawn_active(Some(PieceType::O), 0.);
+    let height = field.active_piece().unwrap().pos().y as usize;
+    assert_eq!(field.hard_drop_active(0.), height);
+    assert!(field.active_piece().is_none());
+    assert_eq!(field.field().row_bits(0).map(u16::count_ones), Some(2));
+}
+
+#[test]
+fn move_results() {
+    let mut field = ActiveField::with_seed(1);
+    assert_eq!(field.move_active_left(0.), MoveResult::NoActivePiece);
+    assert_eq!(
+        field.spawn_active(Some(PieceType::T), 0.),
+        SpawnResult::Spawned
+    );
+    assert_eq!(field.rotate_active_cw(0.), MoveResult::KickedWith(0));
+
+    let mut result = MoveResult::Moved;
+    for _ in 0..field.field().width() {
+        result = field.move_active_left(0.);
+    }
+    assert_eq!(result, MoveResult::Blocked);
+    assert!(!result.is_success());
+    assert_eq!(field.active_piece().unwrap().kick_index(), None);
+
+    let (x, y) = (4, Field::TOP_HEIGHT);
+    field.field.set_tile(x, y, Tile::Garbage);
+    assert_eq!(
+        field.spawn_active(Some(PieceType::T), 0.),
+        SpawnResult::Blocked
+    );
+}
+
+#[test]
+fn tile_links() {
+    let mut field = Field::new();
+    let piece = ActivePiece::new(PieceType::T, 0.);
+    field.project(&piece, (4, 1).into(), Tile::Piece(PieceType::T));
+    let center = TileLinks::LEFT | TileLinks::RIGHT | TileLinks::UP;
+    assert_eq!(field.tile_links(4, 1), center);
+    assert_eq!(field.tile_links(3, 1), TileLinks::RIGHT);
+    assert_eq!(field.tile_links(4, 2), TileLinks::DOWN);
+
+    // links move along with the tiles
+    field.insert_garbage(1, 0);
+    assert_eq!(field.tile_links(4, 2), center);
+    assert!(field.tile_links(4, 0).is_empty());
+
+    // replacing a tile unlinks its neighbors
+    field.set_tile(4, 2, Tile::Garbage);
+    for (x, y) in [(3, 2), (5, 2), (4, 3)] {
+        assert!(field.tile_links(x, y).is_empty());
+    }
+}
+
+#[test]
+fn zone_collects_lines() {
+    let mut field = ActiveField::with_seed(1);
+    field.start_zone();
+    assert!(field.is_in_zone());
+    for y in [0, 2] {
+        for x in 0..10 {
+            field.field_mut().set_tile(x, y, Tile::Garbage);
+        }
+    }
+    field.field_mut().set_tile(3, 1, Tile::Garbage);
+
+    // full rows sink to the bottom instead of clearing
+    let clear = field.clear_lines(0.5, 0.);
+    assert!(clear.rows.is_empty());
+    assert_eq!(field.field().zone_rows(), 2);
+    assert_eq!(field.field().get_tile(3, 2), Some(Tile::Garbage));
+    assert_eq!(field.field().get_tile(4, 2), Some(Tile::Empty));
+
+    // garbage goes in above the collected rows
+    field.field_mut().insert_garbage(1, 0);
+    assert_eq!(field.field().get_tile(0, 2), Some(Tile::Empty));
+    assert_eq!(field.field().get_tile(1, 2), Some(Tile::Garbage));
+
+    let clear = field.end_zone(1.);
+    assert_eq!(clear.rows, vec![0, 1]);
+    assert!(!field.is_in_zone());
+    assert_eq!(field.field().zone_rows(), 0);
+}
+
+#[test]
+fn item_tiles() {
+    let mut field = ActiveField::with_seed(1);
+    for x in 0..10 {
+        field.field_mut().set_tile(x, 0, Tile::Garbage);
+        field.field_mut().set_tile(x, 2, Tile::Garbage);
+    }
+    field.field_mut().set_tile(4, 1, Tile::Garbage);
+    field
+        .field_mut()
+        .set_tile(7, 2, Tile::Item(Item::ClearBottomRow));
+    field.field_mut().set_tile(0, 0, Tile::Item(Item::Scramble));
+    field.field_mut().set_tile(9, 0, Tile::Empty);
+    field.drain_events().count();
+
+    // clearing the upper row clears the bottom row, which triggers the next item
+    let clear = field.clear_lines(0.5, 0.);
+    assert_eq!(clear.rows, vec![2, 0]);
+    let items: Vec<_> = field
+        .drain_events()
+        .filter_map(|event| match event {
+            FieldEvent::ItemTriggered { item, x, y } => Some((item, x, y)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        items,
+        vec![(Item::ClearBottomRow, 7, 2), (Item::Scramble, 0, 0)]
+    );
+
+    let mut s = String::new();
+    Tile::Item(Item::Custom(5)).stringify(&mut s);
+    assert_eq!(
+        Tile::parse_from_str(&s),
+        Some((Tile::Item(Item::Custom(5)), s.len()))
+    );
+}
+
+#[test]
+fn custom_item_range() {
+    assert_eq!(Item::custom(253), Some(Item::Custom(253)));
+    assert_eq!(Item::custom(254), None);
+    assert_eq!(Item::from_index(255), Some(Item::Custom(253)));
+
+    let json = serde_json::to_string(&Item::Custom(253)).unwrap();
+    assert_eq!(
+        serde_json::from_str::<Item>(&json).unwrap(),
+        Item::Custom(253)
+    );
+    let json = json.replace("253", "254");
+    assert!(serde_json::from_str::<Item>(&json).is_err());
+}
+
+#[test]
+fn tile_lists() {
+    let mut field = Field::new();
+    field.set_tile(0, 0, Tile::Garbage);
+    field.set_tile(1, 0, Tile::Piece(PieceType::T));
+    field.set_tile(2, 0, Tile::Item(Item::Scramble));
+    field.set_tile(3, 0, Tile::Clear(1.5));
+
+    let mut s = String::new();
+    for tile in field.tiles() {
+        tile.stringify(&mut s);
+    }
+    let tiles = Tile::parse_list(&s).unwrap();
+    assert_eq!(&tiles, field.tiles());
+    assert!(Tile::parse_list("G?").is_none());
+
+    let copy = Field::from_tile_list(field.width(), tiles).unwrap();
+    assert_eq!(copy.tiles(), field.tiles());
+    assert_eq!(copy.row_bits(0), field.row_bits(0));
+    assert!(Field::from_tile_list(3, vec![Tile::Empty; 4]).is_none());
+}
+
+#[test]
+fn clear_timeout_by_lines() {
+    let mut field = Field::new();
+    for x in 0..10 {
+        for y in 0..3 {
+            field.set_tile(x, y, Tile::Garbage);
+        }
+    }
+    field.set_tile(0, 2, Tile::Empty);
+    field.clear_lines(0.);
+    for x in 1..10 {
+        field.set_tile(x, 4, Tile::Garbage);
+    }
+    field.set_tile(0, 2, Tile::Garbage);
+    field.clear_lines(0.1);
+
+    // the double takes longer than the single
+    let timeout = ClearTimeout::by_lines(0.2, 0.5, 0.5, 1.);
+    field.clean_lines(timeout, 0.35);
+    assert_eq!(field.clear_rows(), 2);
+    field.clean_lines(timeout, 0.6);
+    assert_eq!(field.clear_rows(), 0);
+
+    let json = serde_json::to_string(&ClearTimeout::uniform(0.5)).unwrap();
+    assert_eq!(json, "0.5");
+    let parsed: ClearTimeout = serde_json::from_str("[0.2,0.5,0.5,1.0]").unwrap();
+    assert_eq!(parsed, timeout);
+    assert_eq!(parsed.for_lines(6), 1.);
+}
@@ -0,0 +1,175 @@
+//! Board analysis helpers for bots and training tools.
+//!
+//! All metrics ignore cleared rows that have not been removed from the data yet.
+
+use super::{Field, Tile};
+
+/// Aggregate metrics describing the stack of a field.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SurfaceMetrics {
+    /// Sum of all column heights.
+    pub aggregate_height: usize,
+    /// Height of the tallest column.
+    pub max_height: usize,
+    /// Number of empty tiles that have a non-empty tile somewhere above them.
+    pub holes: usize,
+    /// Sum of height differences between adjacent columns.
+    pub bumpiness: usize,
+    /// Sum of 1 + 2 + … + depth over all wells.
+    pub cumulative_wells: usize,
+    /// Number of horizontal changes between empty and non-empty tiles, counting walls as
+    /// non-empty.
+    pub row_transitions: usize,
+    /// Number of vertical changes between empty and non-empty tiles, counting the floor as
+    /// non-empty.
+    pub column_transitions: usize,
+}
+
+impl Field {
+    /// Returns the occupancy bits of all rows that have not been cleared, from the bottom up.
+    pub fn live_rows(&self) -> Vec<u16> {
+        self.rows
+            .iter()
+            .enumerate()
+            .filter(|(y, _)| !matches!(self.get_tile(0, *y), Some(Tile::Clear(_))))
+            .map(|(_, row)| *row)
+            .collect()
+    }
+
+    /// Returns the height of each column, i.e. the number of rows up to and including its
+    /// highest non-empty tile.
+    pub fn column_heights(&self) -> Vec<usize> {
+        Self::heights(&self.live_rows(), self.width)
+    }
+
+    fn heights(rows: &[u16], width: usize) -> Vec<usize> {
+        (0..width)
+            .map(|x| {
+                rows.iter()
+                    .rposition(|row| row & 1 << x != 0)
+                    .map_or(0, |y| y + 1)
+            })
+            .collect()
+    }
+
+    /// Returns the number of empty tiles that are covered by a non-empty tile.
+    pub fn hole_count(&self) -> usize {
+        Self::holes(&self.live_rows())
+    }
+
+    fn holes(rows: &[u16]) -> usize {
+        let mut covered = 0u16;
+        let mut holes = 0;
+        for row in rows.iter().rev() {
+            holes += (covered & !row).count_ones() as usize;
+            covered |= row;
+        }
+        holes
+    }
+
+    /// Returns the sum of height differences between adjacent columns.
+    pub fn bumpiness(&self) -> usize {
+        Self::bumps(&self.column_heights())
+    }
+
+    fn bumps(heights: &[usize]) -> usize {
+        heights
+            .windows(2)
+            .map(|pair| (pair[0] as isize - pair[1] as isize).unsigned_abs())
+            .sum()
+    }
+
+    /// Returns the well depth of each column, i.e. how far it is below the lower of its two
+    /// neighbors (walls count as reaching the top of the field).
+    pub fn well_depths(&self) -> Vec<usize> {
+        Self::wells(&self.column_heights(), self.height)
+    }
+
+    fn wells(heights: &[usize], wall: usize) -> Vec<usize> {
+        (0..heights.len())
+            .map(|x| {
+                let left = x.checked_sub(1).map_or(wall, |x| heights[x]);
+                let right = heights.get(x + 1).copied().unwrap_or(wall);
+                left.min(right).saturating_sub(heights[x])
+            })
+            .collect()
+    }
+
+    /// Computes all surface metrics at once.
+    pub fn surface_metrics(&self) -> SurfaceMetrics {
+        let rows = self.live_rows();
+        let heights = Self::heights(&rows, self.width);
+        let max_height = heights.iter().copied().max().unwrap_or(0);
+        let full = self.full_row();
+
+        let row_transitions = rows[..max_height]
+            .iter()
+            .map(|row| {
+                // add walls on both sides and count bit changes
+                let walled = (u32::from(*row) << 1) | 1 | (1 << (self.width + 1));
+                ((walled ^ (walled >> 1)) & ((1 << (self.width + 1)) - 1)).count_ones() as usize
+            })
+            .sum();
+
+        let mut column_transitions = 0;
+        let mut below = full;
+        for row in &rows[..max_height] {
+            column_transitions += (below ^ row).count_ones() as usize;
+            below = *row;
+        }
+        // the space above the stack is empty
+        column_transitions += below.count_ones() as usize;
+
+        SurfaceMetrics {
+            aggregate_height: heights.iter().sum(),
+            max_height,
+            holes: Self::holes(&rows),
+            bumpiness: Self::bumps(&heights),
+            cumulative_wells: Self::wells(&heights, self.height)
+                .iter()
+                .map(|depth| depth * (depth + 1) / 2)
+                .sum(),
+            row_transitions,
+            column_transitions,
+        }
+    }
+}
+
+#[test]
+fn surface_analysis() {
+    let mut field = Field::new();
+    // column heights 2 1 0 3 0 0 0 0 0 1, with a hole at (3, 1)
+    for &(x, y) in &[(0, 0), (0, 1), (1, 0), (3, 0), (3, 2), (9, 0)] {
+        field.set_tile(x, y, Tile::Garbage);
+    }
+
+    assert_eq!(field.column_heights(), vec![2, 1, 0, 3, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(field.hole_count(), 1);
+    assert_eq!(field.bumpiness(), 1 + 1 + 3 + 3 + 1);
+    assert_eq!(field.well_depths(), vec![0, 0, 1, 0, 0, 0, 0, 0, 0, 0]);
+
+    let metrics = field.surface_metrics();
+    assert_eq!(metrics.aggregate_height, 7);
+    assert_eq!(metrics.max_height, 3);
+    assert_eq!(metrics.cumulative_wells, 1);
+    // row 0: |X X _ X _ _ _ _ _ X| -> 4 changes, row 1: X _ ... -> 2, row 2: _ _ _ X _ ... -> 4
+    assert_eq!(metrics.row_transitions, 4 + 2 + 4);
+    // columns 2, 4–8 start empty on the floor, column 3 has a hole, columns 0, 1, 9 end below
+    // the stack
+    assert_eq!(metrics.column_transitions, 6 + 3 + 3);
+
+    // cleared rows are ignored
+    let mut cleared = Field::new();
+    for x in 0..cleared.width() {
+        cleared.set_tile(x, 0, Tile::Garbage);
+    }
+    cleared.set_tile(4, 1, Tile::Garbage);
+    cleared.clear_lines(0.);
+    assert_eq!(cleared.column_heights()[4], 1);
+    assert_eq!(cleared.hole_count(), 0);
+
+    // a single column is a well as deep as the field
+    let narrow = Field::with_size(1, 20, 4).unwrap();
+    assert_eq!(narrow.well_depths(), vec![20]);
+    assert_eq!(narrow.surface_metrics().cumulative_wells, 20 * 21 / 2);
+}
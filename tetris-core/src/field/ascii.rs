@@ -0,0 +1,150 @@
+//! Plain text representation of fields, mainly for tests and puzzle setups.
+//!
+//! Fields are written as one line per row from the top down, with one character per tile:
+//!
+//! - `.`: empty (a space or `_` is also accepted when parsing)
+//! - `I`, `J`, `L`, `O`, `S`, `T`, `Z`: a piece tile
+//! - `C`: a custom piece tile (the piece id is not preserved and parses as 0)
+//! - `G`: garbage (`X` or `#` is also accepted when parsing)
+//! - `-`: a cleared tile (the clear time is not preserved and parses as 0)
+//! - `*`: an item tile (the item is not preserved and parses as custom item 0)
+//!
+//! Only rows up to the skyline or the highest non-empty row are written. When parsing, the field
+//! is padded with empty rows up to the default height.
+
+use super::{Field, Item, PieceType, Tile};
+use core::fmt;
+use core::str::FromStr;
+
+fn tile_char(tile: Tile) -> char {
+    match tile {
+        Tile::Empty => '.',
+        Tile::Piece(PieceType::Custom(_)) => 'C',
+        Tile::Piece(piece) => {
+            let mut s = String::new();
+            piece.stringify(&mut s);
+            s.chars().next().unwrap_or('C')
+        }
+        Tile::Garbage => 'G',
+        Tile::Clear(_) => '-',
+        Tile::Item(_) => '*',
+    }
+}
+
+fn parse_tile(c: char) -> Option<Tile> {
+    match c {
+        '.' | ' ' | '_' => Some(Tile::Empty),
+        'C' => Some(Tile::Piece(PieceType::Custom(0))),
+        'G' | 'X' | '#' => Some(Tile::Garbage),
+        '-' => Some(Tile::Clear(0.)),
+        '*' => Some(Tile::Item(Item::Custom(0))),
+        c => c.to_string().parse().ok().map(Tile::Piece),
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let data_rows = self.tiles.len() / self.width;
+        let highest = self
+            .rows
+            .iter()
+            .rposition(|row| *row != 0)
+            .map_or(0, |y| y + 1);
+        let rows = self.skyline().max(highest).min(data_rows);
+
+        for y in (0..rows).rev() {
+            for tile in &self.tiles[y * self.width..(y + 1) * self.width] {
+                write!(f, "{}", tile_char(*tile))?;
+            }
+            if y > 0 {
+                writeln!(f)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Field {
+    /// Creates a field from rows of tile characters (e.g. `"XX__XXXXXX"`), from the top down.
+    ///
+    /// The field is padded with empty rows up to the default height.
+    pub fn from_rows(lines: &[&str]) -> Result<Field, &'static str> {
+        let width = lines.first().map_or(0, |line| line.chars().count());
+        if lines.iter().any(|line| line.chars().count() != width) {
+            return Err("rows have different widths");
+        }
+        let height = lines.len().max(Field::HEIGHT);
+
+        let mut tiles = Vec::with_capacity(width * height);
+        let mut clear_rows = 0;
+        for line in lines.iter().rev() {
+            let mut is_clear = false;
+            for c in line.chars() {
+                let tile = parse_tile(c).ok_or("invalid tile")?;
+                is_clear |= matches!(tile, Tile::Clear(_));
+                tiles.push(tile);
+            }
+            if is_clear {
+                clear_rows += 1;
+            }
+        }
+        tiles.resize(width * height, Tile::Empty);
+
+        Field::from_tiles(width, height, Field::TOP_HEIGHT, clear_rows, tiles)
+            .ok_or("invalid field width")
+    }
+}
+
+impl FromStr for Field {
+    type Err = &'static str;
+
+    /// Parses a field. Leading and trailing blank lines are ignored.
+    fn from_str(s: &str) -> Result<Field, Self::Err> {
+        let lines: Vec<_> = s.trim_matches('\n').lines().collect();
+        Field::from_rows(&lines)
+    }
+}
+
+#[test]
+fn ascii_fields() {
+    let field: Field = "
+T.........
+TTJJJ.ZZ.G
+----------
+TIIII.OOGG
+"
+    .parse()
+    .unwrap();
+
+    assert_eq!(field.width(), 10);
+    assert_eq!(field.get_tile(0, 3), Some(Tile::Piece(PieceType::T)));
+    assert_eq!(field.get_tile(9, 2), Some(Tile::Garbage));
+    assert_eq!(field.get_tile(1, 1), Some(Tile::Clear(0.)));
+    assert_eq!(field.clear_rows(), 1);
+
+    let text = field.to_string();
+    let lines: Vec<_> = text.lines().collect();
+    // rows up to the skyline are written
+    assert_eq!(lines.len(), field.skyline());
+    assert_eq!(
+        lines[lines.len() - 4..],
+        ["T.........", "TTJJJ.ZZ.G", "----------", "TIIII.OOGG"]
+    );
+    assert_eq!(text.parse::<Field>().unwrap().to_string(), text);
+
+    assert!("...\n....".parse::<Field>().is_err());
+    assert!("..x".parse::<Field>().is_err());
+}
+
+#[test]
+fn field_from_rows() {
+    let field = Field::from_rows(&["T___ZZ____", "XX__#XXXXX"]).unwrap();
+    assert_eq!(field.get_tile(0, 1), Some(Tile::Piece(PieceType::T)));
+    assert_eq!(field.get_tile(2, 0), Some(Tile::Empty));
+    assert_eq!(field.get_tile(4, 0), Some(Tile::Garbage));
+    assert_eq!(field.get_tile(5, 1), Some(Tile::Piece(PieceType::Z)));
+    assert_eq!(field.height(), Field::HEIGHT);
+
+    assert!(Field::from_rows(&["XXXX", "XXX"]).is_err());
+    assert!(Field::from_rows(&[]).is_err());
+}
@@ -0,0 +1,137 @@
+//! Sticky gravity for the cascade clear mode.
+
+use super::{Field, Tile};
+
+impl Field {
+    /// Returns true if the tile is part of the stack, i.e. not empty and not a cleared line.
+    fn is_solid(tile: Tile) -> bool {
+        matches!(tile, Tile::Piece(_) | Tile::Garbage | Tile::Item(_))
+    }
+
+    /// Finds all groups of orthogonally connected solid tiles.
+    fn tile_groups(&self) -> Vec<Vec<(usize, usize)>> {
+        let rows = self.tiles.len() / self.width;
+        let mut visited = vec![false; self.tiles.len()];
+        let mut groups = Vec::new();
+
+        for start in 0..self.tiles.len() {
+            if visited[start] || !Self::is_solid(self.tiles[start]) {
+                continue;
+            }
+            visited[start] = true;
+            let mut group = Vec::new();
+            let mut stack = vec![(start % self.width, start / self.width)];
+            while let Some((x, y)) = stack.pop() {
+                group.push((x, y));
+                let neighbors = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+                for &(nx, ny) in &neighbors {
+                    if nx >= self.width || ny >= rows {
+                        continue;
+                    }
+                    let index = ny * self.width + nx;
+                    if !visited[index] && Self::is_solid(self.tiles[index]) {
+                        visited[index] = true;
+                        stack.push((nx, ny));
+                    }
+                }
+            }
+            groups.push(group);
+        }
+
+        groups
+    }
+
+    /// Returns true if every tile of the group has space below it.
+    fn can_drop(&self, group: &[(usize, usize)]) -> bool {
+        group.iter().all(|&(x, y)| {
+            y > 0 && (self.get_tile(x, y - 1) == Some(Tile::Empty) || group.contains(&(x, y - 1)))
+        })
+    }
+
+    /// Moves the group down by one row.
+    ///
+    /// Since groups are connected, all linked neighbors move along and links are preserved.
+    fn drop_group(&mut self, group: &mut [(usize, usize)]) {
+        let tiles: Vec<_> = group
+            .iter()
+            .map(|&(x, y)| (self.get_tile(x, y).unwrap(), self.tile_links(x, y)))
+            .collect();
+        for &(x, y) in group.iter() {
+            self.set_tile(x, y, Tile::Empty);
+        }
+        for (pos, (tile, links)) in group.iter_mut().zip(tiles) {
+            pos.1 -= 1;
+            self.set_tile(pos.0, pos.1, tile);
+            self.links[pos.1 * self.width + pos.0] = links;
+        }
+    }
+
+    /// Lets connected groups of tiles fall until they rest on the floor or on other tiles.
+    ///
+    /// Returns true if any tiles moved.
+    pub(super) fn cascade(&mut self) -> bool {
+        let mut groups = self.tile_groups();
+        // lower groups first, so groups above can land on them
+        groups.sort_by_key(|group| group.iter().map(|&(_, y)| y).min());
+
+        let mut moved = false;
+        loop {
+            let mut any_moved = false;
+            for group in &mut groups {
+                while self.can_drop(group) {
+                    self.drop_group(group);
+                    any_moved = true;
+                }
+            }
+            if !any_moved {
+                break;
+            }
+            moved = true;
+        }
+        moved
+    }
+}
+
+#[test]
+fn cascade_clears() {
+    use super::ClearMode;
+
+    let setup = |clear_mode| {
+        let mut field = Field::new();
+        field.set_clear_mode(clear_mode);
+        for x in 0..field.width() {
+            if x > 0 {
+                field.set_tile(x, 0, Tile::Garbage);
+            }
+            field.set_tile(x, 1, Tile::Garbage);
+        }
+        field.set_tile(0, 2, Tile::Garbage);
+        field
+    };
+
+    // the tile above the cleared line stays floating
+    let mut field = setup(ClearMode::Naive);
+    assert_eq!(field.clear_lines(0.), vec![1]);
+    assert!(field.clean_lines(-1., 0.).is_empty());
+    assert_eq!(field.get_tile(0, 1), Some(Tile::Garbage));
+    assert_eq!(field.get_tile(0, 0), Some(Tile::Empty));
+
+    // the tile falls into the gap and completes another line
+    let mut field = setup(ClearMode::Cascade);
+    assert_eq!(field.clear_lines(0.), vec![1]);
+    assert_eq!(field.clean_lines(-1., 0.), vec![0]);
+    assert!(field.tiles().iter().all(|tile| *tile == Tile::Empty));
+
+    // with a timeout, the chain line waits to be removed
+    let mut field = setup(ClearMode::Cascade);
+    field.clear_lines(0.);
+    assert_eq!(field.clean_lines(0.5, 1.), vec![0]);
+    assert_eq!(field.clear_rows(), 1);
+    field.clean_lines(0.5, 2.);
+    assert_eq!(field.clear_rows(), 0);
+}
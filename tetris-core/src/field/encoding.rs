@@ -0,0 +1,334 @@
+//! Compact binary encoding for fields.
+//!
+//! The encoding starts with a header of five little-endian `u16`s (width, height, top height,
+//! number of clear rows, and number of rows in the data), followed by the rows from the bottom
+//! up. Each row starts with a tag byte:
+//!
+//! - `0`: a run of empty rows. The next byte contains the length of the run.
+//! - `1`: a row of tiles, packed as 4 bits per tile (low nibble first).
+//! - `2`: like `1`, but followed by an `f64` timestamp for the row’s clear tiles.
+//! - `3`: a row of tiles, one byte per tile. Used for rows that contain custom pieces.
+//! - `4`: like `3`, but followed by an `f64` timestamp for the row’s clear tiles.
+//! - `5`: items in the next row: a count byte followed by a column and an item index byte for
+//!   each item. Item tiles are encoded as garbage in the row itself.
+
+use super::{Field, Item, PieceType, Tile};
+use core::convert::TryInto;
+
+const ROW_EMPTY: u8 = 0;
+const ROW_TILES: u8 = 1;
+const ROW_TILES_CLEAR: u8 = 2;
+const ROW_TILES_WIDE: u8 = 3;
+const ROW_TILES_WIDE_CLEAR: u8 = 4;
+const ROW_ITEMS: u8 = 5;
+
+const TILE_EMPTY: u8 = 0;
+const TILE_GARBAGE: u8 = 8;
+const TILE_CLEAR: u8 = 9;
+const WIDE_TILE_CLEAR: u8 = u8::MAX - 1;
+const WIDE_TILE_GARBAGE: u8 = u8::MAX;
+
+fn encode_tile(tile: Tile) -> u8 {
+    match tile {
+        Tile::Empty => TILE_EMPTY,
+        Tile::Piece(piece) => piece.index() + 1,
+        Tile::Garbage | Tile::Item(_) => TILE_GARBAGE,
+        Tile::Clear(_) => TILE_CLEAR,
+    }
+}
+
+fn encode_wide_tile(tile: Tile) -> u8 {
+    match tile {
+        Tile::Empty => TILE_EMPTY,
+        Tile::Piece(piece) => piece.index() + 1,
+        Tile::Garbage | Tile::Item(_) => WIDE_TILE_GARBAGE,
+        Tile::Clear(_) => WIDE_TILE_CLEAR,
+    }
+}
+
+fn decode_wide_tile(code: u8, clear_time: f64) -> Option<Tile> {
+    match code {
+        TILE_EMPTY => Some(Tile::Empty),
+        WIDE_TILE_GARBAGE => Some(Tile::Garbage),
+        WIDE_TILE_CLEAR => Some(Tile::Clear(clear_time)),
+        code => PieceType::from_index(code - 1).map(Tile::Piece),
+    }
+}
+
+fn decode_tile(code: u8, clear_time: f64) -> Option<Tile> {
+    match code {
+        TILE_EMPTY => Some(Tile::Empty),
+        TILE_GARBAGE => Some(Tile::Garbage),
+        TILE_CLEAR => Some(Tile::Clear(clear_time)),
+        code => PieceType::from_index(code - 1)
+            .filter(|piece| !piece.is_custom())
+            .map(Tile::Piece),
+    }
+}
+
+/// A cursor for reading encoded data.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|b| b[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.bytes(2)
+            .map(|b| u16::from_le_bytes(b.try_into().unwrap()))
+    }
+
+    fn f64(&mut self) -> Option<f64> {
+        self.bytes(8)
+            .map(|b| f64::from_le_bytes(b.try_into().unwrap()))
+    }
+}
+
+impl Tile {
+    /// The code of empty tiles returned by [`Tile::code`].
+    pub const CODE_EMPTY: u8 = TILE_EMPTY;
+    /// The code of cleared tiles returned by [`Tile::code`].
+    pub const CODE_CLEAR: u8 = WIDE_TILE_CLEAR;
+    /// The code of garbage and item tiles returned by [`Tile::code`].
+    pub const CODE_GARBAGE: u8 = WIDE_TILE_GARBAGE;
+
+    /// Returns the code of this tile in one-byte-per-tile rows: 0 for empty tiles, the piece
+    /// index plus one for pieces, 254 for cleared tiles and 255 for garbage and items.
+    pub fn code(self) -> u8 {
+        encode_wide_tile(self)
+    }
+}
+
+impl Field {
+    /// Writes the [code](Tile::code) of each tile to the buffer, replacing its contents, in the
+    /// same order as [`Field::tiles`].
+    pub fn tile_codes_into(&self, buf: &mut Vec<u8>) {
+        buf.clear();
+        buf.extend(self.tiles.iter().map(|tile| tile.code()));
+    }
+
+    /// Appends the compact binary encoding of this field to the buffer.
+    pub fn encode_into(&self, buf: &mut Vec<u8>) {
+        let rows = self.tiles.len() / self.width;
+        for value in &[
+            self.width,
+            self.height,
+            self.top_height,
+            self.clear_rows,
+            rows,
+        ] {
+            buf.extend_from_slice(&(*value as u16).to_le_bytes());
+        }
+
+        let mut empty_run: u8 = 0;
+        for row in self.tiles.chunks(self.width) {
+            if row.iter().all(|tile| *tile == Tile::Empty) {
+                if empty_run == u8::MAX {
+                    buf.push(ROW_EMPTY);
+                    buf.push(empty_run);
+                    empty_run = 0;
+                }
+                empty_run += 1;
+                continue;
+            }
+            if empty_run > 0 {
+                buf.push(ROW_EMPTY);
+                buf.push(empty_run);
+                empty_run = 0;
+            }
+
+            let clear_time = row.iter().find_map(|tile| match tile {
+                Tile::Clear(time) => Some(*time),
+                _ => None,
+            });
+            let items: Vec<_> = row
+                .iter()
+                .enumerate()
+                .filter_map(|(x, tile)| match tile {
+                    Tile::Item(item) => Some((x as u8, item.index())),
+                    _ => None,
+                })
+                .collect();
+            if !items.is_empty() {
+                buf.push(ROW_ITEMS);
+                buf.push(items.len() as u8);
+                for (x, item) in items {
+                    buf.push(x);
+                    buf.push(item);
+                }
+            }
+
+            let has_custom = row
+                .iter()
+                .any(|tile| matches!(tile, Tile::Piece(piece) if piece.is_custom()));
+            if has_custom {
+                buf.push(if clear_time.is_some() {
+                    ROW_TILES_WIDE_CLEAR
+                } else {
+                    ROW_TILES_WIDE
+                });
+                buf.extend(row.iter().map(|tile| encode_wide_tile(*tile)));
+            } else {
+                buf.push(if clear_time.is_some() {
+                    ROW_TILES_CLEAR
+                } else {
+                    ROW_TILES
+                });
+                for pair in row.chunks(2) {
+                    let lo = encode_tile(pair[0]);
+                    let hi = pair.get(1).map_or(0, |tile| encode_tile(*tile));
+                    buf.push(lo | (hi << 4));
+                }
+            }
+            if let Some(time) = clear_time {
+                buf.extend_from_slice(&time.to_le_bytes());
+            }
+        }
+        if empty_run > 0 {
+            buf.push(ROW_EMPTY);
+            buf.push(empty_run);
+        }
+    }
+
+    /// Decodes a field from its compact binary encoding.
+    ///
+    /// Returns None if the data is invalid.
+    pub fn decode(data: &[u8]) -> Option<Field> {
+        let mut reader = Reader(data);
+        let width = reader.u16()? as usize;
+        let height = reader.u16()? as usize;
+        let top_height = reader.u16()? as usize;
+        let clear_rows = reader.u16()? as usize;
+        let rows = reader.u16()? as usize;
+        // rows beyond the height only come from cleared rows waiting to be removed
+        if width == 0 || width > Field::MAX_WIDTH || rows > height + clear_rows {
+            return None;
+        }
+
+        let mut tiles = Vec::new();
+        let mut items = Vec::new();
+        while tiles.len() < width * rows {
+            let row_start = tiles.len();
+            match reader.u8()? {
+                ROW_ITEMS => {
+                    for _ in 0..reader.u8()? {
+                        let x = reader.u8()? as usize;
+                        let item = Item::from_index(reader.u8()?)?;
+                        if x >= width {
+                            return None;
+                        }
+                        items.push((x, item));
+                    }
+                    continue;
+                }
+                ROW_EMPTY => {
+                    let run = reader.u8()? as usize;
+                    tiles.extend((0..run * width).map(|_| Tile::Empty));
+                }
+                tag @ ROW_TILES | tag @ ROW_TILES_CLEAR => {
+                    let packed = reader.bytes(width.div_ceil(2))?;
+                    let clear_time = if tag == ROW_TILES_CLEAR {
+                        reader.f64()?
+                    } else {
+                        0.
+                    };
+                    for x in 0..width {
+                        let code = (packed[x / 2] >> ((x % 2) * 4)) & 0xF;
+                        tiles.push(decode_tile(code, clear_time)?);
+                    }
+                }
+                tag @ ROW_TILES_WIDE | tag @ ROW_TILES_WIDE_CLEAR => {
+                    let codes = reader.bytes(width)?;
+                    let clear_time = if tag == ROW_TILES_WIDE_CLEAR {
+                        reader.f64()?
+                    } else {
+                        0.
+                    };
+                    for code in codes {
+                        tiles.push(decode_wide_tile(*code, clear_time)?);
+                    }
+                }
+                _ => return None,
+            }
+            for (x, item) in items.drain(..) {
+                let tile = tiles.get_mut(row_start + x)?;
+                if *tile != Tile::Garbage {
+                    return None;
+                }
+                *tile = Tile::Item(item);
+            }
+        }
+        if tiles.len() != width * rows || !reader.0.is_empty() {
+            return None;
+        }
+
+        Field::from_tiles(width, height, top_height, clear_rows, tiles)
+    }
+}
+
+#[test]
+fn encoding_round_trip() {
+    let mut field = Field::new();
+    for x in 0..9 {
+        field.set_tile(x, 0, Tile::Piece(PieceType::all()[x % 7]));
+        field.set_tile(x, 2, Tile::Garbage);
+    }
+    for x in 0..10 {
+        field.set_tile(x, 1, Tile::Clear(1.25));
+    }
+
+    let mut buf = Vec::new();
+    field.encode_into(&mut buf);
+    // header, three tile rows, a clear timestamp and one run of empty rows
+    assert_eq!(buf.len(), 10 + 3 * 6 + 8 + 2);
+
+    let decoded = Field::decode(&buf).unwrap();
+    assert_eq!(decoded.width(), field.width());
+    assert_eq!(decoded.top_height(), field.top_height());
+    assert_eq!(decoded.tiles(), field.tiles());
+
+    assert!(Field::decode(&buf[..buf.len() - 1]).is_none());
+
+    field.set_tile(3, 3, Tile::Piece(PieceType::Custom(12)));
+    let mut buf = Vec::new();
+    field.encode_into(&mut buf);
+    assert_eq!(Field::decode(&buf).unwrap().tiles(), field.tiles());
+
+    field.set_tile(5, 0, Tile::Item(Item::Custom(3)));
+    field.set_tile(1, 3, Tile::Item(Item::Scramble));
+    let mut buf = Vec::new();
+    field.encode_into(&mut buf);
+    assert_eq!(Field::decode(&buf).unwrap().tiles(), field.tiles());
+
+    field.tile_codes_into(&mut buf);
+    assert_eq!(buf.len(), field.tiles().len());
+    assert_eq!(&buf[..3], &[1, 2, 3]);
+    assert_eq!(buf[5], WIDE_TILE_GARBAGE);
+    assert_eq!(buf[10], WIDE_TILE_CLEAR);
+    assert_eq!(buf[33], PieceType::Custom(12).index() + 1);
+    assert_eq!(Tile::Item(Item::Scramble).code(), Tile::CODE_GARBAGE);
+}
+
+#[test]
+fn decode_rejects_oversized_header() {
+    let header = |values: [u16; 5]| -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect()
+    };
+    // too wide for the bitboard; must not be allocated for
+    assert!(Field::decode(&header([u16::MAX, u16::MAX, 22, 0, u16::MAX])).is_none());
+    // more rows than the height and clear rows account for
+    assert!(Field::decode(&header([10, 40, 22, 0, u16::MAX])).is_none());
+}
@@ -0,0 +1,191 @@
+//! Left/right mirroring of fields and pieces.
+
+use super::{
+    ActiveField, ActivePiece, Field, PieceType, Placement, Rotation, Shape, Tile, TileLinks,
+};
+use crate::geom::{Point2, Rect};
+
+impl PieceType {
+    /// Returns the piece type with the opposite chirality (J and L, S and Z). Other pieces are
+    /// their own mirror images.
+    pub fn mirrored(&self) -> PieceType {
+        match self {
+            PieceType::J => PieceType::L,
+            PieceType::L => PieceType::J,
+            PieceType::S => PieceType::Z,
+            PieceType::Z => PieceType::S,
+            other => *other,
+        }
+    }
+}
+
+impl Rotation {
+    /// Returns the rotation of a mirrored piece, i.e. clockwise and counter-clockwise swapped.
+    pub fn mirrored(&self) -> Rotation {
+        match self {
+            Rotation::CW => Rotation::CCW,
+            Rotation::CCW => Rotation::CW,
+            other => *other,
+        }
+    }
+}
+
+impl TileLinks {
+    /// Swaps the left and right links.
+    fn mirrored(self) -> TileLinks {
+        let horizontal = self.0 & (Self::LEFT.0 | Self::RIGHT.0);
+        let swapped = if horizontal == Self::LEFT.0 || horizontal == Self::RIGHT.0 {
+            horizontal ^ (Self::LEFT.0 | Self::RIGHT.0)
+        } else {
+            horizontal
+        };
+        TileLinks(self.0 & !(Self::LEFT.0 | Self::RIGHT.0) | swapped)
+    }
+}
+
+impl Tile {
+    /// Returns the tile as it appears in a mirrored field.
+    fn mirrored(self) -> Tile {
+        match self {
+            Tile::Piece(piece) => Tile::Piece(piece.mirrored()),
+            other => other,
+        }
+    }
+}
+
+impl ActivePiece {
+    /// Returns this piece mirrored left to right in a field of the given width, such that it
+    /// covers the mirrored tiles.
+    ///
+    /// Custom piece shapes are not mirrored.
+    pub fn mirrored(&self, width: usize) -> ActivePiece {
+        let flipped = self.iter_tiles().map(|tile| {
+            let x = self.pos.x + tile.x;
+            Point2::new(width as isize - 1 - x, self.pos.y + tile.y)
+        });
+        let target = Rect::around_tiles(flipped).map_or(self.pos, |bounds| bounds.min);
+
+        let mut piece = *self;
+        if piece.custom_shape.is_none() {
+            piece.piece_type = self.piece_type.mirrored();
+        }
+        piece.rotation = self.rotation.mirrored();
+        let offset = piece
+            .bounding_box()
+            .map_or(Point2::new(0, 0), |bounds| bounds.min);
+        piece.pos = Point2::new(target.x - offset.x, target.y - offset.y);
+        piece
+    }
+}
+
+impl Field {
+    /// Returns a copy of this field mirrored left to right.
+    ///
+    /// Piece tiles swap their chirality, so e.g. a J tile becomes an L tile.
+    pub fn mirrored(&self) -> Field {
+        let mut field = self.clone();
+        let width = self.width;
+        for (y, row) in field.tiles.chunks_mut(width).enumerate() {
+            row.reverse();
+            for tile in row.iter_mut() {
+                *tile = tile.mirrored();
+            }
+            field.rows[y] = Field::row_mask(row);
+        }
+        for row in field.obstacles.iter_mut() {
+            *row = row.reverse_bits() >> (Field::MAX_WIDTH - width);
+        }
+        for row in field.links.chunks_mut(width) {
+            row.reverse();
+            for links in row.iter_mut() {
+                *links = links.mirrored();
+            }
+        }
+        field
+    }
+}
+
+impl Placement {
+    /// Returns the equivalent placement on the mirrored field.
+    pub fn mirrored(&self) -> Placement {
+        Placement {
+            piece: self.piece.mirrored(self.field.width()),
+            spin: self.spin,
+            info: self.info,
+            field: self.field.mirrored(),
+        }
+    }
+}
+
+impl ActiveField {
+    /// Returns a copy of this game mirrored left to right, including the active piece, the
+    /// queue, the held piece and pending garbage.
+    ///
+    /// The piece RNG is not affected, so pieces dealt later are not mirrored.
+    pub fn mirrored(&self) -> ActiveField {
+        let mut field = self.clone();
+        let width = self.field.width();
+        field.field = self.field.mirrored();
+        field.active_piece = self.active_piece.map(|piece| piece.mirrored(width));
+        field.held_piece = self.held_piece.map(|piece| piece.mirrored());
+        for piece in field.queue.iter_mut().chain(field.bag_history.iter_mut()) {
+            *piece = piece.mirrored();
+        }
+        for garbage in field.pending_garbage.iter_mut() {
+            garbage.hole_column = width.saturating_sub(1 + garbage.hole_column);
+        }
+        field.events.clear();
+        field
+    }
+}
+
+#[test]
+fn mirroring() {
+    let field: Field = "\
+        LL........\n\
+        .L..SS....\n\
+        .L.SS..GGG"
+        .parse()
+        .unwrap();
+    let mirrored = field.mirrored();
+    let expected: Field = "\
+        ........JJ\n\
+        ....ZZ..J.\n\
+        GGG..ZZ.J."
+        .parse()
+        .unwrap();
+    assert_eq!(mirrored.tiles(), expected.tiles());
+    assert_eq!(mirrored.mirrored().tiles(), field.tiles());
+
+    let mut linked = Field::new();
+    let piece = ActivePiece::new(PieceType::L, 0.);
+    linked.project(&piece, (1, 1).into(), Tile::Piece(PieceType::L));
+    let mirrored = linked.mirrored();
+    assert_eq!(
+        mirrored.tile_links(8, 1),
+        TileLinks::LEFT | TileLinks::RIGHT
+    );
+    assert_eq!(mirrored.tile_links(9, 1), TileLinks::LEFT);
+    assert_eq!(mirrored.tile_links(7, 1), TileLinks::RIGHT | TileLinks::UP);
+
+    // pieces cover the mirrored tiles in every rotation
+    for piece_type in PieceType::all() {
+        for rotation in 0..4 {
+            let mut piece = ActivePiece::new(piece_type, 0.);
+            piece.rotation = rotation.into();
+            piece.pos = Point2::new(2, 5);
+            let mirrored = piece.mirrored(10);
+            let mut expected: Vec<_> = piece
+                .iter_tiles()
+                .map(|t| (9 - (t.x + 2), t.y + 5))
+                .collect();
+            let mut actual: Vec<_> = mirrored
+                .iter_tiles()
+                .map(|t| (t.x + mirrored.pos.x, t.y + mirrored.pos.y))
+                .collect();
+            expected.sort();
+            actual.sort();
+            assert_eq!(actual, expected);
+        }
+    }
+}
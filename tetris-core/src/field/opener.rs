@@ -0,0 +1,225 @@
+//! Perfect clear opener detection.
+
+use super::{ActiveField, Field, HoldResult, Placement, Shape};
+use std::collections::HashSet;
+
+/// A setup to build at the start of a game, from which a perfect clear can be completed.
+///
+/// Setups are described by their shape, so trainers can supply the openers they teach along with
+/// their mirrored variants.
+#[derive(Debug, Clone)]
+pub struct PcSetup {
+    name: String,
+    /// The tiles of the finished setup.
+    field: Field,
+}
+
+impl PcSetup {
+    /// Creates a setup from rows of tiles, using the plain text format of [`Field::from_rows`].
+    ///
+    /// Only the occupied tiles matter, not the piece types.
+    pub fn new(name: &str, rows: &[&str]) -> Result<PcSetup, &'static str> {
+        let field = Field::from_rows(rows)?;
+        let tiles: u32 = field.rows.iter().map(|row| row.count_ones()).sum();
+        if tiles == 0 || !tiles.is_multiple_of(4) {
+            return Err("setup must consist of whole pieces");
+        }
+        Ok(PcSetup {
+            name: name.into(),
+            field,
+        })
+    }
+
+    /// Returns the name of the setup.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns the number of pieces in the setup.
+    pub fn piece_count(&self) -> usize {
+        let tiles: u32 = self.field.rows.iter().map(|row| row.count_ones()).sum();
+        tiles as usize / 4
+    }
+
+    /// Returns the setup mirrored left to right.
+    pub fn mirrored(&self) -> PcSetup {
+        PcSetup {
+            name: format!("{} (mirrored)", self.name),
+            field: self.field.mirrored(),
+        }
+    }
+
+    fn row(&self, y: usize) -> u16 {
+        self.field.rows.get(y).copied().unwrap_or(0)
+    }
+}
+
+/// A step in a sequence of placements.
+#[derive(Debug, Clone)]
+pub struct OpenerStep {
+    /// True if the active piece should be swapped with the held piece first.
+    pub hold: bool,
+    /// Where to place the piece.
+    pub placement: Placement,
+}
+
+/// What a placement search is trying to reach.
+#[derive(Clone, Copy)]
+enum Goal<'a> {
+    /// Fill exactly the tiles of the setup without clearing lines.
+    Setup(&'a PcSetup),
+    /// Clear the field, staying below the given number of rows.
+    PerfectClear { height: usize },
+}
+
+impl ActiveField {
+    /// Finds the first of the given setups that can be built on this field with the active piece,
+    /// the held piece and the queue, using hold where necessary.
+    ///
+    /// Returns the index of the setup and the placements that build it. Only as many pieces as
+    /// are in the preview can be used.
+    pub fn find_pc_setup(&self, setups: &[PcSetup]) -> Option<(usize, Vec<OpenerStep>)> {
+        setups.iter().enumerate().find_map(|(i, setup)| {
+            let depth = setup.piece_count();
+            if depth > self.preview_depth() + 1 {
+                return None;
+            }
+            self.search_goal(Goal::Setup(setup), depth, &mut HashSet::new())
+                .map(|steps| (i, steps))
+        })
+    }
+
+    /// Finds a sequence of at most `max_pieces` placements that results in a perfect clear
+    /// without building higher than the given number of rows.
+    pub fn find_perfect_clear(&self, height: usize, max_pieces: usize) -> Option<Vec<OpenerStep>> {
+        let max_pieces = max_pieces.min(self.preview_depth() + 1);
+        self.search_goal(
+            Goal::PerfectClear { height },
+            max_pieces,
+            &mut HashSet::new(),
+        )
+    }
+
+    /// Depth-first search for placements that reach the goal. `failed` contains the hashes and
+    /// height limits of states that are known not to lead to the goal.
+    fn search_goal(
+        &self,
+        goal: Goal,
+        depth: usize,
+        failed: &mut HashSet<(u64, usize)>,
+    ) -> Option<Vec<OpenerStep>> {
+        let height = match goal {
+            Goal::PerfectClear { height } => height,
+            Goal::Setup(_) => 0,
+        };
+        if depth == 0 || !failed.insert((self.state_hash(), height)) {
+            return None;
+        }
+
+        for hold in [false, true].iter().copied() {
+            let mut root = self.clone();
+            if hold {
+                match root.swap_held_piece(0.) {
+                    HoldResult::Swapped | HoldResult::TookFromQueue => (),
+                    _ => continue,
+                }
+            }
+
+            for placement in root.enumerate_placements() {
+                let mut child = root.clone();
+                child.apply_placement(&placement);
+                child.clear_lines(f64::NEG_INFINITY, 0.);
+                if child.is_top_out() {
+                    continue;
+                }
+
+                let (reached, next_goal) = match goal {
+                    Goal::Setup(setup) => {
+                        let fits = placement.info.lines_cleared == 0
+                            && placement.piece.iter_tiles().all(|tile| {
+                                let x = placement.piece.pos.x + tile.x;
+                                let y = placement.piece.pos.y + tile.y;
+                                y >= 0 && setup.row(y as usize) & (1 << x) != 0
+                            });
+                        if !fits {
+                            continue;
+                        }
+                        let rows = child.field.rows.len().max(setup.field.rows.len());
+                        let reached = (0..rows)
+                            .all(|y| child.field.rows.get(y).copied().unwrap_or(0) == setup.row(y));
+                        (reached, goal)
+                    }
+                    Goal::PerfectClear { height } => {
+                        let height = match height.checked_sub(placement.info.lines_cleared) {
+                            Some(height) => height,
+                            None => continue,
+                        };
+                        let field = &child.field;
+                        if field.rows.iter().skip(height).any(|row| *row != 0) {
+                            continue;
+                        }
+                        let empty: usize = field.rows[..height.min(field.rows.len())]
+                            .iter()
+                            .map(|row| field.width - row.count_ones() as usize)
+                            .sum();
+                        if empty > 4 * (depth - 1) {
+                            continue;
+                        }
+                        (empty == 0, Goal::PerfectClear { height })
+                    }
+                };
+
+                let step = OpenerStep {
+                    hold,
+                    placement: placement.clone(),
+                };
+                if reached {
+                    return Some(vec![step]);
+                }
+                child.spawn_active(None, 0.);
+                if let Some(mut rest) = child.search_goal(next_goal, depth - 1, failed) {
+                    rest.insert(0, step);
+                    return Some(rest);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn pc_openers() {
+    use super::{PieceType, Tile};
+
+    let mut field = ActiveField::with_seed(1);
+    let mut pieces = field.piece_set().clone();
+    for piece in [
+        PieceType::J,
+        PieceType::L,
+        PieceType::S,
+        PieceType::T,
+        PieceType::Z,
+    ] {
+        pieces.remove(piece);
+    }
+    field.set_piece_set(pieces);
+    field.spawn_active(None, 0.);
+
+    // five pieces are needed to clear two lines of I and O pieces
+    assert!(field.find_perfect_clear(2, 4).is_none());
+    let steps = field.find_perfect_clear(2, 5).unwrap();
+    assert_eq!(steps.len(), 5);
+
+    let setup = PcSetup::new("box", &["OO........", "OO........"]).unwrap();
+    assert_eq!(setup.piece_count(), 1);
+    let flat = PcSetup::new("flat", &["IIII......"]).unwrap();
+    assert!(PcSetup::new("partial", &["III......."]).is_err());
+
+    let (index, steps) = field.find_pc_setup(&[flat, setup.mirrored()]).unwrap();
+    let placed = &steps[0].placement.field;
+    if index == 0 {
+        assert_eq!(placed.get_tile(3, 0), Some(Tile::Piece(PieceType::I)));
+    } else {
+        assert_eq!(placed.get_tile(9, 1), Some(Tile::Piece(PieceType::O)));
+    }
+}
@@ -0,0 +1,305 @@
+//! Search for reachable piece placements.
+
+use super::{
+    ActiveField, ActivePiece, Field, LockResult, PieceAction, Rotation, Shape, SpinType, Tile,
+};
+use crate::eval::PlacementInfo;
+use crate::sim::Input;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// A position in which the active piece can be locked.
+#[derive(Debug, Clone)]
+pub struct Placement {
+    /// The piece in its final position.
+    pub piece: ActivePiece,
+    /// The spin the piece would be locked with.
+    pub spin: SpinType,
+    /// Features of the placement, for evaluation.
+    pub info: PlacementInfo,
+    /// The field after locking the piece and removing cleared lines.
+    pub field: Field,
+}
+
+/// Identifies a search state by position, rotation, and whether the last action was a rotation
+/// (which matters for spins).
+type StateKey = (isize, isize, usize, bool);
+
+fn state_key(piece: &ActivePiece) -> StateKey {
+    (
+        piece.pos.x,
+        piece.pos.y,
+        piece.rotation.cw_steps(),
+        piece.last_action == PieceAction::Rotate,
+    )
+}
+
+/// Inputs that are explored when enumerating placements.
+const SEARCH_INPUTS: [Input; 5] = [
+    Input::MoveLeft,
+    Input::MoveRight,
+    Input::MoveDown,
+    Input::RotateCW,
+    Input::RotateCCW,
+];
+
+/// Inputs that are explored when finding paths. Sonic drops make for shorter paths.
+const PATH_INPUTS: [Input; 6] = [
+    Input::MoveLeft,
+    Input::MoveRight,
+    Input::SonicDrop,
+    Input::MoveDown,
+    Input::RotateCW,
+    Input::RotateCCW,
+];
+
+impl ActiveField {
+    /// Applies a single movement input to a copy of a piece, without affecting the field.
+    ///
+    /// Returns None if the input had no effect.
+    fn simulate_input(&self, piece: &ActivePiece, input: Input) -> Option<ActivePiece> {
+        let mut piece = *piece;
+        let time = piece.last_move_time;
+        let moved = match input {
+            Input::MoveLeft => piece.try_move(&self.field, -1, 0, time).is_success(),
+            Input::MoveRight => piece.try_move(&self.field, 1, 0, time).is_success(),
+            Input::MoveDown => piece.try_move(&self.field, 0, -1, time).is_success(),
+            Input::RotateCW | Input::RotateCCW => {
+                let rotation = if input == Input::RotateCW { 1 } else { -1 };
+                let rotation_system = self.rotation_system_for(piece.piece_type);
+                piece
+                    .try_rotate(&self.field, rotation_system, rotation, time)
+                    .is_success()
+            }
+            Input::SonicDrop => {
+                let mut moved = false;
+                // use field height as an upper limit in case of invalid state
+                for _ in 0..self.field.height {
+                    if !piece.try_move(&self.field, 0, -1, time).is_success() {
+                        break;
+                    }
+                    moved = true;
+                }
+                moved
+            }
+            Input::HardDrop | Input::Hold => false,
+        };
+        if moved {
+            Some(piece)
+        } else {
+            None
+        }
+    }
+
+    /// Returns all distinct placements the active piece can reach by moving, rotating and soft
+    /// dropping, including tucks and spins.
+    ///
+    /// Placements that occupy the same tiles with the same spin are only returned once.
+    pub fn enumerate_placements(&self) -> Vec<Placement> {
+        let start = match self.active_piece {
+            Some(piece) => piece,
+            None => return Vec::new(),
+        };
+
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::new();
+        visited.insert(state_key(&start));
+        queue.push_back(start);
+
+        let mut seen_placements = HashSet::new();
+        let mut placements = Vec::new();
+
+        while let Some(piece) = queue.pop_front() {
+            if piece.is_on_ground(&self.field) {
+                let spin = piece.spin_type(&self.field, self.rotation_system_for(piece.piece_type));
+                let mut tiles: Vec<_> = piece
+                    .iter_tiles()
+                    .map(|tile| (piece.pos.x + tile.x, piece.pos.y + tile.y))
+                    .collect();
+                tiles.sort_unstable();
+                if seen_placements.insert((tiles, spin)) {
+                    placements.push(self.place(piece, spin));
+                }
+            }
+
+            for input in &SEARCH_INPUTS {
+                if let Some(next) = self.simulate_input(&piece, *input) {
+                    if visited.insert(state_key(&next)) {
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        placements
+    }
+
+    /// Returns the shortest sequence of inputs that moves the active piece onto the ground in the
+    /// given column and rotation, or None if that is not possible.
+    ///
+    /// The path does not include the final hard drop.
+    pub fn find_path(&self, x: isize, rotation: Rotation) -> Option<Vec<Input>> {
+        self.find_path_where(|piece| {
+            piece.pos.x == x && piece.rotation == rotation && piece.is_on_ground(&self.field)
+        })
+    }
+
+    /// Returns the shortest sequence of inputs that moves the active piece into the position and
+    /// rotation of the target piece (e.g. a piece from `enumerate_placements`), or None if that is
+    /// not possible.
+    ///
+    /// If the target’s last action was a rotation, the path will also end with a rotation so that
+    /// spins are preserved.
+    pub fn find_path_to(&self, target: &ActivePiece) -> Option<Vec<Input>> {
+        let target_key = state_key(target);
+        self.find_path_where(|piece| state_key(piece) == target_key)
+    }
+
+    /// Finds the shortest path to a piece state that matches the predicate.
+    fn find_path_where<F>(&self, mut is_target: F) -> Option<Vec<Input>>
+    where
+        F: FnMut(&ActivePiece) -> bool,
+    {
+        let start = self.active_piece?;
+        let start_key = state_key(&start);
+
+        // maps states to the state and input they were reached from
+        let mut parents: HashMap<StateKey, Option<(StateKey, Input)>> = HashMap::new();
+        let mut queue = VecDeque::new();
+        parents.insert(start_key, None);
+        queue.push_back(start);
+
+        while let Some(piece) = queue.pop_front() {
+            let key = state_key(&piece);
+            if is_target(&piece) {
+                let mut path = Vec::new();
+                let mut current = key;
+                while let Some(Some((parent, input))) = parents.get(&current) {
+                    path.push(*input);
+                    current = *parent;
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            for input in &PATH_INPUTS {
+                if let Some(next) = self.simulate_input(&piece, *input) {
+                    let next_key = state_key(&next);
+                    if let Entry::Vacant(entry) = parents.entry(next_key) {
+                        entry.insert(Some((key, *input)));
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Moves the active piece into the given placement and locks it.
+    pub fn apply_placement(&mut self, placement: &Placement) -> Option<LockResult> {
+        self.active_piece = Some(placement.piece);
+        self.lock_active()
+    }
+
+    /// Locks a copy of the piece into a copy of the field.
+    fn place(&self, piece: ActivePiece, spin: SpinType) -> Placement {
+        let mut field = self.field.clone();
+        field.project(&piece, piece.pos, Tile::Piece(piece.piece_type));
+        let cleared = field.clear_lines(0.);
+
+        let tile_ys: Vec<_> = piece.iter_tiles().map(|t| piece.pos.y + t.y).collect();
+        let eroded = tile_ys
+            .iter()
+            .filter(|y| cleared.contains(&(**y as usize)))
+            .count();
+        let min_y = tile_ys.iter().copied().min().unwrap_or(0);
+        let max_y = tile_ys.iter().copied().max().unwrap_or(0);
+        // don't count rows that were already cleared before this piece
+        let clear_rows_below = (0..min_y.max(0) as usize)
+            .filter(|y| matches!(self.field.get_tile(0, *y), Some(Tile::Clear(_))))
+            .count();
+
+        // remove all cleared rows
+        field.clean_lines(-1., 0.);
+
+        Placement {
+            piece,
+            spin,
+            info: PlacementInfo {
+                lines_cleared: cleared.len(),
+                landing_height: ((min_y + max_y) / 2) as usize - clear_rows_below,
+                eroded_cells: eroded * cleared.len(),
+            },
+            field,
+        }
+    }
+}
+
+#[test]
+fn placement_enumeration() {
+    use super::PieceType;
+
+    let mut field = ActiveField::with_seed(1);
+    field.spawn_active(Some(PieceType::O), 0.);
+    // an O piece can be placed in 9 columns on an empty field
+    assert_eq!(field.enumerate_placements().len(), 9);
+
+    field.spawn_active(Some(PieceType::I), 0.);
+    // 7 horizontal + 10 vertical
+    assert_eq!(field.enumerate_placements().len(), 17);
+
+    // T-spin double setup
+    let mut field = ActiveField::with_seed(1);
+    let rows = ["X  XXXXXXX", "X   XXXXXX", "XX XXXXXXX"];
+    for (y, row) in rows.iter().rev().enumerate() {
+        for (x, c) in row.chars().enumerate() {
+            if c == 'X' {
+                field.field.set_tile(x, y, Tile::Garbage);
+            }
+        }
+    }
+    field.spawn_active(Some(PieceType::T), 0.);
+    let placements = field.enumerate_placements();
+    assert!(placements
+        .iter()
+        .any(|p| p.spin == SpinType::Full && p.info.lines_cleared == 2));
+}
+
+#[test]
+fn path_finding() {
+    use super::{MoveResult, PieceType};
+
+    let mut field = ActiveField::with_seed(1);
+    field.spawn_active(Some(PieceType::T), 0.);
+
+    // every placement can be reached, and following the path ends up in the same place
+    for placement in field.enumerate_placements() {
+        let path = field.find_path_to(&placement.piece).expect("no path");
+        let mut copy = field.clone();
+        for input in &path {
+            let result = match input {
+                Input::MoveLeft => copy.move_active_left(0.),
+                Input::MoveRight => copy.move_active_right(0.),
+                Input::MoveDown => copy.move_active_down(0.),
+                Input::SonicDrop => {
+                    copy.sonic_drop_active(0.);
+                    MoveResult::Moved
+                }
+                Input::RotateCW => copy.rotate_active_cw(0.),
+                Input::RotateCCW => copy.rotate_active_ccw(0.),
+                _ => unreachable!(),
+            };
+            // paths don’t contain useless inputs
+            assert!(result.is_success());
+        }
+        let piece = copy.active_piece().unwrap();
+        assert_eq!(piece.pos(), placement.piece.pos());
+        assert_eq!(piece.rotation(), placement.piece.rotation());
+    }
+
+    let path = field.find_path(0, Rotation::CW).unwrap();
+    assert!(path.contains(&Input::RotateCW));
+    assert!(path.contains(&Input::SonicDrop));
+    assert!(field.find_path(-5, Rotation::None).is_none());
+}
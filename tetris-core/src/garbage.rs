@@ -0,0 +1,181 @@
+//! Placement of the holes in garbage lines.
+
+use crate::field::{ActiveField, Field, PendingGarbage};
+use crate::rng::Pcg32;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// How the holes in garbage lines are placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum GarbagePattern {
+    /// Each attack has a random hole column that is the same for all of its lines.
+    #[default]
+    PerAttack,
+    /// All lines have their hole in the same column, which is chosen once.
+    Clean,
+    /// The hole column changes after the given number of lines.
+    ChangeEvery(usize),
+    /// Each line has its hole in a different column than the line before, as in a cheese race.
+    Cheese,
+    /// Each line has its hole in a random column.
+    Random,
+}
+
+/// Generates hole columns for garbage lines according to a pattern.
+///
+/// The generator remembers the last hole column, so patterns continue across attacks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GarbageGenerator {
+    pattern: GarbagePattern,
+    rng: Pcg32,
+    /// The column of the last generated hole.
+    hole_column: Option<usize>,
+    /// The number of lines that have been generated since the hole column last changed.
+    lines_in_column: usize,
+}
+
+impl GarbageGenerator {
+    /// Creates a generator whose hole columns are determined by the given seed.
+    pub fn new(pattern: GarbagePattern, seed: u64) -> GarbageGenerator {
+        GarbageGenerator {
+            pattern,
+            rng: Pcg32::new(seed),
+            hole_column: None,
+            lines_in_column: 0,
+        }
+    }
+
+    /// Returns the pattern.
+    pub fn pattern(&self) -> GarbagePattern {
+        self.pattern
+    }
+
+    /// Sets the pattern. The last hole column is kept.
+    pub fn set_pattern(&mut self, pattern: GarbagePattern) {
+        self.pattern = pattern;
+    }
+
+    /// Generates garbage lines for a field of the given width, from the bottom up.
+    ///
+    /// Consecutive lines with the same hole column are merged.
+    pub fn generate(&mut self, lines: usize, width: usize) -> Vec<PendingGarbage> {
+        let mut garbage: Vec<PendingGarbage> = Vec::new();
+        if lines == 0 || width == 0 {
+            return garbage;
+        }
+        let attack_column = self.rng.gen_range(0, width);
+        for _ in 0..lines {
+            let hole_column = self.next_column(width, attack_column);
+            match garbage.last_mut() {
+                Some(last) if last.hole_column == hole_column => last.lines += 1,
+                _ => garbage.push(PendingGarbage {
+                    lines: 1,
+                    hole_column,
+                }),
+            }
+        }
+        garbage
+    }
+
+    /// Returns the hole column of the next line.
+    fn next_column(&mut self, width: usize, attack_column: usize) -> usize {
+        let previous = self.hole_column.filter(|column| *column < width);
+        let column = match (self.pattern, previous) {
+            (GarbagePattern::PerAttack, _) => attack_column,
+            (GarbagePattern::Clean, Some(previous)) => previous,
+            (GarbagePattern::ChangeEvery(lines), Some(previous))
+                if self.lines_in_column < lines.max(1) =>
+            {
+                previous
+            }
+            (GarbagePattern::ChangeEvery(_), Some(previous))
+            | (GarbagePattern::Cheese, Some(previous))
+                if width > 1 =>
+            {
+                // pick one of the other columns
+                let column = self.rng.gen_range(0, width - 1);
+                if column >= previous {
+                    column + 1
+                } else {
+                    column
+                }
+            }
+            _ => self.rng.gen_range(0, width),
+        };
+        if previous == Some(column) {
+            self.lines_in_column += 1;
+        } else {
+            self.lines_in_column = 1;
+        }
+        self.hole_column = Some(column);
+        column
+    }
+}
+
+impl Field {
+    /// Inserts rows of garbage at the bottom of the field with holes placed by the generator.
+    ///
+    /// Returns true if any non-empty tiles were discarded.
+    pub fn insert_garbage_with(&mut self, lines: usize, generator: &mut GarbageGenerator) -> bool {
+        let mut overflow = false;
+        for garbage in generator.generate(lines, self.width()) {
+            overflow |= self.insert_garbage(garbage.lines, garbage.hole_column);
+        }
+        overflow
+    }
+}
+
+impl ActiveField {
+    /// Adds incoming garbage with holes placed by the generator to the pending garbage queue.
+    pub fn queue_garbage_with(&mut self, lines: usize, generator: &mut GarbageGenerator) {
+        for garbage in generator.generate(lines, self.field().width()) {
+            self.queue_garbage(garbage.lines, garbage.hole_column);
+        }
+    }
+}
+
+#[test]
+fn garbage_patterns() {
+    let columns = |pattern, attacks: &[usize]| {
+        let mut generator = GarbageGenerator::new(pattern, 5);
+        let mut columns = Vec::new();
+        for lines in attacks {
+            for garbage in generator.generate(*lines, 10) {
+                columns.extend(std::iter::repeat_n(garbage.hole_column, garbage.lines));
+            }
+        }
+        columns
+    };
+
+    let clean = columns(GarbagePattern::Clean, &[3, 2, 4]);
+    assert_eq!(clean.len(), 9);
+    assert!(clean.iter().all(|column| *column == clean[0]));
+
+    let per_attack = columns(GarbagePattern::PerAttack, &[3, 2]);
+    assert!(per_attack[..3]
+        .iter()
+        .all(|column| *column == per_attack[0]));
+    assert_eq!(per_attack[3], per_attack[4]);
+
+    let cheese = columns(GarbagePattern::Cheese, &[5, 5]);
+    assert!(cheese.windows(2).all(|pair| pair[0] != pair[1]));
+
+    let change = columns(GarbagePattern::ChangeEvery(3), &[2, 5, 2]);
+    for chunk in change.chunks(3) {
+        assert!(chunk.iter().all(|column| *column == chunk[0]));
+    }
+    assert!(change
+        .chunks(3)
+        .zip(change.chunks(3).skip(1))
+        .all(|(a, b)| a[0] != b[0]));
+
+    assert!(columns(GarbagePattern::Random, &[20])
+        .iter()
+        .all(|c| *c < 10));
+
+    let mut field = ActiveField::with_seed(1);
+    let mut generator = GarbageGenerator::new(GarbagePattern::Cheese, 1);
+    field.queue_garbage_with(4, &mut generator);
+    assert_eq!(field.pending_garbage_lines(), 4);
+    assert_eq!(field.pending_garbage().len(), 4);
+}
@@ -1,6 +1,6 @@
 //! Geometric primitives.
 
-use core::ops::{Add, Mul, MulAssign};
+use core::ops::{Add, Mul, MulAssign, Sub};
 use serde::{Deserialize, Serialize};
 
 macro_rules! impl_ty {
@@ -26,9 +26,9 @@ macro_rules! impl_ty {
             }
         }
 
-        impl<T> Into<($($dty,)+)> for $t<T> {
-            fn into(self) -> ($($dty,)+) {
-                ($(self.$dim,)+)
+        impl<T> From<$t<T>> for ($($dty,)+) {
+            fn from(this: $t<T>) -> Self {
+                ($(this.$dim,)+)
             }
         }
     }
@@ -64,6 +64,126 @@ where
     }
 }
 
+/// An axis-aligned rectangle that contains the points from `min` (inclusive) to `max`
+/// (exclusive).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Rect<T> {
+    pub min: Point2<T>,
+    pub max: Point2<T>,
+}
+
+fn partial_min<T: PartialOrd>(a: T, b: T) -> T {
+    if b < a {
+        b
+    } else {
+        a
+    }
+}
+
+fn partial_max<T: PartialOrd>(a: T, b: T) -> T {
+    if b > a {
+        b
+    } else {
+        a
+    }
+}
+
+impl<T> Rect<T> {
+    pub fn new(min: Point2<T>, max: Point2<T>) -> Self {
+        Rect { min, max }
+    }
+}
+
+impl<T> Rect<T>
+where
+    T: PartialOrd + Copy,
+{
+    /// Returns true if the rectangle contains no points.
+    pub fn is_empty(&self) -> bool {
+        !(self.min.x < self.max.x && self.min.y < self.max.y)
+    }
+
+    /// Returns true if the point is inside the rectangle.
+    pub fn contains(&self, point: Point2<T>) -> bool {
+        self.min.x <= point.x
+            && point.x < self.max.x
+            && self.min.y <= point.y
+            && point.y < self.max.y
+    }
+
+    /// Returns true if the other rectangle is entirely inside this one.
+    pub fn contains_rect(&self, other: &Rect<T>) -> bool {
+        other.is_empty()
+            || (self.min.x <= other.min.x
+                && other.max.x <= self.max.x
+                && self.min.y <= other.min.y
+                && other.max.y <= self.max.y)
+    }
+
+    /// Returns true if the rectangles overlap.
+    pub fn intersects(&self, other: &Rect<T>) -> bool {
+        self.intersection(other).is_some()
+    }
+
+    /// Returns the overlapping part of the rectangles, or None if they don’t overlap.
+    pub fn intersection(&self, other: &Rect<T>) -> Option<Rect<T>> {
+        let rect = Rect::new(
+            Point2::new(
+                partial_max(self.min.x, other.min.x),
+                partial_max(self.min.y, other.min.y),
+            ),
+            Point2::new(
+                partial_min(self.max.x, other.max.x),
+                partial_min(self.max.y, other.max.y),
+            ),
+        );
+        Some(rect).filter(|rect| !rect.is_empty())
+    }
+
+    /// Returns the smallest rectangle that contains both rectangles. Empty rectangles are ignored.
+    pub fn union(&self, other: &Rect<T>) -> Rect<T> {
+        if other.is_empty() {
+            return *self;
+        } else if self.is_empty() {
+            return *other;
+        }
+        Rect::new(
+            Point2::new(
+                partial_min(self.min.x, other.min.x),
+                partial_min(self.min.y, other.min.y),
+            ),
+            Point2::new(
+                partial_max(self.max.x, other.max.x),
+                partial_max(self.max.y, other.max.y),
+            ),
+        )
+    }
+}
+
+impl<T> Rect<T>
+where
+    T: Sub<T, Output = T> + Copy,
+{
+    pub fn width(&self) -> T {
+        self.max.x - self.min.x
+    }
+
+    pub fn height(&self) -> T {
+        self.max.y - self.min.y
+    }
+}
+
+impl Rect<isize> {
+    /// Returns the smallest rectangle that covers all of the given unit-sized tiles, or None if
+    /// there are no tiles.
+    pub fn around_tiles(tiles: impl IntoIterator<Item = Point2<isize>>) -> Option<Rect<isize>> {
+        tiles
+            .into_iter()
+            .map(|tile| Rect::new(tile, Point2::new(tile.x + 1, tile.y + 1)))
+            .reduce(|a, b| a.union(&b))
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Vector3<T> {
     pub x: T,
@@ -118,6 +238,10 @@ macro_rules! impl_mul_add_ident {
 }
 
 impl_mul_add_ident!(isize, 1, 0);
+impl_mul_add_ident!(i32, 1, 0);
+impl_mul_add_ident!(i64, 1, 0);
+impl_mul_add_ident!(f32, 1., 0.);
+impl_mul_add_ident!(f64, 1., 0.);
 
 impl<T> Matrix3<T>
 where
@@ -143,6 +267,34 @@ where
     }
 }
 
+impl<T> Mul<T> for Vector3<T>
+where
+    T: Mul<T, Output = T> + Copy,
+{
+    type Output = Self;
+    fn mul(self, rhs: T) -> Self {
+        Vector3 {
+            x: self.x * rhs,
+            y: self.y * rhs,
+            z: self.z * rhs,
+        }
+    }
+}
+
+impl<T> Mul<T> for Matrix3<T>
+where
+    T: Mul<T, Output = T> + Copy,
+{
+    type Output = Self;
+    fn mul(self, rhs: T) -> Self {
+        Matrix3 {
+            x: self.x * rhs,
+            y: self.y * rhs,
+            z: self.z * rhs,
+        }
+    }
+}
+
 impl<T> Mul<Matrix3<T>> for Matrix3<T>
 where
     T: Add<T, Output = T> + Mul<T, Output = T> + Copy,
@@ -205,3 +357,53 @@ fn matrix_multiplication() {
     assert_eq!(a * b, ab, "matrix mult is wrong");
     assert_eq!(a * c, ac, "matrix vector mult is wrong");
 }
+
+#[test]
+fn rects() {
+    let a = Rect::new(Point2::new(0, 0), Point2::new(4, 3));
+    let b = Rect::new(Point2::new(2, 1), Point2::new(6, 2));
+    assert_eq!((a.width(), a.height()), (4, 3));
+    assert!(a.contains(Point2::new(3, 2)));
+    assert!(!a.contains(Point2::new(4, 2)));
+    assert_eq!(
+        a.intersection(&b),
+        Some(Rect::new(Point2::new(2, 1), Point2::new(4, 2)))
+    );
+    assert_eq!(a.union(&b), Rect::new(Point2::new(0, 0), Point2::new(6, 3)));
+    assert!(!a.contains_rect(&b));
+    assert!(a.union(&b).contains_rect(&b));
+
+    let c = Rect::new(Point2::new(4, 0), Point2::new(5, 1));
+    assert!(!a.intersects(&c));
+    assert!(a.intersects(&b));
+
+    let tiles = [Point2::new(-1, 0), Point2::new(0, 0), Point2::new(1, 1)];
+    assert_eq!(
+        Rect::around_tiles(tiles.iter().copied()),
+        Some(Rect::new(Point2::new(-1, 0), Point2::new(2, 2)))
+    );
+    assert_eq!(Rect::around_tiles(Vec::new()), None);
+}
+
+#[test]
+fn float_matrices() {
+    let a: Matrix3<f64> = (
+        (0.5, 0., 0.).into(),
+        (0., 2., 0.).into(),
+        (1., 0., 1.).into(),
+    )
+        .into();
+    let v: Vector3<f64> = (2., 3., 1.).into();
+    assert_eq!(Matrix3::<f64>::identity() * a, a);
+    assert_eq!(Matrix3::<f64>::identity() * v, v);
+    assert_eq!(a * 2. * v, (4., 12., 2.).into());
+    assert_eq!(v * 0.5, (1., 1.5, 0.5).into());
+
+    let identity = Matrix3::<f32>::identity() * 3.;
+    assert_eq!(identity.y.y, 3.);
+    assert_eq!(
+        Matrix3::<i32>::identity() * Vector3::new(1, 2, 3),
+        (1, 2, 3).into()
+    );
+    assert_eq!(Vector3::<i64>::new(1, 2, 3) * 2, (2, 4, 6).into());
+}
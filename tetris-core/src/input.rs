@@ -0,0 +1,250 @@
+//! Auto-repeat handling for held keys.
+
+use crate::field::{Duration, Field};
+use crate::sim::Input;
+use serde::{Deserialize, Serialize};
+use std::iter;
+
+/// The most repeats of a held key that a single update emits, so that a long time step (e.g. in a
+/// backgrounded tab) can't produce huge numbers of inputs. Moving the maximum field width always
+/// reaches the wall.
+const MAX_REPEATS_PER_UPDATE: usize = Field::MAX_WIDTH;
+
+/// Auto-repeat timings.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputConfig {
+    /// Delayed auto shift: time a direction must be held before it starts repeating.
+    pub das: Duration,
+    /// Auto repeat rate: time between repeated moves once DAS has charged. If this is zero, the
+    /// piece moves all the way to the wall at once, and so do new pieces while the key is held.
+    pub arr: Duration,
+    /// Time between repeated soft drop moves. If this is zero, soft drop is a sonic drop.
+    pub soft_drop_interval: Duration,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        InputConfig {
+            das: 10. / 60.,
+            arr: 2. / 60.,
+            soft_drop_interval: 1. / 60.,
+        }
+    }
+}
+
+/// A held key that repeats.
+#[derive(Debug, Clone, Copy)]
+struct HeldKey {
+    input: Input,
+    /// Time since the key was pressed.
+    held_time: Duration,
+    /// Number of repeats emitted so far, not counting the initial press.
+    repeats: usize,
+}
+
+impl HeldKey {
+    fn new(input: Input) -> HeldKey {
+        HeldKey {
+            input,
+            held_time: 0.,
+            repeats: 0,
+        }
+    }
+}
+
+/// Converts key presses and releases into repeated inputs.
+///
+/// Left and right movement repeat using DAS and ARR; if both are held, the one pressed last
+/// wins. Soft drop (`MoveDown`) repeats at the soft drop interval. All other inputs are passed
+/// through once per press.
+#[derive(Debug, Clone, Default)]
+pub struct InputState {
+    config: InputConfig,
+    left_held: bool,
+    right_held: bool,
+    down_held: bool,
+    horizontal: Option<HeldKey>,
+    soft_drop: Option<HeldKey>,
+}
+
+impl InputState {
+    pub fn new(config: InputConfig) -> InputState {
+        InputState {
+            config,
+            ..InputState::default()
+        }
+    }
+
+    /// Returns the auto-repeat timings.
+    pub fn config(&self) -> &InputConfig {
+        &self.config
+    }
+
+    /// Sets the auto-repeat timings.
+    pub fn set_config(&mut self, config: InputConfig) {
+        self.config = config;
+    }
+
+    /// Handles a key press and returns the input that should be applied immediately, if any.
+    ///
+    /// Presses of keys that are already held (e.g. from OS key repeat) are ignored.
+    pub fn key_down(&mut self, input: Input) -> Option<Input> {
+        match input {
+            Input::MoveLeft | Input::MoveRight => {
+                let held = if input == Input::MoveLeft {
+                    &mut self.left_held
+                } else {
+                    &mut self.right_held
+                };
+                if *held {
+                    return None;
+                }
+                *held = true;
+                self.horizontal = Some(HeldKey::new(input));
+                Some(input)
+            }
+            Input::MoveDown => {
+                if self.down_held {
+                    return None;
+                }
+                self.down_held = true;
+                self.soft_drop = Some(HeldKey::new(input));
+                Some(self.soft_drop_input())
+            }
+            input => Some(input),
+        }
+    }
+
+    /// Handles a key release.
+    ///
+    /// If the released direction was overriding the opposite direction that is still held, the
+    /// opposite direction starts charging DAS again.
+    pub fn key_up(&mut self, input: Input) {
+        match input {
+            Input::MoveLeft | Input::MoveRight => {
+                let (held, other, other_held) = if input == Input::MoveLeft {
+                    (&mut self.left_held, Input::MoveRight, self.right_held)
+                } else {
+                    (&mut self.right_held, Input::MoveLeft, self.left_held)
+                };
+                *held = false;
+                if self.horizontal.is_some_and(|key| key.input == input) {
+                    self.horizontal = if other_held {
+                        Some(HeldKey::new(other))
+                    } else {
+                        None
+                    };
+                }
+            }
+            Input::MoveDown => {
+                self.down_held = false;
+                self.soft_drop = None;
+            }
+            _ => (),
+        }
+    }
+
+    /// Releases all keys.
+    pub fn reset(&mut self) {
+        *self = InputState::new(self.config);
+    }
+
+    /// Advances time and returns repeated inputs that should be applied, in order.
+    pub fn update(&mut self, dt: Duration) -> Vec<Input> {
+        let mut inputs = Vec::new();
+
+        if let Some(key) = &mut self.horizontal {
+            key.held_time += dt;
+            let das = self.config.das;
+            let arr = self.config.arr;
+            let count = if key.held_time < das {
+                0
+            } else if arr <= 0. {
+                // keep shifting new pieces to the wall, like soft drop keeps dropping them
+                MAX_REPEATS_PER_UPDATE
+            } else {
+                let target = (((key.held_time - das) / arr) as usize).saturating_add(1);
+                let count = target.saturating_sub(key.repeats);
+                key.repeats = key.repeats.max(target);
+                count
+            };
+            inputs.extend(iter::repeat_n(key.input, count.min(MAX_REPEATS_PER_UPDATE)));
+        }
+
+        let soft_drop_input = self.soft_drop_input();
+        if let Some(key) = &mut self.soft_drop {
+            key.held_time += dt;
+            let interval = self.config.soft_drop_interval;
+            if interval <= 0. {
+                // keep dropping new pieces
+                inputs.push(soft_drop_input);
+            } else {
+                let target = (key.held_time / interval) as usize;
+                let count = target
+                    .saturating_sub(key.repeats)
+                    .min(MAX_REPEATS_PER_UPDATE);
+                inputs.extend(iter::repeat_n(soft_drop_input, count));
+                key.repeats = target;
+            }
+        }
+
+        inputs
+    }
+
+    fn soft_drop_input(&self) -> Input {
+        if self.config.soft_drop_interval <= 0. {
+            Input::SonicDrop
+        } else {
+            Input::MoveDown
+        }
+    }
+}
+
+#[test]
+fn auto_repeat() {
+    let mut state = InputState::new(InputConfig {
+        das: 0.1,
+        arr: 0.05,
+        soft_drop_interval: 0.,
+    });
+    assert_eq!(state.key_down(Input::MoveLeft), Some(Input::MoveLeft));
+    assert_eq!(state.key_down(Input::MoveLeft), None);
+    assert!(state.update(0.09).is_empty());
+    assert_eq!(state.update(0.02), vec![Input::MoveLeft]);
+    assert_eq!(state.update(0.1), vec![Input::MoveLeft, Input::MoveLeft]);
+
+    // the last pressed direction wins, and releasing it recharges the other one
+    assert_eq!(state.key_down(Input::MoveRight), Some(Input::MoveRight));
+    assert!(state.update(0.05).is_empty());
+    state.key_up(Input::MoveRight);
+    assert!(state.update(0.05).is_empty());
+    assert_eq!(state.update(0.06), vec![Input::MoveLeft]);
+    state.key_up(Input::MoveLeft);
+    assert!(state.update(1.).is_empty());
+
+    // zero ARR moves to the wall at once
+    state.set_config(InputConfig {
+        arr: 0.,
+        ..*state.config()
+    });
+    state.key_down(Input::MoveRight);
+    assert_eq!(state.update(0.2).len(), Field::MAX_WIDTH);
+    // and keeps doing so for new pieces
+    assert_eq!(state.update(0.01).len(), Field::MAX_WIDTH);
+    state.key_up(Input::MoveRight);
+
+    // a long update with a tiny ARR doesn't produce more moves than are useful
+    state.set_config(InputConfig {
+        arr: 1e-9,
+        ..*state.config()
+    });
+    state.key_down(Input::MoveRight);
+    assert_eq!(state.update(1e6).len(), Field::MAX_WIDTH);
+    state.key_up(Input::MoveRight);
+
+    // zero soft drop interval turns into sonic drops
+    assert_eq!(state.key_down(Input::MoveDown), Some(Input::SonicDrop));
+    assert_eq!(state.update(0.01), vec![Input::SonicDrop]);
+    assert_eq!(state.key_down(Input::HardDrop), Some(Input::HardDrop));
+}
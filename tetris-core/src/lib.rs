@@ -1,4 +1,21 @@
 //! Core gameplay.
 
+pub mod attack;
+pub mod battle;
+pub mod bot;
+pub mod config;
+pub mod doubles;
+pub mod eval;
 pub mod field;
+pub mod garbage;
 pub mod geom;
+pub mod input;
+pub mod modes;
+pub mod piece_set;
+pub mod replay;
+pub mod rng;
+pub mod rollback;
+pub mod rotation;
+pub mod sim;
+pub mod speed;
+pub mod stats;
@@ -0,0 +1,239 @@
+//! Single-player game modes.
+
+use crate::field::{ActiveField, Duration, LockResult, SpinType, Timestamp};
+use crate::sim::{Input, Simulation, StepResult};
+use crate::speed::{LevelProgress, SpeedCurve};
+use serde::{Deserialize, Serialize};
+
+/// A single-player game mode and its goal.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum GameMode {
+    /// Clear the given number of lines as quickly as possible.
+    Sprint { lines: usize },
+    /// Score as many points as possible within the time limit.
+    Ultra { time_limit: Duration },
+    /// Clear the given number of lines while gravity speeds up every 10 lines.
+    Marathon { lines: usize },
+}
+
+impl GameMode {
+    /// Returns the standard 40 line sprint.
+    pub fn sprint() -> GameMode {
+        GameMode::Sprint { lines: 40 }
+    }
+
+    /// Returns the standard two-minute ultra.
+    pub fn ultra() -> GameMode {
+        GameMode::Ultra { time_limit: 120. }
+    }
+
+    /// Returns the standard 150 line marathon.
+    pub fn marathon() -> GameMode {
+        GameMode::Marathon { lines: 150 }
+    }
+}
+
+/// Whether a game is still running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ModeStatus {
+    /// The game is still running.
+    Playing,
+    /// The goal of the mode was reached.
+    Completed,
+    /// The field topped out before reaching the goal.
+    ToppedOut,
+}
+
+/// Statistics of a game.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModeStats {
+    /// Time since the start of the game.
+    pub time: Timestamp,
+    /// Number of cleared lines.
+    pub lines: usize,
+    /// Number of locked pieces.
+    pub pieces: usize,
+    /// Current score.
+    pub score: usize,
+    /// Current level.
+    pub level: usize,
+}
+
+/// Returns the guideline score for a locked piece at the given level.
+///
+/// Drop points are not included; see [`drop_score`].
+pub fn lock_score(result: &LockResult, level: usize) -> usize {
+    let base = match (result.spin, result.lines_cleared) {
+        (SpinType::None, 0) => 0,
+        (SpinType::None, 1) => 100,
+        (SpinType::None, 2) => 300,
+        (SpinType::None, 3) => 500,
+        (SpinType::None, _) => 800,
+        (SpinType::Mini, 0) => 100,
+        (SpinType::Mini, 1) => 200,
+        (SpinType::Mini, _) => 400,
+        (SpinType::Full, 0) => 400,
+        (SpinType::Full, 1) => 800,
+        (SpinType::Full, 2) => 1200,
+        (SpinType::Full, _) => 1600,
+    };
+    let base = if result.back_to_back {
+        base * 3 / 2
+    } else {
+        base
+    };
+    let combo = 50 * result.combo.saturating_sub(1);
+    (base + combo) * level.max(1)
+}
+
+/// Returns the guideline drop score for a locked piece: one point per tile soft dropped and two
+/// per tile hard dropped.
+pub fn drop_score(result: &LockResult) -> usize {
+    result.soft_drop_cells + 2 * result.hard_drop_cells
+}
+
+/// A single-player game that tracks progress towards the goal of its mode.
+#[derive(Debug, Clone)]
+pub struct ModeGame {
+    mode: GameMode,
+    sim: Simulation,
+    stats: ModeStats,
+    status: ModeStatus,
+}
+
+impl ModeGame {
+    /// Creates a new game in the given mode.
+    pub fn new(
+        mode: GameMode,
+        field: ActiveField,
+        lock_delay: Duration,
+        clear_timeout: Duration,
+    ) -> ModeGame {
+        let mut sim = Simulation::new(field, lock_delay, clear_timeout);
+        sim.set_speed_curve(SpeedCurve::guideline_marathon());
+        ModeGame {
+            mode,
+            stats: ModeStats {
+                level: sim.level(),
+                ..ModeStats::default()
+            },
+            sim,
+            status: ModeStatus::Playing,
+        }
+    }
+
+    /// Returns the game mode.
+    pub fn mode(&self) -> GameMode {
+        self.mode
+    }
+
+    /// Returns the underlying simulation.
+    pub fn simulation(&self) -> &Simulation {
+        &self.sim
+    }
+
+    /// Returns the current statistics.
+    pub fn stats(&self) -> &ModeStats {
+        &self.stats
+    }
+
+    /// Returns whether the game is still running.
+    pub fn status(&self) -> ModeStatus {
+        self.status
+    }
+
+    /// Returns true if the game has ended.
+    pub fn is_finished(&self) -> bool {
+        self.status != ModeStatus::Playing
+    }
+
+    /// Applies player input. See [`Simulation::apply_input`].
+    pub fn apply_input(&mut self, input: Input) -> bool {
+        !self.is_finished() && self.sim.apply_input(input)
+    }
+
+    /// Sets whether soft drop is being held.
+    pub fn set_soft_drop(&mut self, soft_drop: bool) {
+        self.sim.set_soft_drop(soft_drop);
+    }
+
+    /// Advances the game by the given amount of time.
+    ///
+    /// Once the game has ended, this does nothing.
+    pub fn step(&mut self, dt: Duration) -> StepResult {
+        if self.is_finished() {
+            return StepResult::default();
+        }
+
+        let dt = match self.mode {
+            // don't overshoot the time limit
+            GameMode::Ultra { time_limit } => dt.min(time_limit - self.sim.time()).max(0.),
+            _ => dt,
+        };
+        let result = self.sim.step(dt);
+        self.stats.time = self.sim.time();
+
+        if let Some(locked) = &result.locked {
+            self.stats.pieces += 1;
+            self.stats.lines += locked.lines_cleared;
+            self.stats.score += lock_score(locked, self.stats.level) + drop_score(locked);
+        }
+
+        if let GameMode::Marathon { .. } = self.mode {
+            let level = self.sim.speed_curve().level(&LevelProgress {
+                lines: self.stats.lines,
+                pieces: self.stats.pieces,
+                score: self.stats.score,
+            });
+            self.sim.set_level(level);
+            self.stats.level = level;
+        }
+
+        let completed = match self.mode {
+            GameMode::Sprint { lines } | GameMode::Marathon { lines } => self.stats.lines >= lines,
+            GameMode::Ultra { time_limit } => self.stats.time >= time_limit,
+        };
+        if completed {
+            self.status = ModeStatus::Completed;
+        } else if result.topped_out {
+            self.status = ModeStatus::ToppedOut;
+        }
+
+        result
+    }
+}
+
+#[test]
+fn guideline_scores() {
+    assert_eq!(lock_score(&LockResult::test(0, SpinType::None), 3), 0);
+    assert_eq!(lock_score(&LockResult::test(4, SpinType::None), 1), 800);
+    assert_eq!(lock_score(&LockResult::test(2, SpinType::Full), 2), 2400);
+    assert_eq!(lock_score(&LockResult::test(0, SpinType::Mini), 1), 100);
+
+    let bonus = LockResult {
+        combo: 3,
+        back_to_back: true,
+        ..LockResult::test(4, SpinType::None)
+    };
+    assert_eq!(lock_score(&bonus, 1), 1200 + 100);
+}
+
+#[test]
+fn mode_completion() {
+    let mut ultra = ModeGame::new(GameMode::ultra(), ActiveField::with_seed(1), 0.5, 0.5);
+    for _ in 0..60 * 10 {
+        ultra.step(1. / 4.);
+    }
+    assert_eq!(ultra.status(), ModeStatus::Completed);
+    assert_eq!(ultra.stats().time, 120.);
+
+    // dropping pieces without clearing lines eventually tops out
+    let mut sprint = ModeGame::new(GameMode::sprint(), ActiveField::with_seed(1), 0.5, 0.5);
+    for _ in 0..1000 {
+        sprint.step(1. / 60.);
+        sprint.apply_input(Input::HardDrop);
+    }
+    assert_eq!(sprint.status(), ModeStatus::ToppedOut);
+    assert!(sprint.stats().pieces > 0);
+    assert!(!sprint.apply_input(Input::MoveLeft));
+}
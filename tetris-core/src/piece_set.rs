@@ -0,0 +1,335 @@
+//! Sets of piece types, including custom shapes.
+
+use crate::field::{PieceType, Rotation};
+use crate::geom::Point2;
+use crate::rotation::RotationSystem;
+use serde::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+
+/// The point a custom shape rotates about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RotationCenter {
+    /// Rotates about the center of the origin tile (like T pieces).
+    Origin,
+    /// Rotates about the bottom right corner of the origin tile (like I pieces).
+    Corner,
+    /// Does not rotate (like O pieces).
+    Fixed,
+}
+
+/// The tiles of a piece in each rotation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "PieceShapeData", into = "PieceShapeData")]
+pub struct PieceShape {
+    /// Tile offsets for each rotation, indexed by clockwise steps.
+    rotations: [[(i8, i8); PieceShape::MAX_TILES]; 4],
+    /// Number of tiles in the shape.
+    len: u8,
+}
+
+impl PieceShape {
+    /// The maximum number of tiles in a shape.
+    pub const MAX_TILES: usize = 8;
+
+    /// Creates a shape from its tiles in the spawn orientation.
+    ///
+    /// Returns None if there are too many tiles or they are too far from the origin.
+    pub fn new(tiles: &[(isize, isize)], center: RotationCenter) -> Option<PieceShape> {
+        if tiles.len() > Self::MAX_TILES {
+            return None;
+        }
+        let mut rotations = [[(0, 0); Self::MAX_TILES]; 4];
+        for (i, &(x, y)) in tiles.iter().enumerate() {
+            let (mut x, mut y) = (x, y);
+            for rotation in &mut rotations {
+                rotation[i] = (x.try_into().ok()?, y.try_into().ok()?);
+                (x, y) = match center {
+                    RotationCenter::Origin => (y, -x),
+                    RotationCenter::Corner => (y + 1, -x),
+                    RotationCenter::Fixed => (x, y),
+                };
+            }
+        }
+        Some(PieceShape {
+            rotations,
+            len: tiles.len() as u8,
+        })
+    }
+
+    /// Returns the shape of a built-in piece type.
+    ///
+    /// Custom piece types have an empty shape.
+    pub fn of(piece_type: PieceType) -> PieceShape {
+        let mut rotations = [[(0, 0); Self::MAX_TILES]; 4];
+        let mut len = 0;
+        for (steps, rotation) in rotations.iter_mut().enumerate() {
+            let tiles = piece_type.rotated_tiles(steps.into());
+            for (i, &(x, y)) in tiles.iter().enumerate() {
+                rotation[i] = (x as i8, y as i8);
+            }
+            len = tiles.len() as u8;
+        }
+        PieceShape { rotations, len }
+    }
+
+    /// Returns the number of tiles in this shape.
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Returns true if this shape has no tiles.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterates over the tiles of this shape in the given rotation.
+    pub fn iter_tiles(self, rotation: Rotation) -> impl Iterator<Item = Point2<isize>> {
+        let tiles = self.rotations[rotation.cw_steps()];
+        (0..self.len()).map(move |i| Point2::new(tiles[i].0 as isize, tiles[i].1 as isize))
+    }
+}
+
+/// Serialized form of a piece shape, which is checked for too many tiles.
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "PieceShape")]
+struct PieceShapeData {
+    rotations: [[(i8, i8); PieceShape::MAX_TILES]; 4],
+    len: u8,
+}
+
+impl TryFrom<PieceShapeData> for PieceShape {
+    type Error = &'static str;
+
+    fn try_from(data: PieceShapeData) -> Result<PieceShape, Self::Error> {
+        if data.len as usize > PieceShape::MAX_TILES {
+            return Err("too many tiles in piece shape");
+        }
+        Ok(PieceShape {
+            rotations: data.rotations,
+            len: data.len,
+        })
+    }
+}
+
+impl From<PieceShape> for PieceShapeData {
+    fn from(shape: PieceShape) -> PieceShapeData {
+        PieceShapeData {
+            rotations: shape.rotations,
+            len: shape.len,
+        }
+    }
+}
+
+/// The definition of a piece in a piece set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PieceDef {
+    /// The piece type.
+    pub piece_type: PieceType,
+    /// The tiles of the piece.
+    pub shape: PieceShape,
+    /// Offset that is added to the spawn position.
+    pub spawn_offset: (isize, isize),
+    /// Display color as RGBA.
+    pub color: [f32; 4],
+    /// Overrides the field’s rotation system for this piece (e.g. with a custom kick table).
+    pub rotation_system: Option<RotationSystem>,
+}
+
+/// The set of pieces that can appear in a game.
+///
+/// Each bag in the queue contains every piece of the set once.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "PieceSetData", into = "PieceSetData")]
+pub struct PieceSet {
+    pieces: Vec<PieceDef>,
+    /// Number of registered custom pieces.
+    custom_count: u8,
+}
+
+/// Serialized form of a piece set, which is checked for unregistered custom pieces.
+#[derive(Serialize, Deserialize)]
+#[serde(rename = "PieceSet")]
+struct PieceSetData {
+    pieces: Vec<PieceDef>,
+    custom_count: u8,
+}
+
+impl TryFrom<PieceSetData> for PieceSet {
+    type Error = &'static str;
+
+    fn try_from(data: PieceSetData) -> Result<PieceSet, Self::Error> {
+        let set = PieceSet {
+            pieces: data.pieces,
+            custom_count: data.custom_count,
+        };
+        set.check_pieces()?;
+        Ok(set)
+    }
+}
+
+impl From<PieceSet> for PieceSetData {
+    fn from(set: PieceSet) -> PieceSetData {
+        PieceSetData {
+            pieces: set.pieces,
+            custom_count: set.custom_count,
+        }
+    }
+}
+
+impl Default for PieceSet {
+    fn default() -> Self {
+        Self::standard()
+    }
+}
+
+impl PieceSet {
+    /// The maximum number of custom pieces in a set.
+    pub const MAX_CUSTOM: usize = 240;
+
+    /// Creates an empty piece set.
+    pub fn new() -> PieceSet {
+        PieceSet {
+            pieces: Vec::new(),
+            custom_count: 0,
+        }
+    }
+
+    /// Creates a piece set with the seven standard tetrominoes.
+    pub fn standard() -> PieceSet {
+        let mut set = PieceSet::new();
+        for piece_type in PieceType::all() {
+            set.pieces.push(PieceDef {
+                piece_type,
+                shape: PieceShape::of(piece_type),
+                spawn_offset: (0, 0),
+                color: Self::standard_color(piece_type),
+                rotation_system: None,
+            });
+        }
+        set
+    }
+
+    fn standard_color(piece_type: PieceType) -> [f32; 4] {
+        match piece_type {
+            PieceType::I => [0.26, 0.77, 0.9, 1.],
+            PieceType::J => [0.39, 0.48, 0.77, 1.],
+            PieceType::L => [0.92, 0.6, 0.31, 1.],
+            PieceType::O => [1., 0.88, 0.16, 1.],
+            PieceType::S => [0.3, 0.74, 0.42, 1.],
+            PieceType::T => [0.55, 0.38, 0.85, 1.],
+            PieceType::Z => [0.96, 0.28, 0.33, 1.],
+            PieceType::Custom(_) => [1., 1., 1., 1.],
+        }
+    }
+
+    /// Registers a custom piece and returns its piece type.
+    ///
+    /// Returns None if the set is full.
+    pub fn register(
+        &mut self,
+        shape: PieceShape,
+        spawn_offset: (isize, isize),
+        color: [f32; 4],
+        rotation_system: Option<RotationSystem>,
+    ) -> Option<PieceType> {
+        if self.custom_count as usize >= Self::MAX_CUSTOM {
+            return None;
+        }
+        let piece_type = PieceType::Custom(self.custom_count);
+        self.custom_count += 1;
+        self.pieces.push(PieceDef {
+            piece_type,
+            shape,
+            spawn_offset,
+            color,
+            rotation_system,
+        });
+        Some(piece_type)
+    }
+
+    /// Removes a built-in piece type from the set.
+    pub fn remove(&mut self, piece_type: PieceType) {
+        if !piece_type.is_custom() {
+            self.pieces.retain(|def| def.piece_type != piece_type);
+        }
+    }
+
+    /// Returns the definition of a piece type.
+    pub fn get(&self, piece_type: PieceType) -> Option<&PieceDef> {
+        self.pieces.iter().find(|def| def.piece_type == piece_type)
+    }
+
+    /// Returns all piece types in this set.
+    pub fn bag(&self) -> Vec<PieceType> {
+        self.pieces.iter().map(|def| def.piece_type).collect()
+    }
+
+    /// Returns true if this set contains no pieces.
+    pub fn is_empty(&self) -> bool {
+        self.pieces.is_empty()
+    }
+
+    /// Checks that the set can be used to deal pieces, e.g. after deserializing it.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        if self.is_empty() {
+            return Err("piece set is empty");
+        }
+        self.check_pieces()
+    }
+
+    /// Checks that every custom piece is registered and every shape fits, which must hold even
+    /// for empty sets.
+    fn check_pieces(&self) -> Result<(), &'static str> {
+        if self.custom_count as usize > Self::MAX_CUSTOM {
+            return Err("too many custom pieces");
+        }
+        for def in &self.pieces {
+            if let PieceType::Custom(id) = def.piece_type {
+                if id >= self.custom_count {
+                    return Err("unregistered custom piece");
+                }
+            }
+            if def.shape.len() > PieceShape::MAX_TILES {
+                return Err("too many tiles in piece shape");
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_shapes() {
+    let standard = PieceSet::standard();
+    assert_eq!(standard.bag(), PieceType::all());
+
+    // the standard I piece rotates like a custom shape with the same pivot
+    let i = PieceShape::new(&[(-1, 0), (0, 0), (1, 0), (2, 0)], RotationCenter::Corner).unwrap();
+    assert_eq!(i, standard.get(PieceType::I).unwrap().shape);
+
+    let mut set = PieceSet::new();
+    let pentomino = [(-2, 0), (-1, 0), (0, 0), (1, 0), (2, 0)];
+    let shape = PieceShape::new(&pentomino, RotationCenter::Origin).unwrap();
+    let ty = set.register(shape, (0, 0), [1.; 4], None).unwrap();
+    assert_eq!(ty, PieceType::Custom(0));
+    assert_eq!(set.bag(), vec![ty]);
+
+    let tiles: Vec<_> = shape.iter_tiles(Rotation::CW).map(|p| (p.x, p.y)).collect();
+    assert_eq!(tiles, vec![(0, 2), (0, 1), (0, 0), (0, -1), (0, -2)]);
+}
+
+#[test]
+fn deserialize_rejects_invalid_pieces() {
+    let mut set = PieceSet::new();
+    let shape = PieceShape::new(&[(0, 0), (1, 0)], RotationCenter::Origin).unwrap();
+    set.register(shape, (0, 0), [1.; 4], None).unwrap();
+    let json = serde_json::to_string(&set).unwrap();
+    assert_eq!(serde_json::from_str::<PieceSet>(&json).unwrap(), set);
+
+    let too_long = json.replace("\"len\":2", "\"len\":200");
+    assert!(serde_json::from_str::<PieceSet>(&too_long).is_err());
+    let unregistered = json.replace("{\"Custom\":0}", "{\"Custom\":1}");
+    assert!(serde_json::from_str::<PieceSet>(&unregistered).is_err());
+    let out_of_range = json.replace("{\"Custom\":0}", "{\"Custom\":250}");
+    assert!(serde_json::from_str::<PieceType>("{\"Custom\":250}").is_err());
+    assert!(serde_json::from_str::<PieceSet>(&out_of_range).is_err());
+}
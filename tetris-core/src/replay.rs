@@ -0,0 +1,293 @@
+//! Game replays.
+
+use crate::config::GameConfig;
+use crate::field::{ClearTimeout, Duration, Timestamp};
+use crate::sim::{Input, Simulation};
+use crate::speed::SpeedCurve;
+use serde::{Deserialize, Serialize};
+
+/// Events that are recorded in a replay.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ReplayEvent {
+    /// The simulation was advanced by some amount of time.
+    Step(Duration),
+    /// Player input was applied.
+    Input(Input),
+    /// The level was changed.
+    SetLevel(usize),
+    /// Soft drop was pressed or released.
+    SetSoftDrop(bool),
+}
+
+/// A replay event with the simulation time at which it occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ReplayEntry {
+    /// Simulation time after the event was applied.
+    pub time: Timestamp,
+    /// The event.
+    pub event: ReplayEvent,
+}
+
+/// A recording of a game, consisting of the piece seed and all events that affected the
+/// simulation.
+///
+/// Since the piece sequence is determined by the seed, re-simulating the events will produce the
+/// exact same game.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Replay {
+    seed: u64,
+    lock_delay: Duration,
+    clear_timeout: ClearTimeout,
+    soft_drop_factor: f64,
+    #[serde(default)]
+    speed_curve: SpeedCurve,
+    #[serde(default)]
+    tick_rate: Option<u32>,
+    /// Field settings of games created from a config; older replays use the defaults.
+    #[serde(default)]
+    config: Option<GameConfig>,
+    entries: Vec<ReplayEntry>,
+}
+
+impl Replay {
+    /// Creates an empty replay.
+    pub fn new(
+        seed: u64,
+        lock_delay: Duration,
+        clear_timeout: ClearTimeout,
+        soft_drop_factor: f64,
+    ) -> Replay {
+        Replay {
+            seed,
+            lock_delay,
+            clear_timeout,
+            soft_drop_factor,
+            speed_curve: SpeedCurve::default(),
+            tick_rate: None,
+            config: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the seed of the piece RNG.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns the lock delay used in the game.
+    pub fn lock_delay(&self) -> Duration {
+        self.lock_delay
+    }
+
+    /// Returns the clear timeout used in the game.
+    pub fn clear_timeout(&self) -> ClearTimeout {
+        self.clear_timeout
+    }
+
+    /// Returns the soft drop factor used in the game.
+    pub fn soft_drop_factor(&self) -> f64 {
+        self.soft_drop_factor
+    }
+
+    /// Returns the speed curve used in the game.
+    pub fn speed_curve(&self) -> &SpeedCurve {
+        &self.speed_curve
+    }
+
+    /// Sets the speed curve used in the game.
+    pub fn set_speed_curve(&mut self, speed_curve: SpeedCurve) {
+        self.speed_curve = speed_curve;
+    }
+
+    /// Returns the tick rate of the game, if it was measured in ticks.
+    pub fn tick_rate(&self) -> Option<u32> {
+        self.tick_rate
+    }
+
+    /// Sets the tick rate of the game.
+    pub fn set_tick_rate(&mut self, tick_rate: Option<u32>) {
+        self.tick_rate = tick_rate;
+    }
+
+    /// Returns the settings the game’s field was created with, if it was created from a config.
+    ///
+    /// The config’s seed is the replay’s seed.
+    pub fn config(&self) -> Option<&GameConfig> {
+        self.config.as_ref()
+    }
+
+    /// Sets the settings the game’s field was created with.
+    pub fn set_config(&mut self, config: Option<GameConfig>) {
+        self.config = config;
+    }
+
+    /// Returns all recorded entries.
+    pub fn entries(&self) -> &[ReplayEntry] {
+        &self.entries
+    }
+
+    /// Returns the time of the last recorded entry.
+    pub fn duration(&self) -> Timestamp {
+        self.entries.last().map_or(0., |entry| entry.time)
+    }
+
+    /// Appends an event that occurred at the given simulation time.
+    pub fn push(&mut self, time: Timestamp, event: ReplayEvent) {
+        let time = match event {
+            ReplayEvent::Step(dt) => time + dt,
+            _ => time,
+        };
+        self.entries.push(ReplayEntry { time, event });
+    }
+
+    /// Re-simulates the entire replay and returns the final simulation state.
+    pub fn simulate(&self) -> Simulation {
+        let mut player = ReplayPlayer::new(self.clone());
+        player.advance_to_end();
+        player.into_simulation()
+    }
+}
+
+/// Plays back a replay incrementally.
+#[derive(Debug, Clone)]
+pub struct ReplayPlayer {
+    replay: Replay,
+    sim: Simulation,
+    cursor: usize,
+}
+
+impl ReplayPlayer {
+    /// Creates a new replay player positioned at the beginning of the replay.
+    pub fn new(replay: Replay) -> ReplayPlayer {
+        ReplayPlayer {
+            sim: Simulation::for_replay(&replay),
+            replay,
+            cursor: 0,
+        }
+    }
+
+    /// Returns the replay being played.
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    /// Returns the current simulation state.
+    pub fn simulation(&self) -> &Simulation {
+        &self.sim
+    }
+
+    /// Returns the current simulation state mutably, e.g. to drain field events.
+    ///
+    /// Changing the game state will make the playback diverge from the replay.
+    pub fn simulation_mut(&mut self) -> &mut Simulation {
+        &mut self.sim
+    }
+
+    /// Consumes the player and returns the simulation state.
+    pub fn into_simulation(self) -> Simulation {
+        self.sim
+    }
+
+    /// Returns true if all events have been played back.
+    pub fn is_finished(&self) -> bool {
+        self.cursor >= self.replay.entries.len()
+    }
+
+    /// Applies all events up to and including the given simulation time.
+    pub fn advance_to(&mut self, time: Timestamp) {
+        while let Some(entry) = self.replay.entries.get(self.cursor) {
+            if entry.time > time {
+                break;
+            }
+            self.sim.apply_event(entry.event);
+            self.cursor += 1;
+        }
+    }
+
+    /// Applies all remaining events.
+    pub fn advance_to_end(&mut self) {
+        while let Some(entry) = self.replay.entries.get(self.cursor) {
+            self.sim.apply_event(entry.event);
+            self.cursor += 1;
+        }
+    }
+}
+
+#[test]
+fn replay_round_trip() {
+    use crate::field::ActiveField;
+
+    let mut sim = Simulation::new(ActiveField::with_seed(7), 0.5, 0.5);
+    sim.start_recording();
+    let inputs = [
+        Input::MoveLeft,
+        Input::RotateCW,
+        Input::HardDrop,
+        Input::Hold,
+        Input::MoveRight,
+        Input::MoveRight,
+        Input::SonicDrop,
+    ];
+    for (i, input) in inputs.iter().cycle().take(200).enumerate() {
+        sim.step(1. / 60.);
+        if i % 3 == 0 {
+            sim.apply_input(*input);
+        }
+        if i == 100 {
+            sim.set_level(4);
+        }
+    }
+    let replay = sim.stop_recording().unwrap();
+    let replayed = replay.simulate();
+
+    assert_eq!(replayed.time(), sim.time());
+    assert_eq!(
+        replayed.field().field().tiles(),
+        sim.field().field().tiles()
+    );
+    assert_eq!(replayed.field().queue(), sim.field().queue());
+    assert_eq!(replayed.field().held_piece(), sim.field().held_piece());
+}
+
+#[test]
+fn replay_round_trip_with_config() {
+    use crate::field::{EmptyHold, HoldConfig};
+
+    let config = GameConfig::builder()
+        .field_size(8, 30, 20)
+        .preview_depth(3)
+        .hold_config(HoldConfig {
+            empty_hold: EmptyHold::Redeal,
+            ..HoldConfig::default()
+        })
+        .build()
+        .unwrap();
+    let mut sim = Simulation::with_config(&config);
+    sim.start_recording();
+    let inputs = [
+        Input::MoveLeft,
+        Input::HardDrop,
+        Input::RotateCCW,
+        Input::Hold,
+        Input::MoveRight,
+        Input::HardDrop,
+    ];
+    for (i, input) in inputs.iter().cycle().take(200).enumerate() {
+        sim.step(1. / 60.);
+        if i % 3 == 0 {
+            sim.apply_input(*input);
+        }
+    }
+    let replay = sim.stop_recording().unwrap();
+    let json = serde_json::to_string(&replay).unwrap();
+    let replay: Replay = serde_json::from_str(&json).unwrap();
+    let replayed = replay.simulate();
+
+    assert_eq!(replayed.field().field().width(), 8);
+    assert_eq!(
+        replayed.field().field().tiles(),
+        sim.field().field().tiles()
+    );
+    assert_eq!(replayed.field().queue(), sim.field().queue());
+    assert_eq!(replayed.field().held_piece(), sim.field().held_piece());
+}
@@ -0,0 +1,86 @@
+//! Deterministic random number generation.
+
+use rand::{Error, RngCore};
+use serde::{Deserialize, Serialize};
+
+/// A small seedable PCG-32 random number generator.
+///
+/// Unlike the thread RNG, the output only depends on the seed, so two generators with the same
+/// seed will produce the same sequence on every platform. The state is plain data and can be
+/// serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Pcg32 {
+    state: u64,
+}
+
+impl Pcg32 {
+    const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+    const INCREMENT: u64 = 1_442_695_040_888_963_407;
+
+    /// Creates a new generator from the given seed.
+    pub fn new(seed: u64) -> Pcg32 {
+        let mut rng = Pcg32 { state: 0 };
+        rng.step();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.step();
+        rng
+    }
+
+    /// Returns the internal state, which determines all future output.
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    /// Creates a generator that continues from a state returned by [`Pcg32::state`].
+    pub fn from_state(state: u64) -> Pcg32 {
+        Pcg32 { state }
+    }
+
+    fn step(&mut self) {
+        self.state = self
+            .state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(Self::INCREMENT);
+    }
+}
+
+impl RngCore for Pcg32 {
+    fn next_u32(&mut self) -> u32 {
+        let state = self.state;
+        self.step();
+        let xorshifted = (((state >> 18) ^ state) >> 27) as u32;
+        let rot = (state >> 59) as u32;
+        xorshifted.rotate_right(rot)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let lo = u64::from(self.next_u32());
+        let hi = u64::from(self.next_u32());
+        (hi << 32) | lo
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[test]
+fn pcg32_is_deterministic() {
+    let mut a = Pcg32::new(1234);
+    let mut b = Pcg32::new(1234);
+    let mut c = Pcg32::new(1235);
+    assert_eq!(Pcg32::from_state(c.state()), c);
+    let a: Vec<_> = (0..16).map(|_| a.next_u32()).collect();
+    let b: Vec<_> = (0..16).map(|_| b.next_u32()).collect();
+    let c: Vec<_> = (0..16).map(|_| c.next_u32()).collect();
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+}
@@ -0,0 +1,217 @@
+//! Checkpoints and rollback for client-side prediction.
+
+use crate::replay::ReplayEvent;
+use crate::sim::{Input, Simulation, StepResult, Tick};
+use std::collections::VecDeque;
+
+/// Runs a tick-based simulation one tick at a time while keeping checkpoints of past states, so
+/// that inputs arriving late (or corrections from an authoritative simulation) can be applied by
+/// rewinding and re-simulating.
+///
+/// Events are buffered by tick and applied at the start of that tick, before the simulation is
+/// advanced.
+#[derive(Debug, Clone)]
+pub struct Rollback {
+    sim: Simulation,
+    /// The current tick, i.e. the next tick to be simulated.
+    tick: Tick,
+    /// Snapshots of the simulation at the start of a tick, sorted by tick.
+    checkpoints: VecDeque<(Tick, Simulation)>,
+    /// A checkpoint is taken whenever the current tick is a multiple of this.
+    interval: Tick,
+    /// The maximum number of checkpoints to keep.
+    capacity: usize,
+    /// Buffered events, sorted by tick.
+    events: VecDeque<(Tick, ReplayEvent)>,
+}
+
+impl Rollback {
+    /// Starts at tick 0 with the given simulation, which should have a tick rate.
+    ///
+    /// A checkpoint is taken every `interval` ticks, and at most `capacity` checkpoints are kept,
+    /// which limits how far back rollbacks can go.
+    pub fn new(sim: Simulation, interval: Tick, capacity: usize) -> Rollback {
+        debug_assert!(sim.tick_rate().is_some(), "simulation has no tick rate");
+        let mut checkpoints = VecDeque::new();
+        checkpoints.push_back((0, sim.clone()));
+        Rollback {
+            sim,
+            tick: 0,
+            checkpoints,
+            interval: interval.max(1),
+            capacity: capacity.max(1),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Returns the current simulation.
+    pub fn simulation(&self) -> &Simulation {
+        &self.sim
+    }
+
+    /// Returns the current tick.
+    pub fn tick(&self) -> Tick {
+        self.tick
+    }
+
+    /// Returns the earliest tick that can be rolled back to.
+    pub fn earliest_tick(&self) -> Tick {
+        self.checkpoints
+            .front()
+            .map_or(self.tick, |(tick, _)| *tick)
+    }
+
+    /// Buffers player input for the current tick.
+    pub fn input(&mut self, input: Input) {
+        self.insert_event(self.tick, ReplayEvent::Input(input));
+    }
+
+    /// Buffers an event for the given tick. `Step` events are ignored.
+    ///
+    /// If the tick has already been simulated, this rolls back and re-simulates up to the current
+    /// tick. Returns false if the tick is too far in the past.
+    pub fn insert_event(&mut self, tick: Tick, event: ReplayEvent) -> bool {
+        if let ReplayEvent::Step(_) = event {
+            return true;
+        }
+        if tick < self.earliest_tick() {
+            return false;
+        }
+        let index = self.events.partition_point(|(t, _)| *t <= tick);
+        self.events.insert(index, (tick, event));
+
+        if tick < self.tick {
+            let now = self.tick;
+            self.rollback_to(tick);
+            self.advance_to(now);
+        }
+        true
+    }
+
+    /// Replaces the state at the start of the given tick with an authoritative one, then
+    /// re-simulates up to the current tick.
+    ///
+    /// Returns false if the tick is too far in the past.
+    pub fn correct(&mut self, tick: Tick, sim: Simulation) -> bool {
+        if tick < self.earliest_tick() {
+            return false;
+        }
+        let now = self.tick.max(tick);
+        self.checkpoints.retain(|(t, _)| *t < tick);
+        self.checkpoints.push_back((tick, sim.clone()));
+        self.sim = sim;
+        self.tick = tick;
+        self.advance_to(now);
+        true
+    }
+
+    /// Rewinds to the state at the start of the given tick, using the closest earlier checkpoint
+    /// and re-applying buffered events.
+    ///
+    /// Buffered events for later ticks are kept and will be applied again when advancing.
+    /// Returns false if the tick is too far in the past or in the future.
+    pub fn rollback_to(&mut self, tick: Tick) -> bool {
+        if tick > self.tick {
+            return false;
+        }
+        let index = match self.checkpoints.iter().rposition(|(t, _)| *t <= tick) {
+            Some(index) => index,
+            None => return false,
+        };
+        self.checkpoints.truncate(index + 1);
+        let (checkpoint_tick, sim) = &self.checkpoints[index];
+        self.tick = *checkpoint_tick;
+        self.sim = sim.clone();
+        self.advance_to(tick);
+        true
+    }
+
+    /// Applies buffered events for the current tick and advances the simulation by one tick.
+    pub fn advance(&mut self) -> StepResult {
+        let start = self.events.partition_point(|(t, _)| *t < self.tick);
+        for (tick, event) in self.events.iter().skip(start) {
+            if *tick != self.tick {
+                break;
+            }
+            self.sim.apply_event(*event);
+        }
+
+        let result = self.sim.step_ticks(1);
+        self.tick += 1;
+        if self.tick.is_multiple_of(self.interval) {
+            self.checkpoint();
+        }
+        result
+    }
+
+    /// Advances until the given tick.
+    fn advance_to(&mut self, tick: Tick) {
+        while self.tick < tick {
+            self.advance();
+        }
+    }
+
+    /// Takes a checkpoint at the current tick and discards old checkpoints and events.
+    fn checkpoint(&mut self) {
+        let tick = self.tick;
+        self.checkpoints.retain(|(t, _)| *t < tick);
+        self.checkpoints.push_back((tick, self.sim.clone()));
+        while self.checkpoints.len() > self.capacity {
+            self.checkpoints.pop_front();
+        }
+        let earliest = self.earliest_tick();
+        while self.events.front().is_some_and(|(t, _)| *t < earliest) {
+            self.events.pop_front();
+        }
+    }
+}
+
+#[test]
+fn rollback_late_input() {
+    use crate::field::ActiveField;
+
+    let sim = Simulation::with_tick_rate(ActiveField::with_seed(3), 30, 30, 60);
+    let mut predicted = Rollback::new(sim.clone(), 10, 8);
+    let mut authoritative = Rollback::new(sim, 10, 8);
+
+    for tick in 0..40 {
+        if tick == 12 {
+            authoritative.input(Input::MoveLeft);
+        }
+        if tick == 15 {
+            authoritative.input(Input::HardDrop);
+            predicted.input(Input::HardDrop);
+        }
+        authoritative.advance();
+        predicted.advance();
+    }
+    assert_ne!(
+        predicted.simulation().field().state_hash(),
+        authoritative.simulation().field().state_hash()
+    );
+
+    // the input arrives late
+    assert!(predicted.insert_event(12, ReplayEvent::Input(Input::MoveLeft)));
+    assert_eq!(predicted.tick(), 40);
+    assert_eq!(
+        predicted.simulation().field().state_hash(),
+        authoritative.simulation().field().state_hash()
+    );
+
+    // corrections replace the predicted state
+    let mut corrected = Rollback::new(
+        Simulation::with_tick_rate(ActiveField::with_seed(3), 30, 30, 60),
+        10,
+        8,
+    );
+    corrected.advance_to(40);
+    assert!(corrected.correct(40, authoritative.simulation().clone()));
+    assert_eq!(
+        corrected.simulation().field().state_hash(),
+        authoritative.simulation().field().state_hash()
+    );
+
+    assert!(!predicted.rollback_to(41));
+    assert!(predicted.rollback_to(25));
+    assert_eq!(predicted.tick(), 25);
+}
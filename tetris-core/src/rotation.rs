@@ -0,0 +1,161 @@
+//! Rotation systems and wall kick tables.
+
+use crate::field::{PieceType, Rotation};
+use serde::{Deserialize, Serialize};
+
+/// Rotation systems, which determine the wall kicks that are tried when a piece is rotated.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub enum RotationSystem {
+    /// The Super Rotation System used by guideline games.
+    #[default]
+    Srs,
+    /// Arika-style kicks: the piece is nudged one tile to the right or left if the rotation is
+    /// blocked, and I pieces never kick.
+    ///
+    /// Note that this only affects the kicks; pieces still use the SRS orientations.
+    Ars,
+    /// No wall kicks at all, as in the classic Nintendo games.
+    Nintendo,
+    /// A custom kick table.
+    Custom(KickTable),
+}
+
+/// Offsets tried in order when no kicks are allowed.
+const NO_KICKS: &[(isize, isize)] = &[(0, 0)];
+
+impl RotationSystem {
+    /// Returns the list of offsets that should be tried in order when rotating a piece.
+    ///
+    /// The first offset that does not collide is used. Empty if the rotation is not possible.
+    pub fn kicks(&self, piece_type: PieceType, from: Rotation, to: Rotation) -> &[(isize, isize)] {
+        match self {
+            RotationSystem::Srs => srs_kicks(piece_type, from, to),
+            RotationSystem::Ars => match piece_type {
+                PieceType::I | PieceType::O => NO_KICKS,
+                _ => &[(0, 0), (1, 0), (-1, 0)],
+            },
+            RotationSystem::Nintendo => NO_KICKS,
+            RotationSystem::Custom(table) => table.kicks(piece_type, from, to),
+        }
+    }
+}
+
+/// Returns the SRS kick table, or an empty list if the rotation is invalid.
+fn srs_kicks(piece_type: PieceType, from: Rotation, to: Rotation) -> &'static [(isize, isize)] {
+    const TABLE_INDEX: [(Rotation, Rotation, usize); 8] = [
+        (Rotation::None, Rotation::CW, 0),
+        (Rotation::CW, Rotation::None, 1),
+        (Rotation::CW, Rotation::Flip, 2),
+        (Rotation::Flip, Rotation::CW, 3),
+        (Rotation::Flip, Rotation::CCW, 4),
+        (Rotation::CCW, Rotation::Flip, 5),
+        (Rotation::CCW, Rotation::None, 6),
+        (Rotation::None, Rotation::CCW, 7),
+    ];
+    const KICKS_I: [&[(isize, isize)]; 8] = [
+        &[(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+        &[(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+        &[(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+        &[(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+        &[(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+        &[(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+        &[(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+        &[(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+    ];
+    const KICKS_JLSTZ: [&[(isize, isize)]; 8] = [
+        &[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+        &[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+        &[(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+        &[(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+        &[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+        &[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+        &[(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+        &[(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+    ];
+
+    let table_index = TABLE_INDEX.iter().find(|(f, t, _)| *f == from && *t == to);
+    match table_index {
+        Some((_, _, index)) => match piece_type {
+            PieceType::I => KICKS_I[*index],
+            PieceType::O => NO_KICKS,
+            PieceType::J
+            | PieceType::L
+            | PieceType::S
+            | PieceType::T
+            | PieceType::Z
+            | PieceType::Custom(_) => KICKS_JLSTZ[*index],
+        },
+        None => &[],
+    }
+}
+
+/// A custom wall kick table.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KickTable {
+    entries: Vec<KickTableEntry>,
+}
+
+/// A single entry in a custom kick table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KickTableEntry {
+    /// The piece type this entry applies to, or None if it applies to all pieces.
+    pub piece_type: Option<PieceType>,
+    /// The rotation before rotating.
+    pub from: Rotation,
+    /// The rotation after rotating.
+    pub to: Rotation,
+    /// Offsets that will be tried in order.
+    pub kicks: Vec<(isize, isize)>,
+}
+
+impl KickTable {
+    /// Creates an empty kick table, in which no rotation will kick.
+    pub fn new() -> KickTable {
+        KickTable::default()
+    }
+
+    /// Adds an entry to the table.
+    ///
+    /// Entries added earlier take precedence over later ones.
+    pub fn add_entry(&mut self, entry: KickTableEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Returns the kicks for the given rotation, falling back to no kicks if there is no entry.
+    pub fn kicks(&self, piece_type: PieceType, from: Rotation, to: Rotation) -> &[(isize, isize)] {
+        self.entries
+            .iter()
+            .find(|entry| {
+                entry.piece_type.is_none_or(|t| t == piece_type)
+                    && entry.from == from
+                    && entry.to == to
+            })
+            .map_or(NO_KICKS, |entry| &entry.kicks)
+    }
+}
+
+#[test]
+fn srs_kick_symmetry() {
+    // kicks for the reverse rotation must be the negated kicks
+    for piece_type in PieceType::all() {
+        for from in 0..4 {
+            let from = Rotation::from(from);
+            for dir in &[1, -1] {
+                let to = from + *dir;
+                let kicks = RotationSystem::Srs.kicks(piece_type, from, to);
+                let back = RotationSystem::Srs.kicks(piece_type, to, from);
+                assert_eq!(kicks.len(), back.len());
+                for (a, b) in kicks.iter().zip(back) {
+                    assert_eq!(
+                        (a.0, a.1),
+                        (-b.0, -b.1),
+                        "{:?} {:?} -> {:?}",
+                        piece_type,
+                        from,
+                        to
+                    );
+                }
+            }
+        }
+    }
+}
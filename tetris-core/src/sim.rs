@@ -0,0 +1,539 @@
+//! Time-based game simulation.
+
+use crate::config::GameConfig;
+use crate::field::{
+    ActiveField, ClearTimeout, Duration, HoldResult, LockResult, SpawnInput, Timestamp,
+};
+use crate::replay::{Replay, ReplayEvent};
+use crate::speed::SpeedCurve;
+use serde::{Deserialize, Serialize};
+
+/// A number of frames in a simulation with a tick rate.
+pub type Tick = u64;
+
+/// Returns the time between gravity steps at the given level, following the guideline formula.
+pub fn gravity_interval(level: usize) -> Duration {
+    let level = level.max(1) as f64;
+    (0.8 - ((level - 1.) * 0.007)).powf(level - 1.)
+}
+
+/// Player inputs that can be applied to a simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Input {
+    /// Moves the active piece left.
+    MoveLeft,
+    /// Moves the active piece right.
+    MoveRight,
+    /// Moves the active piece down by one row.
+    MoveDown,
+    /// Moves the active piece all the way down without locking it.
+    SonicDrop,
+    /// Moves the active piece all the way down and locks it.
+    HardDrop,
+    /// Rotates the active piece clockwise.
+    RotateCW,
+    /// Rotates the active piece counter-clockwise.
+    RotateCCW,
+    /// Swaps the active piece with the held piece.
+    Hold,
+}
+
+/// Runs gravity, auto-locking, spawning and line clears for an active field.
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    /// The simulated field.
+    field: ActiveField,
+    /// Current simulation time.
+    time: Timestamp,
+    /// Time left until the next gravity step.
+    step_cooldown: Duration,
+    /// If set, time is measured in integer ticks at this rate per second instead of seconds.
+    tick_rate: Option<u32>,
+    /// The current level, which determines the gravity speed.
+    level: usize,
+    /// Gravity speed at each level.
+    speed_curve: SpeedCurve,
+    /// Whether soft drop is currently being held.
+    soft_drop: bool,
+    /// Gravity speed multiplier while soft dropping.
+    soft_drop_factor: f64,
+    /// Time a piece may rest on the ground before being locked.
+    lock_delay: Duration,
+    /// Time after which cleared lines are removed.
+    clear_timeout: ClearTimeout,
+    /// Whether the field has topped out.
+    is_game_over: bool,
+    /// Rotation and hold inputs received while there was no active piece.
+    spawn_input: SpawnInput,
+    /// Result of a hard drop lock that has not been reported in a step yet.
+    pending_lock: Option<LockResult>,
+    /// The replay being recorded, if any.
+    recording: Option<Replay>,
+    /// The settings the field was created with, which replays need to recreate it.
+    config: Option<GameConfig>,
+}
+
+/// Things that happened during a single simulation step.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StepResult {
+    /// True if gravity moved the active piece down.
+    pub moved_down: bool,
+    /// Set if the active piece was locked.
+    pub locked: Option<LockResult>,
+    /// The number of lines that were cleared.
+    pub cleared_lines: usize,
+    /// True if the field topped out during this step.
+    pub topped_out: bool,
+}
+
+impl Simulation {
+    /// The default multiplier for gravity speed while soft dropping.
+    pub const SOFT_DROP_FACTOR: f64 = 20.;
+
+    /// Creates a new simulation at level 1, starting at time 0.
+    pub fn new(
+        field: ActiveField,
+        lock_delay: Duration,
+        clear_timeout: impl Into<ClearTimeout>,
+    ) -> Simulation {
+        Simulation {
+            field,
+            time: 0.,
+            step_cooldown: gravity_interval(1),
+            tick_rate: None,
+            level: 1,
+            speed_curve: SpeedCurve::default(),
+            soft_drop: false,
+            soft_drop_factor: Self::SOFT_DROP_FACTOR,
+            lock_delay,
+            clear_timeout: clear_timeout.into(),
+            is_game_over: false,
+            spawn_input: SpawnInput::default(),
+            pending_lock: None,
+            recording: None,
+            config: None,
+        }
+    }
+
+    /// Creates a new simulation at level 1 that measures time in integer ticks at the given rate
+    /// per second, starting at tick 0.
+    ///
+    /// All times, including the timestamps passed to the field, are then tick counts. Since these
+    /// are whole numbers, the simulation is bit-exact regardless of the platform or of how the
+    /// caller measures real time.
+    pub fn with_tick_rate(
+        field: ActiveField,
+        lock_delay: Tick,
+        clear_timeout: Tick,
+        tick_rate: u32,
+    ) -> Simulation {
+        let mut sim = Simulation::new(field, lock_delay as Duration, clear_timeout as Duration);
+        sim.tick_rate = Some(tick_rate);
+        sim.step_cooldown = sim.gravity_interval();
+        sim
+    }
+
+    /// Creates a new simulation at level 1 with the given settings.
+    pub fn with_config(config: &GameConfig) -> Simulation {
+        let config = config.with_fixed_seed();
+        let mut sim = Simulation::new(
+            ActiveField::with_config(&config),
+            config.lock_delay(),
+            config.clear_timeout(),
+        );
+        sim.set_speed_curve(config.speed_curve().clone());
+        sim.config = Some(config);
+        sim
+    }
+
+    /// Creates a simulation that will play back the given replay from the beginning.
+    pub(crate) fn for_replay(replay: &Replay) -> Simulation {
+        let field = match replay.config() {
+            Some(config) => ActiveField::with_config(config),
+            None => ActiveField::with_seed(replay.seed()),
+        };
+        let mut sim = Simulation::new(field, replay.lock_delay(), replay.clear_timeout());
+        sim.config = replay.config().cloned();
+        sim.soft_drop_factor = replay.soft_drop_factor();
+        sim.tick_rate = replay.tick_rate();
+        sim.set_speed_curve(replay.speed_curve().clone());
+        sim
+    }
+
+    /// Returns the simulated field.
+    pub fn field(&self) -> &ActiveField {
+        &self.field
+    }
+
+    /// Returns the simulated field mutably, e.g. for running player input.
+    pub fn field_mut(&mut self) -> &mut ActiveField {
+        &mut self.field
+    }
+
+    /// Returns the tick rate, if time is measured in ticks.
+    pub fn tick_rate(&self) -> Option<u32> {
+        self.tick_rate
+    }
+
+    /// Returns the current simulation time.
+    pub fn time(&self) -> Timestamp {
+        self.time
+    }
+
+    /// Returns the current level.
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    /// Sets the current level.
+    pub fn set_level(&mut self, level: usize) {
+        if level != self.level {
+            self.record(ReplayEvent::SetLevel(level));
+        }
+        self.level = level;
+        // don't wait for the slower gravity of the previous level
+        self.step_cooldown = self.step_cooldown.min(self.gravity_interval());
+    }
+
+    /// Returns the speed curve.
+    pub fn speed_curve(&self) -> &SpeedCurve {
+        &self.speed_curve
+    }
+
+    /// Sets the speed curve, which determines the gravity speed at each level.
+    ///
+    /// This should be called before the first step, like [`Simulation::start_recording`].
+    pub fn set_speed_curve(&mut self, speed_curve: SpeedCurve) {
+        self.speed_curve = speed_curve;
+        self.step_cooldown = self.gravity_interval();
+    }
+
+    /// Returns the time after which cleared lines are removed.
+    pub fn clear_timeout(&self) -> ClearTimeout {
+        self.clear_timeout
+    }
+
+    /// Sets the time after which cleared lines are removed.
+    pub fn set_clear_timeout(&mut self, clear_timeout: impl Into<ClearTimeout>) {
+        self.clear_timeout = clear_timeout.into();
+    }
+
+    /// Returns true if soft drop is being held.
+    pub fn is_soft_dropping(&self) -> bool {
+        self.soft_drop
+    }
+
+    /// Sets whether soft drop is being held, which speeds up gravity.
+    pub fn set_soft_drop(&mut self, soft_drop: bool) {
+        if soft_drop != self.soft_drop {
+            self.record(ReplayEvent::SetSoftDrop(soft_drop));
+        }
+        if soft_drop && !self.soft_drop {
+            self.step_cooldown = self.step_cooldown.min(self.gravity_interval());
+        }
+        self.soft_drop = soft_drop;
+    }
+
+    /// Sets the gravity speed multiplier while soft dropping.
+    pub fn set_soft_drop_factor(&mut self, factor: f64) {
+        self.soft_drop_factor = factor;
+    }
+
+    /// Returns true if the field has topped out.
+    pub fn is_game_over(&self) -> bool {
+        self.is_game_over
+    }
+
+    /// Returns how far the active piece is into its lock delay at the current time, from 0 to 1.
+    ///
+    /// Returns None if there is no active piece or it isn’t on the ground.
+    pub fn lock_progress(&self) -> Option<f64> {
+        self.field.lock_progress(self.lock_delay, self.time)
+    }
+
+    /// Returns the current time between gravity steps, taking soft drop into account.
+    ///
+    /// With a tick rate, this is rounded to whole ticks, unless it's shorter than a tick. Then the
+    /// fraction is kept so that pieces fall several rows per tick instead of instantly.
+    pub fn gravity_interval(&self) -> Duration {
+        let mut interval = self.speed_curve.gravity_interval(self.level);
+        if self.soft_drop {
+            interval /= self.soft_drop_factor;
+        }
+        match self.tick_rate {
+            Some(tick_rate) => {
+                let ticks = interval * tick_rate as f64;
+                if ticks >= 1. {
+                    ticks.round()
+                } else {
+                    ticks
+                }
+            }
+            None => interval,
+        }
+    }
+
+    /// Starts recording a replay.
+    ///
+    /// This should be called before the first step, since the replay will start from a fresh
+    /// field with the same seed.
+    pub fn start_recording(&mut self) {
+        let mut replay = Replay::new(
+            self.field.seed(),
+            self.lock_delay,
+            self.clear_timeout,
+            self.soft_drop_factor,
+        );
+        replay.set_speed_curve(self.speed_curve.clone());
+        replay.set_tick_rate(self.tick_rate);
+        replay.set_config(self.config.clone());
+        if self.level != 1 {
+            replay.push(self.time, ReplayEvent::SetLevel(self.level));
+        }
+        self.recording = Some(replay);
+    }
+
+    /// Returns the replay being recorded, if any.
+    pub fn recording(&self) -> Option<&Replay> {
+        self.recording.as_ref()
+    }
+
+    /// Stops recording and returns the recorded replay.
+    pub fn stop_recording(&mut self) -> Option<Replay> {
+        self.recording.take()
+    }
+
+    /// Continues recording into a replay returned by [`Simulation::stop_recording`], which must
+    /// have been recorded up to the current state.
+    pub fn resume_recording(&mut self, replay: Replay) {
+        self.recording = Some(replay);
+    }
+
+    fn record(&mut self, event: ReplayEvent) {
+        if let Some(replay) = &mut self.recording {
+            replay.push(self.time, event);
+        }
+    }
+
+    /// Applies a replay event without recording it.
+    pub(crate) fn apply_event(&mut self, event: ReplayEvent) {
+        let recording = self.recording.take();
+        match event {
+            ReplayEvent::Step(dt) => {
+                self.step(dt);
+            }
+            ReplayEvent::Input(input) => {
+                self.apply_input(input);
+            }
+            ReplayEvent::SetLevel(level) => self.set_level(level),
+            ReplayEvent::SetSoftDrop(soft_drop) => self.set_soft_drop(soft_drop),
+        }
+        self.recording = recording;
+    }
+
+    /// Applies player input to the field at the current simulation time.
+    ///
+    /// Rotation and hold inputs received while there is no active piece are buffered and applied
+    /// when the next piece spawns.
+    ///
+    /// Returns false if the input had no effect, e.g. because the piece was blocked.
+    pub fn apply_input(&mut self, input: Input) -> bool {
+        self.record(ReplayEvent::Input(input));
+
+        let time = self.time;
+        let field = &mut self.field;
+        if field.active_piece().is_none() {
+            match input {
+                Input::RotateCW => self.spawn_input.rotation += 1,
+                Input::RotateCCW => self.spawn_input.rotation -= 1,
+                Input::Hold => self.spawn_input.hold = true,
+                _ => return false,
+            }
+            return true;
+        }
+        match input {
+            Input::MoveLeft => field.move_active_left(time).is_success(),
+            Input::MoveRight => field.move_active_right(time).is_success(),
+            Input::MoveDown => field.soft_drop_active(time).is_success(),
+            Input::SonicDrop => {
+                let prev_pos = field.active_piece().map(|piece| piece.pos());
+                field.sonic_drop_active(time);
+                field.active_piece().map(|piece| piece.pos()) != prev_pos
+            }
+            Input::HardDrop => {
+                self.pending_lock = field.hard_drop_and_lock(time);
+                true
+            }
+            Input::RotateCW => field.rotate_active_cw(time).is_success(),
+            Input::RotateCCW => field.rotate_active_ccw(time).is_success(),
+            Input::Hold => {
+                let result = field.swap_held_piece(time);
+                result != HoldResult::AlreadyHeld && result != HoldResult::NoActivePiece
+            }
+        }
+    }
+
+    /// Spawns the next piece, applying buffered spawn inputs.
+    fn spawn_active(&mut self, time: Timestamp) {
+        let input = core::mem::take(&mut self.spawn_input);
+        self.field.spawn_active_with(None, input, time);
+    }
+
+    /// Advances a simulation with a tick rate by the given number of ticks.
+    pub fn step_ticks(&mut self, ticks: Tick) -> StepResult {
+        debug_assert!(self.tick_rate.is_some(), "simulation has no tick rate");
+        self.step(ticks as Duration)
+    }
+
+    /// Advances the simulation by the given amount of time.
+    pub fn step(&mut self, dt: Duration) -> StepResult {
+        let mut result = StepResult::default();
+        if self.is_game_over {
+            return result;
+        }
+
+        self.time += dt;
+        self.record(ReplayEvent::Step(dt));
+        let time = self.time;
+        result.locked = self.pending_lock.take();
+
+        if self.field.active_piece().is_none() {
+            self.spawn_active(time);
+        }
+
+        // gravity pauses during a zone
+        if !self.field.is_in_zone() {
+            self.step_cooldown -= dt;
+        }
+        if self.step_cooldown <= 0. {
+            let prev_pos = self.field.active_piece().map(|piece| piece.pos());
+            let interval = self.gravity_interval();
+            // at high gravity, the piece may fall several rows in a single step
+            let mut rows = 0;
+            while self.step_cooldown <= 0. && rows < self.field.field().height() {
+                let moved = if self.soft_drop {
+                    self.field.soft_drop_active(time)
+                } else {
+                    self.field.move_active_down(time)
+                };
+                if !moved.is_success() {
+                    break;
+                }
+                self.step_cooldown += interval;
+                rows += 1;
+            }
+            if self.step_cooldown <= 0. {
+                self.step_cooldown = interval;
+            }
+            result.moved_down = self.field.active_piece().map(|piece| piece.pos()) != prev_pos;
+        }
+
+        if self.field.should_lock_active(self.lock_delay, time) {
+            result.locked = self.field.lock_active();
+            self.spawn_active(time);
+        }
+
+        result.cleared_lines = self.field.clear_lines(self.clear_timeout, time).count();
+
+        if self.field.is_top_out() {
+            self.is_game_over = true;
+            result.topped_out = true;
+        }
+
+        result
+    }
+}
+
+#[test]
+fn simulation_gravity() {
+    let mut sim = Simulation::new(ActiveField::with_seed(1), 0.5, 0.5);
+    sim.step(0.);
+    let start = sim.field().active_piece().unwrap().pos();
+    let result = sim.step(gravity_interval(1));
+    assert!(result.moved_down);
+    assert_eq!(sim.field().active_piece().unwrap().pos().y, start.y - 1);
+
+    // the piece should eventually land and lock
+    let mut locked = false;
+    for _ in 0..60 * 30 {
+        if sim.step(1. / 60.).locked.is_some() {
+            locked = true;
+            break;
+        }
+    }
+    assert!(locked);
+}
+
+#[test]
+fn simulation_20g() {
+    let mut sim = Simulation::new(ActiveField::with_seed(1), 0.5, 0.5);
+    sim.set_speed_curve(SpeedCurve::tgm_master());
+    sim.set_level(500);
+    // pieces land as soon as they spawn
+    assert!(sim.step(0.).moved_down);
+    let piece = *sim.field().active_piece().unwrap();
+    assert!(piece.is_on_ground(sim.field().field()));
+}
+
+#[test]
+fn simulation_ticks() {
+    let mut sim = Simulation::with_tick_rate(ActiveField::with_seed(1), 30, 31, 60);
+    sim.start_recording();
+    sim.step_ticks(0);
+    let start = sim.field().active_piece().unwrap().pos();
+    assert!(!sim.step_ticks(59).moved_down);
+    assert!(sim.step_ticks(1).moved_down);
+    assert_eq!(sim.field().active_piece().unwrap().pos().y, start.y - 1);
+    assert_eq!(sim.time(), 60.);
+
+    for i in 0..600 {
+        if i % 7 == 0 {
+            sim.apply_input(Input::MoveLeft);
+        }
+        if i % 40 == 0 {
+            sim.apply_input(Input::HardDrop);
+        }
+        sim.step_ticks(1);
+    }
+
+    // re-simulating the replay gives the exact same state
+    let replayed = sim.recording().unwrap().simulate();
+    assert_eq!(replayed.tick_rate(), Some(60));
+    assert_eq!(replayed.time(), sim.time());
+    assert_eq!(replayed.field().state_hash(), sim.field().state_hash());
+}
+
+#[test]
+fn simulation_ticks_fast_gravity() {
+    let mut sim = Simulation::with_tick_rate(ActiveField::with_seed(1), 30, 31, 60);
+    // about 2.4 rows per tick
+    sim.set_level(15);
+    assert!(sim.gravity_interval() > 0.);
+    sim.step_ticks(0);
+    let start = sim.field().active_piece().unwrap().pos();
+    assert!(sim.step_ticks(1).moved_down);
+    assert_eq!(sim.field().active_piece().unwrap().pos().y, start.y - 2);
+}
+
+#[test]
+fn simulation_drop_cells() {
+    let mut sim = Simulation::new(ActiveField::with_seed(1), 0.5, 0.5);
+    sim.step(0.);
+    let start = sim.field().active_piece().unwrap().pos().y;
+    sim.apply_input(Input::MoveDown);
+    sim.set_soft_drop(true);
+    sim.step(gravity_interval(1));
+    sim.set_soft_drop(false);
+    let y = sim.field().active_piece().unwrap().pos().y;
+    assert_eq!(start - y, 2);
+
+    let ghost = sim.field().ghost_pos().unwrap().y;
+    sim.apply_input(Input::HardDrop);
+    let locked = sim.step(0.).locked.unwrap();
+    assert_eq!(locked.soft_drop_cells, 2);
+    assert_eq!(locked.hard_drop_cells as isize, y - ghost);
+    assert_eq!(
+        crate::modes::drop_score(&locked),
+        2 + 2 * (y - ghost) as usize
+    );
+}
@@ -0,0 +1,210 @@
+//! Level progression and gravity speed.
+
+use crate::field::Duration;
+use crate::sim::gravity_interval;
+use serde::{Deserialize, Serialize};
+
+/// Frame rate that frame-based gravity tables are specified in.
+const FRAME_RATE: f64 = 60.;
+
+/// Gravity of 1G (one row per frame) in the units of [`Gravity::Thresholds`].
+pub const ONE_G: u32 = 256;
+
+/// Gravity at which pieces fall through the entire visible field within a frame, and are thus
+/// dropped to the ground instantly.
+pub const MAX_GRAVITY: u32 = 20 * ONE_G;
+
+/// Progress made in a game, from which the level is derived.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LevelProgress {
+    /// Number of cleared lines.
+    pub lines: usize,
+    /// Number of locked pieces.
+    pub pieces: usize,
+    /// Current score.
+    pub score: usize,
+}
+
+/// How the level increases over the course of a game.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Leveling {
+    /// Starts at level 1 and goes up by one every given number of lines.
+    Lines(usize),
+    /// Starts at level 1 and grows logarithmically with the score.
+    Score,
+    /// Starts at level 0 and goes up by one for every locked piece and every cleared line.
+    PiecesAndLines,
+}
+
+/// How fast pieces fall at each level.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Gravity {
+    /// The guideline formula.
+    Guideline,
+    /// Frames per row at 60 FPS, starting at level 1. Levels past the end use the last entry.
+    Frames(Vec<u32>),
+    /// Gravity in 1/256 rows per frame at 60 FPS, starting at the given levels, sorted by level.
+    /// Levels before the first entry use the first entry.
+    Thresholds(Vec<(usize, u32)>),
+}
+
+/// Determines the level and the gravity speed at each level.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SpeedCurve {
+    /// How the level increases.
+    pub leveling: Leveling,
+    /// Gravity at each level.
+    pub gravity: Gravity,
+    /// The highest reachable level, if any.
+    pub max_level: Option<usize>,
+}
+
+impl Default for SpeedCurve {
+    fn default() -> Self {
+        Self::guideline_marathon()
+    }
+}
+
+impl SpeedCurve {
+    /// Guideline marathon: a level every 10 lines, up to level 15.
+    pub fn guideline_marathon() -> SpeedCurve {
+        SpeedCurve {
+            leveling: Leveling::Lines(10),
+            gravity: Gravity::Guideline,
+            max_level: Some(15),
+        }
+    }
+
+    /// NES: a level every 10 lines, with the NTSC gravity table.
+    pub fn nes() -> SpeedCurve {
+        SpeedCurve {
+            leveling: Leveling::Lines(10),
+            gravity: Gravity::Frames(vec![
+                48, 43, 38, 33, 28, 23, 18, 13, 8, 6, 5, 5, 5, 4, 4, 4, 3, 3, 3, 2, 2, 2, 2, 2, 2,
+                2, 2, 2, 2, 1,
+            ]),
+            max_level: None,
+        }
+    }
+
+    /// TGM master mode: levels 0 to 999, reaching 20G at level 500.
+    pub fn tgm_master() -> SpeedCurve {
+        SpeedCurve {
+            leveling: Leveling::PiecesAndLines,
+            gravity: Gravity::Thresholds(vec![
+                (0, 4),
+                (30, 6),
+                (35, 8),
+                (40, 10),
+                (50, 12),
+                (60, 16),
+                (70, 32),
+                (80, 48),
+                (90, 64),
+                (100, 80),
+                (120, 96),
+                (140, 112),
+                (160, 128),
+                (170, 144),
+                (200, 4),
+                (220, 32),
+                (230, 64),
+                (233, 96),
+                (236, 128),
+                (239, 160),
+                (243, 192),
+                (247, 224),
+                (251, 256),
+                (300, 512),
+                (330, 768),
+                (360, 1024),
+                (400, 1280),
+                (420, 1024),
+                (450, 768),
+                (500, MAX_GRAVITY),
+            ]),
+            max_level: Some(999),
+        }
+    }
+
+    /// The score-based curve used in multiplayer rooms.
+    pub fn score() -> SpeedCurve {
+        SpeedCurve {
+            leveling: Leveling::Score,
+            gravity: Gravity::Guideline,
+            max_level: None,
+        }
+    }
+
+    /// Returns the level for the given progress.
+    pub fn level(&self, progress: &LevelProgress) -> usize {
+        let level = match self.leveling {
+            Leveling::Lines(per_level) => 1 + progress.lines / per_level.max(1),
+            Leveling::Score => {
+                ((progress.score as f64 / 1000.).powf(1.4) + 2.).ln().ceil() as usize
+            }
+            Leveling::PiecesAndLines => progress.pieces + progress.lines,
+        };
+        match self.max_level {
+            Some(max_level) => level.min(max_level),
+            None => level,
+        }
+    }
+
+    /// Returns the time between gravity steps at the given level.
+    ///
+    /// At gravity faster than one row per frame, this is shorter than a frame; a zero interval
+    /// drops pieces to the ground instantly.
+    pub fn gravity_interval(&self, level: usize) -> Duration {
+        match &self.gravity {
+            Gravity::Guideline => gravity_interval(level),
+            Gravity::Frames(table) => {
+                let index = level.saturating_sub(1);
+                let frames = table.get(index).or_else(|| table.last()).copied();
+                frames.unwrap_or(1) as f64 / FRAME_RATE
+            }
+            Gravity::Thresholds(table) => {
+                let gravity = table
+                    .iter()
+                    .take_while(|(start, _)| *start <= level)
+                    .last()
+                    .or_else(|| table.first())
+                    .map_or(ONE_G, |(_, gravity)| *gravity);
+                if gravity == 0 {
+                    Duration::INFINITY
+                } else if gravity >= MAX_GRAVITY {
+                    0.
+                } else {
+                    ONE_G as f64 / (gravity as f64 * FRAME_RATE)
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn speed_curves() {
+    let marathon = SpeedCurve::guideline_marathon();
+    let progress = |lines, pieces, score| LevelProgress {
+        lines,
+        pieces,
+        score,
+    };
+    assert_eq!(marathon.level(&progress(0, 0, 0)), 1);
+    assert_eq!(marathon.level(&progress(25, 0, 0)), 3);
+    assert_eq!(marathon.level(&progress(1000, 0, 0)), 15);
+    assert_eq!(marathon.gravity_interval(1), 1.);
+
+    let nes = SpeedCurve::nes();
+    assert_eq!(nes.gravity_interval(1), 48. / 60.);
+    assert_eq!(nes.gravity_interval(100), 1. / 60.);
+
+    let tgm = SpeedCurve::tgm_master();
+    assert_eq!(tgm.level(&progress(4, 30, 0)), 34);
+    assert_eq!(tgm.gravity_interval(0), 64. / 60.);
+    assert_eq!(tgm.gravity_interval(251), 1. / 60.);
+    assert_eq!(tgm.gravity_interval(999), 0.);
+
+    assert_eq!(SpeedCurve::score().level(&progress(0, 0, 0)), 1);
+}
@@ -0,0 +1,145 @@
+//! Statistics collected over the course of a game.
+
+use crate::field::{Duration, LockResult, SpinType};
+use serde::{Deserialize, Serialize};
+
+/// Number of line clears of each kind.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClearCounts {
+    pub singles: usize,
+    pub doubles: usize,
+    pub triples: usize,
+    pub tetrises: usize,
+    /// Full spins that cleared lines.
+    pub spins: usize,
+    /// Mini spins that cleared lines.
+    pub mini_spins: usize,
+    pub perfect_clears: usize,
+}
+
+/// Accumulates statistics while a game is being played.
+///
+/// The embedding game reports time, inputs, locked pieces and attacks as they happen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    /// Time played.
+    pub time: Duration,
+    /// Number of locked pieces.
+    pub pieces: usize,
+    /// Number of inputs (key presses).
+    pub inputs: usize,
+    /// Number of cleared lines.
+    pub lines: usize,
+    /// Number of garbage lines sent to opponents.
+    pub attack_sent: usize,
+    /// Number of garbage lines inserted into the field.
+    pub garbage_received: usize,
+    /// Number of line clears of each kind.
+    pub clears: ClearCounts,
+    /// The highest combo reached.
+    pub max_combo: usize,
+}
+
+impl Stats {
+    /// Creates empty statistics.
+    pub fn new() -> Stats {
+        Stats::default()
+    }
+
+    /// Adds to the time played.
+    pub fn record_time(&mut self, dt: Duration) {
+        self.time += dt;
+    }
+
+    /// Counts an input.
+    pub fn record_input(&mut self) {
+        self.inputs += 1;
+    }
+
+    /// Counts a locked piece and its line clear.
+    pub fn record_lock(&mut self, result: &LockResult) {
+        self.pieces += 1;
+        self.lines += result.lines_cleared;
+        self.garbage_received += result.received_garbage;
+        self.max_combo = self.max_combo.max(result.combo);
+
+        if result.lines_cleared == 0 {
+            return;
+        }
+        let clears = &mut self.clears;
+        match (result.spin, result.lines_cleared) {
+            (SpinType::Full, _) => clears.spins += 1,
+            (SpinType::Mini, _) => clears.mini_spins += 1,
+            (SpinType::None, 1) => clears.singles += 1,
+            (SpinType::None, 2) => clears.doubles += 1,
+            (SpinType::None, 3) => clears.triples += 1,
+            (SpinType::None, _) => clears.tetrises += 1,
+        }
+        if result.perfect_clear {
+            clears.perfect_clears += 1;
+        }
+    }
+
+    /// Counts garbage lines sent to opponents.
+    pub fn record_attack(&mut self, lines: usize) {
+        self.attack_sent += lines;
+    }
+
+    /// Returns pieces per second.
+    pub fn pps(&self) -> f64 {
+        per_unit(self.pieces as f64, self.time)
+    }
+
+    /// Returns inputs per piece.
+    pub fn kpp(&self) -> f64 {
+        per_unit(self.inputs as f64, self.pieces as f64)
+    }
+
+    /// Returns attack per minute.
+    pub fn apm(&self) -> f64 {
+        per_unit(self.attack_sent as f64, self.time / 60.)
+    }
+}
+
+fn per_unit(value: f64, units: f64) -> f64 {
+    if units > 0. {
+        value / units
+    } else {
+        0.
+    }
+}
+
+#[test]
+fn stats_tracking() {
+    let lock = |lines_cleared, spin, combo| LockResult {
+        combo,
+        ..LockResult::test(lines_cleared, spin)
+    };
+
+    let mut stats = Stats::new();
+    assert_eq!(stats.pps(), 0.);
+    assert_eq!(stats.kpp(), 0.);
+
+    stats.record_time(2.);
+    for _ in 0..6 {
+        stats.record_input();
+    }
+    stats.record_lock(&lock(0, SpinType::None, 0));
+    stats.record_lock(&lock(2, SpinType::Full, 1));
+    stats.record_lock(&LockResult {
+        received_garbage: 3,
+        ..lock(4, SpinType::None, 2)
+    });
+    stats.record_attack(10);
+
+    assert_eq!(stats.pieces, 3);
+    assert_eq!(stats.lines, 6);
+    assert_eq!(stats.garbage_received, 3);
+    assert_eq!(stats.max_combo, 2);
+    assert_eq!(stats.clears.spins, 1);
+    assert_eq!(stats.clears.tetrises, 1);
+    assert_eq!(stats.clears.singles, 0);
+    assert_eq!(stats.pps(), 1.5);
+    assert_eq!(stats.kpp(), 2.);
+    assert_eq!(stats.apm(), 300.);
+}
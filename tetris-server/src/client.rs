@@ -1,119 +1,94 @@
 use crate::game::GameManager;
 use crate::protocol::{ClientMsg, ServerMsg};
+use axum::extract::ws::{CloseFrame, Message, WebSocket};
 use core::hash::{Hash, Hasher};
-use futures::future::{self, Either, Future};
-use futures::stream::Stream;
-use futures::sync::mpsc;
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
 use parking_lot::Mutex;
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::{Duration, Instant};
-use tokio::codec::Framed;
-use tokio::net::TcpStream;
-use tokio::prelude::*;
-use tokio::timer::Delay;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
 use uuid::Uuid;
-use websocket::r#async::MessageCodec;
-use websocket::CloseData;
-use websocket::OwnedMessage;
-use websocket::WebSocketError;
 
 const CLIENT_HANDSHAKE_TIMEOUT_SECS: u64 = 3;
 const MAX_CLIENT_PACKET_SIZE: usize = 1_000_000;
 
-pub fn accept(
-    gm: Arc<Mutex<GameManager>>,
-    socket: Framed<TcpStream, MessageCodec<OwnedMessage>>,
-    addr: SocketAddr,
-) -> impl Future<Item = (), Error = ()> {
-    let did_accept = Arc::new(Mutex::new(false));
-    let did_accept2 = did_accept.clone();
-
-    let f = socket
-        .into_future()
-        .map_err(|(err, _)| err)
-        .and_then(move |(message, socket)| match message {
-            Some(OwnedMessage::Text(text)) => match serde_json::from_str(&text) {
-                Ok(ClientMsg::Init { name, token }) => {
-                    info!(
-                        "got init from {} with name {} and token {}",
-                        addr, name, token
-                    );
-
-                    *did_accept2.lock() = true;
-                    match Client::new(gm, name, token, socket, addr) {
-                        Ok(client) => Either::A(client),
-                        Err(client) => Either::A(client),
-                    }
-                }
-                _ => Either::B(future::ok(())),
-            },
-            _ => Either::B(future::ok(())),
-        })
-        .map_err(move |err| error!("websocket error at {}: {}", addr, err));
-
-    struct ClientAccept<F> {
-        f: F,
-        timeout: Option<(Delay, Arc<Mutex<bool>>, SocketAddr)>,
-    }
-
-    impl<F: Future<Item = (), Error = ()>> Future for ClientAccept<F> {
-        type Item = ();
-        type Error = ();
+pub async fn accept(gm: Arc<Mutex<GameManager>>, socket: WebSocket, addr: SocketAddr) {
+    let (sink, mut stream) = socket.split();
 
-        fn poll(&mut self) -> Result<Async<()>, ()> {
-            match &mut self.timeout {
-                Some((ref mut timeout, did_accept, addr)) => match timeout.poll() {
-                    Ok(Async::NotReady) => self.f.poll(),
-                    Ok(Async::Ready(())) => {
-                        if *did_accept.lock() {
-                            self.timeout = None;
-                            self.f.poll()
-                        } else {
-                            info!("dropping connection from {} (init timed out)", addr);
-                            Ok(Async::Ready(()))
-                        }
-                    }
-                    Err(_) => Err(()),
-                },
-                None => self.f.poll(),
+    let handshake = Duration::from_secs(CLIENT_HANDSHAKE_TIMEOUT_SECS);
+    let (name, token) = match timeout(handshake, stream.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => match serde_json::from_str(&text) {
+            Ok(ClientMsg::Init { name, token }) => {
+                info!("got init from {} with name {}", addr, name);
+                (name, token)
             }
+            _ => return,
+        },
+        Ok(Some(Err(err))) => {
+            error!("websocket error at {}: {}", addr, err);
+            return;
         }
-    }
-
-    let timeout = Delay::new(Instant::now() + Duration::from_secs(CLIENT_HANDSHAKE_TIMEOUT_SECS));
+        Ok(_) => return,
+        Err(_) => {
+            info!("dropping connection from {} (init timed out)", addr);
+            return;
+        }
+    };
 
-    ClientAccept {
-        timeout: Some((timeout, did_accept, addr)),
-        f,
+    let client = Client::new(gm, name, token, addr);
+    if let Err(err) = client.run(sink, stream).await {
+        error!("websocket error at {}: {}", addr, err);
     }
 }
 
 #[derive(Clone)]
 pub struct ClientHandle {
     id: Uuid,
-    sender: mpsc::UnboundedSender<OwnedMessage>,
+    sender: mpsc::UnboundedSender<Message>,
 }
 
 impl ClientHandle {
     /// Sends a message to the client.
     pub fn send(&self, msg: ServerMsg) {
         match serde_json::to_string(&msg) {
-            Ok(msg) => self.send_msg(OwnedMessage::Text(msg)),
+            Ok(msg) => self.send_msg(Message::Text(msg)),
             Err(err) => error!("failed to serialize client packet: {}", err),
         }
     }
 
+    /// Closes the connection with the given status and reason text.
+    pub fn close(&self, status_code: u16, reason: String) {
+        self.send_msg(Message::Close(Some(CloseFrame {
+            code: status_code,
+            reason: reason.into(),
+        })));
+    }
+
     /// Sends a websocket message.
     ///
     /// (actually just puts it in a queue)
-    fn send_msg(&self, message: OwnedMessage) {
-        if let Err(_) = self.sender.unbounded_send(message) {
+    fn send_msg(&self, message: Message) {
+        if self.sender.send(message).is_err() {
             error!("failed to put message in client message queue");
         }
     }
 }
 
+#[cfg(test)]
+impl ClientHandle {
+    /// Creates a handle whose messages are discarded.
+    pub fn detached() -> ClientHandle {
+        let (sender, _) = mpsc::unbounded_channel();
+        ClientHandle {
+            id: Uuid::new_v4(),
+            sender,
+        }
+    }
+}
+
 impl PartialEq for ClientHandle {
     fn eq(&self, rhs: &Self) -> bool {
         self.id == rhs.id
@@ -134,50 +109,38 @@ struct Client {
     name: String,
     gm: Arc<Mutex<GameManager>>,
     registered: bool,
-    socket: Framed<TcpStream, MessageCodec<OwnedMessage>>,
     addr: SocketAddr,
-    msg_queue: mpsc::UnboundedReceiver<OwnedMessage>,
-    msg_queue_in: mpsc::UnboundedSender<OwnedMessage>,
-    closing: Option<CloseData>,
+    msg_queue: mpsc::UnboundedReceiver<Message>,
+    msg_queue_in: mpsc::UnboundedSender<Message>,
+    closing: Option<CloseFrame<'static>>,
 }
 
 impl Client {
-    fn new(
-        gm: Arc<Mutex<GameManager>>,
-        name: String,
-        token: String,
-        socket: Framed<TcpStream, MessageCodec<OwnedMessage>>,
-        addr: SocketAddr,
-    ) -> Result<Client, Client> {
-        let (msg_queue_in, msg_queue) = mpsc::unbounded();
+    fn new(gm: Arc<Mutex<GameManager>>, name: String, token: String, addr: SocketAddr) -> Client {
+        let (msg_queue_in, msg_queue) = mpsc::unbounded_channel();
 
         let mut client = Client {
             id: Uuid::new_v4(),
             name: name.clone(),
             gm,
             registered: false,
-            socket,
             addr,
             closing: None,
             msg_queue,
             msg_queue_in,
         };
 
-        let mut is_err = false;
-        if let Err(_) = client
+        let added = client
             .gm
             .lock()
-            .add_client(name, token, client.create_handle())
-        {
-            client.create_handle().send(ServerMsg::NameTaken);
-            is_err = true;
-        }
-        if is_err {
-            return Err(client);
+            .add_client(name, token, client.create_handle());
+        if let Err(msg) = added {
+            client.create_handle().send(msg);
+            return client;
         }
 
         client.registered = true;
-        Ok(client)
+        client
     }
 
     pub fn create_handle(&self) -> ClientHandle {
@@ -196,19 +159,59 @@ impl Client {
             ClientMsg::CreateGame {
                 password,
                 client_fields,
+                name,
+                settings,
             } => {
-                self.gm
-                    .lock()
-                    .create_room(self.name.clone(), password, client_fields);
+                self.gm.lock().create_room(
+                    self.name.clone(),
+                    name,
+                    password,
+                    client_fields,
+                    settings,
+                );
             }
             ClientMsg::JoinGame { name, password } => {
                 self.gm.lock().join_room(self.name.clone(), name, password);
             }
+            ClientMsg::JoinRoom { id, password } => {
+                self.gm
+                    .lock()
+                    .join_room_by_id(self.name.clone(), &id, password);
+            }
+            ClientMsg::ListRooms => {
+                self.gm.lock().send_room_list(&self.name);
+            }
+            ClientMsg::QueueMatch { mode } => {
+                self.gm.lock().queue_match(&self.name, mode);
+            }
+            ClientMsg::LeaveQueue => {
+                self.gm.lock().leave_queue(&self.name);
+            }
             ClientMsg::StartGame => {
                 self.gm.lock().start_game(&self.name);
             }
-            ClientMsg::GameCommand { command } => {
-                self.gm.lock().run_game_command(&self.name, command);
+            ClientMsg::CancelStart => {
+                self.gm.lock().cancel_start(&self.name);
+            }
+            ClientMsg::KickPlayer { name } => {
+                self.gm.lock().kick_player(&self.name, &name, false);
+            }
+            ClientMsg::BanPlayer { name } => {
+                self.gm.lock().kick_player(&self.name, &name, true);
+            }
+            ClientMsg::TransferOwnership { name } => {
+                self.gm.lock().transfer_ownership(&self.name, &name);
+            }
+            ClientMsg::Pause => {
+                self.gm.lock().set_paused(&self.name, true);
+            }
+            ClientMsg::Resume => {
+                self.gm.lock().set_paused(&self.name, false);
+            }
+            ClientMsg::GameCommand { command, time, seq } => {
+                self.gm
+                    .lock()
+                    .run_game_command(&self.name, command, time, seq);
             }
             ClientMsg::Field { field } => {
                 self.gm.lock().update_client_field(&self.name, field);
@@ -218,92 +221,80 @@ impl Client {
 
     /// Marks this connection as closed with the given status and reason text.
     ///
-    /// This will send a final close message through the websocket and resolve this future.
-    /// Note that this will not actually forcefully close the connection until this struct is
-    /// dropped.
+    /// This will send a final close message through the websocket and end the connection.
     pub fn close(&mut self, status_code: u16, reason: String) {
         info!(
             "closing connection to {}: {} {:?}",
             self.addr, status_code, reason
         );
-        self.closing = Some(CloseData {
-            status_code,
-            reason,
+        self.closing = Some(CloseFrame {
+            code: status_code,
+            reason: reason.into(),
         });
     }
-}
 
-impl Drop for Client {
-    fn drop(&mut self) {
+    /// Handles a message from the websocket.
+    fn handle_ws_msg(&mut self, msg: Message) {
         if self.registered {
-            self.gm.lock().remove_client(&self.name);
-        }
-    }
-}
-
-impl Future for Client {
-    type Item = ();
-    type Error = WebSocketError;
-
-    fn poll(&mut self) -> Result<Async<()>, WebSocketError> {
-        if let Some(close_data) = &self.closing {
-            self.socket
-                .start_send(OwnedMessage::Close(Some(close_data.clone())))?;
-            return self.socket.poll_complete();
+            self.gm.lock().touch(&self.name);
         }
+        if let Message::Text(text) = msg {
+            if text.len() > MAX_CLIENT_PACKET_SIZE {
+                self.close(
+                    1009,
+                    format!(
+                        "packet too large (exceeds {} bytes)",
+                        MAX_CLIENT_PACKET_SIZE
+                    ),
+                );
+                return;
+            }
 
-        loop {
-            match self.msg_queue.poll().unwrap() {
-                Async::Ready(Some(msg)) => {
-                    self.socket.start_send(msg)?;
-                }
-                _ => break,
+            match serde_json::from_str(&text) {
+                Ok(msg) => self.handle_msg(msg),
+                Err(err) => self.close(4000, format!("parse error: {}", err)),
             }
         }
+    }
 
-        self.socket.poll_complete()?;
-
-        while let Async::Ready(msg) = self.socket.poll()? {
-            if let Some(msg) = msg {
-                match msg {
-                    OwnedMessage::Text(text) => {
-                        if text.len() > MAX_CLIENT_PACKET_SIZE {
-                            self.close(
-                                1009,
-                                format!(
-                                    "packet too large (exceeds {} bytes)",
-                                    MAX_CLIENT_PACKET_SIZE
-                                ),
-                            );
-                            return Ok(Async::NotReady);
-                        }
-
-                        let msg = match serde_json::from_str(&text) {
-                            Ok(msg) => msg,
-                            Err(err) => {
-                                self.close(4000, format!("parse error: {}", err));
-                                return Ok(Async::NotReady);
-                            }
-                        };
-
-                        self.handle_msg(msg);
+    /// Relays queued messages to the websocket and handles incoming messages until the connection
+    /// is closed.
+    async fn run(
+        mut self,
+        mut sink: SplitSink<WebSocket, Message>,
+        mut stream: SplitStream<WebSocket>,
+    ) -> Result<(), axum::Error> {
+        while self.closing.is_none() {
+            tokio::select! {
+                msg = self.msg_queue.recv() => match msg {
+                    Some(Message::Close(Some(close_frame))) => {
+                        self.close(close_frame.code, close_frame.reason.into_owned());
                     }
-                    OwnedMessage::Ping(payload) => {
-                        if let Err(err) = self
-                            .msg_queue_in
-                            .unbounded_send(OwnedMessage::Ping(payload))
-                        {
-                            error!("failed to put message in client message queue: {}", err);
-                        }
+                    Some(msg) => sink.send(msg).await?,
+                    None => break,
+                },
+                msg = stream.next() => match msg {
+                    Some(msg) => self.handle_ws_msg(msg?),
+                    None => {
+                        info!("dropping connection to {} (socket closed)", self.addr);
+                        return Ok(());
                     }
-                    _ => (),
-                }
-            } else {
-                info!("dropping connection to {} (socket closed)", self.addr);
-                return Ok(Async::Ready(()));
+                },
             }
         }
 
-        Ok(Async::NotReady)
+        if let Some(close_frame) = self.closing.take() {
+            sink.send(Message::Close(Some(close_frame))).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        if self.registered {
+            let handle = self.create_handle();
+            self.gm.lock().remove_client(&self.name, &handle);
+        }
     }
 }
@@ -0,0 +1,61 @@
+//! Server configuration file.
+
+use crate::protocol::RoomSettings;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+/// Server configuration, as read from a TOML file.
+///
+/// Every field is optional; command line flags override values from the file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ServerConfig {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// Prefer the X-Real-IP header for obtaining client addresses.
+    pub proxy: Option<bool>,
+    /// Path to serve files from over HTTP.
+    #[serde(rename = "static")]
+    pub static_path: Option<String>,
+    /// Directory to save game replays to and serve them from.
+    pub replays: Option<String>,
+    /// Game ticks per second.
+    pub tick_rate: Option<f64>,
+    pub limits: Limits,
+    /// Settings of rooms created without any.
+    pub room_defaults: Option<RoomSettings>,
+}
+
+/// Limits on connections and rooms.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Limits {
+    /// Seconds a connection may be idle before it’s closed.
+    pub client_timeout: Option<u64>,
+    /// Seconds a room may be idle before it’s closed.
+    pub room_timeout: Option<u64>,
+    /// The maximum number of connected clients.
+    pub max_clients: Option<usize>,
+    /// The maximum number of rooms.
+    pub max_rooms: Option<usize>,
+}
+
+impl ServerConfig {
+    /// Reads a configuration file.
+    pub fn load(path: &Path) -> Result<ServerConfig, String> {
+        let contents = fs::read_to_string(path).map_err(|err| err.to_string())?;
+        let config: ServerConfig = toml::from_str(&contents).map_err(|err| err.to_string())?;
+        if let Some(tick_rate) = config.tick_rate {
+            if !(tick_rate > 0. && tick_rate <= 1000.) {
+                return Err(format!("tick rate {} is out of range", tick_rate));
+            }
+        }
+        if let Some(room_defaults) = &config.room_defaults {
+            room_defaults
+                .validate()
+                .map_err(|err| format!("invalid room defaults: {}", err))?;
+        }
+        Ok(config)
+    }
+}
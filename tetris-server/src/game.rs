@@ -1,61 +1,92 @@
 use crate::client::ClientHandle;
-use crate::protocol::{ClientDesc, FieldState, GameCommand, ServerMsg};
-use core::f64::consts::E;
-use futures::prelude::*;
+use crate::protocol::{
+    ClientDesc, FieldState, GameCommand, GameReplay, PlayerResult, RoomDesc, RoomMode,
+    RoomSettings, ServerMsg,
+};
+use core::cmp::Reverse;
 use parking_lot::Mutex;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
 use std::sync::{Arc, Weak};
 use std::time::Instant;
-use tetris_core::field::ActiveField;
-use tetris_core::field::{Duration, Timestamp};
-use tokio::timer::DelayQueue;
+use tetris_core::attack::AttackTable;
+use tetris_core::field::{Duration, Tile, Timestamp};
+use tetris_core::modes::{drop_score, lock_score};
+use tetris_core::replay::Replay;
+use tetris_core::sim::Simulation;
+use tetris_core::speed::LevelProgress;
+use tetris_core::stats::Stats;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::{self, MissedTickBehavior};
 use uuid::Uuid;
 
-const TICK_INTERVAL_NS: u64 = 16_666_667;
+/// Default game ticks per second.
+pub const DEFAULT_TICK_RATE: f64 = 60.;
 
-pub struct GMScheduler {
-    last_time: Instant,
-    tick_queue: Arc<Mutex<DelayQueue<SchedulerMsg>>>,
-    gm: Weak<Mutex<GameManager>>,
+/// How long a disconnected player keeps their name and seat in a room for reconnecting.
+const RECONNECT_TIMEOUT_SECS: u64 = 30;
+
+/// How many players waiting for the same mode are put in a room together.
+const MATCH_PLAYERS: usize = 2;
+
+/// How often idle clients and rooms are looked for.
+const SWEEP_INTERVAL_SECS: u64 = 1;
+
+/// How long clients and rooms may go without activity before they're closed.
+#[derive(Debug, Clone, Copy)]
+pub struct IdleTimeouts {
+    /// Connections that haven't sent anything for this long are closed.
+    pub client: core::time::Duration,
+    /// Rooms whose players haven't sent anything for this long are closed.
+    pub room: core::time::Duration,
 }
 
-enum SchedulerMsg {
-    Start(Instant),
-    Tick,
+/// Server-wide options for the game manager.
+#[derive(Debug, Clone)]
+pub struct ManagerOptions {
+    pub timeouts: IdleTimeouts,
+    /// Time between game ticks.
+    pub tick_interval: core::time::Duration,
+    /// The maximum number of connected clients, or None if unlimited.
+    pub max_clients: Option<usize>,
+    /// The maximum number of rooms, or None if unlimited.
+    pub max_rooms: Option<usize>,
+    /// Settings of rooms created without any.
+    pub room_defaults: RoomSettings,
+    /// Where rooms save replays of finished games, if anywhere.
+    pub replay_dir: Option<PathBuf>,
 }
 
-impl Future for GMScheduler {
-    type Item = ();
-    type Error = ();
+/// Runs game ticks while there are clients or rooms.
+pub struct GMScheduler {
+    tick_interval: core::time::Duration,
+    /// Notified when the game manager wants ticks to start.
+    wake: Arc<Notify>,
+    gm: Weak<Mutex<GameManager>>,
+}
+
+impl GMScheduler {
+    pub async fn run(self) {
+        loop {
+            self.wake.notified().await;
 
-    fn poll(&mut self) -> Result<Async<()>, ()> {
-        if let Some(gm) = Weak::upgrade(&self.gm) {
-            let mut tick_queue = self.tick_queue.lock();
-            let mut gm = gm.lock();
+            let mut interval = time::interval(self.tick_interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval.tick().await;
+            let mut last_time = Instant::now();
             loop {
-                match tick_queue.poll() {
-                    Ok(Async::Ready(Some(expired))) => {
-                        match expired.get_ref() {
-                            SchedulerMsg::Start(instant) => self.last_time = *instant,
-                            SchedulerMsg::Tick => {
-                                let delta_time = self.last_time.elapsed();
-                                gm.tick(delta_time.as_micros() as f64 / 1_000_000.);
-                            }
-                        }
-                        self.last_time = Instant::now();
-                        if gm.wants_tick() {
-                            self.tick_queue.lock().insert(
-                                SchedulerMsg::Tick,
-                                core::time::Duration::from_nanos(TICK_INTERVAL_NS),
-                            );
-                        }
-                    }
-                    Ok(Async::Ready(None)) | Ok(Async::NotReady) => return Ok(Async::NotReady),
-                    Err(_) => return Err(()),
+                interval.tick().await;
+                let gm = match Weak::upgrade(&self.gm) {
+                    Some(gm) => gm,
+                    None => return,
+                };
+                let mut gm = gm.lock();
+                gm.tick(last_time.elapsed().as_micros() as f64 / 1_000_000.);
+                last_time = Instant::now();
+                if !gm.wants_tick() {
+                    break;
                 }
             }
-        } else {
-            Ok(Async::Ready(()))
         }
     }
 }
@@ -64,133 +95,528 @@ pub struct GameManager {
     rooms: HashMap<Uuid, Arc<Mutex<Room>>>,
     client_rooms: HashMap<String, Uuid>,
     clients: HashMap<String, ClientHandle>,
-    tick_queue: Arc<Mutex<DelayQueue<SchedulerMsg>>>,
+    /// Re-entry tokens of connected clients and of disconnected players who may reconnect.
+    tokens: HashMap<String, String>,
+    /// Players who disconnected while in a room, by the time they disconnected.
+    disconnected: HashMap<String, Instant>,
+    /// When each connected client last sent a message.
+    last_activity: HashMap<String, Instant>,
+    /// Players waiting for a match, in the order they joined the queue.
+    match_queue: Vec<(String, RoomMode)>,
+    options: ManagerOptions,
+    last_sweep: Instant,
+    wake_scheduler: Arc<Notify>,
 }
 
 impl GameManager {
-    pub fn new() -> (Arc<Mutex<GameManager>>, GMScheduler) {
-        let tick_queue = Arc::new(Mutex::new(DelayQueue::new()));
+    pub fn new(options: ManagerOptions) -> (Arc<Mutex<GameManager>>, GMScheduler) {
+        let wake_scheduler = Arc::new(Notify::new());
         let mut scheduler = GMScheduler {
-            last_time: Instant::now(),
-            tick_queue: tick_queue.clone(),
+            tick_interval: options.tick_interval,
+            wake: Arc::clone(&wake_scheduler),
             gm: Weak::new(),
         };
         let gm = Arc::new(Mutex::new(GameManager {
             rooms: HashMap::new(),
             client_rooms: HashMap::new(),
             clients: HashMap::new(),
-            tick_queue,
+            tokens: HashMap::new(),
+            disconnected: HashMap::new(),
+            last_activity: HashMap::new(),
+            match_queue: Vec::new(),
+            options,
+            last_sweep: Instant::now(),
+            wake_scheduler,
         }));
         scheduler.gm = Arc::downgrade(&gm);
         (gm, scheduler)
     }
 
     fn start_tick(&mut self) {
-        let mut tick_queue = self.tick_queue.lock();
-        if tick_queue.is_empty() {
-            tick_queue.insert(
-                SchedulerMsg::Start(Instant::now()),
-                core::time::Duration::from_nanos(TICK_INTERVAL_NS),
-            );
-        }
+        self.wake_scheduler.notify_one();
     }
 
     fn tick(&mut self, dt: Duration) {
-        for (_, room) in &self.rooms {
-            room.lock().tick(dt);
+        self.expire_disconnected();
+        if self.last_sweep.elapsed() >= core::time::Duration::from_secs(SWEEP_INTERVAL_SECS) {
+            self.last_sweep = Instant::now();
+            self.sweep_idle();
+        }
+        let mut rooms_changed = false;
+        for room in self.rooms.values() {
+            let mut room = room.lock();
+            let was_in_game = room.is_in_game();
+            room.tick(dt);
+            rooms_changed |= room.is_in_game() != was_in_game;
+        }
+        if rooms_changed {
+            self.broadcast_room_list();
         }
     }
 
     fn wants_tick(&self) -> bool {
-        !self.rooms.is_empty()
+        !self.rooms.is_empty() || !self.clients.is_empty()
+    }
+
+    /// Records that a client sent a message, which keeps it and its room from being closed.
+    pub fn touch(&mut self, name: &str) {
+        if self.clients.contains_key(name) {
+            self.last_activity.insert(name.into(), Instant::now());
+        }
+        if let Some(room_id) = self.client_rooms.get(name) {
+            self.rooms[room_id].lock().last_activity = Instant::now();
+        }
+    }
+
+    /// Closes connections and rooms that have been idle for longer than the timeouts.
+    fn sweep_idle(&mut self) {
+        let idle_clients: Vec<_> = self
+            .last_activity
+            .iter()
+            .filter(|(_, time)| time.elapsed() >= self.options.timeouts.client)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in idle_clients {
+            self.last_activity.remove(&name);
+            if let Some(client) = self.clients.get(&name) {
+                client.close(4001, "idle for too long".into());
+            }
+        }
+
+        let idle_rooms: Vec<_> = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| room.lock().last_activity.elapsed() >= self.options.timeouts.room)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &idle_rooms {
+            self.close_room(*id);
+        }
+        if !idle_rooms.is_empty() {
+            self.broadcast_room_list();
+        }
+    }
+
+    /// Removes a room and all of its players.
+    fn close_room(&mut self, id: Uuid) {
+        if let Some(room) = self.rooms.remove(&id) {
+            let room = room.lock();
+            info!("closing room {:?}", room.name);
+            room.broadcast(ServerMsg::RoomClosed);
+            for name in room.players.keys() {
+                self.client_rooms.remove(name);
+                if self.disconnected.remove(name).is_some() {
+                    self.tokens.remove(name);
+                }
+            }
+        }
+        self.retry_matches();
     }
 
     fn broadcast_client_list(&self) {
         let msg = ServerMsg::ClientList {
             clients: self
                 .clients
-                .iter()
-                .map(|(name, _)| {
-                    let room = match self.client_rooms.get(name) {
-                        Some(id) => Some(&self.rooms[&id]),
-                        None => None,
-                    };
+                .keys()
+                .map(|name| {
+                    let room = self.client_rooms.get(name).map(|id| &self.rooms[id]);
                     ClientDesc {
                         name: name.clone(),
-                        in_game: room.map_or(false, |r| r.lock().is_in_game()),
+                        in_game: room.is_some_and(|r| r.lock().is_in_game()),
                         has_game: true,
-                        client_fields: room.map_or(false, |r| r.lock().uses_client_fields()),
+                        client_fields: room.is_some_and(|r| r.lock().uses_client_fields()),
                         proposed_game: false,
+                        is_owner: false,
                     }
                 })
                 .collect(),
         };
 
-        for (_, client) in &self.clients {
+        for client in self.clients.values() {
             client.send(msg.clone());
         }
     }
 
+    fn room_list(&self) -> ServerMsg {
+        ServerMsg::RoomList {
+            rooms: self
+                .rooms
+                .iter()
+                .map(|(id, room)| room.lock().describe(*id))
+                .collect(),
+        }
+    }
+
+    /// Sends the room list to a client.
+    pub fn send_room_list(&self, name: &str) {
+        if let Some(client) = self.clients.get(name) {
+            client.send(self.room_list());
+        }
+    }
+
+    /// Sends the room list to all clients that aren’t in a room.
+    fn broadcast_room_list(&self) {
+        let msg = self.room_list();
+        for (name, client) in &self.clients {
+            if !self.client_rooms.contains_key(name) {
+                client.send(msg.clone());
+            }
+        }
+    }
+
+    /// Registers a client under the given name.
+    ///
+    /// If the name is taken, the client may take over the existing connection (or reconnect to a
+    /// room it was disconnected from) by presenting the same non-empty token. On failure, returns
+    /// the message to send to the client.
     pub fn add_client(
         &mut self,
         name: String,
-        _token: String,
+        token: String,
         handle: ClientHandle,
-    ) -> Result<(), ()> {
-        // TODO: tokens for re-entry
-        if self.clients.contains_key(&name) {
-            return Err(());
+    ) -> Result<(), ServerMsg> {
+        self.expire_disconnected();
+        if let Some(existing) = self.tokens.get(&name) {
+            if token.is_empty() || *existing != token {
+                return Err(ServerMsg::NameTaken);
+            }
+            info!("client {} reconnected", name);
+            // the replaced connection must not keep acting as this player
+            if let Some(prev) = self.clients.get(&name) {
+                prev.close(4002, "connected from elsewhere".into());
+            }
+            self.disconnected.remove(&name);
+            if let Some(room_id) = self.client_rooms.get(&name) {
+                self.rooms[room_id]
+                    .lock()
+                    .reconnect_player(&name, handle.clone());
+            }
+        } else {
+            if self
+                .options
+                .max_clients
+                .is_some_and(|max| self.clients.len() >= max)
+            {
+                return Err(ServerMsg::ServerFull);
+            }
+            self.tokens.insert(name.clone(), token);
         }
-        self.clients.insert(name, handle);
+        self.last_activity.insert(name.clone(), Instant::now());
+        self.clients.insert(name.clone(), handle);
+        self.start_tick();
         self.broadcast_client_list();
+        if !self.client_rooms.contains_key(&name) {
+            self.send_room_list(&name);
+        }
         Ok(())
     }
 
-    pub fn remove_client(&mut self, name: &str) {
-        self.remove_from_rooms(name);
+    /// Removes a client whose connection was closed.
+    ///
+    /// Does nothing if another connection has taken over the name. Players in a room keep their
+    /// seat for a while so they can reconnect.
+    pub fn remove_client(&mut self, name: &str, handle: &ClientHandle) {
+        if self.clients.get(name) != Some(handle) {
+            return;
+        }
         self.clients.remove(name);
+        self.last_activity.remove(name);
+        self.match_queue.retain(|(queued, _)| queued != name);
+        let can_reconnect = self.tokens.get(name).is_some_and(|token| !token.is_empty());
+        match self.client_rooms.get(name) {
+            Some(room_id) if can_reconnect => {
+                self.rooms[room_id].lock().disconnect_player(name);
+                self.disconnected.insert(name.into(), Instant::now());
+            }
+            _ => {
+                self.remove_from_rooms(name);
+                self.tokens.remove(name);
+            }
+        }
         self.broadcast_client_list();
     }
 
+    /// Removes disconnected players who haven't reconnected in time.
+    fn expire_disconnected(&mut self) {
+        let timeout = core::time::Duration::from_secs(RECONNECT_TIMEOUT_SECS);
+        let expired: Vec<_> = self
+            .disconnected
+            .iter()
+            .filter(|(_, time)| time.elapsed() >= timeout)
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in expired {
+            self.disconnected.remove(&name);
+            self.remove_from_rooms(&name);
+            self.tokens.remove(&name);
+        }
+    }
+
     fn remove_room(&mut self, id: Uuid) {
         self.rooms.remove(&id);
+        self.retry_matches();
     }
 
     fn remove_from_rooms(&mut self, name: &str) {
         if let Some(room_id) = self.client_rooms.remove(name) {
-            let room_m = self.rooms.get_mut(&room_id).unwrap();
-            let mut room = room_m.lock();
-            room.remove_player(name);
-            if room.is_empty() {
-                drop(room);
-                drop(room_m);
+            let is_empty = {
+                let mut room = self.rooms[&room_id].lock();
+                room.remove_player(name);
+                room.is_empty()
+            };
+            if is_empty {
                 self.remove_room(room_id);
             }
+            self.broadcast_room_list();
         }
     }
 
-    pub fn create_room(&mut self, name: String, password: String, client_fields: bool) {
-        if let Some(client) = self.clients.get(&name).map(|client| client.clone()) {
+    pub fn create_room(
+        &mut self,
+        name: String,
+        room_name: Option<String>,
+        password: String,
+        client_fields: bool,
+        settings: Option<RoomSettings>,
+    ) {
+        if let Some(client) = self.clients.get(&name).cloned() {
+            if self
+                .options
+                .max_rooms
+                .is_some_and(|max| self.rooms.len() >= max)
+            {
+                client.send(ServerMsg::FailedCreateGame {
+                    reason: "too many rooms".into(),
+                });
+                return;
+            }
+            let settings = settings.unwrap_or_else(|| self.options.room_defaults.clone());
+            let valid = match settings.validate() {
+                Ok(()) if client_fields && settings.mode.needs_server_fields() => {
+                    Err("this mode needs server fields")
+                }
+                valid => valid,
+            };
+            if let Err(reason) = valid {
+                client.send(ServerMsg::FailedCreateGame {
+                    reason: reason.into(),
+                });
+                return;
+            }
+            self.leave_queue(&name);
             self.remove_from_rooms(&name);
             let room_id = Uuid::new_v4();
-            let mut room = Room::new(password, client_fields);
-            room.add_player(name, client);
+            let room_name = room_name.unwrap_or_else(|| name.clone());
+            let mut room = Room::new(
+                room_name,
+                password,
+                client_fields,
+                settings,
+                self.options.replay_dir.clone(),
+            );
+            room.add_player(name.clone(), client);
             self.rooms.insert(room_id, Arc::new(Mutex::new(room)));
+            self.client_rooms.insert(name, room_id);
+            self.broadcast_room_list();
         }
     }
 
     pub fn join_room(&mut self, name: String, room_member: String, password: String) {
-        if let Some(client) = self.clients.get(&name) {
-            if let Some(id) = self.client_rooms.get(&room_member).map(|id| *id) {
-                let room_m = &self.rooms[&id];
-                let mut room = room_m.lock();
-                if room.password == password {
-                    room.add_player(name.clone(), client.clone());
-                    self.client_rooms.insert(name, id);
+        match self.client_rooms.get(&room_member).copied() {
+            Some(id) => self.join_room_with_id(name, id, password),
+            None => self.fail_join(&name),
+        }
+    }
+
+    /// Joins a room by the ID from the room list.
+    pub fn join_room_by_id(&mut self, name: String, id: &str, password: String) {
+        match Uuid::parse_str(id) {
+            Ok(id) if self.rooms.contains_key(&id) => self.join_room_with_id(name, id, password),
+            _ => self.fail_join(&name),
+        }
+    }
+
+    fn join_room_with_id(&mut self, name: String, id: Uuid, password: String) {
+        if let Some(client) = self.clients.get(&name).cloned() {
+            if self.client_rooms.get(&name) == Some(&id) {
+                return;
+            }
+            {
+                let mut room = self.rooms[&id].lock();
+                if room.password != password || room.banned.contains(&name) {
+                    client.send(ServerMsg::FailedJoinGame);
+                    return;
+                }
+                if !room.add_player(name.clone(), client) {
                     return;
                 }
             }
+            self.leave_queue(&name);
+            self.remove_from_rooms(&name);
+            self.client_rooms.insert(name, id);
+            self.broadcast_room_list();
+        }
+    }
+
+    /// Puts a client in the queue for a match in the given mode, leaving any room it’s in.
+    ///
+    /// Once enough players are waiting for the same mode, they’re put in a new room whose game
+    /// starts right away.
+    pub fn queue_match(&mut self, name: &str, mode: RoomMode) {
+        let client = match self.clients.get(name) {
+            Some(client) => client.clone(),
+            None => return,
+        };
+        if let Err(reason) = self.match_settings(mode).validate() {
+            client.send(ServerMsg::FailedQueueMatch {
+                reason: reason.into(),
+            });
+            return;
+        }
+
+        self.remove_from_rooms(name);
+        self.match_queue.retain(|(queued, _)| queued != name);
+        self.match_queue.push((name.into(), mode));
+
+        if !self.try_match(mode) {
+            let waiting: Vec<_> = self
+                .match_queue
+                .iter()
+                .filter(|(_, queued_mode)| *queued_mode == mode)
+                .map(|(name, _)| name)
+                .collect();
+            for name in &waiting {
+                self.clients[*name].send(ServerMsg::Queued {
+                    mode,
+                    waiting: waiting.len(),
+                });
+            }
+        }
+    }
+
+    /// Returns the settings of rooms created for matches in the given mode.
+    fn match_settings(&self, mode: RoomMode) -> RoomSettings {
+        RoomSettings {
+            mode,
+            max_players: Some(MATCH_PLAYERS),
+            ..self.options.room_defaults.clone()
+        }
+    }
+
+    /// Puts the first players waiting for the given mode in a new room if there are enough of
+    /// them and the room limit allows it. Returns true if a room was created.
+    fn try_match(&mut self, mode: RoomMode) -> bool {
+        let waiting: Vec<_> = self
+            .match_queue
+            .iter()
+            .filter(|(_, queued_mode)| *queued_mode == mode)
+            .map(|(name, _)| name.clone())
+            .collect();
+        let room_limit_reached = self
+            .options
+            .max_rooms
+            .is_some_and(|max| self.rooms.len() >= max);
+        if waiting.len() < MATCH_PLAYERS || room_limit_reached {
+            return false;
+        }
+
+        let players = &waiting[..MATCH_PLAYERS];
+        self.match_queue
+            .retain(|(queued, _)| !players.contains(queued));
+        info!("matched {} in {:?}", players.join(", "), mode);
+
+        let room_id = Uuid::new_v4();
+        // nobody else can join without the password
+        let password = Uuid::new_v4().to_string();
+        let mut room = Room::new(
+            players.join(" vs "),
+            password,
+            false,
+            self.match_settings(mode),
+            self.options.replay_dir.clone(),
+        );
+        for name in players {
+            room.add_player(name.clone(), self.clients[name].clone());
+            self.client_rooms.insert(name.clone(), room_id);
+        }
+        for name in players {
+            room.proposed_game(name);
+        }
+        self.rooms.insert(room_id, Arc::new(Mutex::new(room)));
+        self.start_tick();
+        self.broadcast_room_list();
+        true
+    }
+
+    /// Matches waiting players that had to wait for the room limit, e.g. after a room closed.
+    fn retry_matches(&mut self) {
+        let mut modes: Vec<RoomMode> = Vec::new();
+        for (_, mode) in &self.match_queue {
+            if !modes.contains(mode) {
+                modes.push(*mode);
+            }
+        }
+        for mode in modes {
+            self.try_match(mode);
+        }
+    }
+
+    /// Takes a client out of the match queue.
+    pub fn leave_queue(&mut self, name: &str) {
+        let len = self.match_queue.len();
+        self.match_queue.retain(|(queued, _)| queued != name);
+        if self.match_queue.len() != len {
+            if let Some(client) = self.clients.get(name) {
+                client.send(ServerMsg::LeftQueue);
+            }
+        }
+    }
 
+    /// Adds a spectator to the room with the given ID, returning the field states it’s sent.
+    ///
+    /// The stream starts with the current fields and ends when the room is closed. Spectators count
+    /// towards the client limit, and each room allows at most `MAX_WATCHERS` of them.
+    pub fn watch_room(&self, id: &str, password: &str) -> Option<mpsc::Receiver<ServerMsg>> {
+        let id = Uuid::parse_str(id).ok()?;
+        if self
+            .options
+            .max_clients
+            .is_some_and(|max| self.clients.len() + self.watcher_count() >= max)
+        {
+            return None;
+        }
+        let mut room = self.rooms.get(&id)?.lock();
+        if room.password != password {
+            return None;
+        }
+        room.prune_watchers();
+        if room.watchers.len() >= MAX_WATCHERS {
+            return None;
+        }
+        let (sender, receiver) = mpsc::channel(WATCHER_BUFFER);
+        if !room.sent_fields.is_empty() {
+            let _ = sender.try_send(ServerMsg::Fields {
+                fields: room.sent_fields.clone(),
+            });
+        }
+        room.watchers.push(sender);
+        Some(receiver)
+    }
+
+    /// Returns the number of spectators in all rooms.
+    fn watcher_count(&self) -> usize {
+        self.rooms
+            .values()
+            .map(|room| {
+                let mut room = room.lock();
+                room.prune_watchers();
+                room.watchers.len()
+            })
+            .sum()
+    }
+
+    fn fail_join(&self, name: &str) {
+        if let Some(client) = self.clients.get(name) {
             client.send(ServerMsg::FailedJoinGame);
         }
     }
@@ -198,27 +624,85 @@ impl GameManager {
     pub fn start_game(&mut self, name: &str) {
         if let Some(room_id) = self.client_rooms.get(name) {
             self.rooms
-                .get_mut(&room_id)
+                .get_mut(room_id)
                 .unwrap()
                 .lock()
                 .proposed_game(name);
 
             self.start_tick();
+            self.broadcast_room_list();
+        }
+    }
+
+    /// Removes a player from the room owned by `owner`, optionally banning them from rejoining.
+    pub fn kick_player(&mut self, owner: &str, name: &str, ban: bool) {
+        let room_id = match self.client_rooms.get(owner) {
+            Some(id) if name != owner && self.client_rooms.get(name) == Some(id) => *id,
+            _ => return,
+        };
+        {
+            let mut room = self.rooms[&room_id].lock();
+            if room.owner != owner {
+                return;
+            }
+            if ban {
+                room.banned.insert(name.into());
+            }
+        }
+        info!(
+            "{} {} {} from their room",
+            owner,
+            if ban { "banned" } else { "kicked" },
+            name
+        );
+        self.remove_from_rooms(name);
+        if let Some(client) = self.clients.get(name) {
+            client.send(ServerMsg::Kicked { banned: ban });
+        }
+    }
+
+    pub fn transfer_ownership(&mut self, owner: &str, name: &str) {
+        if let Some(room_id) = self.client_rooms.get(owner) {
+            self.rooms[room_id].lock().transfer_ownership(owner, name);
+        }
+    }
+
+    pub fn set_paused(&mut self, name: &str, paused: bool) {
+        if let Some(room_id) = self.client_rooms.get(name) {
+            self.rooms[room_id].lock().set_paused(name, paused);
+        }
+    }
+
+    pub fn cancel_start(&mut self, name: &str) {
+        if let Some(room_id) = self.client_rooms.get(name) {
+            self.rooms[room_id].lock().cancel_start(name);
         }
     }
 
-    pub fn run_game_command(&mut self, name: &str, command: GameCommand) {
+    pub fn run_game_command(
+        &mut self,
+        name: &str,
+        command: GameCommand,
+        time: Option<Timestamp>,
+        seq: Option<u64>,
+    ) {
         if let Some(room_id) = self.client_rooms.get(name) {
             self.rooms
-                .get_mut(&room_id)
+                .get_mut(room_id)
                 .unwrap()
                 .lock()
-                .run_game_command(name, command);
+                .run_game_command(name, command, time, seq);
         }
     }
 
     pub fn update_client_field(&mut self, name: &str, field: FieldState) {
-        // TODO
+        if let Some(room_id) = self.client_rooms.get(name) {
+            self.rooms
+                .get_mut(room_id)
+                .unwrap()
+                .lock()
+                .update_client_field(name, field);
+        }
     }
 }
 
@@ -230,21 +714,134 @@ enum RoomFields {
 struct RoomClient {
     client: ClientHandle,
     proposed_game: bool,
+    /// False while the player is disconnected and may still reconnect.
+    connected: bool,
 }
 
 const ROOM_START_TIME: Timestamp = -3.;
 
+/// How many messages may be waiting for a spectator before they’re disconnected.
+const WATCHER_BUFFER: usize = 64;
+
+/// The most spectators a room can have at once.
+const MAX_WATCHERS: usize = 16;
+
+/// Number of ticks after which all fields are sent in full instead of as deltas.
+const FIELD_KEYFRAME_INTERVAL: u32 = 60;
+
+/// How far ahead of the room a client field’s time may be, in seconds.
+const CLIENT_TIME_TOLERANCE: Duration = 2.;
+/// Piece placement rate assumed to be impossible, in pieces per second.
+const MAX_PIECES_PER_SECOND: f64 = 20.;
+/// An upper bound for the score of a single lock at level 1, including spins, back-to-back and
+/// reasonable combos.
+const MAX_LOCK_SCORE: usize = 4000;
+
+/// Sanity-checks a field sent by a client against its previous field and the room settings.
+///
+/// This doesn’t re-simulate the game, but rejects fields that grow, clear lines or score faster
+/// than any player could.
+fn check_client_field(
+    field: &FieldState,
+    prev: Option<&FieldState>,
+    settings: &RoomSettings,
+    room_time: Timestamp,
+) -> Result<(), &'static str> {
+    let game = &settings.game;
+    if field.width != game.width() || field.tile_count() != game.width() * game.height() {
+        return Err("wrong field size");
+    }
+    if !field.time.is_finite() || field.time > room_time + CLIENT_TIME_TOLERANCE {
+        return Err("time is ahead of the room");
+    }
+    let (prev_time, prev_tiles, prev_score, prev_level) = match prev {
+        Some(prev) => (prev.time, prev.filled_tiles(), prev.score, prev.level),
+        None => (0., 0, 0, 0),
+    };
+    if field.time < prev_time || field.score < prev_score || field.level < prev_level {
+        return Err("time, score or level went backwards");
+    }
+
+    let pieces = ((field.time - prev_time) * MAX_PIECES_PER_SECOND).ceil() as usize + 1;
+    let tiles = field.filled_tiles();
+    if tiles > prev_tiles.saturating_add(pieces.saturating_mul(4))
+        || prev_tiles > tiles.saturating_add(pieces.saturating_mul(4 * field.width))
+    {
+        return Err("too many tiles placed or cleared");
+    }
+
+    // clients don't report lines or pieces, so assume as many as could have been played so far
+    let total_pieces = (field.time * MAX_PIECES_PER_SECOND).ceil() as usize + 1;
+    let max_level = game.speed_curve().level(&LevelProgress {
+        lines: total_pieces.saturating_mul(4),
+        pieces: total_pieces,
+        score: field.score,
+    });
+    if field.level > max_level.max(1) {
+        return Err("level is too high for the score");
+    }
+    let max_score = MAX_LOCK_SCORE
+        .saturating_mul(field.level.max(1))
+        .saturating_add(2 * game.height());
+    if field.score - prev_score > pieces.saturating_mul(max_score) {
+        return Err("score increased too quickly");
+    }
+    Ok(())
+}
+
+/// Returns the number of whole seconds until the game starts at time zero.
+fn countdown_seconds(time: Timestamp) -> u32 {
+    (-time).max(0.).ceil() as u32
+}
+
 pub struct Room {
+    name: String,
+    /// The player who can kick and ban others; initially the creator.
+    owner: String,
+    /// Players who may not rejoin.
+    banned: HashSet<String>,
     players: HashMap<String, RoomClient>,
     time: Timestamp,
     fields: RoomFields,
+    settings: RoomSettings,
+    /// Number of players when the game started.
+    started_with: usize,
+    /// Names of players who have topped out or reached the goal, in order. Players who left the
+    /// room are kept for the results.
+    finish_order: Vec<String>,
+    /// Names of players who have topped out, in order.
+    knockouts: Vec<String>,
+    /// Names of players whose client fields changed since the last tick.
+    dirty_fields: HashSet<String>,
+    /// Names of players who sent impossible client fields.
+    disqualified: HashSet<String>,
+    /// The last field states sent to players, which deltas are computed against.
+    sent_fields: HashMap<String, FieldState>,
+    ticks_since_keyframe: u32,
     password: String,
     running: bool,
+    /// If true, time is frozen and game commands are ignored.
+    paused: bool,
+    /// Where to save replays of finished games, if anywhere.
+    replay_dir: Option<PathBuf>,
+    /// When a player last joined or sent a message.
+    last_activity: Instant,
+    /// Spectators outside the room, who are sent field states in full.
+    watchers: Vec<mpsc::Sender<ServerMsg>>,
 }
 
 impl Room {
-    fn new(password: String, client_fields: bool) -> Room {
+    fn new(
+        name: String,
+        password: String,
+        client_fields: bool,
+        settings: RoomSettings,
+        replay_dir: Option<PathBuf>,
+    ) -> Room {
         Room {
+            name,
+            owner: String::new(),
+            banned: HashSet::new(),
             players: HashMap::new(),
             time: ROOM_START_TIME,
             fields: if client_fields {
@@ -252,22 +849,42 @@ impl Room {
             } else {
                 RoomFields::ServerFields(HashMap::new())
             },
+            settings,
+            started_with: 0,
+            finish_order: Vec::new(),
+            knockouts: Vec::new(),
+            dirty_fields: HashSet::new(),
+            disqualified: HashSet::new(),
+            sent_fields: HashMap::new(),
+            ticks_since_keyframe: 0,
             password,
             running: false,
+            paused: false,
+            replay_dir,
+            last_activity: Instant::now(),
+            watchers: Vec::new(),
         }
     }
 
     fn uses_client_fields(&self) -> bool {
-        match self.fields {
-            RoomFields::ClientFields(_) => true,
-            _ => false,
-        }
+        matches!(self.fields, RoomFields::ClientFields(_))
     }
 
     fn is_in_game(&self) -> bool {
         self.running
     }
 
+    fn describe(&self, id: Uuid) -> RoomDesc {
+        RoomDesc {
+            id: id.to_string(),
+            name: self.name.clone(),
+            players: self.players.len(),
+            max_players: self.settings.max_players,
+            in_game: self.is_in_game(),
+            has_password: !self.password.is_empty(),
+        }
+    }
+
     fn broadcast_clients(&self) {
         self.broadcast(ServerMsg::PlayerList {
             players: self
@@ -279,35 +896,122 @@ impl Room {
                     has_game: true,
                     client_fields: self.uses_client_fields(),
                     proposed_game: player.proposed_game,
+                    is_owner: *name == self.owner,
                 })
                 .collect(),
         });
     }
 
-    fn add_player(&mut self, name: String, client: ClientHandle) {
+    /// Adds a player to the room, or sends them `RoomFull` and returns false if there's no space.
+    fn add_player(&mut self, name: String, client: ClientHandle) -> bool {
+        if self
+            .settings
+            .max_players
+            .is_some_and(|max| self.players.len() >= max)
+        {
+            client.send(ServerMsg::RoomFull);
+            return false;
+        }
+        if self.players.is_empty() {
+            self.owner = name.clone();
+        }
+        self.last_activity = Instant::now();
         self.players.insert(
             name,
             RoomClient {
                 client: client.clone(),
                 proposed_game: false,
+                connected: true,
             },
         );
         client.send(ServerMsg::JoinedGame);
         self.broadcast_clients();
+        true
     }
 
     fn remove_player(&mut self, name: &str) {
         self.players.remove(name);
+        self.migrate_owner();
         self.broadcast_clients();
     }
 
+    /// Promotes another player to owner if the owner has left or disconnected.
+    ///
+    /// Connected players are preferred; the owner is kept if nobody else is connected.
+    /// Returns true if the owner changed.
+    fn migrate_owner(&mut self) -> bool {
+        if self.players.get(&self.owner).is_some_and(|p| p.connected) {
+            return false;
+        }
+        let next = self
+            .players
+            .iter()
+            .filter(|(_, player)| player.connected)
+            .map(|(name, _)| name)
+            .min()
+            .or_else(|| self.players.keys().min());
+        match next {
+            Some(next) if *next != self.owner => {
+                info!("{} is now the owner of room {:?}", next, self.name);
+                self.owner = next.clone();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn transfer_ownership(&mut self, owner: &str, name: &str) {
+        if self.owner == owner && name != owner && self.players.contains_key(name) {
+            self.owner = name.into();
+            self.broadcast_clients();
+        }
+    }
+
+    /// Stops sending messages to a player until they reconnect.
+    fn disconnect_player(&mut self, name: &str) {
+        if let Some(player) = self.players.get_mut(name) {
+            player.connected = false;
+        }
+        if self.migrate_owner() {
+            self.broadcast_clients();
+        }
+    }
+
+    /// Resumes sending messages to a player on a new connection, bringing them up to date with
+    /// the game in progress.
+    fn reconnect_player(&mut self, name: &str, client: ClientHandle) {
+        if let Some(player) = self.players.get_mut(name) {
+            player.client = client.clone();
+            player.connected = true;
+            client.send(ServerMsg::JoinedGame);
+            if self.running {
+                client.send(ServerMsg::StartedGame {
+                    client_fields: self.uses_client_fields(),
+                    settings: Box::new(self.settings.clone()),
+                });
+                let fields = match &self.fields {
+                    RoomFields::ClientFields(fields) => fields.clone(),
+                    RoomFields::ServerFields(fields) => fields
+                        .iter()
+                        .map(|(name, field)| (name.clone(), field.serialize()))
+                        .collect(),
+                };
+                client.send(ServerMsg::Fields { fields });
+                if self.paused {
+                    client.send(ServerMsg::Paused { paused: true });
+                }
+            }
+            self.broadcast_clients();
+        }
+    }
+
     fn proposed_game(&mut self, name: &str) {
         if let Some(player) = self.players.get_mut(name) {
             player.proposed_game = true;
             player.client.send(ServerMsg::ConfirmedStartGame);
             self.broadcast_clients();
 
-            for (_, player) in &self.players {
+            for player in self.players.values() {
                 if !player.proposed_game {
                     return;
                 }
@@ -316,17 +1020,164 @@ impl Room {
         }
     }
 
+    /// Withdraws a player’s request to start the game, if it hasn’t started yet.
+    fn cancel_start(&mut self, name: &str) {
+        if self.running {
+            return;
+        }
+        if let Some(player) = self.players.get_mut(name) {
+            if player.proposed_game {
+                player.proposed_game = false;
+                self.broadcast_clients();
+            }
+        }
+    }
+
+    /// Pauses or resumes the running game. Only the owner can do this.
+    fn set_paused(&mut self, name: &str, paused: bool) {
+        if self.owner == name && self.running && self.paused != paused {
+            self.paused = paused;
+            self.broadcast(ServerMsg::Paused { paused });
+        }
+    }
+
     fn start_game(&mut self) {
+        if let RoomFields::ServerFields(fields) = &mut self.fields {
+            for name in self.players.keys() {
+                let field = PlayerField::new(&self.settings, self.replay_dir.is_some());
+                fields.insert(name.clone(), field);
+            }
+        }
+        self.started_with = self.players.len();
         self.running = true;
         self.broadcast(ServerMsg::StartedGame {
             client_fields: self.uses_client_fields(),
+            settings: Box::new(self.settings.clone()),
+        });
+        self.broadcast(ServerMsg::Countdown {
+            seconds_left: countdown_seconds(self.time),
         });
     }
 
+    /// Ranks the players according to the mode.
+    fn results(&self) -> Vec<PlayerResult> {
+        let result = |name: &String, completed, time, score| PlayerResult {
+            name: name.clone(),
+            place: 0,
+            completed,
+            time,
+            score,
+            lines: 0,
+            attack: 0,
+            knockout: self.knockouts.iter().position(|n| n == name).map(|i| i + 1),
+        };
+        let mut results: Vec<_> = match &self.fields {
+            RoomFields::ClientFields(fields) => fields
+                .iter()
+                .map(|(name, field)| result(name, false, field.time, field.score))
+                .collect(),
+            RoomFields::ServerFields(fields) => fields
+                .iter()
+                .map(|(name, field)| {
+                    let (time, completed) = field
+                        .finish
+                        .map_or((field.sim.time(), false), |f| (f.time, f.completed));
+                    PlayerResult {
+                        lines: field.stats.lines,
+                        attack: field.stats.attack_sent,
+                        ..result(name, completed, time, field.score)
+                    }
+                })
+                .collect(),
+        };
+
+        match self.settings.mode {
+            RoomMode::Sprint { .. } => results.sort_by(|a, b| {
+                b.completed.cmp(&a.completed).then_with(|| {
+                    if a.completed {
+                        a.time.total_cmp(&b.time)
+                    } else {
+                        b.lines.cmp(&a.lines)
+                    }
+                })
+            }),
+            RoomMode::Versus => {
+                // players who are still in the game come first, then those knocked out last
+                results.sort_by_key(|result| {
+                    let index = self.finish_order.iter().position(|n| *n == result.name);
+                    Reverse(index.unwrap_or(usize::MAX))
+                })
+            }
+            RoomMode::Endless | RoomMode::Ultra { .. } => {
+                results.sort_by_key(|result| Reverse(result.score))
+            }
+        }
+        for (i, result) in results.iter_mut().enumerate() {
+            result.place = i + 1;
+        }
+        results
+    }
+
+    /// Saves the replays of server fields to the replay directory, returning the replay ID.
+    fn save_replay(&mut self, results: &[PlayerResult]) -> Option<String> {
+        let dir = self.replay_dir.as_ref()?;
+        let replays: HashMap<_, _> = match &mut self.fields {
+            RoomFields::ServerFields(fields) => fields
+                .iter_mut()
+                .filter_map(|(name, field)| Some((name.clone(), field.stop_recording()?)))
+                .collect(),
+            RoomFields::ClientFields(_) => return None,
+        };
+        if replays.is_empty() {
+            return None;
+        }
+        let replay = GameReplay {
+            settings: self.settings.clone(),
+            results: results.to_vec(),
+            replays,
+        };
+        let json = match serde_json::to_vec(&replay) {
+            Ok(json) => json,
+            Err(err) => {
+                error!("failed to serialize replay: {}", err);
+                return None;
+            }
+        };
+        let id = Uuid::new_v4().to_string();
+        let path = dir.join(format!("{}.json", id));
+        tokio::spawn(async move {
+            if let Err(err) = tokio::fs::write(path, json).await {
+                error!("failed to save replay: {}", err);
+            }
+        });
+        Some(id)
+    }
+
     fn end_game(&mut self) {
-        self.broadcast(ServerMsg::EndedGame);
+        let results = self.results();
+        let replay = self.save_replay(&results);
+        self.broadcast(ServerMsg::GameResults {
+            mode: self.settings.mode,
+            results,
+            replay,
+        });
+        let stats = match &self.fields {
+            RoomFields::ServerFields(fields) => fields
+                .iter()
+                .map(|(name, field)| (name.clone(), field.stats))
+                .collect(),
+            RoomFields::ClientFields(_) => HashMap::new(),
+        };
+        self.broadcast(ServerMsg::EndedGame { stats });
         self.running = false;
+        self.paused = false;
         self.time = ROOM_START_TIME;
+        self.dirty_fields.clear();
+        self.disqualified.clear();
+        self.sent_fields.clear();
+        self.ticks_since_keyframe = 0;
+        self.finish_order.clear();
+        self.knockouts.clear();
         self.fields = if self.uses_client_fields() {
             RoomFields::ClientFields(HashMap::new())
         } else {
@@ -334,144 +1185,745 @@ impl Room {
         };
     }
 
-    fn run_game_command(&mut self, name: &str, command: GameCommand) {
-        if self.running && self.time >= 0. {
-            match &mut self.fields {
-                RoomFields::ServerFields(fields) => {
-                    if let Some(field) = fields.get_mut(name) {
-                        field.run_game_command(command);
-                    }
+    fn run_game_command(
+        &mut self,
+        name: &str,
+        command: GameCommand,
+        time: Option<Timestamp>,
+        seq: Option<u64>,
+    ) {
+        if self.running && !self.paused && self.time >= 0. {
+            if let RoomFields::ServerFields(fields) = &mut self.fields {
+                if let Some(field) = fields.get_mut(name) {
+                    field.run_game_command(command, time, seq);
                 }
-                _ => (),
             }
         }
     }
 
+    /// Stores a field state sent by a player in a client-fields room, to be broadcast on the next
+    /// tick.
+    fn update_client_field(&mut self, name: &str, field: FieldState) {
+        if !self.running || self.paused || self.time < 0. || !self.players.contains_key(name) {
+            return;
+        }
+        if self.disqualified.contains(name) {
+            return;
+        }
+        let fields = match &mut self.fields {
+            RoomFields::ClientFields(fields) => fields,
+            RoomFields::ServerFields(_) => return,
+        };
+        let prev = fields.get(name);
+        let reason = match check_client_field(&field, prev, &self.settings, self.time) {
+            Ok(()) => {
+                fields.insert(name.into(), field);
+                self.dirty_fields.insert(name.into());
+                return;
+            }
+            Err(reason) => reason,
+        };
+
+        warn!("disqualifying {}: {}", name, reason);
+        // the last valid field (or an empty one) ends their game
+        let (width, height) = (self.settings.game.width(), self.settings.game.height());
+        let mut last = prev.cloned().unwrap_or_else(|| FieldState {
+            width,
+            tiles: vec![Tile::Empty; width * height].into(),
+            active: None,
+            next: None,
+            time: 0.,
+            score: 0,
+            level: 0,
+            is_game_over: false,
+        });
+        last.is_game_over = true;
+        fields.insert(name.into(), last);
+        self.dirty_fields.insert(name.into());
+        self.disqualified.insert(name.into());
+        self.broadcast(ServerMsg::Disqualified {
+            name: name.into(),
+            reason: reason.into(),
+        });
+    }
+
     fn is_empty(&self) -> bool {
         self.players.is_empty()
     }
 
     fn broadcast(&self, msg: ServerMsg) {
-        for (_, player) in &self.players {
+        for player in self.players.values().filter(|player| player.connected) {
             player.client.send(msg.clone());
         }
     }
 
+    /// Removes spectators that have disconnected, which is otherwise only noticed when sending.
+    fn prune_watchers(&mut self) {
+        self.watchers.retain(|watcher| !watcher.is_closed());
+    }
+
+    /// Sends updated fields to the players as deltas against the states sent before, or in full on
+    /// every `FIELD_KEYFRAME_INTERVAL`th tick. Fields that weren't updated aren't sent.
+    fn broadcast_fields(&mut self, updated: HashMap<String, FieldState>) {
+        if !updated.is_empty() && !self.watchers.is_empty() {
+            let msg = ServerMsg::Fields {
+                fields: updated.clone(),
+            };
+            // spectators that can't keep up are disconnected
+            self.watchers
+                .retain(|watcher| watcher.try_send(msg.clone()).is_ok());
+        }
+
+        self.ticks_since_keyframe += 1;
+        let keyframe = self.ticks_since_keyframe >= FIELD_KEYFRAME_INTERVAL;
+        if keyframe {
+            self.ticks_since_keyframe = 0;
+        }
+
+        let mut full = HashMap::new();
+        let mut deltas = HashMap::new();
+        for (name, field) in updated {
+            let prev = self.sent_fields.get(&name).filter(|_| !keyframe);
+            match prev.and_then(|prev| field.delta(prev)) {
+                Some(delta) => {
+                    deltas.insert(name.clone(), delta);
+                }
+                None => {
+                    full.insert(name.clone(), field.clone());
+                }
+            }
+            self.sent_fields.insert(name, field);
+        }
+        if !full.is_empty() {
+            self.broadcast(ServerMsg::Fields { fields: full });
+        }
+        if !deltas.is_empty() {
+            self.broadcast(ServerMsg::FieldDelta { fields: deltas });
+        }
+    }
+
     pub fn tick(&mut self, dt: Duration) {
-        if self.running {
+        if self.running && !self.paused {
+            let prev_time = self.time;
             self.time += dt;
 
+            if prev_time < 0. {
+                let seconds_left = countdown_seconds(self.time);
+                if seconds_left != countdown_seconds(prev_time) {
+                    self.broadcast(ServerMsg::Countdown { seconds_left });
+                }
+            }
             if self.time < 0. {
                 return;
             }
 
             let mut updated_fields = HashMap::new();
-            let mut is_still_playing = false;
+            let mut finished = Vec::new();
 
-            match &mut self.fields {
+            // only players who are still in the room keep the game running
+            let players = &self.players;
+            let participants: Vec<String> = match &mut self.fields {
+                RoomFields::ClientFields(fields) => {
+                    for name in self.dirty_fields.drain() {
+                        if let Some(field) = fields.get(&name) {
+                            if field.is_game_over {
+                                finished.push((name.clone(), true));
+                            }
+                            updated_fields.insert(name, field.clone());
+                        }
+                    }
+                    // players who haven't sent a field yet are still playing
+                    players.keys().cloned().collect()
+                }
                 RoomFields::ServerFields(fields) => {
-                    for (name, field) in fields {
+                    for (name, field) in fields.iter_mut() {
                         field.tick(dt);
                         if field.is_dirty {
                             field.is_dirty = false;
                             updated_fields.insert(name.clone(), field.serialize());
                         }
-                        if !field.field.is_top_out() {
-                            is_still_playing = true;
+                        if let Some(finish) = field.finish {
+                            finished.push((name.clone(), !finish.completed));
                         }
                     }
+                    fields
+                        .keys()
+                        .filter(|name| players.contains_key(*name))
+                        .cloned()
+                        .collect()
+                }
+            };
+            let remaining = |finish_order: &[String]| {
+                participants
+                    .iter()
+                    .filter(|name| !finish_order.contains(name))
+                    .count()
+            };
+            for (name, topped_out) in finished {
+                if self.finish_order.contains(&name) {
+                    continue;
+                }
+                self.finish_order.push(name.clone());
+                if topped_out {
+                    self.knockouts.push(name.clone());
+                    self.broadcast(ServerMsg::KnockedOut {
+                        name,
+                        remaining: remaining(&self.finish_order),
+                    });
                 }
-                _ => (), // TODO
             }
 
-            if !updated_fields.is_empty() {
-                self.broadcast(ServerMsg::Fields {
-                    fields: updated_fields,
-                });
-            }
+            self.broadcast_fields(updated_fields);
 
-            if !is_still_playing {
+            let playing = remaining(&self.finish_order);
+            let ended = match self.settings.mode {
+                RoomMode::Versus => playing == 0 || (playing == 1 && self.started_with > 1),
+                _ => playing == 0,
+            };
+            if ended {
                 self.end_game();
             }
         }
     }
 }
 
-const CLEAR_TIMEOUT: Duration = 0.5;
-const LOCK_DELAY: Duration = 0.5;
+/// How a player’s game ended.
+#[derive(Debug, Clone, Copy)]
+struct Finish {
+    /// The player’s game time when they finished.
+    time: Timestamp,
+    /// True if the player reached the goal of the mode rather than topping out.
+    completed: bool,
+}
+
+/// How far back in game time inputs may be applied, to make up for network latency.
+const MAX_INPUT_DELAY: Duration = 0.25;
+
+/// Something that happened to a server field.
+#[derive(Debug, Clone, Copy)]
+enum FieldEvent {
+    Step(Duration),
+    Input(GameCommand),
+}
 
 struct PlayerField {
-    field: ActiveField,
+    sim: Simulation,
+    attack_table: AttackTable,
+    mode: RoomMode,
+    finish: Option<Finish>,
     score: usize,
-    time: Timestamp,
-    step_cooldown: Duration,
-    is_game_over: bool,
+    stats: Stats,
     is_dirty: bool,
+    /// The field as it was up to `MAX_INPUT_DELAY` ago, for applying late inputs at the time they
+    /// were made.
+    rewind_base: Option<Box<PlayerField>>,
+    /// Steps and inputs since `rewind_base`.
+    rewind_events: VecDeque<FieldEvent>,
+    /// Total duration of the steps in `rewind_events`.
+    rewind_duration: Duration,
+    /// Sequence number of the last input, if the client sends them.
+    last_input_seq: Option<u64>,
 }
 
 impl PlayerField {
-    fn level(&self) -> usize {
-        // TODO: needs tweaking
-        ((self.score as f64 / 1000.).powf(1.4) + 2.).log(E).ceil() as usize
+    fn new(settings: &RoomSettings, record: bool) -> PlayerField {
+        let mut field = PlayerField {
+            sim: Simulation::with_config(&settings.game),
+            attack_table: settings.attack_table.clone(),
+            mode: settings.mode,
+            finish: None,
+            score: 0,
+            stats: Stats::new(),
+            is_dirty: true,
+            rewind_base: None,
+            rewind_events: VecDeque::new(),
+            rewind_duration: 0.,
+            last_input_seq: None,
+        };
+        // only the rewind base records, so that late inputs are recorded where they were applied
+        // and re-simulating doesn’t need a copy of the replay
+        let mut base = field.snapshot();
+        if record {
+            base.sim.start_recording();
+        }
+        field.rewind_base = Some(Box::new(base));
+        field
     }
 
-    fn step_cooldown(&self) -> Duration {
-        let level = self.level();
-        (0.8 - ((level as f64 - 1.) * 0.007)).powf(level as f64 - 1.)
+    /// Stops recording and returns the replay, including the events since the rewind base.
+    fn stop_recording(&mut self) -> Option<Replay> {
+        let base = self.rewind_base.as_mut()?;
+        for event in self.rewind_events.drain(..) {
+            match event {
+                FieldEvent::Step(dt) => base.step(dt),
+                FieldEvent::Input(command) => base.apply_input(command),
+            }
+        }
+        self.rewind_duration = 0.;
+        base.sim.stop_recording()
+    }
+
+    /// Returns a copy of the field’s game state, without rewind history.
+    fn snapshot(&self) -> PlayerField {
+        PlayerField {
+            sim: self.sim.clone(),
+            attack_table: self.attack_table.clone(),
+            mode: self.mode,
+            finish: self.finish,
+            score: self.score,
+            stats: self.stats,
+            is_dirty: false,
+            rewind_base: None,
+            rewind_events: VecDeque::new(),
+            rewind_duration: 0.,
+            last_input_seq: None,
+        }
+    }
+
+    fn level(&self) -> usize {
+        self.sim.speed_curve().level(&LevelProgress {
+            score: self.score,
+            ..LevelProgress::default()
+        })
     }
 
     fn tick(&mut self, dt: Duration) {
-        if !self.is_game_over {
-            self.time += dt;
+        if self.finish.is_some() {
+            return;
+        }
+        self.step(dt);
+        self.rewind_events.push_back(FieldEvent::Step(dt));
+        self.rewind_duration += dt;
 
-            self.step_cooldown -= dt;
-            if self.step_cooldown <= 0. {
-                self.field.move_active_down(self.time);
-                if self.field.should_lock_active(LOCK_DELAY, self.time) {
-                    self.field.lock_active();
-                    self.field.spawn_active(None, self.time);
+        // move the rewind base forward to at most MAX_INPUT_DELAY ago
+        while self.rewind_duration > MAX_INPUT_DELAY {
+            let base = match &mut self.rewind_base {
+                Some(base) => base,
+                None => break,
+            };
+            match self.rewind_events.pop_front() {
+                Some(FieldEvent::Step(dt)) => {
+                    base.step(dt);
+                    self.rewind_duration -= dt;
                 }
-                self.step_cooldown = self.step_cooldown();
-                self.is_dirty = true;
+                Some(FieldEvent::Input(command)) => base.apply_input(command),
+                None => break,
             }
+        }
+    }
 
-            let cleared_lines = self.field.clear_lines(CLEAR_TIMEOUT, self.time);
+    fn step(&mut self, dt: Duration) {
+        if self.finish.is_none() {
+            let dt = match self.mode {
+                // don't overshoot the time limit
+                RoomMode::Ultra { time_limit } => dt.min(time_limit - self.sim.time()).max(0.),
+                _ => dt,
+            };
+            let level = self.level();
+            self.sim.set_level(level);
+            let result = self.sim.step(dt);
+            self.stats.record_time(dt);
+            if let Some(locked) = &result.locked {
+                self.stats.record_lock(locked);
+                self.stats.record_attack(self.attack_table.attack(locked));
+                self.score += lock_score(locked, level) + drop_score(locked);
+            }
 
-            // TODO: score
+            if result.moved_down
+                || result.locked.is_some()
+                || result.cleared_lines > 0
+                || result.topped_out
+            {
+                self.is_dirty = true;
+            }
 
-            if self.field.is_top_out() {
-                self.is_game_over = true;
+            let completed = match self.mode {
+                RoomMode::Sprint { lines } => self.stats.lines >= lines,
+                RoomMode::Ultra { time_limit } => self.sim.time() >= time_limit,
+                RoomMode::Endless | RoomMode::Versus => false,
+            };
+            if completed || self.sim.is_game_over() {
+                self.finish = Some(Finish {
+                    time: self.sim.time(),
+                    completed,
+                });
                 self.is_dirty = true;
             }
         }
     }
 
-    fn run_game_command(&mut self, command: GameCommand) {
-        match command {
-            GameCommand::MoveLeft => self.field.move_active_left(self.time),
-            GameCommand::MoveRight => self.field.move_active_right(self.time),
-            GameCommand::SoftDrop => self.field.move_active_down(self.time),
-            GameCommand::HardDrop => {
-                self.field.sonic_drop_active(self.time);
-                self.field.lock_active();
+    /// Runs a command sent by the player.
+    ///
+    /// If the player’s game time when they sent it is given, the command is applied at that time
+    /// (at most `MAX_INPUT_DELAY` ago) and the field is re-simulated from there. Commands with a
+    /// sequence number that isn’t newer than the last one are ignored.
+    fn run_game_command(
+        &mut self,
+        command: GameCommand,
+        time: Option<Timestamp>,
+        seq: Option<u64>,
+    ) {
+        if self.finish.is_some() {
+            return;
+        }
+        if let Some(seq) = seq {
+            if self.last_input_seq.is_some_and(|last| seq <= last) {
+                return;
             }
-            GameCommand::RotateCW => self.field.rotate_active_cw(self.time),
-            GameCommand::RotateCCW => self.field.rotate_active_ccw(self.time),
-            GameCommand::SwapHeld => self.field.swap_held_piece(self.time),
+            self.last_input_seq = Some(seq);
         }
+
+        let delay = time.map_or(0., |time| {
+            (self.sim.time() - time).max(0.).min(self.rewind_duration)
+        });
+        if delay <= 0. || self.rewind_base.is_none() {
+            self.apply_input(command);
+            self.rewind_events.push_back(FieldEvent::Input(command));
+            return;
+        }
+
+        // find where the command goes, splitting the step it happened during
+        let mut index = self.rewind_events.len();
+        let mut remaining = delay;
+        while remaining > 0. && index > 0 {
+            let event = self.rewind_events[index - 1];
+            match event {
+                FieldEvent::Step(dt) if dt <= remaining => {
+                    remaining -= dt;
+                    index -= 1;
+                }
+                FieldEvent::Step(dt) => {
+                    self.rewind_events[index - 1] = FieldEvent::Step(dt - remaining);
+                    self.rewind_events
+                        .insert(index, FieldEvent::Step(remaining));
+                    remaining = 0.;
+                }
+                FieldEvent::Input(_) => index -= 1,
+            }
+        }
+        self.rewind_events.insert(index, FieldEvent::Input(command));
+
+        let mut field = match &mut self.rewind_base {
+            Some(base) => {
+                let recording = base.sim.stop_recording();
+                let field = base.snapshot();
+                if let Some(recording) = recording {
+                    base.sim.resume_recording(recording);
+                }
+                field
+            }
+            None => return,
+        };
+        for event in &self.rewind_events {
+            match *event {
+                FieldEvent::Step(dt) => field.step(dt),
+                FieldEvent::Input(command) => field.apply_input(command),
+            }
+        }
+        self.sim = field.sim;
+        self.finish = field.finish;
+        self.score = field.score;
+        self.stats = field.stats;
         self.is_dirty = true;
     }
 
+    fn apply_input(&mut self, command: GameCommand) {
+        if self.finish.is_some() {
+            return;
+        }
+        self.stats.record_input();
+        if self.sim.apply_input(command.into()) {
+            self.is_dirty = true;
+        }
+    }
+
     fn serialize(&self) -> FieldState {
+        let field = self.sim.field();
         FieldState {
-            width: self.field.field().width(),
-            tiles: self.field.field().tiles().clone().into(),
-            active: self.field.active_piece().map(Clone::clone),
-            next: self.field.queue().get(0).map(Clone::clone),
-            time: self.time,
+            width: field.field().width(),
+            tiles: field.field().tiles().clone().into(),
+            active: field.active_piece().copied(),
+            next: field.queue().front().copied(),
+            time: self.sim.time(),
             score: self.score,
             level: self.level(),
-            is_game_over: self.is_game_over,
+            is_game_over: self.finish.is_some(),
         }
     }
 }
+
+#[cfg(test)]
+fn client_field(time: Timestamp, score: usize, level: usize, filled: usize) -> FieldState {
+    let settings = RoomSettings::default();
+    let (width, height) = (settings.game.width(), settings.game.height());
+    let mut tiles = vec![Tile::Garbage; filled];
+    tiles.resize(width * height, Tile::Empty);
+    FieldState {
+        width,
+        tiles: tiles.into(),
+        active: None,
+        next: None,
+        time,
+        score,
+        level,
+        is_game_over: false,
+    }
+}
+
+#[test]
+fn client_field_checks() {
+    let settings = RoomSettings::default();
+    let first = client_field(1., 100, 1, 8);
+    assert_eq!(check_client_field(&first, None, &settings, 1.), Ok(()));
+    let next = client_field(2., 1200, 1, 16);
+    assert_eq!(
+        check_client_field(&next, Some(&first), &settings, 2.),
+        Ok(())
+    );
+
+    let mut wrong_size = first.clone();
+    wrong_size.width += 1;
+    assert!(check_client_field(&wrong_size, None, &settings, 1.).is_err());
+    assert!(check_client_field(&first, None, &settings, -5.).is_err());
+    assert!(check_client_field(&first, Some(&next), &settings, 2.).is_err());
+
+    // placing the whole field at once
+    let filled = client_field(1.1, 100, 1, 200);
+    assert!(check_client_field(&filled, Some(&first), &settings, 2.).is_err());
+
+    let fast = client_field(1.1, 100_000, 1, 8);
+    assert!(check_client_field(&fast, Some(&first), &settings, 2.).is_err());
+
+    // a huge level neither passes nor lets the score through
+    let high_level = client_field(1.1, usize::MAX, usize::MAX, 8);
+    assert!(check_client_field(&high_level, Some(&first), &settings, 2.).is_err());
+    let high_level = client_field(1.1, 100, 1000, 8);
+    assert!(check_client_field(&high_level, Some(&first), &settings, 2.).is_err());
+}
+
+#[cfg(test)]
+fn test_player_field() -> PlayerField {
+    let mut settings = RoomSettings::default();
+    settings.game = settings.game.with_fixed_seed();
+    PlayerField::new(&settings, false)
+}
+
+#[cfg(test)]
+impl Clone for PlayerField {
+    fn clone(&self) -> Self {
+        PlayerField {
+            rewind_base: self.rewind_base.clone(),
+            rewind_events: self.rewind_events.clone(),
+            rewind_duration: self.rewind_duration,
+            last_input_seq: self.last_input_seq,
+            ..self.snapshot()
+        }
+    }
+}
+
+#[test]
+fn late_input_matches_on_time_input() {
+    let mut on_time = test_player_field();
+    let mut late = on_time.clone();
+
+    for _ in 0..24 {
+        on_time.tick(1. / 60.);
+        late.tick(1. / 60.);
+    }
+    let input_time = on_time.sim.time();
+    on_time.run_game_command(GameCommand::MoveLeft, None, None);
+    for _ in 0..6 {
+        on_time.tick(1. / 60.);
+        late.tick(1. / 60.);
+    }
+    late.run_game_command(GameCommand::MoveLeft, Some(input_time), None);
+
+    let (on_time_piece, late_piece) = (
+        on_time.sim.field().active_piece().unwrap(),
+        late.sim.field().active_piece().unwrap(),
+    );
+    assert_eq!(late_piece.pos(), on_time_piece.pos());
+    assert_eq!(late_piece.rotation(), on_time_piece.rotation());
+    assert_eq!(
+        late.sim.field().field().tiles(),
+        on_time.sim.field().field().tiles()
+    );
+    assert_eq!(late.stats.inputs, 1);
+    assert_eq!(late.score, on_time.score);
+    assert!(late.is_dirty);
+}
+
+#[test]
+fn late_input_is_recorded_where_it_was_applied() {
+    let mut settings = RoomSettings::default();
+    settings.game = settings.game.with_fixed_seed();
+    let mut field = PlayerField::new(&settings, true);
+    for _ in 0..30 {
+        field.tick(1. / 60.);
+    }
+    let input_time = field.sim.time() - 0.05;
+    field.run_game_command(GameCommand::MoveLeft, Some(input_time), None);
+    for _ in 0..60 {
+        field.tick(1. / 60.);
+    }
+
+    let replay = field.stop_recording().unwrap();
+    assert_eq!(
+        replay.simulate().field().state_hash(),
+        field.sim.field().state_hash()
+    );
+}
+
+#[test]
+fn late_input_is_clamped() {
+    let mut field = test_player_field();
+    for _ in 0..60 {
+        field.tick(1. / 60.);
+    }
+    assert!(field.rewind_duration <= MAX_INPUT_DELAY);
+
+    // made a second ago, so it lands at the oldest kept time
+    field.run_game_command(GameCommand::MoveLeft, Some(0.), None);
+    assert!(matches!(field.rewind_events[0], FieldEvent::Input(_)));
+    let steps: Duration = field
+        .rewind_events
+        .iter()
+        .map(|event| match event {
+            FieldEvent::Step(dt) => *dt,
+            FieldEvent::Input(_) => 0.,
+        })
+        .sum();
+    assert!((steps - field.rewind_duration).abs() < 1e-9);
+
+    // made in the future, so it's applied now
+    field.run_game_command(GameCommand::MoveRight, Some(100.), None);
+    assert!(matches!(
+        field.rewind_events.back(),
+        Some(FieldEvent::Input(GameCommand::MoveRight))
+    ));
+}
+
+#[test]
+fn stale_input_seq_is_dropped() {
+    let mut field = test_player_field();
+    field.tick(1. / 60.);
+    field.run_game_command(GameCommand::MoveLeft, None, Some(5));
+    field.run_game_command(GameCommand::MoveLeft, None, Some(3));
+    field.run_game_command(GameCommand::MoveLeft, None, Some(5));
+    assert_eq!(field.stats.inputs, 1);
+    assert_eq!(field.last_input_seq, Some(5));
+    field.run_game_command(GameCommand::MoveLeft, None, Some(6));
+    assert_eq!(field.stats.inputs, 2);
+}
+
+#[test]
+fn leaving_players_dont_keep_the_game_running() {
+    let settings = RoomSettings {
+        mode: RoomMode::Versus,
+        ..RoomSettings::default()
+    };
+    for &client_fields in &[true, false] {
+        let mut room = Room::new(
+            String::new(),
+            String::new(),
+            client_fields,
+            settings.clone(),
+            None,
+        );
+        for name in &["a", "b", "c"] {
+            room.add_player(name.to_string(), ClientHandle::detached());
+        }
+        room.start_game();
+        room.time = 0.;
+
+        if let RoomFields::ClientFields(fields) = &mut room.fields {
+            let mut field = client_field(0., 0, 1, 0);
+            field.is_game_over = true;
+            fields.insert("a".into(), field);
+            room.dirty_fields.insert("a".into());
+        } else {
+            room.finish_order.push("a".into());
+        }
+        room.tick(1. / 60.);
+        assert!(room.running);
+
+        // b and c are still playing after the knocked out player leaves
+        room.remove_player("a");
+        room.tick(1. / 60.);
+        assert!(room.running);
+
+        room.remove_player("b");
+        room.tick(1. / 60.);
+        assert!(!room.running);
+    }
+}
+
+#[cfg(test)]
+fn test_manager(max_rooms: Option<usize>) -> (Arc<Mutex<GameManager>>, GMScheduler) {
+    let timeout = core::time::Duration::from_secs(60);
+    GameManager::new(ManagerOptions {
+        timeouts: IdleTimeouts {
+            client: timeout,
+            room: timeout,
+        },
+        tick_interval: core::time::Duration::from_millis(16),
+        max_clients: None,
+        max_rooms,
+        room_defaults: RoomSettings::default(),
+        replay_dir: None,
+    })
+}
+
+#[test]
+fn queued_players_are_matched() {
+    let (gm, _scheduler) = test_manager(Some(1));
+    let mut gm = gm.lock();
+    for name in &["a", "b", "c", "d"] {
+        gm.add_client(name.to_string(), String::new(), ClientHandle::detached())
+            .unwrap();
+    }
+
+    gm.queue_match("a", RoomMode::Versus);
+    assert!(gm.rooms.is_empty());
+    gm.queue_match("b", RoomMode::Versus);
+    assert_eq!(gm.rooms.len(), 1);
+    assert!(gm.client_rooms.contains_key("a"));
+    assert_eq!(gm.client_rooms.get("a"), gm.client_rooms.get("b"));
+    assert!(gm.match_queue.is_empty());
+
+    // the room limit is reached, so the next pair waits until the room closes
+    gm.queue_match("c", RoomMode::Versus);
+    gm.queue_match("d", RoomMode::Versus);
+    assert_eq!(gm.match_queue.len(), 2);
+    gm.remove_from_rooms("a");
+    gm.remove_from_rooms("b");
+    assert!(gm.match_queue.is_empty());
+    assert!(gm.client_rooms.contains_key("c"));
+    assert_eq!(gm.client_rooms.get("c"), gm.client_rooms.get("d"));
+}
+
+#[test]
+fn watchers_are_limited() {
+    let (gm, _scheduler) = test_manager(None);
+    let mut gm = gm.lock();
+    gm.add_client("a".into(), String::new(), ClientHandle::detached())
+        .unwrap();
+    gm.create_room("a".into(), None, String::new(), false, None);
+    let id = gm.client_rooms["a"].to_string();
+
+    let mut watchers: Vec<_> = (0..MAX_WATCHERS)
+        .map(|_| gm.watch_room(&id, "").unwrap())
+        .collect();
+    assert!(gm.watch_room(&id, "").is_none());
+    // spectators that left make room for new ones
+    watchers.pop();
+    assert!(gm.watch_room(&id, "").is_some());
+
+    // spectators count towards the client limit
+    gm.options.max_clients = Some(MAX_WATCHERS);
+    watchers.clear();
+    let _watchers: Vec<_> = (1..MAX_WATCHERS)
+        .map(|_| gm.watch_room(&id, "").unwrap())
+        .collect();
+    assert!(gm.watch_room(&id, "").is_none());
+}
@@ -1,27 +1,34 @@
 #[macro_use]
 extern crate log;
 
+use axum::extract::ws::WebSocketUpgrade;
+use axum::extract::{self, ConnectInfo, State};
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::Response;
+use axum::routing::get;
+use axum::Router;
 use clap::*;
-use hyper::method::Method;
-use hyper::uri::RequestUri;
+use parking_lot::Mutex;
+use serde::Deserialize;
 use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::process::exit;
 use std::sync::Arc;
-use tokio::prelude::*;
-use tokio::reactor::Handle;
+use std::time::Duration;
+use tokio::net::TcpListener;
 use tokio::runtime::Runtime;
-use websocket::header::Headers;
-use websocket::r#async::Server;
-use websocket::server::InvalidConnection;
 
 mod client;
+mod config;
 mod game;
 mod http;
 mod protocol;
 
 const DEFAULT_HOST: &str = "127.0.0.1";
-const DEFAULT_PORT: &str = "7375";
+const DEFAULT_PORT: u16 = 7375;
+const DEFAULT_CLIENT_TIMEOUT: u64 = 600;
+const DEFAULT_ROOM_TIMEOUT: u64 = 1800;
 
 fn main() {
     let matches = app_from_crate!()
@@ -32,6 +39,13 @@ fn main() {
                 .multiple(true)
                 .help("Enables debug/trace logging (repeat to increase verbosity)"),
         )
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .takes_value(true)
+                .help("Reads settings from a TOML file (flags override its values)"),
+        )
         .arg(
             Arg::with_name("host")
                 .short("H")
@@ -49,10 +63,34 @@ fn main() {
                 .takes_value(true)
                 .help(&format!("Sets the port (default: {})", DEFAULT_PORT)),
         )
+        .arg(
+            Arg::with_name("client-timeout")
+                .long("client-timeout")
+                .takes_value(true)
+                .help(&format!(
+                    "Closes connections idle for this many seconds (default: {})",
+                    DEFAULT_CLIENT_TIMEOUT
+                )),
+        )
+        .arg(
+            Arg::with_name("room-timeout")
+                .long("room-timeout")
+                .takes_value(true)
+                .help(&format!(
+                    "Closes rooms idle for this many seconds (default: {})",
+                    DEFAULT_ROOM_TIMEOUT
+                )),
+        )
         .arg(Arg::with_name("proxy").short("P").long("proxy").help(
             "Set to prefer the X-Real-IP header for obtaining client addresses\n\
              (note that this can be spoofed if the client is connecting directly)",
         ))
+        .arg(
+            Arg::with_name("no-proxy")
+                .long("no-proxy")
+                .conflicts_with("proxy")
+                .help("Ignores the X-Real-IP header even if the config file enables it"),
+        )
         .arg(
             Arg::with_name("static")
                 .short("s")
@@ -60,9 +98,30 @@ fn main() {
                 .takes_value(true)
                 .help("Set this to a path to serve files over HTTP"),
         )
+        .arg(
+            Arg::with_name("replays")
+                .short("r")
+                .long("replays")
+                .takes_value(true)
+                .help("Set this to a directory to save game replays to and serve them from"),
+        )
         .get_matches();
 
-    let host = matches.value_of("host").unwrap_or(DEFAULT_HOST);
+    let config = match matches.value_of("config") {
+        Some(path) => match config::ServerConfig::load(Path::new(path)) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("failed to read config file “{}”: {}", path, err);
+                exit(1);
+            }
+        },
+        None => config::ServerConfig::default(),
+    };
+
+    let host = matches
+        .value_of("host")
+        .or(config.host.as_deref())
+        .unwrap_or(DEFAULT_HOST);
     let host: IpAddr = match host.parse() {
         Ok(host) => host,
         Err(_) => {
@@ -71,18 +130,69 @@ fn main() {
         }
     };
 
-    let port = matches.value_of("port").unwrap_or(DEFAULT_PORT);
-    let port: u16 = match port.parse() {
-        Ok(port) => port,
-        Err(_) => {
-            eprintln!("invalid port “{}”", port);
-            exit(1);
-        }
+    let port = match matches.value_of("port") {
+        Some(port) => match port.parse() {
+            Ok(port) => port,
+            Err(_) => {
+                eprintln!("invalid port “{}”", port);
+                exit(1);
+            }
+        },
+        None => config.port.unwrap_or(DEFAULT_PORT),
     };
 
-    let proxy = matches.is_present("proxy");
+    let timeout_arg = |name: &str, value: Option<u64>, default: u64| {
+        let secs = match matches.value_of(name) {
+            Some(secs) => match secs.parse() {
+                Ok(secs) => secs,
+                Err(_) => {
+                    eprintln!("invalid {} “{}”", name, secs);
+                    exit(1);
+                }
+            },
+            None => value.unwrap_or(default),
+        };
+        Duration::from_secs(secs)
+    };
+    let timeouts = game::IdleTimeouts {
+        client: timeout_arg(
+            "client-timeout",
+            config.limits.client_timeout,
+            DEFAULT_CLIENT_TIMEOUT,
+        ),
+        room: timeout_arg(
+            "room-timeout",
+            config.limits.room_timeout,
+            DEFAULT_ROOM_TIMEOUT,
+        ),
+    };
+
+    let proxy = if matches.is_present("no-proxy") {
+        false
+    } else {
+        matches.is_present("proxy") || config.proxy.unwrap_or(false)
+    };
 
-    let static_path = matches.value_of("static").map(|path| String::from(path));
+    let static_path = matches
+        .value_of("static")
+        .map(String::from)
+        .or(config.static_path);
+
+    let replay_path = matches
+        .value_of("replays")
+        .map(String::from)
+        .or(config.replays);
+    if let Some(replay_path) = &replay_path {
+        if let Err(err) = std::fs::create_dir_all(replay_path) {
+            eprintln!(
+                "failed to create replay directory “{}”: {}",
+                replay_path, err
+            );
+            exit(1);
+        }
+    }
+
+    let tick_rate = config.tick_rate.unwrap_or(game::DEFAULT_TICK_RATE);
 
     let (log_level, lib_log_level) = match matches.occurrences_of("verbose") {
         0 => (log::LevelFilter::Info, log::LevelFilter::Info),
@@ -107,129 +217,160 @@ fn main() {
         })
         .level(log_level)
         // set a different log level for some targets that’d spam stderr otherwise
-        .level_for("tokio_threadpool", lib_log_level)
-        .level_for("tokio_reactor", lib_log_level)
-        .level_for("tokio_io", lib_log_level)
+        .level_for("mio", lib_log_level)
         .level_for("hyper", lib_log_level)
+        .level_for("tungstenite", lib_log_level)
+        .level_for("tokio_tungstenite", lib_log_level)
         .chain(std::io::stderr())
         .apply()
         .expect("Failed to initialize logger");
 
-    let (game_manager, gm_scheduler) = game::GameManager::new();
+    let (game_manager, gm_scheduler) = game::GameManager::new(game::ManagerOptions {
+        timeouts,
+        tick_interval: Duration::from_secs_f64(1. / tick_rate),
+        max_clients: config.limits.max_clients,
+        max_rooms: config.limits.max_rooms,
+        room_defaults: config.room_defaults.unwrap_or_default(),
+        replay_dir: replay_path.clone().map(PathBuf::from),
+    });
 
-    let mut runtime = Runtime::new().expect("failed to create tokio runtime");
+    let state = Arc::new(ServerState {
+        game_manager,
+        proxy,
+        static_path,
+        replay_path,
+    });
 
-    runtime
-        .block_on::<_, _, ()>(futures::lazy(move || {
-            let server = match Server::bind((host, port), &Handle::default()) {
-                Ok(server) => server,
-                Err(err) => {
-                    eprintln!("failed to bind to {}:{}: {}", host, port, err);
-                    exit(1);
-                }
-            };
-
-            info!("Listening on {}:{}", host, port);
-
-            tokio::spawn(gm_scheduler);
-
-            server
-                .incoming()
-                .then(move |result| match result {
-                    Ok(res) => Ok(Some(res)),
-                    Err(InvalidConnection {
-                        stream,
-                        parsed,
-                        buffer: _,
-                        error: _,
-                    }) => {
-                        if let (Some(stream), None) = (&stream, &parsed) {
-                            match stream.peer_addr() {
-                                Ok(addr) => info!("Ignoring invalid connection from {}", addr),
-                                Err(_) => {
-                                    info!("Ignoring invalid connection from an unknown address");
-                                }
-                            }
-                        } else if let (Some(stream), Some(req)) = (stream, parsed) {
-                            match stream.peer_addr() {
-                                Ok(addr) => {
-                                    let addr = peer_addr(&req.headers, addr, proxy);
-                                    http::handle_http(static_path.as_ref(), stream, req, addr);
-                                }
-                                Err(_) => {
-                                    info!("Ignoring invalid connection from an unknown address");
-                                }
-                            };
-                        } else {
-                            info!("Ignoring invalid connection from an unknown address");
-                        }
-                        Ok(None)
-                    }
-                })
-                .filter_map(|item| item)
-                .for_each(move |(upgrade, addr)| {
-                    let addr = peer_addr(&upgrade.headers, addr, proxy);
-
-                    let accept = match &upgrade.request.subject {
-                        (Method::Get, RequestUri::AbsolutePath(path)) => match &**path {
-                            "/tetris" => true,
-                            path => {
-                                info!(
-                                    "Rejecting websocket connection from {} (bad path {})",
-                                    addr, path
-                                );
-                                false
-                            }
-                        },
-                        (m, p) => {
-                            info!(
-                                "Rejecting websocket connection from {} (bad request {} {})",
-                                addr, m, p
-                            );
-                            false
-                        }
-                    };
-
-                    if accept {
-                        let gm_ref = Arc::clone(&game_manager);
-
-                        info!("Accepting websocket connection from {}", addr);
-                        tokio::spawn(
-                            upgrade
-                                .accept()
-                                .map_err(move |err| {
-                                    error!(
-                                        "Failed to accept websocket connection from {}: {}",
-                                        addr, err
-                                    );
-                                })
-                                .and_then(move |(client, _)| {
-                                    client::accept(Arc::clone(&gm_ref), client, addr)
-                                }),
-                        );
-                    } else {
-                        tokio::spawn(upgrade.reject().map(|_| {}).map_err(|_| {}));
-                    }
-                    Ok(())
-                })
-        }))
+    let app = Router::new()
+        .route("/tetris", get(websocket))
+        .route("/replays/:id", get(replay))
+        .route("/watch/:id", get(watch))
+        .fallback(static_file)
+        .with_state(state);
+
+    let runtime = Runtime::new().expect("failed to create tokio runtime");
+
+    runtime.block_on(async move {
+        let listener = match TcpListener::bind((host, port)).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                eprintln!("failed to bind to {}:{}: {}", host, port, err);
+                exit(1);
+            }
+        };
+
+        info!("Listening on {}:{}", host, port);
+
+        tokio::spawn(gm_scheduler.run());
+
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
         .expect("server died");
+    });
+}
+
+/// State shared by all requests.
+struct ServerState {
+    game_manager: Arc<Mutex<game::GameManager>>,
+    proxy: bool,
+    static_path: Option<String>,
+    replay_path: Option<String>,
+}
+
+/// Accepts a websocket connection at `/tetris`.
+async fn websocket(
+    State(state): State<Arc<ServerState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let addr = peer_addr(&headers, addr, state.proxy);
+    let gm = Arc::clone(&state.game_manager);
+
+    info!("Accepting websocket connection from {}", addr);
+    upgrade
+        .on_failed_upgrade(move |err| {
+            error!(
+                "Failed to accept websocket connection from {}: {}",
+                addr, err
+            );
+        })
+        .on_upgrade(move |socket| client::accept(gm, socket, addr))
+}
+
+/// Serves a saved replay at `/replays/<id>`.
+async fn replay(
+    State(state): State<Arc<ServerState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    extract::Path(id): extract::Path<String>,
+) -> Response {
+    let addr = peer_addr(&headers, addr, state.proxy);
+    http::serve_replay(state.replay_path.as_deref(), &id, addr).await
+}
+
+#[derive(Deserialize)]
+struct WatchQuery {
+    #[serde(default)]
+    password: String,
+}
+
+/// Streams a room’s field states as server-sent events at `/watch/<room id>`.
+///
+/// Rooms with a password need it as the `password` query parameter.
+async fn watch(
+    State(state): State<Arc<ServerState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    extract::Path(id): extract::Path<String>,
+    extract::Query(query): extract::Query<WatchQuery>,
+) -> Response {
+    let addr = peer_addr(&headers, addr, state.proxy);
+    let fields = state.game_manager.lock().watch_room(&id, &query.password);
+    match fields {
+        Some(fields) => {
+            info!("{}: watching room {}", addr, id);
+            http::event_stream(fields)
+        }
+        None => {
+            info!("{}: can’t watch room {}", addr, id);
+            http::html_error(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+/// Serves static files for any other path, if enabled.
+async fn static_file(
+    State(state): State<Arc<ServerState>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    method: Method,
+    uri: Uri,
+) -> Response {
+    let addr = peer_addr(&headers, addr, state.proxy);
+    match (&method, &state.static_path) {
+        (&Method::GET, Some(static_path)) => http::serve_file(static_path, uri.path(), addr).await,
+        _ => {
+            info!("{}: not found: {} {}", addr, method, uri);
+            http::html_error(StatusCode::NOT_FOUND)
+        }
+    }
 }
 
 /// Resolves a peer address that may be behind a proxy, falling back to the given address otherwise.
-fn peer_addr(headers: &Headers, addr: SocketAddr, proxy: bool) -> SocketAddr {
+fn peer_addr(headers: &HeaderMap, addr: SocketAddr, proxy: bool) -> SocketAddr {
     if proxy {
-        match headers.get_raw("x-real-ip") {
-            Some(bufs) => {
-                if let Some(buf) = bufs.get(0) {
-                    match String::from_utf8_lossy(buf).parse() {
-                        Ok(real_ip) => SocketAddr::new(real_ip, 0), // don’t know the port
-                        Err(_) => addr,
-                    }
-                } else {
-                    addr
-                }
-            }
+        match headers
+            .get("x-real-ip")
+            .and_then(|value| value.to_str().ok())
+        {
+            Some(real_ip) => match real_ip.parse() {
+                Ok(real_ip) => SocketAddr::new(real_ip, 0), // don’t know the port
+                Err(_) => addr,
+            },
             None => addr,
         }
     } else {
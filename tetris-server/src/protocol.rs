@@ -2,7 +2,13 @@ use core::fmt;
 use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::collections::HashMap;
-use tetris_core::field::{ActivePiece, PieceType, Tile, Timestamp};
+use tetris_core::attack::AttackTable;
+use tetris_core::config::GameConfig;
+use tetris_core::field::{ActivePiece, Duration, PieceType, Tile, Timestamp};
+use tetris_core::replay::Replay;
+use tetris_core::sim::Input;
+use tetris_core::speed::SpeedCurve;
+use tetris_core::stats::Stats;
 
 #[derive(Debug, Clone, Copy, Deserialize)]
 pub enum GameCommand {
@@ -22,6 +28,20 @@ pub enum GameCommand {
     SwapHeld,
 }
 
+impl From<GameCommand> for Input {
+    fn from(this: GameCommand) -> Input {
+        match this {
+            GameCommand::MoveLeft => Input::MoveLeft,
+            GameCommand::MoveRight => Input::MoveRight,
+            GameCommand::SoftDrop => Input::MoveDown,
+            GameCommand::HardDrop => Input::HardDrop,
+            GameCommand::RotateCW => Input::RotateCW,
+            GameCommand::RotateCCW => Input::RotateCCW,
+            GameCommand::SwapHeld => Input::Hold,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "type")]
 pub enum ClientMsg {
@@ -32,16 +52,61 @@ pub enum ClientMsg {
     CreateGame {
         password: String,
         client_fields: bool,
+        /// The name shown in the room list; defaults to the creator’s name.
+        #[serde(default)]
+        name: Option<String>,
+        /// Defaults to the server’s room defaults.
+        #[serde(default)]
+        settings: Option<RoomSettings>,
     },
 
     #[serde(rename = "join-game")]
     JoinGame { name: String, password: String },
 
+    #[serde(rename = "join-room")]
+    JoinRoom { id: String, password: String },
+
+    #[serde(rename = "list-rooms")]
+    ListRooms,
+
+    /// Waits for other players who want to play the same mode and puts everyone in a new room.
+    #[serde(rename = "queue-match")]
+    QueueMatch { mode: RoomMode },
+
+    #[serde(rename = "leave-queue")]
+    LeaveQueue,
+
     #[serde(rename = "start-game")]
     StartGame,
 
+    #[serde(rename = "cancel-start")]
+    CancelStart,
+
+    #[serde(rename = "kick-player")]
+    KickPlayer { name: String },
+
+    #[serde(rename = "ban-player")]
+    BanPlayer { name: String },
+
+    #[serde(rename = "transfer-ownership")]
+    TransferOwnership { name: String },
+
+    #[serde(rename = "pause")]
+    Pause,
+
+    #[serde(rename = "resume")]
+    Resume,
+
     #[serde(rename = "game-command")]
-    GameCommand { command: GameCommand },
+    GameCommand {
+        command: GameCommand,
+        /// The player’s game time when they made the input, so it can be applied then.
+        #[serde(default)]
+        time: Option<Timestamp>,
+        /// Increasing input number; inputs that arrive out of order are dropped.
+        #[serde(default)]
+        seq: Option<u64>,
+    },
 
     #[serde(rename = "field")]
     Field { field: FieldState },
@@ -54,6 +119,142 @@ pub struct ClientDesc {
     pub client_fields: bool,
     pub in_game: bool,
     pub proposed_game: bool,
+    /// True if this is the owner of the room, who can kick and ban players.
+    pub is_owner: bool,
+}
+
+/// Maximum field height in room settings.
+const MAX_FIELD_HEIGHT: usize = 100;
+/// Maximum preview depth in room settings.
+const MAX_PREVIEW_DEPTH: usize = 16;
+
+/// How a room’s game is played and when it ends.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum RoomMode {
+    /// Play until everyone has topped out. Players are ranked by score.
+    #[default]
+    #[serde(rename = "endless")]
+    Endless,
+    /// Clear the given number of lines as quickly as possible.
+    #[serde(rename = "sprint")]
+    Sprint {
+        #[serde(default = "RoomMode::sprint_lines")]
+        lines: usize,
+    },
+    /// Score as many points as possible within the time limit in seconds.
+    #[serde(rename = "ultra")]
+    Ultra {
+        #[serde(default = "RoomMode::ultra_time_limit")]
+        time_limit: Duration,
+    },
+    /// The last player who hasn’t topped out wins.
+    #[serde(rename = "versus")]
+    Versus,
+}
+
+impl RoomMode {
+    fn sprint_lines() -> usize {
+        40
+    }
+
+    fn ultra_time_limit() -> Duration {
+        120.
+    }
+
+    /// Returns true if the mode needs the server to track lines and time, which client fields
+    /// don’t report.
+    pub fn needs_server_fields(&self) -> bool {
+        matches!(self, RoomMode::Sprint { .. } | RoomMode::Ultra { .. })
+    }
+}
+
+/// Gameplay settings of a room, which apply to every player’s field.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoomSettings {
+    /// Field size, speed curve, lock delay, clear timeout, preview depth and so on.
+    #[serde(flatten)]
+    pub game: GameConfig,
+    /// Garbage sent by line clears.
+    pub attack_table: AttackTable,
+    pub mode: RoomMode,
+    /// The maximum number of players in the room, or None if unlimited.
+    pub max_players: Option<usize>,
+}
+
+impl Default for RoomSettings {
+    fn default() -> Self {
+        RoomSettings {
+            game: GameConfig::builder()
+                .speed_curve(SpeedCurve::score())
+                .build()
+                .expect("invalid game config"),
+            attack_table: AttackTable::default(),
+            mode: RoomMode::default(),
+            max_players: None,
+        }
+    }
+}
+
+impl RoomSettings {
+    /// Checks that the settings are valid and within the limits of the server.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        self.game.validate()?;
+        if self.game.height() > MAX_FIELD_HEIGHT {
+            return Err("field is too high");
+        }
+        if self.game.preview_depth() > MAX_PREVIEW_DEPTH {
+            return Err("preview is too deep");
+        }
+        if self.max_players == Some(0) {
+            return Err("room must allow at least one player");
+        }
+        match self.mode {
+            RoomMode::Sprint { lines: 0 } => Err("sprint needs at least one line"),
+            RoomMode::Ultra { time_limit } if !(time_limit > 0. && time_limit.is_finite()) => {
+                Err("ultra needs a positive time limit")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A saved game, with a replay of every player’s field.
+#[derive(Serialize, Debug, Clone)]
+pub struct GameReplay {
+    pub settings: RoomSettings,
+    pub results: Vec<PlayerResult>,
+    pub replays: HashMap<String, Replay>,
+}
+
+/// A player’s result at the end of a game.
+#[derive(Serialize, Debug, Clone)]
+pub struct PlayerResult {
+    pub name: String,
+    /// Placement, starting at 1 for the winner.
+    pub place: usize,
+    /// True if the player reached the goal of the mode (sprint and ultra only).
+    pub completed: bool,
+    /// The player’s game time when they finished.
+    pub time: Timestamp,
+    pub score: usize,
+    /// Cleared lines; client fields don’t report these, so they are 0.
+    pub lines: usize,
+    /// Garbage lines sent; 0 for client fields.
+    pub attack: usize,
+    /// If the player topped out, the order in which they did, starting at 1.
+    pub knockout: Option<usize>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RoomDesc {
+    pub id: String,
+    pub name: String,
+    pub players: usize,
+    pub max_players: Option<usize>,
+    pub in_game: bool,
+    pub has_password: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -100,18 +301,8 @@ impl<'de> Visitor<'de> for TileVisitor {
     where
         E: serde::de::Error,
     {
-        let mut cursor = 0;
-        let mut tiles = Vec::with_capacity(128);
-        while cursor < s.len() {
-            let (tile, l) =
-                Tile::parse_from_str(&s[cursor..]).map_err(|_| E::custom("invalid tile list"))?;
-            tiles.push(tile);
-            cursor += l;
-
-            if tiles.len() >= 2048 {
-                break;
-            }
-        }
+        let mut tiles = Tile::parse_list(s).ok_or_else(|| E::custom("invalid tile list"))?;
+        tiles.truncate(2048);
         Ok(TileSerde(tiles))
     }
 }
@@ -128,19 +319,104 @@ pub struct FieldState {
     pub is_game_over: bool,
 }
 
+impl FieldState {
+    /// Returns the total number of tiles.
+    pub fn tile_count(&self) -> usize {
+        self.tiles.0.len()
+    }
+
+    /// Returns the number of tiles that aren’t empty.
+    pub fn filled_tiles(&self) -> usize {
+        self.tiles
+            .0
+            .iter()
+            .filter(|tile| **tile != Tile::Empty)
+            .count()
+    }
+
+    /// Returns the changes since a previously sent state, or None if the field size changed.
+    pub fn delta(&self, prev: &FieldState) -> Option<FieldDelta> {
+        if self.width == 0 || self.width != prev.width || self.tiles.0.len() != prev.tiles.0.len() {
+            return None;
+        }
+        let rows = self
+            .tiles
+            .0
+            .chunks(self.width)
+            .zip(prev.tiles.0.chunks(self.width))
+            .enumerate()
+            .filter(|(_, (row, prev_row))| row != prev_row)
+            .map(|(y, (row, _))| (y, TileSerde(row.to_vec())))
+            .collect();
+        Some(FieldDelta {
+            rows,
+            active: self.active,
+            next: self.next,
+            time: self.time,
+            score: self.score,
+            level: self.level,
+            is_game_over: self.is_game_over,
+        })
+    }
+}
+
+/// Changes to a field since the last state that was sent.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldDelta {
+    /// Changed rows as pairs of the row index from the bottom and all of the row’s tiles.
+    pub rows: Vec<(usize, TileSerde)>,
+    pub active: Option<ActivePiece>,
+    pub next: Option<PieceType>,
+    pub time: Timestamp,
+    pub score: usize,
+    pub level: usize,
+    pub is_game_over: bool,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub enum ServerMsg {
     #[serde(rename = "name-taken")]
     NameTaken,
 
+    /// Sent instead of `NameTaken` if the server has no space for more clients.
+    #[serde(rename = "server-full")]
+    ServerFull,
+
     #[serde(rename = "client-list")]
     ClientList { clients: Vec<ClientDesc> },
 
+    #[serde(rename = "room-list")]
+    RoomList { rooms: Vec<RoomDesc> },
+
     #[serde(rename = "started-game")]
-    StartedGame { client_fields: bool },
+    StartedGame {
+        client_fields: bool,
+        settings: Box<RoomSettings>,
+    },
+
+    #[serde(rename = "failed-create-game")]
+    FailedCreateGame { reason: String },
+
+    /// Sent when a player queues for a match and whenever more players are waiting for the same
+    /// mode.
+    #[serde(rename = "queued")]
+    Queued { mode: RoomMode, waiting: usize },
+    #[serde(rename = "left-queue")]
+    LeftQueue,
+    #[serde(rename = "failed-queue-match")]
+    FailedQueueMatch { reason: String },
 
     #[serde(rename = "joined-game")]
     JoinedGame,
+    /// Sent instead of `FailedJoinGame` if the room has no space left.
+    #[serde(rename = "room-full")]
+    RoomFull,
+    /// Sent to the players of a room that was closed for inactivity.
+    #[serde(rename = "room-closed")]
+    RoomClosed,
+    /// Sent to a player who was removed from their room by its owner.
+    #[serde(rename = "kicked")]
+    Kicked { banned: bool },
     #[serde(rename = "failed-join-game")]
     FailedJoinGame,
     #[serde(rename = "game-client-list")]
@@ -148,9 +424,62 @@ pub enum ServerMsg {
     #[serde(rename = "confirmed-start-game")]
     ConfirmedStartGame,
 
+    #[serde(rename = "knocked-out")]
+    KnockedOut { name: String, remaining: usize },
+
+    /// Sent when a player in a client-fields room sent an impossible field and was taken out of
+    /// the game.
+    #[serde(rename = "disqualified")]
+    Disqualified { name: String, reason: String },
+
+    /// Sent once per second before the game starts; zero means the game has started.
+    #[serde(rename = "countdown")]
+    Countdown { seconds_left: u32 },
+
+    /// Sent when the room owner pauses or resumes the game.
+    #[serde(rename = "paused")]
+    Paused { paused: bool },
+
+    #[serde(rename = "game-results")]
+    GameResults {
+        mode: RoomMode,
+        results: Vec<PlayerResult>,
+        /// ID of the saved replay, which can be downloaded at `/replays/<id>`.
+        replay: Option<String>,
+    },
+
     #[serde(rename = "ended-game")]
-    EndedGame,
+    EndedGame { stats: HashMap<String, Stats> },
 
     #[serde(rename = "fields")]
     Fields { fields: HashMap<String, FieldState> },
+
+    /// Changes to fields, to be applied to the last states received.
+    #[serde(rename = "field-delta")]
+    FieldDelta { fields: HashMap<String, FieldDelta> },
+}
+
+#[test]
+fn room_settings_reject_invalid_piece_sets() {
+    use tetris_core::piece_set::{PieceSet, PieceShape, RotationCenter};
+
+    fn check(json: &str) -> Result<(), String> {
+        let settings: RoomSettings = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        settings.validate().map_err(String::from)
+    }
+
+    let mut piece_set = PieceSet::new();
+    let shape = PieceShape::new(&[(0, 0), (1, 0)], RotationCenter::Origin).unwrap();
+    piece_set.register(shape, (0, 0), [1.; 4], None).unwrap();
+    let settings = RoomSettings {
+        game: GameConfig::builder().piece_set(piece_set).build().unwrap(),
+        ..RoomSettings::default()
+    };
+    let json = serde_json::to_string(&settings).unwrap();
+    assert_eq!(check(&json), Ok(()));
+
+    assert!(check(&json.replace("\"len\":2", "\"len\":200")).is_err());
+    assert!(check(&json.replace("{\"Custom\":0}", "{\"Custom\":249}")).is_err());
+    assert!(check(&json.replace("\"custom_count\":1", "\"custom_count\":0")).is_err());
+    assert!(check(r#"{ "piece_set": { "pieces": [], "custom_count": 0 } }"#).is_err());
 }
@@ -1,17 +1,322 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+use tetris_core::attack::AttackTable;
+use tetris_core::bot::{Bot, BotConfig};
+use tetris_core::config::{GameConfig, GameConfigBuilder};
+use tetris_core::field::{
+    ActiveField, ActivePiece, ClearKind, Field, FieldEvent, LineClear, LockResult, PieceType,
+    Shape, SpawnInput, SpinType, Tile, TopOut,
+};
 use tetris_core::geom::Point2;
-use tetris_core::field::{ActiveField, ActivePiece, Shape, Tile};
+use tetris_core::input::{InputConfig, InputState};
+use tetris_core::modes::{drop_score, lock_score};
+use tetris_core::replay::{Replay, ReplayPlayer};
+use tetris_core::sim::{Input, Simulation};
+use tetris_core::speed::{LevelProgress, SpeedCurve};
+use tetris_core::stats::Stats;
 use wasm_bindgen::prelude::*;
-use wasm_bindgen::throw_str;
+use wasm_bindgen::{throw_str, JsCast};
+
+#[wasm_bindgen]
+extern "C" {
+    pub type Uint8Array;
+
+    #[wasm_bindgen(constructor)]
+    fn new(buffer: &JsValue, byte_offset: u32, length: u32) -> Uint8Array;
+
+    #[wasm_bindgen(js_namespace = WebAssembly)]
+    type Memory;
+
+    #[wasm_bindgen(method, getter)]
+    fn buffer(this: &Memory) -> JsValue;
+
+    /// Options for `createActiveField`.
+    pub type FieldOptions;
+
+    #[wasm_bindgen(method, getter)]
+    fn width(this: &FieldOptions) -> Option<u32>;
+
+    #[wasm_bindgen(method, getter)]
+    fn height(this: &FieldOptions) -> Option<u32>;
+
+    #[wasm_bindgen(method, getter, js_name = visibleHeight)]
+    fn visible_height(this: &FieldOptions) -> Option<u32>;
+
+    #[wasm_bindgen(method, getter, js_name = lockDelay)]
+    fn lock_delay(this: &FieldOptions) -> Option<f64>;
+
+    #[wasm_bindgen(method, getter, js_name = previewCount)]
+    fn preview_count(this: &FieldOptions) -> Option<u32>;
+
+    #[wasm_bindgen(method, getter)]
+    fn randomizer(this: &FieldOptions) -> Option<String>;
+
+    #[wasm_bindgen(method, getter)]
+    fn seed(this: &FieldOptions) -> Option<u64>;
+
+    /// Everything needed to render a field, as returned by `getRenderState`.
+    #[wasm_bindgen(js_name = Object)]
+    pub type RenderState;
+
+    #[wasm_bindgen(constructor, js_class = Object)]
+    fn new() -> RenderState;
+
+    #[wasm_bindgen(method, setter)]
+    fn set_width(this: &RenderState, width: usize);
+
+    #[wasm_bindgen(method, setter)]
+    fn set_tiles(this: &RenderState, tiles: Uint8Array);
+
+    #[wasm_bindgen(method, setter = activePiece)]
+    fn set_active_piece(this: &RenderState, tiles: Box<[i32]>);
+
+    #[wasm_bindgen(method, setter)]
+    fn set_ghost(this: &RenderState, tiles: Box<[i32]>);
+
+    #[wasm_bindgen(method, setter)]
+    fn set_queue(this: &RenderState, queue: Box<[JsValue]>);
+
+    #[wasm_bindgen(method, setter)]
+    fn set_hold(this: &RenderState, hold: Option<String>);
+
+    #[wasm_bindgen(method, setter = clearRows)]
+    fn set_clear_rows(this: &RenderState, rows: Box<[f64]>);
+
+    #[wasm_bindgen(method, setter = topOut)]
+    fn set_top_out(this: &RenderState, top_out: bool);
+
+    /// A field event, as returned by `tick`.
+    #[wasm_bindgen(js_name = Object)]
+    pub type TickEvent;
+
+    #[wasm_bindgen(constructor, js_class = Object)]
+    fn new() -> TickEvent;
+
+    #[wasm_bindgen(method, setter = type)]
+    fn set_type(this: &TickEvent, event_type: &str);
+
+    #[wasm_bindgen(method, setter)]
+    fn set_piece(this: &TickEvent, piece: String);
+
+    #[wasm_bindgen(method, setter)]
+    fn set_lines(this: &TickEvent, lines: usize);
+
+    #[wasm_bindgen(method, setter)]
+    fn set_rows(this: &TickEvent, rows: Box<[u32]>);
+}
+
+/// Converts a field event to an object with a `type` and, depending on the type, the `piece`
+/// type, the number of `lines` or the cleared `rows`.
+fn tick_event(event: &FieldEvent) -> JsValue {
+    let object = TickEvent::new();
+    let event_type = match event {
+        FieldEvent::Spawned { piece_type } => {
+            object.set_piece(piece_type_name(*piece_type));
+            "spawned"
+        }
+        FieldEvent::Moved { .. } => "moved",
+        FieldEvent::Rotated { .. } => "rotated",
+        FieldEvent::Held { piece_type } => {
+            object.set_piece(piece_type_name(*piece_type));
+            "held"
+        }
+        FieldEvent::PieceLocked(result) => {
+            object.set_piece(piece_type_name(result.piece_type));
+            object.set_lines(result.lines_cleared);
+            "locked"
+        }
+        FieldEvent::LinesCleared { rows, .. } => {
+            object.set_lines(rows.len());
+            object.set_rows(rows.iter().map(|y| *y as u32).collect());
+            "linesCleared"
+        }
+        FieldEvent::GarbageInserted { lines } => {
+            object.set_lines(*lines);
+            "garbageInserted"
+        }
+        FieldEvent::ZoneStarted => "zoneStarted",
+        FieldEvent::LinesCollected { lines } => {
+            object.set_lines(*lines);
+            "linesCollected"
+        }
+        FieldEvent::ZoneEnded { lines } => {
+            object.set_lines(*lines);
+            "zoneEnded"
+        }
+        FieldEvent::ItemTriggered { .. } => "itemTriggered",
+        FieldEvent::ToppedOut(_) => "toppedOut",
+    };
+    object.set_type(event_type);
+    object.into()
+}
+
+const INPUT_NAMES: [(Input, &str); 8] = [
+    (Input::MoveLeft, "moveLeft"),
+    (Input::MoveRight, "moveRight"),
+    (Input::MoveDown, "moveDown"),
+    (Input::SonicDrop, "sonicDrop"),
+    (Input::HardDrop, "hardDrop"),
+    (Input::RotateCW, "rotateCW"),
+    (Input::RotateCCW, "rotateCCW"),
+    (Input::Hold, "hold"),
+];
+
+fn input_name(input: Input) -> &'static str {
+    INPUT_NAMES
+        .iter()
+        .find(|(i, _)| *i == input)
+        .map_or("", |(_, name)| name)
+}
+
+fn parse_input(name: &str) -> Option<Input> {
+    INPUT_NAMES
+        .iter()
+        .find(|(_, n)| *n == name)
+        .map(|(input, _)| *input)
+}
+
+fn piece_type_name(piece: PieceType) -> String {
+    let mut buf = String::new();
+    piece.stringify(&mut buf);
+    buf
+}
+
+/// Returns the rows that have been cleared, as pairs of row index and clear time.
+fn clear_rows(field: &Field) -> Box<[f64]> {
+    (0..field.tiles().len() / field.width())
+        .filter_map(|y| match field.get_tile(0, y) {
+            Some(Tile::Clear(time)) => Some([y as f64, time]),
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Writes the tile codes of a field to the buffer and returns a view of it in wasm memory.
+fn tile_codes_view(field: &Field, buffer: &mut Vec<u8>) -> Uint8Array {
+    field.tile_codes_into(buffer);
+    let memory: Memory = wasm_bindgen::memory().unchecked_into();
+    Uint8Array::new(
+        &memory.buffer(),
+        buffer.as_ptr() as u32,
+        buffer.len() as u32,
+    )
+}
+
+/// Returns the tiles of a piece in field coordinates, as x and y pairs.
+fn absolute_tiles(piece: Option<&ActivePiece>) -> Box<[i32]> {
+    piece
+        .into_iter()
+        .flat_map(|piece| {
+            let pos = piece.pos();
+            piece
+                .iter_tiles()
+                .flat_map(move |tile| [(pos.x + tile.x) as i32, (pos.y + tile.y) as i32])
+        })
+        .collect()
+}
+
+/// Numeric codes of the built-in piece types, which can be used instead of their names.
+///
+/// Custom pieces are numbered from 7 in the order they were added to the piece set.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum PieceCode {
+    I = 0,
+    J = 1,
+    L = 2,
+    O = 3,
+    S = 4,
+    T = 5,
+    Z = 6,
+}
+
+/// Codes of tiles that aren’t pieces, as returned by `getTilesBuffer` and `getFieldTileCode`.
+///
+/// Piece tiles use their piece code plus one.
+#[wasm_bindgen]
+#[derive(Clone, Copy)]
+pub enum TileCode {
+    Empty = 0,
+    Clear = 254,
+    Garbage = 255,
+}
 
 #[wasm_bindgen(js_name = Point2)]
 pub struct JsPoint2(Point2<isize>);
 
+/// The simulation behind a field.
+enum Game {
+    /// A game driven by the player.
+    Live(Box<Simulation>),
+    /// A replay being played back.
+    Replay(Box<ReplayPlayer>),
+}
+
+impl Game {
+    fn sim(&self) -> &Simulation {
+        match self {
+            Game::Live(sim) => sim,
+            Game::Replay(player) => player.simulation(),
+        }
+    }
+
+    fn sim_mut(&mut self) -> &mut Simulation {
+        match self {
+            Game::Live(sim) => sim,
+            Game::Replay(player) => player.simulation_mut(),
+        }
+    }
+}
+
 #[wasm_bindgen(js_name = ActiveField)]
-pub struct JsActiveField(ActiveField);
+pub struct JsActiveField {
+    game: Game,
+    stats: Stats,
+    /// The lock delay the field was configured with.
+    lock_delay: f64,
+    /// Guideline score of the pieces locked so far.
+    score: usize,
+    /// Tile codes returned by `getTilesBuffer`.
+    tiles_buffer: Vec<u8>,
+    /// The last locked piece whose line clear hasn't been returned by `clearLines` yet.
+    last_lock: Option<LockResult>,
+    /// Events drained from the field that haven't been returned by `tick` yet.
+    events: VecDeque<FieldEvent>,
+}
 
 #[wasm_bindgen(js_name = ActivePiece)]
 pub struct JsActivePiece(ActivePiece);
 
+#[wasm_bindgen(js_name = Stats)]
+pub struct JsStats(Stats);
+
+#[wasm_bindgen(js_name = Bot)]
+pub struct JsBot(Bot);
+
+/// Auto-repeat handling for held keys.
+#[wasm_bindgen(js_name = InputState)]
+pub struct JsInputState {
+    state: InputState,
+    /// The time of the last key event or tick.
+    time: f64,
+    /// Inputs waiting to be applied on the next tick.
+    pending: Vec<Input>,
+}
+
+/// Lines cleared by `clearLines`.
+#[wasm_bindgen(js_name = LineClear)]
+pub struct JsLineClear {
+    clear: LineClear,
+    attack: usize,
+}
+
+#[wasm_bindgen(js_name = Suggestion)]
+pub struct JsSuggestion {
+    hold: bool,
+    piece: ActivePiece,
+}
+
 #[wasm_bindgen(js_class = Point2)]
 impl JsPoint2 {
     #[wasm_bindgen(js_name = "x", getter)]
@@ -34,11 +339,29 @@ impl JsActivePiece {
         buf
     }
 
+    /// Returns the piece code of the piece type (see `PieceCode`).
+    #[wasm_bindgen(js_name = "typeCode", getter)]
+    pub fn type_code(&self) -> u8 {
+        self.0.piece_type().index()
+    }
+
     #[wasm_bindgen(js_name = "pos", getter)]
     pub fn pos(&self) -> JsPoint2 {
         JsPoint2(self.0.pos())
     }
 
+    /// Returns the rotation in clockwise steps from the spawn orientation (0–3).
+    #[wasm_bindgen(js_name = "rotation", getter)]
+    pub fn rotation(&self) -> usize {
+        self.0.rotation().cw_steps()
+    }
+
+    /// Returns true if this piece was swapped in from hold, in which case it can't be held again.
+    #[wasm_bindgen(js_name = "wasHeld", getter)]
+    pub fn was_held(&self) -> bool {
+        self.0.was_held_piece()
+    }
+
     #[wasm_bindgen(js_name = "getTiles")]
     pub fn tiles(&self) -> Box<[isize]> {
         self.0
@@ -62,100 +385,762 @@ impl JsActivePiece {
     }
 }
 
+impl JsInputState {
+    /// Queues the inputs that repeat until the given time.
+    fn advance_to(&mut self, time: f64) {
+        let dt = (time - self.time).max(0.);
+        self.time = self.time.max(time);
+        self.pending.extend(self.state.update(dt));
+    }
+}
+
+#[wasm_bindgen(js_class = InputState)]
+impl JsInputState {
+    /// Handles a key press of an action with the same names as `ActiveField.applyInput`.
+    #[wasm_bindgen(js_name = "keyDown")]
+    pub fn key_down(&mut self, action: &str, time: f64) {
+        let input = match parse_input(action) {
+            Some(input) => input,
+            None => throw_str(&format!("unknown input {}", action)),
+        };
+        self.advance_to(time);
+        self.pending.extend(self.state.key_down(input));
+    }
+
+    /// Handles a key release.
+    #[wasm_bindgen(js_name = "keyUp")]
+    pub fn key_up(&mut self, action: &str, time: f64) {
+        let input = match parse_input(action) {
+            Some(input) => input,
+            None => throw_str(&format!("unknown input {}", action)),
+        };
+        self.advance_to(time);
+        self.state.key_up(input);
+    }
+
+    /// Releases all keys and discards inputs that haven't been applied yet.
+    #[wasm_bindgen(js_name = "reset")]
+    pub fn reset(&mut self) {
+        self.state.reset();
+        self.pending.clear();
+    }
+
+    /// Applies key presses and repeats up to the given time to the field, in order, and returns
+    /// the names of the applied inputs.
+    ///
+    /// This should be called before `ActiveField.tick` every frame.
+    #[wasm_bindgen(js_name = "tick")]
+    pub fn tick(&mut self, field: &mut JsActiveField, time: f64) -> Box<[JsValue]> {
+        self.advance_to(time);
+        let sim = field.live_sim();
+        self.pending
+            .drain(..)
+            .map(|input| {
+                sim.apply_input(input);
+                JsValue::from_str(input_name(input))
+            })
+            .collect()
+    }
+}
+
+#[wasm_bindgen(js_class = LineClear)]
+impl JsLineClear {
+    /// The indices of the rows that were marked as cleared.
+    #[wasm_bindgen(js_name = "rows", getter)]
+    pub fn rows(&self) -> Box<[u32]> {
+        self.clear.rows.iter().map(|y| *y as u32).collect()
+    }
+
+    /// The number of cleared lines.
+    #[wasm_bindgen(js_name = "count", getter)]
+    pub fn count(&self) -> usize {
+        self.clear.count()
+    }
+
+    /// `none`, `single`, `double`, `triple` or `tetris`.
+    #[wasm_bindgen(js_name = "kind", getter)]
+    pub fn kind(&self) -> String {
+        match self.clear.kind {
+            ClearKind::None => "none",
+            ClearKind::Single => "single",
+            ClearKind::Double => "double",
+            ClearKind::Triple => "triple",
+            ClearKind::Tetris => "tetris",
+        }
+        .into()
+    }
+
+    /// The spin of the piece that completed the lines: `none`, `mini` or `full`.
+    #[wasm_bindgen(js_name = "spin", getter)]
+    pub fn spin(&self) -> String {
+        match self.clear.spin {
+            SpinType::None => "none",
+            SpinType::Mini => "mini",
+            SpinType::Full => "full",
+        }
+        .into()
+    }
+
+    /// True if the field is empty apart from the cleared rows.
+    #[wasm_bindgen(js_name = "perfectClear", getter)]
+    pub fn perfect_clear(&self) -> bool {
+        self.clear.perfect_clear
+    }
+
+    /// The number of garbage lines this clear sends using the guideline attack table, including
+    /// combo and back-to-back bonuses.
+    #[wasm_bindgen(js_name = "attack", getter)]
+    pub fn attack(&self) -> usize {
+        self.attack
+    }
+}
+
+#[wasm_bindgen(js_class = Suggestion)]
+impl JsSuggestion {
+    /// True if the active piece should be swapped with the held piece first.
+    #[wasm_bindgen(js_name = "hold", getter)]
+    pub fn hold(&self) -> bool {
+        self.hold
+    }
+
+    /// The piece in its final position.
+    #[wasm_bindgen(js_name = "piece", getter)]
+    pub fn piece(&self) -> JsActivePiece {
+        JsActivePiece(self.piece)
+    }
+}
+
+#[wasm_bindgen(js_class = Stats)]
+impl JsStats {
+    #[wasm_bindgen(js_name = "time", getter)]
+    pub fn time(&self) -> f64 {
+        self.0.time
+    }
+
+    #[wasm_bindgen(js_name = "pieces", getter)]
+    pub fn pieces(&self) -> usize {
+        self.0.pieces
+    }
+
+    #[wasm_bindgen(js_name = "inputs", getter)]
+    pub fn inputs(&self) -> usize {
+        self.0.inputs
+    }
+
+    #[wasm_bindgen(js_name = "lines", getter)]
+    pub fn lines(&self) -> usize {
+        self.0.lines
+    }
+
+    #[wasm_bindgen(js_name = "attackSent", getter)]
+    pub fn attack_sent(&self) -> usize {
+        self.0.attack_sent
+    }
+
+    #[wasm_bindgen(js_name = "garbageReceived", getter)]
+    pub fn garbage_received(&self) -> usize {
+        self.0.garbage_received
+    }
+
+    #[wasm_bindgen(js_name = "maxCombo", getter)]
+    pub fn max_combo(&self) -> usize {
+        self.0.max_combo
+    }
+
+    #[wasm_bindgen(js_name = "tetrises", getter)]
+    pub fn tetrises(&self) -> usize {
+        self.0.clears.tetrises
+    }
+
+    #[wasm_bindgen(js_name = "spins", getter)]
+    pub fn spins(&self) -> usize {
+        self.0.clears.spins + self.0.clears.mini_spins
+    }
+
+    #[wasm_bindgen(js_name = "perfectClears", getter)]
+    pub fn perfect_clears(&self) -> usize {
+        self.0.clears.perfect_clears
+    }
+
+    #[wasm_bindgen(js_name = "pps", getter)]
+    pub fn pps(&self) -> f64 {
+        self.0.pps()
+    }
+
+    #[wasm_bindgen(js_name = "kpp", getter)]
+    pub fn kpp(&self) -> f64 {
+        self.0.kpp()
+    }
+
+    #[wasm_bindgen(js_name = "apm", getter)]
+    pub fn apm(&self) -> f64 {
+        self.0.apm()
+    }
+}
+
+fn parse_type_override(type_override: JsValue) -> Option<PieceType> {
+    if let Some(s) = type_override.as_string() {
+        match s.parse() {
+            Ok(t) => Some(t),
+            Err(_) => throw_str(&format!("unknown piece type {}", s)),
+        }
+    } else if let Some(code) = type_override.as_f64() {
+        // check the range first, since the cast saturates
+        let piece = if code >= 0. && code <= u8::MAX as f64 && code.fract() == 0. {
+            PieceType::from_index(code as u8)
+        } else {
+            None
+        };
+        match piece {
+            Some(t) => Some(t),
+            None => throw_str(&format!("unknown piece code {}", code)),
+        }
+    } else if type_override.is_null() {
+        None
+    } else {
+        throw_str("type override must be a string, a piece code or null");
+    }
+}
+
+/// Maps field options onto a game configuration builder, throwing if they are invalid.
+fn config_builder(options: &FieldOptions) -> GameConfigBuilder {
+    let defaults = GameConfig::default();
+    let mut builder = GameConfig::builder().field_size(
+        options
+            .width()
+            .map_or(defaults.width(), |width| width as usize),
+        options
+            .height()
+            .map_or(defaults.height(), |height| height as usize),
+        options
+            .visible_height()
+            .map_or(defaults.top_height(), |height| height as usize),
+    );
+    if let Some(lock_delay) = options.lock_delay() {
+        builder = builder.lock_delay(lock_delay);
+    }
+    if let Some(preview_count) = options.preview_count() {
+        builder = builder.preview_depth(preview_count as usize);
+    }
+    if let Some(seed) = options.seed() {
+        builder = builder.seed(seed);
+    }
+    match options.randomizer().as_deref() {
+        None | Some("bag") => (),
+        Some(randomizer) => throw_str(&format!("unknown randomizer {}", randomizer)),
+    }
+    builder
+}
+
+/// Creates a live field from the configuration, throwing if it’s invalid.
+fn create_field(builder: GameConfigBuilder) -> JsActiveField {
+    let config = builder.build().unwrap_or_else(|err| throw_str(err));
+    JsActiveField::new(
+        Game::Live(Box::new(Simulation::with_config(&config))),
+        config.lock_delay(),
+    )
+}
+
+/// Creates a field with the given options, or the defaults if there are none.
+///
+/// All options are optional: `width`, `height` and `visibleHeight` of the field, `lockDelay` in
+/// seconds, `previewCount`, `randomizer` (only `"bag"` is supported) and `seed` as a BigInt.
 #[wasm_bindgen(js_name = "createActiveField")]
-pub fn create_active_field() -> JsActiveField {
-    JsActiveField(ActiveField::new())
+pub fn create_active_field(options: Option<FieldOptions>) -> JsActiveField {
+    create_field(options.map_or_else(GameConfig::builder, |options| config_builder(&options)))
+}
+
+/// Creates a field whose piece sequence is determined by the seed, so that fields with the same
+/// seed (including those on the server) are dealt the same pieces.
+///
+/// Takes the same options as `createActiveField`, except that the seed argument takes precedence.
+#[wasm_bindgen(js_name = "createActiveFieldWithSeed")]
+pub fn create_active_field_with_seed(seed: u64, options: Option<FieldOptions>) -> JsActiveField {
+    let builder = options.map_or_else(GameConfig::builder, |options| config_builder(&options));
+    create_field(builder.seed(seed))
+}
+
+/// Creates a computer player that makes at most the given number of inputs per second.
+#[wasm_bindgen(js_name = "createBot")]
+pub fn create_bot(inputs_per_second: f64) -> JsBot {
+    JsBot(Bot::new(BotConfig {
+        inputs_per_second,
+        ..BotConfig::default()
+    }))
+}
+
+/// Creates an input handler with the given timings in seconds, using the default for any
+/// that are missing.
+#[wasm_bindgen(js_name = "createInputState")]
+pub fn create_input_state(
+    das: Option<f64>,
+    arr: Option<f64>,
+    soft_drop_interval: Option<f64>,
+) -> JsInputState {
+    let defaults = InputConfig::default();
+    JsInputState {
+        state: InputState::new(InputConfig {
+            das: das.unwrap_or(defaults.das),
+            arr: arr.unwrap_or(defaults.arr),
+            soft_drop_interval: soft_drop_interval.unwrap_or(defaults.soft_drop_interval),
+        }),
+        time: 0.,
+        pending: Vec::new(),
+    }
+}
+
+/// Returns the level for a score, using the same speed curve as multiplayer rooms.
+#[wasm_bindgen(js_name = "getLevel")]
+pub fn level(score: usize) -> usize {
+    SpeedCurve::score().level(&LevelProgress {
+        score,
+        ..LevelProgress::default()
+    })
+}
+
+/// Returns the time between gravity steps at a level, using the same speed curve as multiplayer
+/// rooms.
+#[wasm_bindgen(js_name = "getGravityInterval")]
+pub fn gravity_interval(level: usize) -> f64 {
+    SpeedCurve::score().gravity_interval(level)
+}
+
+impl JsActiveField {
+    fn new(game: Game, lock_delay: f64) -> JsActiveField {
+        JsActiveField {
+            game,
+            stats: Stats::new(),
+            lock_delay,
+            score: 0,
+            tiles_buffer: Vec::new(),
+            last_lock: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Returns the simulation of a live game, throwing if this is a replay.
+    fn live_sim(&mut self) -> &mut Simulation {
+        match &mut self.game {
+            Game::Live(sim) => sim,
+            Game::Replay(_) => throw_str("field is playing back a replay"),
+        }
+    }
+
+    fn field(&self) -> &ActiveField {
+        self.game.sim().field()
+    }
+
+    /// Returns the field of a live game, throwing if this is a replay, which changes to the field
+    /// would desync.
+    fn field_mut(&mut self) -> &mut ActiveField {
+        self.live_sim().field_mut()
+    }
+
+    /// Collects locked pieces from the field’s event buffer for stats and scoring, and keeps
+    /// the drained events for `tick`.
+    fn collect_events(&mut self) {
+        let events: Vec<_> = self.game.sim_mut().field_mut().drain_events().collect();
+        for event in events {
+            if let FieldEvent::PieceLocked(result) = &event {
+                self.stats.record_lock(result);
+                self.score += lock_score(result, level(self.score)) + drop_score(result);
+                self.last_lock = Some(*result);
+            }
+            // like the field’s own buffer, drop the oldest events if nobody calls tick
+            if self.events.len() >= ActiveField::MAX_EVENTS {
+                self.events.pop_front();
+            }
+            self.events.push_back(event);
+        }
+    }
 }
 
 #[wasm_bindgen(js_class = ActiveField)]
 impl JsActiveField {
-    #[wasm_bindgen(js_name = "spawnActive")]
-    pub fn spawn_active(&mut self, type_override: JsValue, time: f64) {
-        let type_override = if let Some(s) = type_override.as_string() {
-            match s.parse() {
-                Ok(t) => Some(t),
-                Err(_) => throw_str(&format!("unknown piece type {}", s)),
+    /// Creates a field from state returned by `serialize`.
+    ///
+    /// The lock delay isn’t part of the state, so pass the original field’s `lockDelay` to keep
+    /// it; otherwise the default is used.
+    #[wasm_bindgen(js_name = "deserialize")]
+    pub fn deserialize(json: &str, lock_delay: Option<f64>) -> JsActiveField {
+        let field = match serde_json::from_str(json) {
+            Ok(field) => field,
+            Err(err) => throw_str(&format!("invalid field state: {}", err)),
+        };
+        let lock_delay = lock_delay.unwrap_or(GameConfig::LOCK_DELAY);
+        let sim = Simulation::new(field, lock_delay, GameConfig::CLEAR_TIMEOUT);
+        JsActiveField::new(Game::Live(Box::new(sim)), lock_delay)
+    }
+
+    /// Returns the entire state of the field as JSON, including the tiles, the queue, the held
+    /// and active pieces and the piece RNG. Stats are not included.
+    #[wasm_bindgen(js_name = "serialize")]
+    pub fn serialize(&self) -> String {
+        match serde_json::to_string(self.field()) {
+            Ok(json) => json,
+            Err(err) => throw_str(&err.to_string()),
+        }
+    }
+
+    /// Returns the seed of the piece sequence.
+    #[wasm_bindgen(js_name = "getSeed")]
+    pub fn seed(&self) -> u64 {
+        self.field().seed()
+    }
+
+    /// Returns a hash of the game state that is the same on every platform, so it can be compared
+    /// with the server’s copy of the field to detect desyncs.
+    #[wasm_bindgen(js_name = "stateHash")]
+    pub fn state_hash(&self) -> u64 {
+        self.field().state_hash()
+    }
+
+    /// Creates a field that plays back a replay returned by `exportReplay`.
+    #[wasm_bindgen(js_name = "fromReplay")]
+    pub fn from_replay(json: &str) -> JsActiveField {
+        let replay: Replay = match serde_json::from_str(json) {
+            Ok(replay) => replay,
+            Err(err) => throw_str(&format!("invalid replay: {}", err)),
+        };
+        let lock_delay = replay.lock_delay();
+        JsActiveField::new(
+            Game::Replay(Box::new(ReplayPlayer::new(replay))),
+            lock_delay,
+        )
+    }
+
+    /// Plays back the replay up to the given time. Returns true once it has finished.
+    #[wasm_bindgen(js_name = "stepReplay")]
+    pub fn step_replay(&mut self, to_time: f64) -> bool {
+        match &mut self.game {
+            Game::Replay(player) => {
+                player.advance_to(to_time);
+                player.is_finished()
             }
-        } else if type_override.is_null() {
-            None
-        } else {
-            throw_str("type override must be a string or null");
+            Game::Live(_) => throw_str("field is not playing back a replay"),
+        }
+    }
+
+    /// Starts recording a replay of inputs applied with `applyInput` and time advanced with
+    /// `tick`. Other methods that change the field are not recorded.
+    #[wasm_bindgen(js_name = "startRecording")]
+    pub fn start_recording(&mut self) {
+        self.live_sim().start_recording();
+    }
+
+    /// Returns the replay recorded so far as JSON, or the replay being played back.
+    #[wasm_bindgen(js_name = "exportReplay")]
+    pub fn export_replay(&self) -> Option<String> {
+        let replay = match &self.game {
+            Game::Live(sim) => sim.recording()?,
+            Game::Replay(player) => player.replay(),
         };
+        match serde_json::to_string(replay) {
+            Ok(json) => Some(json),
+            Err(err) => throw_str(&err.to_string()),
+        }
+    }
+
+    /// Applies an input (`moveLeft`, `moveRight`, `moveDown`, `sonicDrop`, `hardDrop`,
+    /// `rotateCW`, `rotateCCW` or `hold`) at the current simulation time.
+    #[wasm_bindgen(js_name = "applyInput")]
+    pub fn apply_input(&mut self, input: &str) -> bool {
+        let input = match parse_input(input) {
+            Some(input) => input,
+            None => throw_str(&format!("unknown input {}", input)),
+        };
+        self.live_sim().apply_input(input)
+    }
 
-        self.0.spawn_active(type_override, time);
+    /// Runs gravity, auto-locking, spawning and line clears for the given number of seconds,
+    /// with the level derived from the score.
+    ///
+    /// Returns the events that occurred since the last call, including those caused by
+    /// `applyInput`. See `TickEvent` for their format.
+    #[wasm_bindgen(js_name = "tick")]
+    pub fn tick(&mut self, dt: f64) -> Box<[JsValue]> {
+        self.collect_events();
+        let level = level(self.score);
+        let sim = self.live_sim();
+        sim.set_level(level);
+        sim.step(dt);
+        self.collect_events();
+        self.events
+            .drain(..)
+            .map(|event| tick_event(&event))
+            .collect()
+    }
+
+    /// Spawns the next piece from the queue, or a piece of the given type (a name or a piece
+    /// code) if it isn’t null.
+    #[wasm_bindgen(js_name = "spawnActive")]
+    pub fn spawn_active(&mut self, type_override: JsValue, time: f64) {
+        self.field_mut()
+            .spawn_active(parse_type_override(type_override), time);
+    }
+
+    /// Spawns a piece with initial rotation (in clockwise steps) and initial hold.
+    #[wasm_bindgen(js_name = "spawnActiveWith")]
+    pub fn spawn_active_with(
+        &mut self,
+        type_override: JsValue,
+        rotation: i32,
+        hold: bool,
+        time: f64,
+    ) {
+        let input = SpawnInput {
+            rotation: rotation as isize,
+            hold,
+        };
+        self.field_mut()
+            .spawn_active_with(parse_type_override(type_override), input, time);
     }
 
     #[wasm_bindgen(js_name = "rotateActiveCCW")]
-    pub fn rotate_active_ccw(&mut self, time: f64) {
-        self.0.rotate_active_ccw(time);
+    pub fn rotate_active_ccw(&mut self, time: f64) -> bool {
+        self.field_mut().rotate_active_ccw(time).is_success()
     }
 
     #[wasm_bindgen(js_name = "rotateActiveCW")]
-    pub fn rotate_active_cw(&mut self, time: f64) {
-        self.0.rotate_active_cw(time);
+    pub fn rotate_active_cw(&mut self, time: f64) -> bool {
+        self.field_mut().rotate_active_cw(time).is_success()
     }
 
     #[wasm_bindgen(js_name = "moveActiveLeft")]
-    pub fn move_active_left(&mut self, time: f64) {
-        self.0.move_active_left(time);
+    pub fn move_active_left(&mut self, time: f64) -> bool {
+        self.field_mut().move_active_left(time).is_success()
     }
 
     #[wasm_bindgen(js_name = "moveActiveRight")]
-    pub fn move_active_right(&mut self, time: f64) {
-        self.0.move_active_right(time);
+    pub fn move_active_right(&mut self, time: f64) -> bool {
+        self.field_mut().move_active_right(time).is_success()
     }
 
     #[wasm_bindgen(js_name = "moveActiveDown")]
-    pub fn move_active_down(&mut self, time: f64) {
-        self.0.move_active_down(time);
+    pub fn move_active_down(&mut self, time: f64) -> bool {
+        self.field_mut().move_active_down(time).is_success()
     }
 
     #[wasm_bindgen(js_name = "ghostPos")]
     pub fn ghost_pos(&mut self) -> JsValue {
-        match self.0.ghost_pos() {
+        match self.field().ghost_pos() {
             Some(pos) => JsValue::from(JsPoint2(pos)),
             None => JsValue::null(),
         }
     }
 
+    #[wasm_bindgen(js_name = "getGhostPiece")]
+    pub fn ghost_piece(&self) -> Option<JsActivePiece> {
+        self.field().ghost_piece().map(JsActivePiece)
+    }
+
+    /// Moves the active piece all the way down without locking it and returns the number of rows
+    /// it moved.
     #[wasm_bindgen(js_name = "sonicDropActive")]
-    pub fn sonic_drop_active(&mut self, time: f64) {
-        self.0.sonic_drop_active(time);
+    pub fn sonic_drop_active(&mut self, time: f64) -> usize {
+        self.field_mut().sonic_drop_active(time)
+    }
+
+    /// Moves the active piece all the way down, locks it and returns the number of rows it moved.
+    #[wasm_bindgen(js_name = "hardDropActive")]
+    pub fn hard_drop_active(&mut self, time: f64) -> usize {
+        self.field_mut().hard_drop_active(time)
     }
 
     #[wasm_bindgen(js_name = "lockActive")]
     pub fn lock_active(&mut self) {
-        self.0.lock_active();
+        self.field_mut().lock_active();
     }
 
     #[wasm_bindgen(js_name = "shouldLockActive")]
     pub fn should_lock_active(&mut self, lock_delay: f64, time: f64) -> bool {
-        self.0.should_lock_active(lock_delay, time)
+        self.field_mut().should_lock_active(lock_delay, time)
+    }
+
+    /// Returns the lock delay the field was created with, in seconds.
+    #[wasm_bindgen(js_name = "lockDelay", getter)]
+    pub fn lock_delay(&self) -> f64 {
+        self.lock_delay
+    }
+
+    #[wasm_bindgen(js_name = "lockProgress")]
+    pub fn lock_progress(&self, lock_delay: f64, time: f64) -> Option<f64> {
+        self.field().lock_progress(lock_delay, time)
     }
 
     #[wasm_bindgen(js_name = "swapHeldPiece")]
     pub fn swap_held_piece(&mut self, time: f64) {
-        self.0.swap_held_piece(time);
+        self.field_mut().swap_held_piece(time);
     }
 
+    /// Marks full lines as cleared and removes expired cleared lines.
     #[wasm_bindgen(js_name = "clearLines")]
-    pub fn clear_lines(&mut self, clear_timeout: f64, time: f64) -> usize {
-        self.0.clear_lines(clear_timeout, time)
+    pub fn clear_lines(&mut self, clear_timeout: f64, time: f64) -> JsLineClear {
+        self.collect_events();
+        let clear = self.field_mut().clear_lines(clear_timeout, time);
+        let attack = match self.last_lock.take() {
+            Some(result) if !clear.is_empty() => AttackTable::guideline().attack(&result),
+            other => {
+                self.last_lock = other;
+                0
+            }
+        };
+        JsLineClear { clear, attack }
+    }
+
+    /// Queues garbage lines with a hole in the given column, e.g. an attack received from the
+    /// server. Pending garbage is cancelled by line clears and inserted into the field when a
+    /// piece locks without clearing lines.
+    #[wasm_bindgen(js_name = "queueGarbage")]
+    pub fn queue_garbage(&mut self, lines: usize, hole_column: usize) {
+        if hole_column >= self.field().field().width() {
+            throw_str("hole column is outside the field");
+        }
+        self.field_mut().queue_garbage(lines, hole_column);
+    }
+
+    /// Returns the pending garbage, oldest first, as pairs of line count and hole column.
+    #[wasm_bindgen(js_name = "getPendingGarbage")]
+    pub fn pending_garbage(&self) -> Box<[u32]> {
+        self.field()
+            .pending_garbage()
+            .iter()
+            .flat_map(|garbage| [garbage.lines as u32, garbage.hole_column as u32])
+            .collect()
+    }
+
+    /// Returns the total number of pending garbage lines.
+    #[wasm_bindgen(js_name = "getPendingGarbageLines")]
+    pub fn pending_garbage_lines(&self) -> usize {
+        self.field().pending_garbage_lines()
     }
 
     #[wasm_bindgen(js_name = "cleanLines")]
     pub fn clean_lines(&mut self, clear_timeout: f64, time: f64) {
-        self.0.clean_lines(clear_timeout, time);
+        self.field_mut().clean_lines(clear_timeout, time);
     }
 
     #[wasm_bindgen(js_name = "isTopOut")]
     pub fn is_top_out(&self) -> bool {
-        self.0.is_top_out()
+        self.field().is_top_out()
+    }
+
+    #[wasm_bindgen(js_name = "getTopOut")]
+    pub fn top_out(&self) -> Option<String> {
+        self.field().top_out().map(|top_out| {
+            match top_out {
+                TopOut::BlockOut => "block-out",
+                TopOut::LockOut => "lock-out",
+                TopOut::Overflow => "overflow",
+            }
+            .into()
+        })
+    }
+
+    #[wasm_bindgen(js_name = "getCombo")]
+    pub fn combo(&self) -> usize {
+        self.field().combo()
+    }
+
+    #[wasm_bindgen(js_name = "getBackToBack")]
+    pub fn back_to_back(&self) -> usize {
+        self.field().back_to_back()
+    }
+
+    /// Counts a key press in the statistics.
+    #[wasm_bindgen(js_name = "recordInput")]
+    pub fn record_input(&mut self) {
+        self.stats.record_input();
+    }
+
+    /// Returns statistics for the game so far, given the time played.
+    ///
+    /// Locked pieces are collected from the field’s event buffer, which only holds a limited
+    /// number of events, so this (or `getScore`) should be called every frame.
+    #[wasm_bindgen(js_name = "getStats")]
+    pub fn stats(&mut self, time: f64) -> JsStats {
+        self.collect_events();
+        self.stats.time = time;
+        JsStats(self.stats)
+    }
+
+    /// Returns the guideline score of the pieces locked so far, including drop points, with the
+    /// level derived from the score as in multiplayer rooms.
+    #[wasm_bindgen(js_name = "getScore")]
+    pub fn score(&mut self) -> usize {
+        self.collect_events();
+        self.score
+    }
+
+    /// Returns the current level, derived from the score.
+    #[wasm_bindgen(js_name = "getLevel")]
+    pub fn level(&mut self) -> usize {
+        self.collect_events();
+        level(self.score)
+    }
+
+    /// Returns all positions in which the active piece can be locked.
+    #[wasm_bindgen(js_name = "enumeratePlacements")]
+    pub fn enumerate_placements(&self) -> Vec<JsActivePiece> {
+        self.field()
+            .enumerate_placements()
+            .into_iter()
+            .map(|placement| JsActivePiece(placement.piece))
+            .collect()
+    }
+
+    /// Returns the placement the bot would choose for the active piece.
+    #[wasm_bindgen(js_name = "suggestPlacement")]
+    pub fn suggest_placement(&self, bot: &JsBot) -> Option<JsSuggestion> {
+        bot.0
+            .suggest_placement(self.field())
+            .map(|(hold, piece)| JsSuggestion { hold, piece })
+    }
+
+    /// Advances the bot by the given amount of time and applies its inputs to this field.
+    ///
+    /// Returns the names of the applied inputs. Hard drops lock the piece, but spawning the next
+    /// piece is up to the caller, as with player input.
+    #[wasm_bindgen(js_name = "runBotStep")]
+    pub fn run_bot_step(&mut self, bot: &mut JsBot, dt: f64, time: f64) -> Box<[JsValue]> {
+        let inputs = bot.0.update(self.field(), dt);
+        for input in &inputs {
+            match input {
+                Input::MoveLeft => {
+                    self.field_mut().move_active_left(time);
+                }
+                Input::MoveRight => {
+                    self.field_mut().move_active_right(time);
+                }
+                Input::MoveDown => {
+                    self.field_mut().soft_drop_active(time);
+                }
+                Input::SonicDrop => {
+                    self.field_mut().sonic_drop_active(time);
+                }
+                Input::HardDrop => {
+                    self.field_mut().hard_drop_active(time);
+                }
+                Input::RotateCW => {
+                    self.field_mut().rotate_active_cw(time);
+                }
+                Input::RotateCCW => {
+                    self.field_mut().rotate_active_ccw(time);
+                }
+                Input::Hold => {
+                    self.field_mut().swap_held_piece(time);
+                }
+            }
+        }
+        inputs
+            .into_iter()
+            .map(|input| JsValue::from_str(input_name(input)))
+            .collect()
     }
 
     #[wasm_bindgen(js_name = "getNextPiece")]
     pub fn next_piece(&self) -> JsValue {
-        match self.0.queue().get(0) {
+        match self.field().queue().front() {
             Some(piece) => {
                 let mut buf = String::new();
                 piece.stringify(&mut buf);
@@ -165,9 +1150,22 @@ impl JsActiveField {
         }
     }
 
+    /// Returns the types of the next `n` pieces, or fewer if the queue is shorter.
+    #[wasm_bindgen(js_name = "getQueue")]
+    pub fn queue(&self, n: usize) -> Box<[JsValue]> {
+        self.field()
+            .peek_queue(n)
+            .map(|piece| {
+                let mut buf = String::new();
+                piece.stringify(&mut buf);
+                JsValue::from_str(&buf)
+            })
+            .collect()
+    }
+
     #[wasm_bindgen(js_name = "getHeldPiece")]
     pub fn held_piece(&self) -> JsValue {
-        match self.0.held_piece() {
+        match self.field().held_piece() {
             Some(piece) => {
                 let mut buf = String::new();
                 piece.stringify(&mut buf);
@@ -177,42 +1175,314 @@ impl JsActiveField {
         }
     }
 
+    /// Like `getQueue`, but returns piece codes.
+    #[wasm_bindgen(js_name = "getQueueCodes")]
+    pub fn queue_codes(&self, n: usize) -> Box<[u8]> {
+        self.field()
+            .peek_queue(n)
+            .map(|piece| piece.index())
+            .collect()
+    }
+
+    /// Like `getHeldPiece`, but returns a piece code.
+    #[wasm_bindgen(js_name = "getHeldPieceCode")]
+    pub fn held_piece_code(&self) -> Option<u8> {
+        self.field().held_piece().map(|piece| piece.index())
+    }
+
     #[wasm_bindgen(js_name = "getActivePiece")]
     pub fn active_piece(&self) -> Option<JsActivePiece> {
-        self.0.active_piece().map(|x| JsActivePiece(*x))
+        self.field().active_piece().map(|x| JsActivePiece(*x))
     }
 
     #[wasm_bindgen(js_name = "getFieldWidth")]
     pub fn field_width(&self) -> usize {
-        self.0.field().width()
+        self.field().field().width()
     }
 
     #[wasm_bindgen(js_name = "getFieldHeight")]
     pub fn field_height(&self) -> usize {
-        self.0.field().height()
+        self.field().field().height()
     }
 
     #[wasm_bindgen(js_name = "getFieldTopHeight")]
     pub fn field_top_height(&self) -> usize {
-        self.0.field().top_height()
+        self.field().field().top_height()
     }
 
     #[wasm_bindgen(js_name = "getFieldClearRows")]
     pub fn field_clear_rows(&self) -> usize {
-        self.0.field().clear_rows()
+        self.field().field().clear_rows()
+    }
+
+    /// Returns which neighbors of a tile belong to the same piece, as bits (1: left, 2: right,
+    /// 4: up, 8: down).
+    #[wasm_bindgen(js_name = "getFieldTileLinks")]
+    pub fn field_tile_links(&self, x: usize, y: usize) -> u8 {
+        self.field().field().tile_links(x, y).bits()
+    }
+
+    /// Returns all field tiles as a view into wasm memory, row by row from the bottom, with one
+    /// byte per tile (0: empty, piece index + 1: piece, 254: cleared, 255: garbage or item).
+    ///
+    /// The view is only valid until the next call into this field, since wasm memory may move.
+    #[wasm_bindgen(js_name = "getTilesBuffer")]
+    pub fn tiles_buffer(&mut self) -> Uint8Array {
+        tile_codes_view(self.game.sim().field().field(), &mut self.tiles_buffer)
+    }
+
+    /// Returns everything needed to render the field in a single call: `width`, `tiles` (as in
+    /// `getTilesBuffer`), the `activePiece` and `ghost` tiles in field coordinates as x and y
+    /// pairs, the `queue` of preview pieces, the `hold` piece, `clearRows` as pairs of row index
+    /// and clear time, and whether the field has topped out (`topOut`).
+    ///
+    /// The tiles view is only valid until the next call into this field.
+    #[wasm_bindgen(js_name = "getRenderState")]
+    pub fn render_state(&mut self) -> RenderState {
+        let state = RenderState::new();
+        let field = self.field().field();
+        state.set_width(field.width());
+        state.set_clear_rows(clear_rows(field));
+        state.set_active_piece(absolute_tiles(self.field().active_piece()));
+        state.set_ghost(absolute_tiles(self.field().ghost_piece().as_ref()));
+        state.set_queue(
+            self.field()
+                .peek_queue(self.field().preview_depth())
+                .map(|piece| JsValue::from_str(&piece_type_name(piece)))
+                .collect(),
+        );
+        state.set_hold(self.field().held_piece().map(piece_type_name));
+        state.set_top_out(self.field().is_top_out());
+        state.set_tiles(self.tiles_buffer());
+        state
+    }
+
+    /// Returns the code of a tile (see `TileCode`), or undefined if it’s outside the field.
+    #[wasm_bindgen(js_name = "getFieldTileCode")]
+    pub fn field_tile_code(&self, x: usize, y: usize) -> Option<u8> {
+        self.field().field().get_tile(x, y).map(Tile::code)
     }
 
     #[wasm_bindgen(js_name = "getFieldTile")]
     pub fn field_get_tile(&self, x: usize, y: usize) -> JsValue {
-        match self.0.field().get_tile(x, y) {
-            Some(Tile::Empty) => JsValue::from_str(""),
-            Some(Tile::Piece(t)) => {
-                let mut buf = String::new();
-                t.stringify(&mut buf);
-                JsValue::from_str(&buf)
+        tile_value(self.field().field().get_tile(x, y))
+    }
+}
+
+/// Converts a tile for `getFieldTile`: a stringified piece or garbage tile, the time a cleared tile
+/// was cleared, or null if it's out of bounds.
+fn tile_value(tile: Option<Tile>) -> JsValue {
+    match tile {
+        Some(Tile::Empty) => JsValue::from_str(""),
+        Some(Tile::Piece(t)) => {
+            let mut buf = String::new();
+            t.stringify(&mut buf);
+            JsValue::from_str(&buf)
+        }
+        Some(Tile::Garbage) => JsValue::from_str("G"),
+        Some(Tile::Clear(time)) => JsValue::from_f64(time),
+        Some(Tile::Item(item)) => JsValue::from_str(&format!("*{}", item.index())),
+        None => JsValue::null(),
+    }
+}
+
+/// Another player’s field as sent by the server in `fields` messages.
+#[derive(Deserialize)]
+struct RemoteFieldState {
+    width: usize,
+    /// Stringified tiles, row by row from the bottom.
+    tiles: String,
+    active: Option<ActivePiece>,
+    next: Option<PieceType>,
+    time: f64,
+    score: usize,
+    level: usize,
+    is_game_over: bool,
+}
+
+/// Changes to another player’s field as sent by the server in `field-delta` messages.
+#[derive(Deserialize)]
+struct RemoteFieldDelta {
+    /// Changed rows by index from the bottom, with all of their tiles stringified.
+    rows: Vec<(usize, String)>,
+    active: Option<ActivePiece>,
+    next: Option<PieceType>,
+    time: f64,
+    score: usize,
+    level: usize,
+    is_game_over: bool,
+}
+
+/// Another player’s field, for rendering opponents.
+#[wasm_bindgen(js_name = RemoteField)]
+pub struct JsRemoteField {
+    field: Field,
+    active: Option<ActivePiece>,
+    next: Option<PieceType>,
+    time: f64,
+    score: usize,
+    level: usize,
+    is_game_over: bool,
+    /// Tile codes returned by `getTilesBuffer`.
+    tiles_buffer: Vec<u8>,
+}
+
+impl JsRemoteField {
+    fn parse(json: &str) -> JsRemoteField {
+        let state: RemoteFieldState = match serde_json::from_str(json) {
+            Ok(state) => state,
+            Err(err) => throw_str(&format!("invalid field state: {}", err)),
+        };
+        let field = match Tile::parse_list(&state.tiles)
+            .and_then(|tiles| Field::from_tile_list(state.width, tiles))
+        {
+            Some(field) => field,
+            None => throw_str("invalid field tiles"),
+        };
+        JsRemoteField {
+            field,
+            active: state.active,
+            next: state.next,
+            time: state.time,
+            score: state.score,
+            level: state.level,
+            is_game_over: state.is_game_over,
+            tiles_buffer: Vec::new(),
+        }
+    }
+
+    /// Returns the active piece moved down as far as it goes.
+    fn ghost_piece(&self) -> Option<ActivePiece> {
+        let mut piece = self.active?;
+        for _ in 0..self.field.height() {
+            if piece.is_on_ground(&self.field) {
+                break;
             }
-            Some(Tile::Clear(time)) => JsValue::from_f64(time),
-            None => JsValue::null(),
+            piece.try_move(&self.field, 0, -1, self.time);
         }
+        Some(piece)
+    }
+}
+
+#[wasm_bindgen(js_class = RemoteField)]
+impl JsRemoteField {
+    /// Creates a field from the server’s JSON representation of a field state.
+    #[wasm_bindgen(js_name = "deserialize")]
+    pub fn deserialize(json: &str) -> JsRemoteField {
+        JsRemoteField::parse(json)
+    }
+
+    /// Replaces the state with a newer one from the server.
+    #[wasm_bindgen(js_name = "update")]
+    pub fn update(&mut self, json: &str) {
+        let tiles_buffer = std::mem::take(&mut self.tiles_buffer);
+        *self = JsRemoteField {
+            tiles_buffer,
+            ..JsRemoteField::parse(json)
+        };
+    }
+
+    /// Applies changes sent by the server to the current state.
+    #[wasm_bindgen(js_name = "applyDelta")]
+    pub fn apply_delta(&mut self, json: &str) {
+        let delta: RemoteFieldDelta = match serde_json::from_str(json) {
+            Ok(delta) => delta,
+            Err(err) => throw_str(&format!("invalid field delta: {}", err)),
+        };
+        let width = self.field.width();
+        for (y, tiles) in &delta.rows {
+            let row = match Tile::parse_list(tiles) {
+                Some(row) if row.len() == width && *y < self.field.height() => row,
+                _ => throw_str("invalid field delta tiles"),
+            };
+            for (x, tile) in row.into_iter().enumerate() {
+                self.field.set_tile(x, *y, tile);
+            }
+        }
+        self.active = delta.active;
+        self.next = delta.next;
+        self.time = delta.time;
+        self.score = delta.score;
+        self.level = delta.level;
+        self.is_game_over = delta.is_game_over;
+    }
+
+    /// Returns the game time of the player when the state was sent.
+    #[wasm_bindgen(js_name = "getTime")]
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    #[wasm_bindgen(js_name = "getScore")]
+    pub fn score(&self) -> usize {
+        self.score
+    }
+
+    #[wasm_bindgen(js_name = "getLevel")]
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    #[wasm_bindgen(js_name = "isGameOver")]
+    pub fn is_game_over(&self) -> bool {
+        self.is_game_over
+    }
+
+    #[wasm_bindgen(js_name = "getActivePiece")]
+    pub fn active_piece(&self) -> Option<JsActivePiece> {
+        self.active.map(JsActivePiece)
+    }
+
+    #[wasm_bindgen(js_name = "getNextPiece")]
+    pub fn next_piece(&self) -> Option<String> {
+        self.next.map(piece_type_name)
+    }
+
+    #[wasm_bindgen(js_name = "getFieldWidth")]
+    pub fn field_width(&self) -> usize {
+        self.field.width()
+    }
+
+    #[wasm_bindgen(js_name = "getFieldHeight")]
+    pub fn field_height(&self) -> usize {
+        self.field.height()
+    }
+
+    /// Returns a tile like `ActiveField.getFieldTile`.
+    #[wasm_bindgen(js_name = "getFieldTile")]
+    pub fn field_get_tile(&self, x: usize, y: usize) -> JsValue {
+        tile_value(self.field.get_tile(x, y))
+    }
+
+    #[wasm_bindgen(js_name = "getGhostPiece")]
+    pub fn get_ghost_piece(&self) -> Option<JsActivePiece> {
+        self.ghost_piece().map(JsActivePiece)
+    }
+
+    /// Returns all field tiles as a view into wasm memory, like `ActiveField.getTilesBuffer`.
+    #[wasm_bindgen(js_name = "getTilesBuffer")]
+    pub fn tiles_buffer(&mut self) -> Uint8Array {
+        tile_codes_view(&self.field, &mut self.tiles_buffer)
+    }
+
+    /// Returns everything needed to render the field, like `ActiveField.getRenderState`. The
+    /// queue only contains the next piece, and there is never a held piece.
+    #[wasm_bindgen(js_name = "getRenderState")]
+    pub fn render_state(&mut self) -> RenderState {
+        let state = RenderState::new();
+        state.set_width(self.field.width());
+        state.set_clear_rows(clear_rows(&self.field));
+        state.set_active_piece(absolute_tiles(self.active.as_ref()));
+        state.set_ghost(absolute_tiles(self.ghost_piece().as_ref()));
+        state.set_queue(
+            self.next
+                .map(|piece| JsValue::from_str(&piece_type_name(piece)))
+                .into_iter()
+                .collect(),
+        );
+        state.set_hold(None);
+        state.set_top_out(self.is_game_over);
+        state.set_tiles(self.tiles_buffer());
+        state
     }
 }